@@ -4,8 +4,13 @@ II_CANISTER_ID="$(dfx canister id internet_identity --network "${ENV:-local}")"
 POUH_ISSUER_CANISTER_ID="$(dfx canister id pouh_issuer --network "${ENV:-local}")"
 SIGNER_CANISTER_ID="$(dfx canister id signer --network "${ENV:-local}")"
 
+# EVM chains are not read locally.
+evm_rpc_canister_id="null"
+
 case $ENV in
 "staging")
+  # The mainnet EVM RPC canister, which reads the state of EVM chains.
+  evm_rpc_canister_id="opt principal \"7hfb6-caaaa-aaaar-qadga-cai\""
   ECDSA_KEY_NAME="test_key_1"
   WALLET="cvthj-wyaaa-aaaad-aaaaq-cai"
   # For security reasons, mainnet root key will be hardcoded in the backend canister.
@@ -15,6 +20,8 @@ case $ENV in
   POUH_ISSUER_VC_URL="https://${POUH_ISSUER_CANISTER_ID}.icp0.io/"
   ;;
 "ic")
+  # The mainnet EVM RPC canister, which reads the state of EVM chains.
+  evm_rpc_canister_id="opt principal \"7hfb6-caaaa-aaaar-qadga-cai\""
   ECDSA_KEY_NAME="key_1"
   WALLET="yit3i-lyaaa-aaaan-qeavq-cai"
   # For security reasons, mainnet root key will be hardcoded in the backend canister.
@@ -34,6 +41,7 @@ echo "(variant {
         ecdsa_key_name = \"$ECDSA_KEY_NAME\";
         allowed_callers = vec {};
         cfs_canister_id = opt principal \"$SIGNER_CANISTER_ID\";
+        evm_rpc_canister_id = $evm_rpc_canister_id;
         supported_credentials = opt vec {
           record {
             credential_type = variant { ProofOfUniqueness };
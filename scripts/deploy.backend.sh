@@ -9,6 +9,8 @@ REWARDS_CANISTER_ID="$(dfx canister id rewards --network "${ENV:-local}" || [[ "
 
 case $ENV in
 "staging")
+  # The mainnet EVM RPC canister, which reads the state of EVM chains.
+  evm_rpc_canister_id="opt principal \"7hfb6-caaaa-aaaar-qadga-cai\""
   ECDSA_KEY_NAME="test_key_1"
   # For security reasons, mainnet root key will be hardcoded in the backend canister.
   ic_root_key_der="null"
@@ -18,6 +20,8 @@ case $ENV in
   DERIVATION_ORIGIN="https://tewsx-xaaaa-aaaad-aadia-cai.icp0.io"
   ;;
 "ic")
+  # The mainnet EVM RPC canister, which reads the state of EVM chains.
+  evm_rpc_canister_id="opt principal \"7hfb6-caaaa-aaaar-qadga-cai\""
   ECDSA_KEY_NAME="key_1"
   # For security reasons, mainnet root key will be hardcoded in the backend canister.
   ic_root_key_der="null"
@@ -27,6 +31,8 @@ case $ENV in
   DERIVATION_ORIGIN="https://oisy.com"
   ;;
 *)
+  # EVM chains are not read locally.
+  evm_rpc_canister_id="null"
   ECDSA_KEY_NAME="dfx_test_key"
   # In order to read the root key we grab the array from the '"root_key": [...]' bit, the brackets
   # to match what candid expects ({}), replace the commas between array entries to match
@@ -62,6 +68,7 @@ if [ -n "${ENV+1}" ]; then
          ecdsa_key_name = \"$ECDSA_KEY_NAME\";
          allowed_callers = $ALLOWED_CALLERS;
          cfs_canister_id = opt principal \"$SIGNER_CANISTER_ID\";
+         evm_rpc_canister_id = $evm_rpc_canister_id;
          derivation_origin = opt \"$DERIVATION_ORIGIN\";
          supported_credentials = opt vec {
             record {
@@ -82,6 +89,7 @@ else
          ecdsa_key_name = \"$ECDSA_KEY_NAME\";
          allowed_callers = $ALLOWED_CALLERS;
          cfs_canister_id = opt principal \"$SIGNER_CANISTER_ID\";
+         evm_rpc_canister_id = $evm_rpc_canister_id;
          derivation_origin = opt \"$DERIVATION_ORIGIN\";
          supported_credentials = opt vec {
             record {
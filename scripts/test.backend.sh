@@ -17,6 +17,14 @@ else
   cargo build --locked --target wasm32-unknown-unknown --release -p backend
 fi
 
+# The stand-in for the EVM RPC canister. The test will resolve target/wasm32-unknown-unknown/release/evm_rpc_stub.wasm unless EVM_RPC_STUB_WASM_PATH is set.
+echo "Building EVM RPC stand-in canister."
+cargo build --locked --target wasm32-unknown-unknown --release -p evm_rpc_stub
+
+# The backend with the endpoints that only the tests use. It is built in its own target directory, so that it does not replace the release build. The test will resolve target/test-endpoints/wasm32-unknown-unknown/release/backend.wasm unless TEST_ENDPOINTS_BACKEND_WASM_PATH is set.
+echo "Building backend canister with test endpoints."
+cargo build --locked --target wasm32-unknown-unknown --release -p backend --features test-endpoints --target-dir target/test-endpoints
+
 if [ -f "./$BITCON_CANISTER_WASM" ]; then
   echo "Use existing $BITCON_CANISTER_WASM canister."
 else
@@ -1,5 +1,10 @@
-use crate::MAX_SYMBOL_LENGTH;
-use shared::types::token::UserToken;
+use crate::{evm_rpc::EvmRpcClient, MAX_SYMBOL_LENGTH};
+use ethers_core::types::H160;
+use shared::types::{
+    ethereum::EvmRpcError,
+    token::{ChainId, UserToken},
+};
+use std::{cell::RefCell, collections::BTreeMap};
 
 pub fn assert_token_symbol_length(token: &UserToken) -> Result<(), String> {
     if let Some(symbol) = token.symbol.as_ref() {
@@ -20,3 +25,100 @@ pub fn assert_token_enabled_is_some(UserToken { enabled, .. }: &UserToken) -> Re
 
     Ok(())
 }
+
+/// The maximum number of token contracts whose metadata is cached.
+const MAX_CACHED_CONTRACTS: usize = 10_000;
+
+/// The metadata read from an ERC20 token contract.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct ContractMetadata {
+    decimals: Option<u8>,
+    symbol: Option<String>,
+}
+
+thread_local! {
+    /// The metadata read from token contracts, by chain and contract, so that each contract is read once: every read
+    /// costs cycles for each provider.
+    ///
+    /// The decimals and the symbol of a token do not change.  The metadata is kept on the heap, so it is read again
+    /// after an upgrade.
+    static CONTRACT_METADATA: RefCell<BTreeMap<(ChainId, H160), ContractMetadata>> = RefCell::default();
+}
+
+/// The cached metadata of a token contract.
+fn cached_contract_metadata(key: &(ChainId, H160)) -> ContractMetadata {
+    CONTRACT_METADATA.with(|metadata| metadata.borrow().get(key).cloned().unwrap_or_default())
+}
+
+/// Caches the metadata of a token contract.  The cache is emptied when it is full.
+fn cache_contract_metadata(key: (ChainId, H160), contract_metadata: ContractMetadata) {
+    CONTRACT_METADATA.with(|metadata| {
+        let mut metadata = metadata.borrow_mut();
+        if metadata.len() >= MAX_CACHED_CONTRACTS && !metadata.contains_key(&key) {
+            metadata.clear();
+        }
+        metadata.insert(key, contract_metadata);
+    });
+}
+
+/// Checks the decimals and the symbol of an ERC20 token, where given, against the token contract.
+///
+/// The check is skipped if the chain cannot be read, i.e. if the EVM RPC canister is not configured or has no
+/// providers for the chain.  The metadata of each contract is read once and then cached.
+///
+/// # Errors
+/// - The contract could not be read, or its decimals or symbol differ from those of the token.
+pub async fn assert_token_matches_contract(
+    token: &UserToken,
+    contract: &H160,
+) -> Result<(), String> {
+    let key = (token.chain_id, *contract);
+    let mut metadata = cached_contract_metadata(&key);
+    let read_decimals = token.decimals.is_some() && metadata.decimals.is_none();
+    let read_symbol = token.symbol.is_some() && metadata.symbol.is_none();
+    if read_decimals || read_symbol {
+        let client = match EvmRpcClient::new(token.chain_id) {
+            Ok(client) => client,
+            Err(EvmRpcError::Disabled | EvmRpcError::UnsupportedChain { .. }) => return Ok(()),
+            Err(err) => return Err(format!("Failed to read the token contract: {err:?}")),
+        };
+        if read_decimals {
+            let decimals = client
+                .erc20_decimals(contract)
+                .await
+                .map_err(|err| format!("Failed to read the token decimals: {err:?}"))?;
+            metadata.decimals = Some(decimals);
+            cache_contract_metadata(key, metadata.clone());
+        }
+        if read_symbol {
+            let symbol = client
+                .erc20_symbol(contract)
+                .await
+                .map_err(|err| format!("Failed to read the token symbol: {err:?}"))?;
+            metadata.symbol = Some(symbol);
+            cache_contract_metadata(key, metadata.clone());
+        }
+    }
+    assert_metadata_matches(token, &metadata)
+}
+
+/// Checks the decimals and the symbol of a token, where given, against the metadata of the contract.
+fn assert_metadata_matches(token: &UserToken, metadata: &ContractMetadata) -> Result<(), String> {
+    if let (Some(decimals), Some(contract_decimals)) = (token.decimals, metadata.decimals) {
+        if decimals != contract_decimals {
+            return Err(format!(
+                "Token decimals {decimals} do not match the contract decimals {contract_decimals}"
+            ));
+        }
+    }
+    if let (Some(symbol), Some(contract_symbol)) = (token.symbol.as_ref(), metadata.symbol.as_ref())
+    {
+        if symbol != contract_symbol {
+            return Err(format!(
+                "Token symbol {symbol} does not match the contract symbol {contract_symbol}"
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use ic_cdk::api::management_canister::bitcoin::{
-    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, BitcoinNetwork,
-    GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse, MillisatoshiPerByte, Utxo,
-    UtxoFilter,
+    bitcoin_get_current_fee_percentiles, bitcoin_get_utxos, bitcoin_send_transaction,
+    BitcoinNetwork, GetCurrentFeePercentilesRequest, GetUtxosRequest, GetUtxosResponse,
+    MillisatoshiPerByte, SendTransactionRequest, Utxo, UtxoFilter,
 };
 
 /// Returns the UTXOs of the given bitcoin address.
@@ -23,22 +23,32 @@ async fn get_utxos(
 
     Ok(utxos_res.0)
 }
-/// Returns all the UTXOs of a specific address.
+/// The UTXOs of an address, and the height of the chain tip they were read at.
+pub struct AddressUtxos {
+    pub utxos: Vec<Utxo>,
+    pub tip_height: u32,
+}
+
+/// Returns all the UTXOs of a specific address, together with the height of the chain tip.
 /// API interface returns a paginated view of the utxos but we need to get them all.
-pub async fn get_all_utxos(
+///
+/// The Bitcoin API only knows about transactions in blocks: the outputs of unconfirmed transactions are never
+/// included, whatever `min_confirmations`.
+pub async fn get_address_utxos(
     network: BitcoinNetwork,
     address: String,
     min_confirmations: Option<u32>,
-) -> Result<Vec<Utxo>, String> {
+) -> Result<AddressUtxos, String> {
     let final_min_confirmations = if network == BitcoinNetwork::Regtest {
         // Tests with Regtest fail if min_confirmations is higher than 1.
-        Some(1)
+        Some(min_confirmations.map_or(1, |min_confirmations| min_confirmations.min(1)))
     } else {
         min_confirmations
     };
     let filter = final_min_confirmations.map(UtxoFilter::MinConfirmations);
     let mut utxos_response = get_utxos(network, address.clone(), filter).await?;
 
+    let tip_height = utxos_response.tip_height;
     let mut all_utxos: Vec<Utxo> = utxos_response.utxos;
     let mut next_page: Option<Vec<u8>> = utxos_response.next_page;
     while next_page.is_some() {
@@ -48,7 +58,10 @@ pub async fn get_all_utxos(
         next_page = utxos_response.next_page;
     }
 
-    Ok(all_utxos)
+    Ok(AddressUtxos {
+        utxos: all_utxos,
+        tip_height,
+    })
 }
 
 /// Returns the 100 fee percentiles measured in millisatoshi/byte.
@@ -56,7 +69,7 @@ pub async fn get_all_utxos(
 ///
 /// Relies on the `bitcoin_get_current_fee_percentiles` endpoint.
 /// See [Bitcoin API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_get_current_fee_percentiles)
-async fn get_current_fee_percentiles(
+pub async fn get_current_fee_percentiles(
     network: BitcoinNetwork,
 ) -> Result<Vec<MillisatoshiPerByte>, String> {
     let res = bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest { network })
@@ -66,18 +79,15 @@ async fn get_current_fee_percentiles(
     Ok(res.0)
 }
 
-/// Returns the 50th percentile for sending fees.
-pub async fn get_fee_per_byte(network: BitcoinNetwork) -> Result<u64, String> {
-    // Get fee percentiles from previous transactions to estimate our own fee.
-    let fee_percentiles = get_current_fee_percentiles(network).await?;
-
-    if fee_percentiles.is_empty() {
-        // There are no fee percentiles. This case can only happen on a regtest
-        // network where there are no non-coinbase transactions. In this case,
-        // we use a default of 2000 millisatoshis/byte (i.e. 2 satoshi/byte)
-        Ok(2000)
-    } else {
-        let middle = fee_percentiles.len() / 2;
-        Ok(fee_percentiles[middle])
-    }
+/// Sends a signed transaction to the Bitcoin network.
+///
+/// Relies on the `bitcoin_send_transaction` endpoint.
+/// See [Bitcoin API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-bitcoin_send_transaction)
+pub async fn send_transaction(network: BitcoinNetwork, transaction: Vec<u8>) -> Result<(), String> {
+    bitcoin_send_transaction(SendTransactionRequest {
+        transaction,
+        network,
+    })
+    .await
+    .map_err(|err| err.1)
 }
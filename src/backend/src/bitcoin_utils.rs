@@ -1,6 +1,19 @@
 //! Functions [inspired by ckBTC Minter](https://github.com/dfinity/ic/blob/285a5db07da50a4e350ec43bf3b488cc6fe36102/rs/bitcoin/ckbtc/minter/src/lib.rs#L1258)
 
-use ic_cdk::api::management_canister::bitcoin::Utxo;
+use crate::signer::transform_network;
+use bitcoin::{
+    absolute::LockTime,
+    hashes::Hash,
+    transaction::{predict_weight, InputWeightPrediction, Version},
+    Address, AddressType, Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, MillisatoshiPerByte, Utxo};
+use shared::types::bitcoin::{
+    BtcFeeTier, BtcFeeTierPercentiles, BtcFeeTierRate, BtcGetBalanceResponse, BtcTxOutput,
+    SelectedUtxosFeeError, SelectedUtxosFeeResponse,
+};
+use std::{iter, str::FromStr};
 
 /// Selects a subset of UTXOs with the specified total target value and removes
 /// the selected UTXOs from the available set.
@@ -86,38 +99,382 @@ pub fn utxos_selection(
     input_utxos
 }
 
-/// Estimates the size of transaction (in vbytes) with the given number of inputs and outputs.
+/// The prediction of the size of an input that spends from the wallet's own address.
+///
+/// Signatures are assumed to have their largest size, so that the fee is never too low.
+/// Addresses whose spending script is unknown to the wallet are estimated as P2WPKH.
+pub fn input_weight_prediction(address: &Address) -> InputWeightPrediction {
+    match address.address_type() {
+        Some(AddressType::P2pkh) => InputWeightPrediction::P2PKH_COMPRESSED_MAX,
+        Some(AddressType::P2tr) => InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH,
+        _ => InputWeightPrediction::P2WPKH_MAX,
+    }
+}
+
+/// Estimates the size of a transaction, in vbytes.
+///
+/// Arguments:
+///   * `input` - the prediction for each input.
+///   * `input_count` - the number of inputs.
+///   * `output_script_lens` - the length of the script of each output.
 // See [MediaWiki](https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki)
-// for the transaction structure and
-// [Stack Exchange](https://bitcoin.stackexchange.com/questions/92587/calculate-transaction-fee-for-external-addresses-which-doesnt-belong-to-my-loca/92600#92600)
-// for transaction size estimate.
-const INPUT_SIZE_VBYTES: u64 = 68;
-const OUTPUT_SIZE_VBYTES: u64 = 31;
-const TX_OVERHEAD_VBYTES: u64 = 11;
-fn tx_vsize_estimate(input_count: u64, output_count: u64) -> u64 {
-    input_count * INPUT_SIZE_VBYTES + output_count * OUTPUT_SIZE_VBYTES + TX_OVERHEAD_VBYTES
+// for the transaction structure, including the segwit marker and flag.
+pub fn estimate_tx_vsize(
+    input: InputWeightPrediction,
+    input_count: usize,
+    output_script_lens: impl IntoIterator<Item = usize>,
+) -> u64 {
+    predict_weight(iter::repeat(input).take(input_count), output_script_lens).to_vbytes_ceil()
 }
 
-/// Estimates the transaction fee, in satoshi, based on the number of utxos and outputs
+/// Estimates the transaction fee, in satoshi, based on the inputs and the scripts of the outputs
 ///
 /// Arguments:
+///   * `input` - the prediction for each input.
 ///   * `selected_utxos_count` - the number of UTXOs used for the transaction.
-///   * `median_fee_millisatoshi_per_vbyte` - the median network fee, in millisatoshi per vbyte.
-///   * `output_count` - the number of outputs of the bitcoin transaction.
+///   * `fee_millisatoshi_per_vbyte` - the fee rate, in millisatoshi per vbyte.
+///   * `output_script_lens` - the length of the script of each output, including change.
+///
+/// Returns `None` if the fee overflows, which no UTXOs could pay.
 pub fn estimate_fee(
-    selected_utxos_count: u64,
-    median_fee_millisatoshi_per_vbyte: u64,
-    output_count: u64,
-) -> u64 {
-    tx_vsize_estimate(selected_utxos_count, output_count) * median_fee_millisatoshi_per_vbyte / 1000
+    input: InputWeightPrediction,
+    selected_utxos_count: usize,
+    fee_millisatoshi_per_vbyte: u64,
+    output_script_lens: impl IntoIterator<Item = usize>,
+) -> Option<u64> {
+    estimate_tx_vsize(input, selected_utxos_count, output_script_lens)
+        .checked_mul(fee_millisatoshi_per_vbyte)
+        .map(|fee| fee / 1000)
+}
+
+/// Whether a UTXO is spent by a pending transaction.
+///
+/// UTXOs are compared by outpoint: a UTXO locked while its transaction was unconfirmed has no height yet.
+pub fn is_locked(locked_utxos: &[Utxo], utxo: &Utxo) -> bool {
+    locked_utxos
+        .iter()
+        .any(|locked| locked.outpoint == utxo.outpoint)
+}
+
+/// Whether a UTXO has at least `min_confirmations` confirmations at the given chain tip.
+pub fn has_confirmations(utxo: &Utxo, tip_height: u32, min_confirmations: u32) -> bool {
+    tip_height.saturating_sub(utxo.height) + 1 >= min_confirmations
+}
+
+/// Sums up the UTXOs of an address into a balance.
+///
+/// A UTXO is confirmed once it has `min_confirmations` confirmations at the given chain tip.
+/// Confirmed UTXOs in `locked_utxos` are spent by pending transactions, so they are not spendable.
+pub fn utxo_balance(
+    utxos: &[Utxo],
+    locked_utxos: &[Utxo],
+    tip_height: u32,
+    min_confirmations: u32,
+) -> BtcGetBalanceResponse {
+    let (confirmed, unconfirmed): (Vec<&Utxo>, Vec<&Utxo>) = utxos
+        .iter()
+        .partition(|utxo| has_confirmations(utxo, tip_height, min_confirmations));
+    let (locked, spendable): (Vec<&Utxo>, Vec<&Utxo>) = confirmed
+        .iter()
+        .partition(|utxo| is_locked(locked_utxos, utxo));
+    BtcGetBalanceResponse {
+        confirmed_satoshis: confirmed.iter().map(|utxo| utxo.value).sum(),
+        unconfirmed_incoming_satoshis: unconfirmed.iter().map(|utxo| utxo.value).sum(),
+        locked_satoshis: locked.iter().map(|utxo| utxo.value).sum(),
+        spendable_utxo_count: u32::try_from(spendable.len()).unwrap_or(u32::MAX),
+    }
+}
+
+/// Parses an address, checking that it belongs to the given network.
+///
+/// # Errors
+/// - Returns `Err` if the address cannot be parsed or is for another network.
+pub fn parse_address(address: &str, network: BitcoinNetwork) -> Result<Address, String> {
+    Address::from_str(address)
+        .map_err(|err| err.to_string())?
+        .require_network(transform_network(network))
+        .map_err(|err| err.to_string())
+}
+
+/// Returns the network of an address, as far as it can be told from the address alone.
+///
+/// Legacy regtest addresses share their prefixes with testnet addresses, so they are taken to be testnet addresses.
+pub fn address_network(address: &str) -> Option<BitcoinNetwork> {
+    let address = Address::from_str(address).ok()?;
+    [
+        BitcoinNetwork::Mainnet,
+        BitcoinNetwork::Testnet,
+        BitcoinNetwork::Regtest,
+    ]
+    .into_iter()
+    .find(|network| address.is_valid_for_network(transform_network(*network)))
+}
+
+/// Validates the outputs of a batch send and returns the script of each output.
+///
+/// # Errors
+/// - Returns `Err` if there are no outputs, an output is zero or has an invalid address,
+///   or the outputs do not add up to `amount_satoshis`.
+pub fn output_scripts(
+    outputs: &[BtcTxOutput],
+    amount_satoshis: u64,
+    network: BitcoinNetwork,
+) -> Result<Vec<ScriptBuf>, SelectedUtxosFeeError> {
+    if outputs.is_empty() {
+        return Err(SelectedUtxosFeeError::InvalidOutputs {
+            msg: "At least one output is required".to_string(),
+        });
+    }
+    let mut total_satoshis: u64 = 0;
+    let mut scripts = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        if output.sent_satoshis == 0 {
+            return Err(SelectedUtxosFeeError::InvalidOutputs {
+                msg: format!("The output to {} is zero", output.destination_address),
+            });
+        }
+        let address = parse_address(&output.destination_address, network).map_err(|_| {
+            SelectedUtxosFeeError::InvalidDestinationAddress {
+                address: output.destination_address.clone(),
+            }
+        })?;
+        total_satoshis = total_satoshis.checked_add(output.sent_satoshis).ok_or(
+            SelectedUtxosFeeError::InvalidOutputs {
+                msg: "The outputs overflow".to_string(),
+            },
+        )?;
+        scripts.push(address.script_pubkey());
+    }
+    if total_satoshis != amount_satoshis {
+        return Err(SelectedUtxosFeeError::InvalidOutputs {
+            msg: format!(
+                "The outputs add up to {total_satoshis} satoshis, not {amount_satoshis} satoshis"
+            ),
+        });
+    }
+    Ok(scripts)
+}
+
+/// Returns the length of the script of each output, for the fee estimate.
+///
+/// Without explicit outputs, we assume a single destination of the same type as the source address.
+///
+/// # Errors
+/// - Returns `Err` if the outputs are invalid, see `output_scripts`.
+pub fn output_script_lens(
+    outputs: Option<&[BtcTxOutput]>,
+    amount_satoshis: u64,
+    source: &Address,
+    network: BitcoinNetwork,
+) -> Result<Vec<usize>, SelectedUtxosFeeError> {
+    Ok(match outputs {
+        Some(outputs) => output_scripts(outputs, amount_satoshis, network)?
+            .iter()
+            .map(|script| script.len())
+            .collect(),
+        None => vec![source.script_pubkey().len()],
+    })
+}
+
+/// Builds an unsigned transaction that spends UTXOs of the segwit `source` address to the outputs, as a PSBT.
+///
+/// The inputs signal replaceability (BIP-125).  Each input records the output it spends,
+/// which signers need to compute the segwit and taproot signature hashes.
+///
+/// # Errors
+/// - Returns `Err` if a UTXO has an invalid txid or an output has an invalid address.
+pub fn build_psbt(
+    utxos: &[Utxo],
+    source: &Address,
+    outputs: &[BtcTxOutput],
+    network: BitcoinNetwork,
+) -> Result<Psbt, String> {
+    let input = utxos
+        .iter()
+        .map(|utxo| {
+            Ok(TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_slice(&utxo.outpoint.txid).map_err(|err| err.to_string())?,
+                    vout: utxo.outpoint.vout,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let output = outputs
+        .iter()
+        .map(|output| {
+            Ok(TxOut {
+                value: Amount::from_sat(output.sent_satoshis),
+                script_pubkey: parse_address(&output.destination_address, network)?.script_pubkey(),
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let unsigned_tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    };
+    psbt_from_unsigned_tx(unsigned_tx, utxos, source)
+}
+
+/// Wraps an unsigned transaction in a PSBT, recording the UTXO of `source` spent by each input.
+///
+/// # Errors
+/// - Returns `Err` if the transaction has signatures.
+pub fn psbt_from_unsigned_tx(
+    unsigned_tx: Transaction,
+    utxos: &[Utxo],
+    source: &Address,
+) -> Result<Psbt, String> {
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).map_err(|err| err.to_string())?;
+    for (psbt_input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+        psbt_input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(utxo.value),
+            script_pubkey: source.script_pubkey(),
+        });
+    }
+    Ok(psbt)
+}
+
+/// Returns the address and amount of each output of a transaction.
+///
+/// # Errors
+/// - Returns `Err` if an output script has no address.
+pub fn tx_outputs(tx: &Transaction, network: BitcoinNetwork) -> Result<Vec<BtcTxOutput>, String> {
+    tx.output
+        .iter()
+        .map(|output| {
+            Ok(BtcTxOutput {
+                destination_address: Address::from_script(
+                    &output.script_pubkey,
+                    transform_network(network),
+                )
+                .map_err(|err| err.to_string())?
+                .to_string(),
+                sent_satoshis: output.value.to_sat(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the index of the change output of a transaction built from a selection, if there is change.
+///
+/// The change output follows the requested outputs.
+pub fn change_output_index(selection: &SelectedUtxosFeeResponse) -> Option<u32> {
+    if selection.change_satoshis == 0 {
+        return None;
+    }
+    selection
+        .outputs
+        .len()
+        .checked_sub(1)
+        .and_then(|index| u32::try_from(index).ok())
+}
+
+/// Whether the UTXOs of a selection pay exactly for its outputs, including change, and its fee.
+pub fn is_funded(selection: &SelectedUtxosFeeResponse) -> bool {
+    let input_satoshis = selection
+        .utxos
+        .iter()
+        .try_fold(0_u64, |sum, utxo| sum.checked_add(utxo.value));
+    let output_satoshis = selection
+        .outputs
+        .iter()
+        .try_fold(selection.fee_satoshis, |sum, output| {
+            sum.checked_add(output.sent_satoshis)
+        });
+    matches!((input_satoshis, output_satoshis), (Some(input), Some(output)) if input == output)
+}
+
+/// Decodes a transaction in its consensus encoding.
+///
+/// # Errors
+/// - Returns `Err` if the bytes are not a transaction or the transaction has no inputs.
+pub fn decode_transaction(raw_tx: &[u8]) -> Result<Transaction, String> {
+    let tx: Transaction = bitcoin::consensus::deserialize(raw_tx).map_err(|err| err.to_string())?;
+    if tx.input.is_empty() {
+        return Err("The transaction has no inputs".to_string());
+    }
+    Ok(tx)
+}
+
+/// Returns the UTXO spent by each input of the transaction.
+///
+/// # Errors
+/// - Returns the outpoint of the first input that does not spend one of the `utxos`.
+pub fn spent_utxos(tx: &Transaction, utxos: &[Utxo]) -> Result<Vec<Utxo>, OutPoint> {
+    tx.input
+        .iter()
+        .map(|input| {
+            utxos
+                .iter()
+                .find(|utxo| {
+                    utxo.outpoint.vout == input.previous_output.vout
+                        && utxo.outpoint.txid == input.previous_output.txid.as_byte_array()
+                })
+                .cloned()
+                .ok_or(input.previous_output)
+        })
+        .collect()
+}
+
+/// The fee rate used if there are no fee percentiles, in millisatoshi per vbyte.
+///
+/// This case can only happen on a regtest network where there are no non-coinbase transactions.
+const DEFAULT_FEE_MILLISATOSHI_PER_VBYTE: u64 = 2000;
+
+/// The highest fee rate that users may request, in millisatoshi per vbyte.
+///
+/// It is far above the fee rates seen on the network, and keeps fees far from overflowing.
+pub const MAX_FEE_MILLISATOSHI_PER_VBYTE: u64 = 10_000_000;
+
+/// Returns the fee rate at the given percentile of the network fees, in millisatoshi per vbyte.
+///
+/// Arguments:
+///   * `fee_percentiles` - the network fee percentiles, as returned by `bitcoin_get_current_fee_percentiles`.
+///   * `percentile` - the percentile to pick, from 0 to 100.
+pub fn fee_rate_at_percentile(fee_percentiles: &[MillisatoshiPerByte], percentile: u8) -> u64 {
+    if fee_percentiles.is_empty() {
+        return DEFAULT_FEE_MILLISATOSHI_PER_VBYTE;
+    }
+    let index =
+        (usize::from(percentile) * fee_percentiles.len() / 100).min(fee_percentiles.len() - 1);
+    fee_percentiles[index]
+}
+
+/// Returns the fee rate of each tier, from slowest to fastest.
+pub fn fee_tiers(
+    fee_percentiles: &[MillisatoshiPerByte],
+    tier_percentiles: BtcFeeTierPercentiles,
+) -> Vec<BtcFeeTierRate> {
+    [BtcFeeTier::Slow, BtcFeeTier::Standard, BtcFeeTier::Fast]
+        .into_iter()
+        .map(|tier| {
+            let percentile = tier_percentiles.percentile(tier);
+            BtcFeeTierRate {
+                tier,
+                percentile,
+                fee_rate_millisatoshi_per_vbyte: fee_rate_at_percentile(
+                    fee_percentiles,
+                    percentile,
+                ),
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use bitcoin::{absolute, transaction, Amount, Transaction, TxIn, TxOut, Witness};
     use ic_cdk::api::management_canister::bitcoin::Outpoint;
 
     // Import the outer scope
     use super::*;
+    use pretty_assertions::assert_eq;
 
     fn assert_utxos_eq(result_utxos: Vec<Utxo>, expected_utxos: Vec<Utxo>) {
         assert_eq!(result_utxos.len(), expected_utxos.len(),);
@@ -239,20 +596,518 @@ mod tests {
         assert_utxos_eq(selected_utxos, vec![utxo_80, utxo_50]);
     }
 
+    const P2WPKH_SCRIPT_LEN: usize = 22;
+    const P2WPKH_INPUT: InputWeightPrediction = InputWeightPrediction::P2WPKH_MAX;
+
+    #[test]
+    fn has_confirmations_counts_the_block_of_the_utxo() {
+        let utxo = Utxo {
+            outpoint: Outpoint {
+                txid: Vec::new(),
+                vout: 0u32,
+            },
+            value: 50u64,
+            height: 100u32,
+        };
+        assert!(has_confirmations(&utxo, 100, 0));
+        assert!(has_confirmations(&utxo, 100, 1));
+        assert!(!has_confirmations(&utxo, 100, 2));
+        assert!(has_confirmations(&utxo, 105, 6));
+        assert!(!has_confirmations(&utxo, 104, 6));
+    }
+
     #[test]
     fn estimate_fee_returns_overhead_if_no_input_nor_output() {
-        assert_eq!(estimate_fee(0, 1000, 0), TX_OVERHEAD_VBYTES);
+        // Without witnesses, there is no segwit marker and flag.
+        assert_eq!(estimate_fee(P2WPKH_INPUT, 0, 1000, []), Some(10));
     }
 
     #[test]
     fn estimate_fee_incrases_per_input_count() {
-        assert_eq!(estimate_fee(2, 1000, 2), 209);
-        assert_eq!(estimate_fee(4, 1000, 2), 345);
+        assert_eq!(
+            estimate_fee(P2WPKH_INPUT, 2, 1000, [P2WPKH_SCRIPT_LEN; 2]),
+            Some(209)
+        );
+        assert_eq!(
+            estimate_fee(P2WPKH_INPUT, 4, 1000, [P2WPKH_SCRIPT_LEN; 2]),
+            Some(345)
+        );
+    }
+
+    #[test]
+    fn estimate_fee_does_not_overflow() {
+        assert_eq!(
+            estimate_fee(P2WPKH_INPUT, 2, u64::MAX, [P2WPKH_SCRIPT_LEN; 2]),
+            None
+        );
     }
 
     #[test]
     fn estimate_fee_incrases_per_output_count() {
-        assert_eq!(estimate_fee(2, 1000, 2), 209);
-        assert_eq!(estimate_fee(2, 1000, 4), 271);
+        assert_eq!(
+            estimate_fee(P2WPKH_INPUT, 2, 1000, [P2WPKH_SCRIPT_LEN; 2]),
+            Some(209)
+        );
+        assert_eq!(
+            estimate_fee(P2WPKH_INPUT, 2, 1000, [P2WPKH_SCRIPT_LEN; 4]),
+            Some(271)
+        );
+    }
+
+    /// Fee percentiles where the fee at percentile `i` is `1000 * (i + 1)` millisatoshi per vbyte.
+    fn mock_fee_percentiles() -> Vec<MillisatoshiPerByte> {
+        (1..=100).map(|i| i * 1000).collect()
+    }
+
+    #[test]
+    fn fee_rate_at_percentile_uses_default_without_percentiles() {
+        assert_eq!(
+            fee_rate_at_percentile(&[], 50),
+            DEFAULT_FEE_MILLISATOSHI_PER_VBYTE
+        );
+    }
+
+    #[test]
+    fn fee_rate_at_percentile_is_clamped_to_highest_fee() {
+        assert_eq!(
+            fee_rate_at_percentile(&mock_fee_percentiles(), 100),
+            100_000
+        );
+        assert_eq!(fee_rate_at_percentile(&[3000], 99), 3000);
+    }
+
+    #[test]
+    fn fee_tiers_use_configured_percentiles() {
+        let tiers = fee_tiers(&mock_fee_percentiles(), BtcFeeTierPercentiles::default());
+
+        assert_eq!(
+            tiers,
+            vec![
+                BtcFeeTierRate {
+                    tier: BtcFeeTier::Slow,
+                    percentile: 25,
+                    fee_rate_millisatoshi_per_vbyte: 26_000,
+                },
+                BtcFeeTierRate {
+                    tier: BtcFeeTier::Standard,
+                    percentile: 50,
+                    fee_rate_millisatoshi_per_vbyte: 51_000,
+                },
+                BtcFeeTierRate {
+                    tier: BtcFeeTier::Fast,
+                    percentile: 75,
+                    fee_rate_millisatoshi_per_vbyte: 76_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn estimate_fee_for_each_tier() {
+        let tier_percentiles = BtcFeeTierPercentiles {
+            slow: 10,
+            standard: 50,
+            fast: 90,
+        };
+        let fees: Vec<u64> = fee_tiers(&mock_fee_percentiles(), tier_percentiles)
+            .into_iter()
+            .map(|tier| {
+                estimate_fee(
+                    P2WPKH_INPUT,
+                    2,
+                    tier.fee_rate_millisatoshi_per_vbyte,
+                    [P2WPKH_SCRIPT_LEN; 2],
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // 209 vbytes at 11, 51 and 91 satoshi per vbyte.
+        assert_eq!(fees, vec![2_299, 10_659, 19_019]);
+    }
+
+    const MAINNET_P2PKH: &str = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+    const MAINNET_P2SH: &str = "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy";
+    const MAINNET_P2WPKH: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+    const MAINNET_P2WSH: &str = "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3";
+    const MAINNET_P2TR: &str = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+    const TESTNET_P2WPKH: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    fn output(destination_address: &str, sent_satoshis: u64) -> BtcTxOutput {
+        BtcTxOutput {
+            destination_address: destination_address.to_string(),
+            sent_satoshis,
+        }
+    }
+
+    fn mainnet_address(address: &str) -> Address {
+        parse_address(address, BitcoinNetwork::Mainnet).expect("valid address")
+    }
+
+    #[test]
+    fn tx_vsize_depends_on_output_script_type() {
+        let vsizes: Vec<u64> = [
+            MAINNET_P2PKH,
+            MAINNET_P2SH,
+            MAINNET_P2WPKH,
+            MAINNET_P2WSH,
+            MAINNET_P2TR,
+        ]
+        .into_iter()
+        .map(|address| {
+            estimate_tx_vsize(
+                P2WPKH_INPUT,
+                1,
+                [mainnet_address(address).script_pubkey().len()],
+            )
+        })
+        .collect();
+
+        assert_eq!(vsizes, vec![113, 111, 110, 122, 122]);
+    }
+
+    #[test]
+    fn tx_vsize_depends_on_input_script_type() {
+        let vsizes: Vec<u64> = [MAINNET_P2PKH, MAINNET_P2WPKH, MAINNET_P2TR]
+            .into_iter()
+            .map(|address| {
+                let address = mainnet_address(address);
+                estimate_tx_vsize(
+                    input_weight_prediction(&address),
+                    1,
+                    [address.script_pubkey().len()],
+                )
+            })
+            .collect();
+
+        // Spending to the same address type, with a legacy transaction for P2PKH.
+        assert_eq!(vsizes, vec![192, 110, 111]);
+    }
+
+    /// A transaction spending the given number of inputs with the given witness, to the given addresses.
+    fn mock_transaction(input_count: usize, witness: &[&[u8]], addresses: &[&str]) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: (0..input_count)
+                .map(|_| TxIn {
+                    witness: Witness::from_slice(witness),
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: addresses
+                .iter()
+                .map(|address| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: mainnet_address(address).script_pubkey(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn tx_vsize_matches_p2wpkh_transaction() {
+        let tx = mock_transaction(2, &[&[0; 72], &[2; 33]], &[MAINNET_P2WSH, MAINNET_P2WPKH]);
+
+        assert_eq!(
+            estimate_tx_vsize(P2WPKH_INPUT, 2, tx.script_pubkey_lens()),
+            tx.vsize() as u64
+        );
+    }
+
+    #[test]
+    fn tx_vsize_matches_p2tr_transaction() {
+        let tx = mock_transaction(3, &[&[0; 64]], &[MAINNET_P2TR, MAINNET_P2PKH]);
+
+        assert_eq!(
+            estimate_tx_vsize(
+                InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH,
+                3,
+                tx.script_pubkey_lens()
+            ),
+            tx.vsize() as u64
+        );
+    }
+
+    #[test]
+    fn tx_vsize_covers_real_transaction() {
+        // TXID 3d3381f968e3a73841cba5e73bf47dcea9f25a9f7663c51c81f1db8229a309a0, with 3 P2WPKH inputs
+        // and P2SH and P2WPKH outputs.  Two of its signatures are a byte shorter than the largest size.
+        let tx: Transaction = bitcoin::consensus::deserialize(
+            &hex::decode(
+                "01000000000103fc9aa70afba04da865f9821734b556cca9fb5710fc1338b97fba811033f755e3\
+                 08000000000000000019b37457784dd04936f011f733b8016c247a9ef08d40007a54a5159d1fc6\
+                 2ee21600000000000000004c4f2937c6ccf8256d9711a19df1ae621722970bf46be925ff15f490\
+                 efa1633d01000000000000000002c0e1e4000000000017a9146983f776902c1d1d0355ae0962cb\
+                 7bc69e9afbde8706a1e600000000001600144257782711458506b89f255202d645e25c41144702\
+                 483045022100dcada0499865a49d0aab8cb113c5f83fd5a97abc793f97f3f53aa4b9d1192ed702\
+                 202094c7934666a30d6adb1cc9e3b6bc14d2ffebd3200f3908c40053ef2df640b501210315434b\
+                 b59b615a383ae87316e784fc11835bb97fab33fdd2578025e9968d516e0247304402201d90b319\
+                 7650569eba4bc0e0b1e2dca77dfac7b80d4366f335b67e92e0546e4402203b4be1d443ad7e3a5e\
+                 a92aafbcdc027bf9ccf5fe68c0bc8f3ebb6ab806c5464c012103e00d92b0fe60731a54fdbcc692\
+                 0934159db8ffd69d55564579b69a22ec5bb7530247304402205ab83b734df818e64d8b9e86a8a7\
+                 5f9d005c0c6e1b988d045604853ab9ccbde002205a580235841df609d6bd67534bdcd301999b18\
+                 e74e197e9e476cdef5fdcbf822012102ebb3e8a4638ede4721fb98e44e3a3cd61fecfe744461b8\
+                 5e0b6a6a10175d5aca00000000",
+            )
+            .expect("invalid hex"),
+        )
+        .expect("invalid transaction");
+
+        let estimate = estimate_tx_vsize(P2WPKH_INPUT, 3, tx.script_pubkey_lens());
+
+        assert_eq!(tx.vsize(), 277);
+        assert_eq!(estimate, 278);
+    }
+
+    #[test]
+    fn parse_address_rejects_other_network() {
+        assert!(parse_address(TESTNET_P2WPKH, BitcoinNetwork::Mainnet).is_err());
+        assert!(parse_address(TESTNET_P2WPKH, BitcoinNetwork::Testnet).is_ok());
+        assert!(parse_address("not an address", BitcoinNetwork::Testnet).is_err());
+    }
+
+    #[test]
+    fn utxo_balance_splits_confirmed_unconfirmed_and_locked() {
+        let utxo = |vout: u32, value: u64, height: u32| Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 32],
+                vout,
+            },
+            value,
+            height,
+        };
+        let utxos = vec![
+            utxo(0, 1_000, 95),
+            utxo(1, 2_000, 95),
+            utxo(2, 4_000, 96),
+            utxo(3, 8_000, 100),
+        ];
+
+        // At tip 100, the first three UTXOs have 6 or 5 confirmations.
+        assert_eq!(
+            utxo_balance(&utxos, &[utxo(1, 2_000, 95)], 100, 6),
+            BtcGetBalanceResponse {
+                confirmed_satoshis: 3_000,
+                unconfirmed_incoming_satoshis: 12_000,
+                locked_satoshis: 2_000,
+                spendable_utxo_count: 1,
+            }
+        );
+        assert_eq!(
+            utxo_balance(&utxos, &[], 100, 1),
+            BtcGetBalanceResponse {
+                confirmed_satoshis: 15_000,
+                unconfirmed_incoming_satoshis: 0,
+                locked_satoshis: 0,
+                spendable_utxo_count: 4,
+            }
+        );
+        assert_eq!(
+            utxo_balance(&[], &[], 100, 6),
+            BtcGetBalanceResponse::default()
+        );
+    }
+
+    #[test]
+    fn address_network_tells_network_from_address() {
+        assert_eq!(address_network(MAINNET_P2TR), Some(BitcoinNetwork::Mainnet));
+        assert_eq!(
+            address_network(TESTNET_P2WPKH),
+            Some(BitcoinNetwork::Testnet)
+        );
+        assert_eq!(
+            address_network("bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33"),
+            Some(BitcoinNetwork::Regtest)
+        );
+        assert_eq!(address_network("not an address"), None);
+    }
+
+    #[test]
+    fn output_scripts_validates_outputs() {
+        let outputs = vec![output(MAINNET_P2PKH, 1_000), output(MAINNET_P2WSH, 2_000)];
+
+        assert_eq!(
+            output_scripts(&outputs, 3_000, BitcoinNetwork::Mainnet).map(|scripts| scripts.len()),
+            Ok(2)
+        );
+        assert_eq!(
+            output_scripts(&outputs, 2_000, BitcoinNetwork::Mainnet),
+            Err(SelectedUtxosFeeError::InvalidOutputs {
+                msg: "The outputs add up to 3000 satoshis, not 2000 satoshis".to_string()
+            })
+        );
+        assert_eq!(
+            output_scripts(&outputs, 3_000, BitcoinNetwork::Testnet),
+            Err(SelectedUtxosFeeError::InvalidDestinationAddress {
+                address: MAINNET_P2PKH.to_string()
+            })
+        );
+        assert!(matches!(
+            output_scripts(&[], 0, BitcoinNetwork::Mainnet),
+            Err(SelectedUtxosFeeError::InvalidOutputs { .. })
+        ));
+        assert!(matches!(
+            output_scripts(&[output(MAINNET_P2PKH, 0)], 0, BitcoinNetwork::Mainnet),
+            Err(SelectedUtxosFeeError::InvalidOutputs { .. })
+        ));
+    }
+
+    #[test]
+    fn build_psbt_spends_utxos_to_outputs() {
+        let source = parse_address(TESTNET_P2WPKH, BitcoinNetwork::Testnet).unwrap();
+        let utxos: Vec<Utxo> = [(1_u8, 30_000), (2, 20_000)]
+            .into_iter()
+            .map(|(txid, value)| Utxo {
+                outpoint: Outpoint {
+                    txid: vec![txid; 32],
+                    vout: u32::from(txid),
+                },
+                value,
+                height: 100,
+            })
+            .collect();
+        let outputs = vec![
+            output(TESTNET_P2WPKH, 40_000),
+            output(
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                8_000,
+            ),
+        ];
+
+        let psbt = build_psbt(&utxos, &source, &outputs, BitcoinNetwork::Testnet).unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[1].previous_output.txid.to_byte_array(), [2; 32]);
+        assert_eq!(tx.input[1].previous_output.vout, 2);
+        assert!(tx.is_explicitly_rbf());
+        assert_eq!(
+            tx.output
+                .iter()
+                .map(|output| output.value.to_sat())
+                .collect::<Vec<_>>(),
+            vec![40_000, 8_000]
+        );
+        assert_eq!(tx.output[0].script_pubkey, source.script_pubkey());
+        assert_eq!(
+            psbt.inputs[0].witness_utxo,
+            Some(TxOut {
+                value: Amount::from_sat(30_000),
+                script_pubkey: source.script_pubkey(),
+            })
+        );
+        assert_eq!(Psbt::deserialize(&psbt.serialize()).unwrap(), psbt);
+        assert_eq!(tx_outputs(tx, BitcoinNetwork::Testnet), Ok(outputs));
+    }
+
+    #[test]
+    fn change_output_follows_requested_outputs() {
+        let output = |sent_satoshis| BtcTxOutput {
+            destination_address: "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string(),
+            sent_satoshis,
+        };
+        let mut selection = SelectedUtxosFeeResponse {
+            utxos: vec![],
+            fee_satoshis: 1_000,
+            amount_satoshis: 50_000,
+            change_satoshis: 49_000,
+            outputs: vec![output(50_000), output(49_000)],
+        };
+        assert_eq!(change_output_index(&selection), Some(1));
+
+        selection.change_satoshis = 0;
+        selection.outputs.pop();
+        assert_eq!(change_output_index(&selection), None);
+    }
+
+    #[test]
+    fn selection_is_funded_by_its_utxos() {
+        let output = |sent_satoshis| BtcTxOutput {
+            destination_address: "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string(),
+            sent_satoshis,
+        };
+        let utxo = |value| Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 32],
+                vout: 0,
+            },
+            value,
+            height: 100,
+        };
+        let mut selection = SelectedUtxosFeeResponse {
+            utxos: vec![utxo(60_000), utxo(40_000)],
+            fee_satoshis: 1_000,
+            amount_satoshis: 50_000,
+            change_satoshis: 49_000,
+            outputs: vec![output(50_000), output(49_000)],
+        };
+        assert!(is_funded(&selection));
+
+        // The inputs do not cover the reported fee.
+        selection.utxos.pop();
+        assert!(!is_funded(&selection));
+
+        // The inputs pay a higher fee than the reported one.
+        selection.utxos = vec![utxo(200_000)];
+        assert!(!is_funded(&selection));
+    }
+
+    #[test]
+    fn build_psbt_rejects_invalid_txid() {
+        let source = parse_address(TESTNET_P2WPKH, BitcoinNetwork::Testnet).unwrap();
+        let utxo = Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 31],
+                vout: 0,
+            },
+            value: 30_000,
+            height: 100,
+        };
+
+        assert!(build_psbt(
+            &[utxo],
+            &source,
+            &[output(TESTNET_P2WPKH, 10_000)],
+            BitcoinNetwork::Testnet
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn decoded_transaction_spends_utxos_of_source() {
+        let source = parse_address(TESTNET_P2WPKH, BitcoinNetwork::Testnet).unwrap();
+        let utxo = |txid: u8| Utxo {
+            outpoint: Outpoint {
+                txid: vec![txid; 32],
+                vout: 1,
+            },
+            value: 30_000,
+            height: 100,
+        };
+        let psbt = build_psbt(
+            &[utxo(1), utxo(2)],
+            &source,
+            &[output(TESTNET_P2WPKH, 50_000)],
+            BitcoinNetwork::Testnet,
+        )
+        .unwrap();
+        let raw_tx = bitcoin::consensus::serialize(&psbt.unsigned_tx);
+
+        let tx = decode_transaction(&raw_tx).unwrap();
+
+        assert_eq!(
+            spent_utxos(&tx, &[utxo(3), utxo(2), utxo(1)]),
+            Ok(vec![utxo(1), utxo(2)])
+        );
+        assert_eq!(
+            spent_utxos(&tx, &[utxo(1)]),
+            Err(tx.input[1].previous_output)
+        );
+    }
+
+    #[test]
+    fn decode_transaction_rejects_invalid_bytes() {
+        assert!(decode_transaction(&[1, 2, 3]).is_err());
     }
 }
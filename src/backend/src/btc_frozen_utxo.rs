@@ -0,0 +1,178 @@
+//! UTXOs that a user has excluded from coin selection.
+use crate::bitcoin_utils::is_locked;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::{BtcFreezeUtxosError, BtcFrozenUtxo};
+
+/// The maximum number of UTXOs a user can freeze.
+pub const MAX_FROZEN_UTXOS: u32 = 500;
+/// The maximum length of the label of a frozen UTXO, in characters.
+pub const MAX_LABEL_LENGTH: u32 = 100;
+
+/// Adds the given UTXOs to the frozen UTXOs.  The label of a UTXO that is already frozen is replaced.
+///
+/// # Errors
+/// - `LabelTooLong` if a label is too long.
+/// - `TooManyFrozenUtxos` if the user would have too many frozen UTXOs.
+///
+/// The frozen UTXOs are not changed on error.
+pub fn freeze(
+    frozen_utxos: &mut Vec<BtcFrozenUtxo>,
+    utxos: Vec<BtcFrozenUtxo>,
+) -> Result<(), BtcFreezeUtxosError> {
+    if utxos.iter().any(|utxo| {
+        utxo.label
+            .as_ref()
+            .is_some_and(|label| label.chars().count() > MAX_LABEL_LENGTH as usize)
+    }) {
+        return Err(BtcFreezeUtxosError::LabelTooLong {
+            max_label_length: MAX_LABEL_LENGTH,
+        });
+    }
+    let mut updated = frozen_utxos.clone();
+    for utxo in utxos {
+        match updated
+            .iter_mut()
+            .find(|frozen| frozen.outpoint == utxo.outpoint)
+        {
+            Some(frozen) => frozen.label = utxo.label,
+            None => updated.push(utxo),
+        }
+    }
+    if updated.len() > MAX_FROZEN_UTXOS as usize {
+        return Err(BtcFreezeUtxosError::TooManyFrozenUtxos {
+            max_frozen_utxos: MAX_FROZEN_UTXOS,
+        });
+    }
+    *frozen_utxos = updated;
+    Ok(())
+}
+
+/// Removes the given outpoints from the frozen UTXOs.  Outpoints that are not frozen are ignored.
+pub fn unfreeze(frozen_utxos: &mut Vec<BtcFrozenUtxo>, outpoints: &[Outpoint]) {
+    frozen_utxos.retain(|frozen| !outpoints.contains(&frozen.outpoint));
+}
+
+/// Whether the outpoint is frozen.
+pub fn is_frozen(frozen_utxos: &[BtcFrozenUtxo], outpoint: &Outpoint) -> bool {
+    frozen_utxos
+        .iter()
+        .any(|frozen| frozen.outpoint == *outpoint)
+}
+
+/// The UTXOs that can be selected: those that are neither spent by a pending transaction nor frozen.
+pub fn spendable_utxos(
+    utxos: Vec<Utxo>,
+    locked_utxos: &[Utxo],
+    frozen_utxos: &[BtcFrozenUtxo],
+) -> Vec<Utxo> {
+    utxos
+        .into_iter()
+        .filter(|utxo| !is_locked(locked_utxos, utxo) && !is_frozen(frozen_utxos, &utxo.outpoint))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn outpoint(vout: u32) -> Outpoint {
+        Outpoint {
+            txid: vec![1; 32],
+            vout,
+        }
+    }
+
+    fn frozen(vout: u32, label: Option<&str>) -> BtcFrozenUtxo {
+        BtcFrozenUtxo {
+            outpoint: outpoint(vout),
+            label: label.map(ToString::to_string),
+        }
+    }
+
+    #[test]
+    fn freezing_again_replaces_the_label() {
+        let mut frozen_utxos = vec![frozen(0, Some("inscription")), frozen(1, None)];
+
+        freeze(
+            &mut frozen_utxos,
+            vec![frozen(1, Some("rare sat")), frozen(2, None)],
+        )
+        .expect("failed to freeze");
+
+        assert_eq!(
+            frozen_utxos,
+            vec![
+                frozen(0, Some("inscription")),
+                frozen(1, Some("rare sat")),
+                frozen(2, None)
+            ]
+        );
+        assert!(is_frozen(&frozen_utxos, &outpoint(2)));
+        assert!(!is_frozen(&frozen_utxos, &outpoint(3)));
+    }
+
+    #[test]
+    fn freezing_too_many_utxos_changes_nothing() {
+        let mut frozen_utxos: Vec<BtcFrozenUtxo> = (0..MAX_FROZEN_UTXOS)
+            .map(|vout| frozen(vout, None))
+            .collect();
+
+        assert_eq!(
+            freeze(&mut frozen_utxos, vec![frozen(0, Some("again"))]),
+            Ok(())
+        );
+        assert_eq!(
+            freeze(
+                &mut frozen_utxos,
+                vec![frozen(1, Some("label")), frozen(MAX_FROZEN_UTXOS, None)]
+            ),
+            Err(BtcFreezeUtxosError::TooManyFrozenUtxos {
+                max_frozen_utxos: MAX_FROZEN_UTXOS
+            })
+        );
+        assert_eq!(frozen_utxos.len(), MAX_FROZEN_UTXOS as usize);
+        assert_eq!(frozen_utxos[1], frozen(1, None));
+    }
+
+    #[test]
+    fn long_labels_are_rejected() {
+        let mut frozen_utxos = vec![];
+        let label = "a".repeat(MAX_LABEL_LENGTH as usize + 1);
+
+        assert_eq!(
+            freeze(&mut frozen_utxos, vec![frozen(0, Some(&label))]),
+            Err(BtcFreezeUtxosError::LabelTooLong {
+                max_label_length: MAX_LABEL_LENGTH
+            })
+        );
+        assert_eq!(frozen_utxos, vec![]);
+    }
+
+    #[test]
+    fn spendable_utxos_are_neither_locked_nor_frozen() {
+        let utxo = |vout: u32| Utxo {
+            outpoint: outpoint(vout),
+            value: 10_000,
+            height: 100,
+        };
+
+        assert_eq!(
+            spendable_utxos(
+                vec![utxo(0), utxo(1), utxo(2)],
+                &[utxo(1)],
+                &[frozen(2, None)]
+            ),
+            vec![utxo(0)]
+        );
+    }
+
+    #[test]
+    fn unfreezing_ignores_unknown_outpoints() {
+        let mut frozen_utxos = vec![frozen(0, None), frozen(1, None)];
+
+        unfreeze(&mut frozen_utxos, &[outpoint(1), outpoint(5)]);
+
+        assert_eq!(frozen_utxos, vec![frozen(0, None)]);
+    }
+}
@@ -0,0 +1,1313 @@
+use crate::types::{
+    BtcPendingTransactionMap, Candid, StoredBtcAddress, StoredPrincipal, StoredTxid,
+};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::BtcPendingTransactionStatus;
+use std::ops::{Bound, RangeInclusive};
+
+const MAX_PENDING_TRANSACTIONS: usize = 1000;
+const MAX_ADDRESS_COUNT_PER_USER: usize = 20;
+const DAY_IN_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// How long transactions are kept after they were created, whatever their status.
+const RETENTION_IN_NS: u64 = 7 * DAY_IN_NS;
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct StoredPendingTransaction {
+    pub txid: Vec<u8>,
+    pub utxos: Vec<Utxo>,
+    pub created_at_timestamp_ns: u64,
+    /// The consensus encoding of the transaction, if it was built or submitted by this canister.
+    ///
+    /// It is needed to bump the fee of the transaction.
+    pub transaction: Option<Vec<u8>>,
+    /// The index of the output that returns the change to the user, if the transaction was built by this canister
+    /// with change.
+    ///
+    /// The change pays for a higher fee when the transaction is replaced by fee.
+    pub change_output_index: Option<u32>,
+    /// The txid of the transaction that replaces this one by fee (BIP-125), if any.
+    pub replaced_by: Option<Vec<u8>>,
+    /// The height of the block that contains the transaction, once it is confirmed.
+    pub confirmed_at_height: Option<u32>,
+}
+
+impl StoredPendingTransaction {
+    /// The status of the transaction at the given time.
+    ///
+    /// A transaction that is neither confirmed nor replaced expires after a day:
+    /// we consider that it failed, and its utxos can be used again.
+    pub fn status(&self, now_ns: u64) -> BtcPendingTransactionStatus {
+        match (self.confirmed_at_height, &self.replaced_by) {
+            (Some(height), _) => BtcPendingTransactionStatus::Confirmed { height },
+            (None, Some(_)) => BtcPendingTransactionStatus::Replaced,
+            (None, None) if self.created_at_timestamp_ns + DAY_IN_NS < now_ns => {
+                BtcPendingTransactionStatus::Expired
+            }
+            (None, None) => BtcPendingTransactionStatus::Pending,
+        }
+    }
+
+    /// The number of blocks confirming the transaction, given the height of the chain tip.
+    pub fn confirmations(&self, tip_height: u32) -> u32 {
+        self.confirmed_at_height
+            .map_or(0, |height| tip_height.saturating_sub(height) + 1)
+    }
+}
+
+// With this structure, if multiple users share the same address
+// they wouldn't share the pending transactions.
+// This is not possible with the current implementation of the addresses in CFS.
+// But something to have in mind for the future.
+pub struct BtcPendingTransactionModel<'a> {
+    /// Map of (`user_principal`, `address`, `txid`) to the pending transactions of the address.
+    pending_transaction_map: &'a mut BtcPendingTransactionMap,
+    /// Maximum number of transactions still pending per `(principal, address)` tuple.
+    ///
+    /// Confirmed, replaced and expired transactions do not count, as they are kept until the end of their retention.
+    max_pending_transactions: usize,
+    /// Maximum number of addresses with transactions still pending per user.
+    max_addresses_per_user: usize,
+}
+
+/// `BtcPendingTransactionModel` should be used to access and manage the pending Bitcoin transactions in the stable memory.
+///
+/// The UTXOs spent by pending transactions are locked, so that they are not selected again until the transactions are pruned.
+impl<'a> BtcPendingTransactionModel<'a> {
+    pub fn new(
+        pending_transaction_map: &'a mut BtcPendingTransactionMap,
+        max_pending_txs: Option<usize>,
+        max_addresses_per_user: Option<usize>,
+    ) -> BtcPendingTransactionModel<'a> {
+        BtcPendingTransactionModel {
+            pending_transaction_map,
+            max_pending_transactions: max_pending_txs.unwrap_or(MAX_PENDING_TRANSACTIONS),
+            max_addresses_per_user: max_addresses_per_user.unwrap_or(MAX_ADDRESS_COUNT_PER_USER),
+        }
+    }
+
+    /// Returns the pending transactions of a specific principal per address, oldest first.
+    pub fn get_pending_transactions(
+        &self,
+        principal: &Principal,
+        address: &str,
+    ) -> Vec<StoredPendingTransaction> {
+        let mut transactions: Vec<StoredPendingTransaction> = self
+            .pending_transaction_map
+            .range(address_range(*principal, address))
+            .map(|(_, transaction)| transaction.0)
+            .collect();
+        transactions.sort_by_key(|transaction| transaction.created_at_timestamp_ns);
+        transactions
+    }
+
+    /// Returns the UTXOs spent by the transactions of a specific principal per address that are still pending.
+    ///
+    /// These UTXOs must not be spent again until the transactions are confirmed or expire.
+    pub fn get_locked_utxos(&self, principal: &Principal, address: &str, now_ns: u64) -> Vec<Utxo> {
+        self.get_pending_transactions(principal, address)
+            .into_iter()
+            .filter(|pending_transaction| {
+                pending_transaction.status(now_ns) == BtcPendingTransactionStatus::Pending
+            })
+            .flat_map(|pending_transaction| pending_transaction.utxos)
+            .collect()
+    }
+
+    /// Whether a specific principal has transactions per address that are still pending.
+    pub fn has_pending_transactions(
+        &self,
+        principal: &Principal,
+        address: &str,
+        now_ns: u64,
+    ) -> bool {
+        self.get_pending_transactions(principal, address)
+            .iter()
+            .any(|pending_transaction| {
+                pending_transaction.status(now_ns) == BtcPendingTransactionStatus::Pending
+            })
+    }
+
+    /// Adds a pending transaction for a specific principal and address.
+    /// It has a limit of transactions still pending set on init.
+    pub fn add_pending_transaction(
+        &mut self,
+        principal: Principal,
+        address: &str,
+        new_transaction: StoredPendingTransaction,
+    ) -> Result<(), String> {
+        if address.len() > StoredBtcAddress::MAX_LENGTH as usize {
+            return Err("Invalid address".to_string());
+        }
+        if new_transaction.txid.len() > StoredTxid::MAX_LENGTH as usize {
+            return Err("Invalid txid".to_string());
+        }
+        let now_ns = new_transaction.created_at_timestamp_ns;
+        let count = self.pending_count(principal, address, now_ns);
+        if count == 0
+            && self.pending_address_count(principal, now_ns) >= self.max_addresses_per_user
+        {
+            return Err("Maximum address per user reached".to_string());
+        }
+        if count >= self.max_pending_transactions {
+            return Err("Maximum pending transactions reached".to_string());
+        }
+        self.pending_transaction_map.insert(
+            key(principal, address, &new_transaction.txid),
+            Candid(new_transaction),
+        );
+        Ok(())
+    }
+
+    /// Adds a transaction that replaces a pending transaction of a specific principal and address,
+    /// and marks the original as replaced.
+    ///
+    /// Both transactions lock the same utxos until they are pruned.
+    pub fn replace_pending_transaction(
+        &mut self,
+        principal: Principal,
+        address: &str,
+        txid: &[u8],
+        replacement: StoredPendingTransaction,
+    ) -> Result<(), String> {
+        if replacement.txid.len() > StoredTxid::MAX_LENGTH as usize {
+            return Err("Invalid txid".to_string());
+        }
+        if self.pending_count(principal, address, replacement.created_at_timestamp_ns)
+            >= self.max_pending_transactions
+        {
+            return Err("Maximum pending transactions reached".to_string());
+        }
+        let original_key = key(principal, address, txid);
+        let Candid(mut original) = self
+            .pending_transaction_map
+            .get(&original_key)
+            .ok_or("Pending transaction not found")?;
+        original.replaced_by = Some(replacement.txid.clone());
+        self.pending_transaction_map
+            .insert(original_key, Candid(original));
+        self.pending_transaction_map.insert(
+            key(principal, address, &replacement.txid),
+            Candid(replacement),
+        );
+        Ok(())
+    }
+
+    /// Updates the status of the transactions of a specific principal and address from the current utxos of the address,
+    /// then prunes the transactions that are past retention.
+    pub fn refresh_pending_transactions(
+        &mut self,
+        principal: Principal,
+        address: &str,
+        current_utxos: &[Utxo],
+        tip_height: u32,
+        now_ns: u64,
+    ) {
+        self.confirm_pending_transactions(principal, address, current_utxos, tip_height);
+        self.prune_pending_transactions(principal, address, now_ns);
+    }
+
+    /// Marks the transactions of a specific principal and address that are found to be in a block as confirmed.
+    ///
+    /// The bitcoin api only exposes the utxos of an address, so a transaction is considered to be confirmed when:
+    /// - One of its outputs to the address is among the current utxos.
+    ///   It is confirmed at the height of that output.
+    /// - None of the transaction's utxos are present in the current utxos list, and the transaction was not replaced.
+    ///   It is confirmed at the height of the chain tip at most, which is used as its height.
+    ///   Normally, all utxos of a pending transaction should be present or not.
+    ///   Partial presence could happen if the utxos of a pending transaction were not really used in the transaction.
+    ///   We don't confirm in partial presence because, in the end, partial presence will be temporary for one day.
+    ///
+    /// Utxos are compared by outpoint, as the height of an output is only known once it is in a block.
+    /// The outputs of an unconfirmed parent, spent by a CPFP child, are never among the current utxos,
+    /// so a child is only confirmed by absence once its parent is confirmed.
+    ///
+    /// The current utxos are those of `address`, so the transactions of the user's other addresses are kept as they are.
+    /// They must be all the utxos of the address, whatever their confirmations:
+    /// utxos hidden by a `min_confirmations` filter would look spent and confirm the transactions spending them.
+    pub fn confirm_pending_transactions(
+        &mut self,
+        principal: Principal,
+        address: &str,
+        current_utxos: &[Utxo],
+        tip_height: u32,
+    ) {
+        let mut transactions = self.get_pending_transactions(&principal, address);
+        let mut changed: Vec<usize> = Vec::new();
+        for (index, pending_transaction) in transactions.iter_mut().enumerate() {
+            if pending_transaction.confirmed_at_height.is_none() {
+                pending_transaction.confirmed_at_height = current_utxos
+                    .iter()
+                    .filter(|utxo| utxo.outpoint.txid == pending_transaction.txid)
+                    .map(|utxo| utxo.height)
+                    .min();
+                if pending_transaction.confirmed_at_height.is_some() {
+                    changed.push(index);
+                }
+            }
+        }
+        // The utxos of a confirmed transaction are not spent by the other transactions that spend them.
+        let confirmed_outpoints: Vec<Outpoint> = transactions
+            .iter()
+            .filter(|pending_transaction| pending_transaction.confirmed_at_height.is_some())
+            .flat_map(|pending_transaction| {
+                pending_transaction
+                    .utxos
+                    .iter()
+                    .map(|utxo| utxo.outpoint.clone())
+            })
+            .collect();
+        let mut confirmed_txids: Vec<Vec<u8>> = transactions
+            .iter()
+            .filter(|pending_transaction| pending_transaction.confirmed_at_height.is_some())
+            .map(|pending_transaction| pending_transaction.txid.clone())
+            .collect();
+        // A parent confirmed by absence may in turn let its child be confirmed, whatever their order.
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for (index, pending_transaction) in transactions.iter_mut().enumerate() {
+                if pending_transaction.confirmed_at_height.is_none()
+                    && pending_transaction.replaced_by.is_none()
+                    && pending_transaction.utxos.iter().all(|utxo| {
+                        !current_utxos
+                            .iter()
+                            .any(|current| current.outpoint == utxo.outpoint)
+                            && !confirmed_outpoints.contains(&utxo.outpoint)
+                            && (utxo.height > 0 || confirmed_txids.contains(&utxo.outpoint.txid))
+                    })
+                {
+                    pending_transaction.confirmed_at_height = Some(tip_height);
+                    confirmed_txids.push(pending_transaction.txid.clone());
+                    changed.push(index);
+                    progress = true;
+                }
+            }
+        }
+        // Only the transactions that changed are written back.
+        for index in changed {
+            let transaction = transactions[index].clone();
+            self.pending_transaction_map.insert(
+                key(principal, address, &transaction.txid),
+                Candid(transaction),
+            );
+        }
+    }
+
+    /// Prunes the transactions of a specific principal and address that were created longer ago than the retention period.
+    ///
+    /// Confirmed, replaced and expired transactions are kept until then, so that users can see what happened to them.
+    pub fn prune_pending_transactions(&mut self, principal: Principal, address: &str, now_ns: u64) {
+        let expired: Vec<_> = self
+            .pending_transaction_map
+            .range(address_range(principal, address))
+            .filter(|(_, transaction)| {
+                transaction.created_at_timestamp_ns + RETENTION_IN_NS < now_ns
+            })
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            self.pending_transaction_map.remove(&key);
+        }
+    }
+
+    /// Returns up to `count` (principal, address) pairs with transactions, following `after` in key order.
+    pub fn addresses_after(
+        &self,
+        after: Option<&(Principal, String)>,
+        count: usize,
+    ) -> Vec<(Principal, String)> {
+        let start = after.map_or(Bound::Unbounded, |(principal, address)| {
+            Bound::Included(key(*principal, address, &[]))
+        });
+        let mut addresses: Vec<(Principal, String)> = Vec::new();
+        for (principal, address, _) in self
+            .pending_transaction_map
+            .keys_range((start, Bound::Unbounded))
+        {
+            let entry = (principal.0, address.0);
+            if Some(&entry) == after || addresses.last() == Some(&entry) {
+                continue;
+            }
+            if addresses.len() == count {
+                break;
+            }
+            addresses.push(entry);
+        }
+        addresses
+    }
+
+    /// The number of transactions of a specific principal and address that are still pending.
+    fn pending_count(&self, principal: Principal, address: &str, now_ns: u64) -> usize {
+        self.pending_transaction_map
+            .range(address_range(principal, address))
+            .filter(|(_, transaction)| {
+                transaction.status(now_ns) == BtcPendingTransactionStatus::Pending
+            })
+            .count()
+    }
+
+    /// The number of addresses of a specific principal that have transactions still pending.
+    fn pending_address_count(&self, principal: Principal, now_ns: u64) -> usize {
+        let principal = StoredPrincipal(principal);
+        let mut addresses: Vec<StoredBtcAddress> = Vec::new();
+        for ((_, address, _), _) in self
+            .pending_transaction_map
+            .range(
+                (
+                    principal,
+                    StoredBtcAddress(String::new()),
+                    StoredTxid(Vec::new()),
+                )..,
+            )
+            .take_while(|((key_principal, _, _), _)| *key_principal == principal)
+            .filter(|(_, transaction)| {
+                transaction.status(now_ns) == BtcPendingTransactionStatus::Pending
+            })
+        {
+            if addresses.last() != Some(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses.len()
+    }
+}
+
+fn key(
+    principal: Principal,
+    address: &str,
+    txid: &[u8],
+) -> (StoredPrincipal, StoredBtcAddress, StoredTxid) {
+    (
+        StoredPrincipal(principal),
+        StoredBtcAddress(address.to_string()),
+        StoredTxid(txid.to_vec()),
+    )
+}
+
+/// The range of keys of the transactions of a specific principal and address.
+fn address_range(
+    principal: Principal,
+    address: &str,
+) -> RangeInclusive<(StoredPrincipal, StoredBtcAddress, StoredTxid)> {
+    key(principal, address, &[])..=key(principal, address, &[0xff; StoredTxid::MAX_LENGTH as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+
+    const UTXO_1: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 0,
+        },
+        value: 1000,
+        height: 100,
+    };
+    const UTXO_2: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 1,
+        },
+        value: 2000,
+        height: 120,
+    };
+    const UTXO_3: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 2,
+        },
+        value: 3000,
+        height: 150,
+    };
+    const UTXO_4: Utxo = Utxo {
+        outpoint: Outpoint {
+            txid: vec![],
+            vout: 2,
+        },
+        value: 8000,
+        height: 160,
+    };
+
+    const PRINCIPAL_TEXT_1: &str =
+        "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+    const PRINCIPAL_TEXT_2: &str =
+        "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+    const ADDRESS_1: &str = "test-address-1";
+    const ADDRESS_2: &str = "test-address-2";
+    const ADDRESS_3: &str = "test-address-3";
+    const ADDRESS_4: &str = "test-address-4";
+
+    fn prepare_btree() -> BtcPendingTransactionMap {
+        const BTC_PENDING_TRANSACTION_MEMORY_ID: MemoryId = MemoryId::new(9);
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        let map =
+            BtcPendingTransactionMap::new(memory.borrow().get(BTC_PENDING_TRANSACTION_MEMORY_ID));
+        map
+    }
+
+    #[test]
+    fn test_get_pending_transactions_empty() {
+        let mut map = prepare_btree();
+        let btc_user_pending_transactions = BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert!(pending_txs.is_empty());
+    }
+
+    #[test]
+    fn test_add_pending_transaction_per_address() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        // Add the pending transaction
+        let result =
+            btc_user_pending_transactions.add_pending_transaction(principal, ADDRESS_1, tx.clone());
+        assert!(result.is_ok());
+
+        // Check that the transaction was added
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(pending_txs.len(), 1);
+        assert_eq!(pending_txs[0], tx);
+
+        // Check that the transaction was added to the proper address
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_2);
+        assert!(pending_txs.is_empty());
+    }
+
+    #[test]
+    fn test_add_pending_transaction_does_not_add_other_principal() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal1 = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let principal2 = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        let result = btc_user_pending_transactions.add_pending_transaction(
+            principal1,
+            ADDRESS_1,
+            tx.clone(),
+        );
+        assert!(result.is_ok());
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal2, ADDRESS_1);
+        assert!(pending_txs.is_empty());
+    }
+
+    // Test for add_pending_transaction when max_pending_transactions is reached
+    #[test]
+    fn test_add_pending_transaction_max_limit() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, Some(3), None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let tx1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: 2_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx3 = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![UTXO_3],
+            created_at_timestamp_ns: 3_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx4 = StoredPendingTransaction {
+            txid: vec![10, 11, 12],
+            utxos: vec![UTXO_4],
+            created_at_timestamp_ns: 4_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        // Add 3 transactions (max_pending_transactions = 3)
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, tx1)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, tx2)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, tx3)
+            .unwrap();
+
+        // Try adding a 4th transaction and expect an error
+        let result =
+            btc_user_pending_transactions.add_pending_transaction(principal, ADDRESS_1, tx4);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Maximum pending transactions reached");
+    }
+
+    // Test for add_pending_transaction when max_addresses_per_user is reached
+    #[test]
+    fn test_add_pending_transaction_max_address_limit() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, Some(3));
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let tx1 = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx2 = StoredPendingTransaction {
+            txid: vec![4, 5, 6],
+            utxos: vec![UTXO_2],
+            created_at_timestamp_ns: 2_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx3 = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            utxos: vec![UTXO_3],
+            created_at_timestamp_ns: 3_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let tx4 = StoredPendingTransaction {
+            txid: vec![10, 11, 12],
+            utxos: vec![UTXO_4],
+            created_at_timestamp_ns: 4_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        // Add 3 transactions (max_addresses_per_user = 3)
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, tx1)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_2, tx2)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_3, tx3)
+            .unwrap();
+
+        // Try adding a 4th address and expect an error
+        let result =
+            btc_user_pending_transactions.add_pending_transaction(principal, ADDRESS_4, tx4);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err(), "Maximum address per user reached");
+    }
+
+    #[test]
+    fn test_limits_only_count_pending_transactions() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, Some(1), Some(1));
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal,
+                ADDRESS_1,
+                pending_transaction(vec![1], vec![UTXO_1], now_ns),
+            )
+            .unwrap();
+        assert_eq!(
+            btc_user_pending_transactions.add_pending_transaction(
+                principal,
+                ADDRESS_2,
+                pending_transaction(vec![2], vec![UTXO_2], now_ns)
+            ),
+            Err("Maximum address per user reached".to_string())
+        );
+        assert_eq!(
+            btc_user_pending_transactions.add_pending_transaction(
+                principal,
+                ADDRESS_1,
+                pending_transaction(vec![2], vec![UTXO_2], now_ns)
+            ),
+            Err("Maximum pending transactions reached".to_string())
+        );
+
+        // Once confirmed, the transaction is kept but no longer counts.
+        btc_user_pending_transactions.confirm_pending_transactions(principal, ADDRESS_1, &[], 200);
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal,
+                ADDRESS_1,
+                pending_transaction(vec![2], vec![UTXO_2], now_ns),
+            )
+            .unwrap();
+
+        // Once expired too.
+        let tomorrow_ns = now_ns + DAY_IN_NS + 1;
+        btc_user_pending_transactions
+            .add_pending_transaction(
+                principal,
+                ADDRESS_2,
+                pending_transaction(vec![3], vec![UTXO_3], tomorrow_ns),
+            )
+            .unwrap();
+        assert_eq!(
+            btc_user_pending_transactions
+                .get_pending_transactions(&principal, ADDRESS_1)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_add_pending_transaction_address_limit_is_per_user() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, Some(1));
+        let principal1 = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let principal2 = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        btc_user_pending_transactions
+            .add_pending_transaction(principal1, ADDRESS_1, tx.clone())
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal2, ADDRESS_2, tx.clone())
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal2, ADDRESS_2, tx.clone())
+            .unwrap();
+
+        assert_eq!(
+            btc_user_pending_transactions.add_pending_transaction(principal1, ADDRESS_2, tx),
+            Err("Maximum address per user reached".to_string())
+        );
+    }
+
+    #[test]
+    fn test_add_pending_transaction_rejects_overlong_address() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let tx = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+
+        assert_eq!(
+            btc_user_pending_transactions.add_pending_transaction(principal, &"a".repeat(91), tx),
+            Err("Invalid address".to_string())
+        );
+    }
+
+    #[test]
+    fn test_add_pending_transaction_rejects_overlong_txid() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        assert_eq!(
+            btc_user_pending_transactions.add_pending_transaction(
+                principal,
+                ADDRESS_1,
+                pending_transaction(vec![1; 33], vec![UTXO_1], 1_000_000)
+            ),
+            Err("Invalid txid".to_string())
+        );
+        assert!(btc_user_pending_transactions
+            .get_pending_transactions(&principal, ADDRESS_1)
+            .is_empty());
+    }
+
+    fn pending_transaction(
+        txid: Vec<u8>,
+        utxos: Vec<Utxo>,
+        created_at_timestamp_ns: u64,
+    ) -> StoredPendingTransaction {
+        StoredPendingTransaction {
+            txid,
+            utxos,
+            created_at_timestamp_ns,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        }
+    }
+
+    #[test]
+    fn test_old_pending_transactions_expire() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let yesterday_ns = 1_000_000;
+        let now_ns = yesterday_ns + DAY_IN_NS;
+
+        let old_transaction = pending_transaction(vec![1, 2, 3], vec![UTXO_1], yesterday_ns);
+        let valid_transaction = pending_transaction(vec![4, 5, 6], vec![UTXO_2], now_ns);
+        for transaction in [&old_transaction, &valid_transaction] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1, UTXO_2],
+            100,
+            now_ns + 1,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(
+            pending_txs
+                .iter()
+                .map(|tx| tx.status(now_ns + 1))
+                .collect::<Vec<_>>(),
+            vec![
+                BtcPendingTransactionStatus::Expired,
+                BtcPendingTransactionStatus::Pending
+            ]
+        );
+        assert!(btc_user_pending_transactions.has_pending_transactions(
+            &principal,
+            ADDRESS_1,
+            now_ns + 1
+        ));
+        assert_eq!(
+            btc_user_pending_transactions.get_locked_utxos(&principal, ADDRESS_1, now_ns + 1),
+            vec![UTXO_2]
+        );
+    }
+
+    #[test]
+    fn test_prune_transactions_past_retention() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let created_ns = 1_000_000;
+        let old_transaction = pending_transaction(vec![1, 2, 3], vec![UTXO_1], created_ns);
+        let valid_transaction =
+            pending_transaction(vec![4, 5, 6], vec![UTXO_2], created_ns + DAY_IN_NS);
+        for transaction in [&old_transaction, &valid_transaction] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            created_ns + RETENTION_IN_NS,
+        );
+        assert_eq!(
+            btc_user_pending_transactions
+                .get_pending_transactions(&principal, ADDRESS_1)
+                .len(),
+            2
+        );
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            created_ns + RETENTION_IN_NS + 1,
+        );
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1),
+            vec![valid_transaction]
+        );
+
+        btc_user_pending_transactions.prune_pending_transactions(
+            principal,
+            ADDRESS_1,
+            created_ns + DAY_IN_NS + RETENTION_IN_NS + 1,
+        );
+        assert_eq!(
+            btc_user_pending_transactions.addresses_after(None, 10),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_confirm_when_utxos_are_spent() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+
+        let transaction_1 = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        let transaction_2 = pending_transaction(vec![4, 5, 6], vec![UTXO_2], now_ns);
+        for transaction in [&transaction_1, &transaction_2] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+
+        let available_utxos = &[UTXO_1];
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            available_utxos,
+            200,
+            now_ns,
+        );
+
+        let pending_txs =
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1);
+        assert_eq!(
+            pending_txs,
+            vec![
+                transaction_1,
+                StoredPendingTransaction {
+                    confirmed_at_height: Some(200),
+                    ..transaction_2
+                }
+            ]
+        );
+        assert_eq!(
+            pending_txs[1].status(now_ns),
+            BtcPendingTransactionStatus::Confirmed { height: 200 }
+        );
+        assert_eq!(pending_txs[1].confirmations(205), 6);
+        assert_eq!(
+            btc_user_pending_transactions.get_locked_utxos(&principal, ADDRESS_1, now_ns),
+            vec![UTXO_1]
+        );
+    }
+
+    #[test]
+    fn test_confirm_at_height_of_outputs() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        let transaction = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, transaction)
+            .unwrap();
+        let change = Utxo {
+            outpoint: Outpoint {
+                txid: vec![1, 2, 3],
+                vout: 1,
+            },
+            value: 500,
+            height: 180,
+        };
+
+        btc_user_pending_transactions.confirm_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[change],
+            200,
+        );
+
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1)[0]
+                .status(now_ns),
+            BtcPendingTransactionStatus::Confirmed { height: 180 }
+        );
+    }
+
+    #[test]
+    fn test_does_not_confirm_with_partial_available_utxos() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        let now_ns = 1_000_000_000_000;
+
+        let transaction_1 = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        let transaction_2 = pending_transaction(vec![4, 5, 6], vec![UTXO_2, UTXO_3], now_ns);
+        for transaction in [&transaction_1, &transaction_2] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+
+        let available_utxos = &[UTXO_1, UTXO_3];
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            available_utxos,
+            200,
+            now_ns,
+        );
+
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1),
+            vec![transaction_1, transaction_2]
+        );
+    }
+
+    #[test]
+    fn test_cpfp_child_is_not_confirmed_before_its_parent() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        let parent = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        // The output of the parent to the address, which is not in a block yet.
+        let parent_output = Utxo {
+            outpoint: Outpoint {
+                txid: parent.txid.clone(),
+                vout: 1,
+            },
+            value: 500,
+            height: 0,
+        };
+        let child = pending_transaction(vec![4, 5, 6], vec![parent_output.clone()], now_ns + 1);
+        for transaction in [&parent, &child] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+        let statuses = |model: &BtcPendingTransactionModel| {
+            model
+                .get_pending_transactions(&principal, ADDRESS_1)
+                .iter()
+                .map(|tx| tx.status(now_ns))
+                .collect::<Vec<_>>()
+        };
+
+        // Neither is in a block: the output of the parent is not among the utxos of the address.
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_1],
+            200,
+            now_ns,
+        );
+        assert_eq!(
+            statuses(&btc_user_pending_transactions),
+            vec![
+                BtcPendingTransactionStatus::Pending,
+                BtcPendingTransactionStatus::Pending
+            ]
+        );
+
+        // The parent is in a block, and its output is not spent yet.
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[Utxo {
+                height: 210,
+                ..parent_output
+            }],
+            210,
+            now_ns,
+        );
+        assert_eq!(
+            statuses(&btc_user_pending_transactions),
+            vec![
+                BtcPendingTransactionStatus::Confirmed { height: 210 },
+                BtcPendingTransactionStatus::Pending
+            ]
+        );
+
+        // The child is in a block too.
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[],
+            220,
+            now_ns,
+        );
+        assert_eq!(
+            statuses(&btc_user_pending_transactions),
+            vec![
+                BtcPendingTransactionStatus::Confirmed { height: 210 },
+                BtcPendingTransactionStatus::Confirmed { height: 220 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cpfp_child_is_confirmed_with_its_parent() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        // The child is created first, so that it is checked before its parent.
+        let parent = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns + 1);
+        let child = pending_transaction(
+            vec![4, 5, 6],
+            vec![Utxo {
+                outpoint: Outpoint {
+                    txid: parent.txid.clone(),
+                    vout: 0,
+                },
+                value: 500,
+                height: 0,
+            }],
+            now_ns,
+        );
+        for transaction in [&parent, &child] {
+            btc_user_pending_transactions
+                .add_pending_transaction(principal, ADDRESS_1, transaction.clone())
+                .unwrap();
+        }
+
+        btc_user_pending_transactions.confirm_pending_transactions(principal, ADDRESS_1, &[], 200);
+
+        assert!(
+            !btc_user_pending_transactions.has_pending_transactions(&principal, ADDRESS_1, now_ns)
+        );
+    }
+
+    #[test]
+    fn test_replaced_transaction_is_not_confirmed_by_its_replacement() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        let original = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        let replacement = pending_transaction(vec![4, 5, 6], vec![UTXO_1], now_ns);
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, original.clone())
+            .unwrap();
+        btc_user_pending_transactions
+            .replace_pending_transaction(principal, ADDRESS_1, &original.txid, replacement)
+            .unwrap();
+
+        btc_user_pending_transactions.confirm_pending_transactions(principal, ADDRESS_1, &[], 200);
+
+        assert_eq!(
+            btc_user_pending_transactions
+                .get_pending_transactions(&principal, ADDRESS_1)
+                .iter()
+                .map(|tx| tx.status(now_ns))
+                .collect::<Vec<_>>(),
+            vec![
+                BtcPendingTransactionStatus::Replaced,
+                BtcPendingTransactionStatus::Confirmed { height: 200 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_locked_utxos_of_all_pending_transactions() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        for (txid, utxos) in [(vec![1], vec![UTXO_1]), (vec![2], vec![UTXO_2, UTXO_3])] {
+            btc_user_pending_transactions
+                .add_pending_transaction(
+                    principal,
+                    ADDRESS_1,
+                    pending_transaction(txid, utxos, 1_000_000),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            btc_user_pending_transactions.get_locked_utxos(&principal, ADDRESS_1, 1_000_000),
+            vec![UTXO_1, UTXO_2, UTXO_3]
+        );
+        assert!(btc_user_pending_transactions
+            .get_locked_utxos(&principal, ADDRESS_2, 1_000_000)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_confirm_keeps_pending_transactions_of_other_addresses() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let now_ns = 1_000_000_000_000;
+
+        let transaction_1 = pending_transaction(vec![1, 2, 3], vec![UTXO_1], now_ns);
+        let transaction_2 = pending_transaction(vec![4, 5, 6], vec![UTXO_2], now_ns);
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, transaction_1)
+            .unwrap();
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_2, transaction_2.clone())
+            .unwrap();
+
+        // The utxos of address 1 contain neither transaction's utxos.
+        btc_user_pending_transactions.refresh_pending_transactions(
+            principal,
+            ADDRESS_1,
+            &[UTXO_3],
+            200,
+            now_ns,
+        );
+
+        assert!(
+            !btc_user_pending_transactions.has_pending_transactions(&principal, ADDRESS_1, now_ns)
+        );
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_2),
+            vec![transaction_2]
+        );
+    }
+
+    #[test]
+    fn test_addresses_after() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal1 = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let principal2 = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        for (principal, address) in [
+            (principal1, ADDRESS_1),
+            (principal1, ADDRESS_2),
+            (principal2, ADDRESS_1),
+        ] {
+            for txid in [vec![1], vec![2]] {
+                btc_user_pending_transactions
+                    .add_pending_transaction(
+                        principal,
+                        address,
+                        pending_transaction(txid, vec![UTXO_1], 1_000_000),
+                    )
+                    .unwrap();
+            }
+        }
+
+        let first = btc_user_pending_transactions.addresses_after(None, 2);
+        assert_eq!(first.len(), 2);
+        let rest = btc_user_pending_transactions.addresses_after(first.last(), 2);
+        assert_eq!(rest.len(), 1);
+        assert!(btc_user_pending_transactions
+            .addresses_after(rest.last(), 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_replace_pending_transaction() {
+        let mut map = prepare_btree();
+        let mut btc_user_pending_transactions =
+            BtcPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let original = StoredPendingTransaction {
+            txid: vec![1, 2, 3],
+            utxos: vec![UTXO_1],
+            created_at_timestamp_ns: 1_000_000,
+            transaction: Some(vec![4, 5, 6]),
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let replacement = StoredPendingTransaction {
+            txid: vec![7, 8, 9],
+            created_at_timestamp_ns: 2_000_000,
+            transaction: Some(vec![10, 11, 12]),
+            change_output_index: None,
+            ..original.clone()
+        };
+        btc_user_pending_transactions
+            .add_pending_transaction(principal, ADDRESS_1, original.clone())
+            .unwrap();
+
+        assert_eq!(
+            btc_user_pending_transactions.replace_pending_transaction(
+                principal,
+                ADDRESS_2,
+                &original.txid,
+                replacement.clone()
+            ),
+            Err("Pending transaction not found".to_string())
+        );
+        btc_user_pending_transactions
+            .replace_pending_transaction(principal, ADDRESS_1, &original.txid, replacement.clone())
+            .unwrap();
+
+        assert_eq!(
+            btc_user_pending_transactions.get_pending_transactions(&principal, ADDRESS_1),
+            vec![
+                StoredPendingTransaction {
+                    replaced_by: Some(replacement.txid.clone()),
+                    ..original
+                },
+                replacement
+            ]
+        );
+    }
+}
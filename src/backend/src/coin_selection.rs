@@ -0,0 +1,935 @@
+//! Coin selection for a personal wallet, [inspired by Bitcoin Core](https://github.com/bitcoin/bitcoin/blob/master/src/wallet/coinselection.cpp).
+//!
+//! Unlike the greedy algorithm of the ckBTC minter, the selection takes the fee rate into account:
+//! every UTXO is valued at its effective value, which is its value minus the fee for spending it.
+
+use crate::bitcoin_utils::{estimate_fee, is_locked, utxos_selection};
+use crate::btc_frozen_utxo::is_frozen;
+use bitcoin::transaction::{predict_weight, InputWeightPrediction};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::{BtcCoinSelectionStrategy, BtcFrozenUtxo, SelectedUtxosFeeError};
+use std::iter;
+
+/// The maximum number of steps of the branch-and-bound search.
+const BNB_TOTAL_TRIES: usize = 100_000;
+
+/// What the selected UTXOs have to pay for.
+#[derive(Copy, Clone, Debug)]
+pub struct SelectionTarget<'a> {
+    /// The sum of the outputs, excluding change.
+    pub amount_satoshis: u64,
+    pub fee_millisatoshi_per_vbyte: u64,
+    /// The prediction of the size of each input, which spends from the wallet's own address.
+    pub input: InputWeightPrediction,
+    /// The length of the script of each output, excluding change.
+    pub output_script_lens: &'a [usize],
+    /// The length of the script of the change output.
+    pub change_script_len: usize,
+    /// The smallest change output that is not dust, in satoshi.  Smaller change goes to the fee.
+    pub change_dust_limit: u64,
+}
+
+impl SelectionTarget<'_> {
+    /// The length of the script of each output, with or without the change output.
+    fn script_lens(&self, with_change: bool) -> impl Iterator<Item = usize> + '_ {
+        self.output_script_lens
+            .iter()
+            .copied()
+            .chain(with_change.then_some(self.change_script_len))
+    }
+
+    /// The estimated fee of a transaction spending the given number of UTXOs, or `None` if it overflows.
+    fn fee(&self, input_count: usize, with_change: bool) -> Option<u64> {
+        estimate_fee(
+            self.input,
+            input_count,
+            self.fee_millisatoshi_per_vbyte,
+            self.script_lens(with_change),
+        )
+    }
+}
+
+/// The UTXOs selected to fund a transaction.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CoinSelection {
+    pub utxos: Vec<Utxo>,
+    /// The fee of the transaction.  Without a change output, any excess goes to the fee.
+    pub fee_satoshis: u64,
+    /// The value of the change output, or zero if there is no change output.
+    pub change_satoshis: u64,
+}
+
+/// Selects UTXOs with the given strategy and removes the selected UTXOs from the available set.
+///
+/// Returns `None` if there are no UTXOs matching the criteria.
+///
+/// PROPERTY: `sum(effective_value(u) for u in available_set) ≥ amount + fee(outputs) ⇒ selection.is_some()`,
+///   except for `Greedy`, where `sum(u.value for u in available_set) ≥ amount + fee(available_set) ⇒ selection.is_some()`.
+/// POSTCONDITION: `selection.is_some() ⇒ sum(u.value for u in selection) = amount + fee + change`.
+/// POSTCONDITION: `selection.is_some() ⇒ change = 0 ∨ change ≥ change_dust_limit`.
+/// POSTCONDITION: `selection.is_none() ⇒ available_utxos did not change.`
+///
+/// # Arguments
+/// * `seed` - The seed for the random order of `SingleRandomDraw`.
+pub fn select_utxos(
+    strategy: BtcCoinSelectionStrategy,
+    available_utxos: &mut Vec<Utxo>,
+    target: SelectionTarget,
+    seed: u64,
+) -> Option<CoinSelection> {
+    match strategy {
+        BtcCoinSelectionStrategy::BranchAndBound => {
+            select_branch_and_bound(available_utxos, target)
+                .or_else(|| select_single_random_draw(available_utxos, target, seed))
+        }
+        BtcCoinSelectionStrategy::SingleRandomDraw => {
+            select_single_random_draw(available_utxos, target, seed)
+        }
+        BtcCoinSelectionStrategy::Greedy => select_greedy(available_utxos, target),
+    }
+}
+
+/// Selects exactly the UTXOs chosen by the user if there are any, otherwise selects among the available UTXOs
+/// with the given strategy.
+///
+/// Returns `None` if there are no UTXOs matching the criteria.
+pub fn select(
+    chosen_utxos: Option<Vec<Utxo>>,
+    mut available_utxos: Vec<Utxo>,
+    strategy: BtcCoinSelectionStrategy,
+    target: SelectionTarget,
+    seed: u64,
+) -> Option<CoinSelection> {
+    if let Some(utxos) = chosen_utxos {
+        select_manual(utxos, target)
+    } else {
+        select_utxos(strategy, &mut available_utxos, target, seed)
+    }
+}
+
+/// A selection with a change output for everything above the amount and the fee.
+///
+/// If the change would be dust, it goes to the fee instead and the change output is dropped.
+/// The UTXOs must cover the amount and the fee of a transaction without change.
+fn selection_with_change(utxos: Vec<Utxo>, target: SelectionTarget) -> CoinSelection {
+    let total: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let with_change = target.fee(utxos.len(), true).and_then(|fee_satoshis| {
+        let change_satoshis = total
+            .checked_sub(target.amount_satoshis)?
+            .checked_sub(fee_satoshis)?;
+        Some((fee_satoshis, change_satoshis))
+    });
+    match with_change {
+        Some((fee_satoshis, change_satoshis)) if change_satoshis >= target.change_dust_limit => {
+            CoinSelection {
+                utxos,
+                fee_satoshis,
+                change_satoshis,
+            }
+        }
+        // Without a change output, everything above the amount goes to the fee.
+        _ => CoinSelection {
+            utxos,
+            fee_satoshis: total - target.amount_satoshis,
+            change_satoshis: 0,
+        },
+    }
+}
+
+/// Whether the UTXOs cover the amount and the fee of a transaction without change.
+fn covers_target(utxos: &[Utxo], target: SelectionTarget) -> bool {
+    let total: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    required_satoshis(utxos.len(), target).is_some_and(|required| total >= required)
+}
+
+/// The amount and the fee of a transaction without change that spends the given number of UTXOs,
+/// or `None` if they overflow.
+fn required_satoshis(input_count: usize, target: SelectionTarget) -> Option<u64> {
+    target
+        .fee(input_count, false)?
+        .checked_add(target.amount_satoshis)
+}
+
+/// A selection of exactly the given UTXOs, as chosen by the user.
+///
+/// Returns `None` if the UTXOs do not cover the amount and the fee of a transaction without change.
+pub fn select_manual(utxos: Vec<Utxo>, target: SelectionTarget) -> Option<CoinSelection> {
+    covers_target(&utxos, target).then(|| selection_with_change(utxos, target))
+}
+
+/// Selects the smallest UTXOs that cover the amount and the fee with the greedy algorithm of the ckBTC minter.
+///
+/// The fee depends on the number of selected UTXOs, so the goal is raised to the amount and the fee of the
+/// previous attempt until the selection covers its own fee.  Each attempt selects more UTXOs than the previous one.
+fn select_greedy(
+    available_utxos: &mut Vec<Utxo>,
+    target: SelectionTarget,
+) -> Option<CoinSelection> {
+    let mut goal = target.amount_satoshis;
+    loop {
+        let mut remaining_utxos = available_utxos.clone();
+        let utxos = utxos_selection(
+            goal,
+            &mut remaining_utxos,
+            target.output_script_lens.len() + 1,
+        );
+        if utxos.is_empty() {
+            return None;
+        }
+        if covers_target(&utxos, target) {
+            *available_utxos = remaining_utxos;
+            return Some(selection_with_change(utxos, target));
+        }
+        goal = required_satoshis(utxos.len(), target)?;
+    }
+}
+
+/// The UTXOs with the given outpoints, as chosen by the user.  Duplicate outpoints are ignored.
+///
+/// Returns `None` if the user did not choose any outpoints.
+///
+/// # Errors
+/// - `UtxoNotFound` if an outpoint is not one of the UTXOs.
+/// - `UtxoLocked` if an outpoint is spent by a pending transaction.
+/// - `UtxoFrozen` if an outpoint is frozen by the user.
+pub fn chosen_utxos(
+    outpoints: Option<&[Outpoint]>,
+    utxos: &[Utxo],
+    locked_utxos: &[Utxo],
+    frozen_utxos: &[BtcFrozenUtxo],
+) -> Result<Option<Vec<Utxo>>, SelectedUtxosFeeError> {
+    let Some(outpoints) = outpoints.filter(|outpoints| !outpoints.is_empty()) else {
+        return Ok(None);
+    };
+    let mut chosen: Vec<Utxo> = Vec::with_capacity(outpoints.len());
+    for outpoint in outpoints {
+        if chosen.iter().any(|utxo| utxo.outpoint == *outpoint) {
+            continue;
+        }
+        let utxo = utxos
+            .iter()
+            .find(|utxo| utxo.outpoint == *outpoint)
+            .ok_or_else(|| SelectedUtxosFeeError::UtxoNotFound {
+                outpoint: outpoint.clone(),
+            })?;
+        if is_locked(locked_utxos, utxo) {
+            return Err(SelectedUtxosFeeError::UtxoLocked {
+                outpoint: outpoint.clone(),
+            });
+        }
+        if is_frozen(frozen_utxos, outpoint) {
+            return Err(SelectedUtxosFeeError::UtxoFrozen {
+                outpoint: outpoint.clone(),
+            });
+        }
+        chosen.push(utxo.clone());
+    }
+    Ok(Some(chosen))
+}
+
+/// Selects all UTXOs that are worth spending, for a transaction without change, and removes them from the
+/// available set.
+///
+/// Returns the selection and the amount that is left for the outputs after the fee, or `None` if the UTXOs cannot
+/// pay for the fee and leave at least `dust_limit_satoshis`, in which case no UTXO is removed.
+///
+/// # Arguments
+/// * `input` - The prediction of the size of each input.
+/// * `output_script_lens` - The length of the script of each output.
+/// * `dust_limit_satoshis` - The smallest amount that the outputs may receive.
+pub fn select_all(
+    available_utxos: &mut Vec<Utxo>,
+    fee_millisatoshi_per_vbyte: u64,
+    input: InputWeightPrediction,
+    output_script_lens: &[usize],
+    dust_limit_satoshis: u64,
+) -> Option<(CoinSelection, u64)> {
+    let target = SelectionTarget {
+        amount_satoshis: 0,
+        fee_millisatoshi_per_vbyte,
+        input,
+        output_script_lens,
+        change_script_len: 0,
+        change_dust_limit: 0,
+    };
+    let indices: Vec<usize> = candidates(available_utxos, SelectionFees::input_fee(target))
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect();
+    let fee_satoshis = target.fee(indices.len(), false)?;
+    let total = indices.iter().try_fold(0_u64, |sum, index| {
+        sum.checked_add(available_utxos[*index].value)
+    })?;
+    let amount_satoshis = total
+        .checked_sub(fee_satoshis)
+        .filter(|amount| *amount >= dust_limit_satoshis)?;
+    Some((
+        CoinSelection {
+            utxos: take_utxos(available_utxos, indices),
+            fee_satoshis,
+            change_satoshis: 0,
+        },
+        amount_satoshis,
+    ))
+}
+
+/// The fees that the effective values are compared against.
+///
+/// All fees are rounded up, so that the sum of the parts covers the estimated fee of the transaction.
+struct SelectionFees {
+    /// The fee for spending one UTXO.
+    input: u64,
+    /// The fee for the outputs, excluding change, and the transaction overhead.
+    base: u64,
+    /// The fee for the change output.
+    change: u64,
+}
+
+impl SelectionFees {
+    /// The fees of a transaction that spends at most `max_input_count` UTXOs.
+    fn new(target: SelectionTarget, max_input_count: usize) -> Self {
+        // The size of the input count depends on the number of inputs, and the segwit marker and flag
+        // on whether there are any inputs.  Both are covered by assuming the largest number of inputs.
+        let input_count = max_input_count.max(1);
+        let weight = |with_change: bool| {
+            predict_weight(
+                iter::repeat(target.input).take(input_count),
+                target.script_lens(with_change),
+            )
+            .to_wu()
+        };
+        let inputs_weight = input_count as u64 * input_weight(target.input);
+        SelectionFees {
+            input: Self::input_fee(target),
+            // The size of the transaction is rounded up to whole vbytes.
+            base: weight_fee(weight(false) - inputs_weight + 3, target),
+            change: weight_fee(weight(true) - weight(false), target),
+        }
+    }
+
+    /// The fee for spending one UTXO.
+    fn input_fee(target: SelectionTarget) -> u64 {
+        weight_fee(input_weight(target.input), target)
+    }
+}
+
+/// The weight of an input, including its outpoint and sequence.
+fn input_weight(input: InputWeightPrediction) -> u64 {
+    input.weight().to_wu() + 4 * (32 + 4 + 4)
+}
+
+/// The fee for the given weight, rounded up.
+fn weight_fee(weight: u64, target: SelectionTarget) -> u64 {
+    weight
+        .saturating_mul(target.fee_millisatoshi_per_vbyte)
+        .div_ceil(4 * 1000)
+}
+
+/// The UTXOs that are worth spending, as (index in the available set, effective value), largest first.
+fn candidates(available_utxos: &[Utxo], input_fee: u64) -> Vec<(usize, u64)> {
+    let mut candidates: Vec<(usize, u64)> = available_utxos
+        .iter()
+        .enumerate()
+        .filter(|(_, utxo)| utxo.value > input_fee)
+        .map(|(index, utxo)| (index, utxo.value - input_fee))
+        .collect();
+    candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+    candidates
+}
+
+/// Removes the UTXOs at the given indices from the available set.
+fn take_utxos(available_utxos: &mut Vec<Utxo>, mut indices: Vec<usize>) -> Vec<Utxo> {
+    indices.sort_unstable();
+    let mut utxos: Vec<Utxo> = indices
+        .into_iter()
+        .rev()
+        .map(|index| available_utxos.remove(index))
+        .collect();
+    utxos.reverse();
+    utxos
+}
+
+/// A selection without change, where everything above the amount goes to the fee.
+fn changeless_selection(
+    available_utxos: &mut Vec<Utxo>,
+    indices: Vec<usize>,
+    target: SelectionTarget,
+) -> CoinSelection {
+    let utxos = take_utxos(available_utxos, indices);
+    let total: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    CoinSelection {
+        utxos,
+        fee_satoshis: total - target.amount_satoshis,
+        change_satoshis: 0,
+    }
+}
+
+/// Searches for the UTXOs that cover the target without a change output, wasting as little as possible.
+///
+/// A selection is accepted if its excess over the target is less than the cost of creating and later
+/// spending a change output.  As the same fee rate is assumed for spending the change, the waste of a
+/// selection is its excess.
+fn select_branch_and_bound(
+    available_utxos: &mut Vec<Utxo>,
+    target: SelectionTarget,
+) -> Option<CoinSelection> {
+    let candidates = candidates(available_utxos, SelectionFees::input_fee(target));
+    let fees = SelectionFees::new(target, candidates.len());
+    let selection_target = target.amount_satoshis.checked_add(fees.base)?;
+    let cost_of_change = fees.change + fees.input;
+    let selected = branch_and_bound(&candidates, selection_target, cost_of_change)?;
+    let indices = selected
+        .into_iter()
+        .map(|position| candidates[position].0)
+        .collect();
+    Some(changeless_selection(available_utxos, indices, target))
+}
+
+/// Depth-first search over the inclusion and exclusion of each candidate, largest first.
+///
+/// Returns the positions in `candidates` of the selection with the least excess in
+/// `[target, target + cost_of_change]`, if any.
+fn branch_and_bound(
+    candidates: &[(usize, u64)],
+    target: u64,
+    cost_of_change: u64,
+) -> Option<Vec<usize>> {
+    // The value of the candidates that have not been decided on yet.
+    let mut lookahead: u64 = candidates.iter().map(|(_, value)| value).sum();
+    if lookahead < target {
+        return None;
+    }
+    let upper_bound = target.saturating_add(cost_of_change);
+    let mut selected: Vec<usize> = Vec::new();
+    let mut selected_value: u64 = 0;
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    // The next candidate to decide on.
+    let mut position = 0;
+
+    for _ in 0..BNB_TOTAL_TRIES {
+        let backtrack = if selected_value + lookahead < target || selected_value > upper_bound {
+            true
+        } else if selected_value >= target {
+            let waste = selected_value - target;
+            if best
+                .as_ref()
+                .map_or(true, |(best_waste, _)| waste < *best_waste)
+            {
+                best = Some((waste, selected.clone()));
+            }
+            true
+        } else {
+            false
+        };
+
+        if backtrack {
+            if matches!(best, Some((0, _))) {
+                break;
+            }
+            let Some(&last) = selected.last() else {
+                break;
+            };
+            // Restore the candidates excluded after the last selected one, then exclude it instead.
+            while position > last + 1 {
+                position -= 1;
+                lookahead += candidates[position].1;
+            }
+            selected.pop();
+            selected_value -= candidates[last].1;
+        } else {
+            let value = candidates[position].1;
+            lookahead -= value;
+            // Excluding a candidate and then including one of the same value would repeat a branch.
+            let repeats_excluded_branch = position > 0
+                && selected.last() != Some(&(position - 1))
+                && candidates[position - 1].1 == value;
+            if !repeats_excluded_branch {
+                selected.push(position);
+                selected_value += value;
+            }
+            position += 1;
+        }
+    }
+
+    best.map(|(_, selected)| selected)
+}
+
+/// Adds UTXOs in random order until they cover the target and a change output that is not dust.
+///
+/// If all UTXOs together cover the target but not the change output, they are all spent without change.
+fn select_single_random_draw(
+    available_utxos: &mut Vec<Utxo>,
+    target: SelectionTarget,
+    seed: u64,
+) -> Option<CoinSelection> {
+    let mut candidates = candidates(available_utxos, SelectionFees::input_fee(target));
+    let fees = SelectionFees::new(target, candidates.len());
+    let selection_target = target.amount_satoshis.checked_add(fees.base)?;
+    let target_with_change = selection_target
+        .saturating_add(fees.change)
+        .saturating_add(target.change_dust_limit);
+    shuffle(&mut candidates, seed);
+
+    let mut indices = Vec::new();
+    let mut selected_value: u64 = 0;
+    for (index, value) in candidates {
+        indices.push(index);
+        selected_value += value;
+        if selected_value >= target_with_change {
+            let utxos = take_utxos(available_utxos, indices);
+            return Some(selection_with_change(utxos, target));
+        }
+    }
+
+    (selected_value >= selection_target)
+        .then(|| changeless_selection(available_utxos, indices, target))
+}
+
+/// Shuffles the items with a `SplitMix64` generator.
+///
+/// The order only needs to be unpredictable enough not to reveal which UTXOs belong together.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = usize::try_from(next() % (i as u64 + 1))
+            .unwrap_or_else(|_| unreachable!("the index is at most {i}"));
+        items.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    const FEE_RATE: u64 = 10_000;
+    const P2WPKH_DUST_LIMIT: u64 = 294;
+    const P2WPKH_SCRIPT_LEN: usize = 22;
+
+    fn utxos(values: &[u64]) -> Vec<Utxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(vout, &value)| Utxo {
+                outpoint: Outpoint {
+                    txid: vec![1; 32],
+                    vout: u32::try_from(vout).expect("too many utxos"),
+                },
+                value,
+                height: 100,
+            })
+            .collect()
+    }
+
+    /// Sending from a P2WPKH address to a P2WPKH address.
+    fn target(amount_satoshis: u64, fee_millisatoshi_per_vbyte: u64) -> SelectionTarget<'static> {
+        SelectionTarget {
+            amount_satoshis,
+            fee_millisatoshi_per_vbyte,
+            input: InputWeightPrediction::P2WPKH_MAX,
+            output_script_lens: &[P2WPKH_SCRIPT_LEN],
+            change_script_len: P2WPKH_SCRIPT_LEN,
+            change_dust_limit: P2WPKH_DUST_LIMIT,
+        }
+    }
+
+    fn values(utxos: &[Utxo]) -> Vec<u64> {
+        utxos.iter().map(|utxo| utxo.value).collect()
+    }
+
+    #[test]
+    fn branch_and_bound_finds_changeless_solution() {
+        // At 10 sat/vbyte, an input costs 680 sats and the output and overhead 423 sats, rounded up.
+        let mut available = utxos(&[100_000, 31_103, 20_680, 50_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::BranchAndBound,
+            &mut available,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        assert_eq!(values(&selection.utxos), vec![31_103, 20_680]);
+        assert_eq!(selection.fee_satoshis, 1_783);
+        assert_eq!(values(&available), vec![100_000, 50_000]);
+    }
+
+    #[test]
+    fn greedy_creates_change_where_branch_and_bound_does_not() {
+        let mut available = utxos(&[100_000, 31_103, 20_680, 50_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::Greedy,
+            &mut available,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        // The UTXO of exactly 50_000 sats would not cover the fee.
+        assert_eq!(values(&selection.utxos), vec![100_000]);
+        assert_eq!(selection.change_satoshis, 100_000 - 50_000 - 1_410);
+    }
+
+    #[test]
+    fn greedy_selects_utxos_for_the_fee() {
+        // The smallest UTXO that covers the amount does not cover the fee.
+        let mut available = utxos(&[50_500, 10_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::Greedy,
+            &mut available,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        assert_eq!(values(&selection.utxos), vec![50_500, 10_000]);
+        assert_eq!(
+            selection.fee_satoshis + selection.change_satoshis,
+            60_500 - 50_000
+        );
+        assert!(available.is_empty());
+
+        let mut available = utxos(&[50_500]);
+        assert_eq!(
+            select_utxos(
+                BtcCoinSelectionStrategy::Greedy,
+                &mut available,
+                target(50_000, FEE_RATE),
+                0,
+            ),
+            None
+        );
+        assert_eq!(values(&available), vec![50_500]);
+    }
+
+    #[test]
+    fn branch_and_bound_ignores_uneconomic_utxos() {
+        let mut available = utxos(&[600, 50_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::BranchAndBound,
+            &mut available,
+            target(1_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        assert_eq!(values(&selection.utxos), vec![50_000]);
+        assert_eq!(values(&available), vec![600]);
+    }
+
+    #[test]
+    fn single_random_draw_adds_change() {
+        let mut available = utxos(&[100_000]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::SingleRandomDraw,
+            &mut available,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        // 1 input and 2 outputs.
+        assert_eq!(selection.fee_satoshis, 1_410);
+        assert_eq!(selection.change_satoshis, 48_590);
+    }
+
+    #[test]
+    fn dust_change_goes_to_fee() {
+        // The change would be 200 sats.
+        let mut available = utxos(&[51_610]);
+
+        let selection = select_utxos(
+            BtcCoinSelectionStrategy::Greedy,
+            &mut available,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+
+        assert_eq!(selection.fee_satoshis, 1_610);
+        assert_eq!(selection.change_satoshis, 0);
+    }
+
+    #[test]
+    fn selection_fails_without_enough_funds() {
+        let mut available = utxos(&[10_000, 20_000]);
+
+        for strategy in [
+            BtcCoinSelectionStrategy::BranchAndBound,
+            BtcCoinSelectionStrategy::SingleRandomDraw,
+        ] {
+            assert_eq!(
+                select_utxos(strategy, &mut available, target(29_000, FEE_RATE), 0),
+                None
+            );
+            assert_eq!(values(&available), vec![10_000, 20_000]);
+        }
+    }
+
+    #[test]
+    fn manual_selection_spends_all_given_utxos() {
+        let selection = select_manual(utxos(&[20_000, 50_000]), target(50_000, FEE_RATE))
+            .expect("no selection");
+
+        // 2 inputs and 2 outputs.
+        assert_eq!(values(&selection.utxos), vec![20_000, 50_000]);
+        assert_eq!(selection.fee_satoshis, 2_090);
+        assert_eq!(selection.change_satoshis, 17_910);
+    }
+
+    #[test]
+    fn manual_selection_without_room_for_change_pays_excess_as_fee() {
+        // Enough for the fee without change, but not with change.
+        let selection =
+            select_manual(utxos(&[51_200]), target(50_000, FEE_RATE)).expect("no selection");
+
+        assert_eq!(selection.fee_satoshis, 1_200);
+        assert_eq!(selection.change_satoshis, 0);
+    }
+
+    #[test]
+    fn manual_selection_fails_without_enough_funds() {
+        assert_eq!(
+            select_manual(utxos(&[50_000]), target(50_000, FEE_RATE)),
+            None
+        );
+    }
+
+    #[test]
+    fn selection_fails_if_amount_and_fee_overflow() {
+        let target = target(u64::MAX - 1_000, FEE_RATE);
+        assert_eq!(select_manual(utxos(&[50_000]), target), None);
+
+        let mut available = utxos(&[u64::MAX - 1_000]);
+        assert_eq!(
+            select_utxos(BtcCoinSelectionStrategy::Greedy, &mut available, target, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn chosen_utxos_are_selected_instead_of_the_available_ones() {
+        let chosen = utxos(&[60_000]);
+        let available = utxos(&[100_000]);
+
+        let selection = select(
+            Some(chosen.clone()),
+            available.clone(),
+            BtcCoinSelectionStrategy::BranchAndBound,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+        assert_eq!(selection.utxos, chosen);
+
+        let selection = select(
+            None,
+            available,
+            BtcCoinSelectionStrategy::BranchAndBound,
+            target(50_000, FEE_RATE),
+            0,
+        )
+        .expect("no selection");
+        assert_eq!(values(&selection.utxos), vec![100_000]);
+    }
+
+    #[test]
+    fn chosen_utxos_are_validated() {
+        let available = utxos(&[10_000, 20_000, 30_000, 40_000]);
+        let locked = vec![available[1].clone()];
+        let frozen = vec![BtcFrozenUtxo {
+            outpoint: available[2].outpoint.clone(),
+            label: None,
+        }];
+        let outpoint = |vout: u32| Outpoint {
+            txid: vec![1; 32],
+            vout,
+        };
+
+        assert_eq!(
+            chosen_utxos(
+                Some(&[outpoint(3), outpoint(0), outpoint(3)]),
+                &available,
+                &locked,
+                &frozen
+            ),
+            Ok(Some(vec![available[3].clone(), available[0].clone()]))
+        );
+        assert_eq!(
+            chosen_utxos(Some(&[]), &available, &locked, &frozen),
+            Ok(None)
+        );
+        assert_eq!(chosen_utxos(None, &available, &locked, &frozen), Ok(None));
+        assert_eq!(
+            chosen_utxos(
+                Some(&[outpoint(0), outpoint(4)]),
+                &available,
+                &locked,
+                &frozen
+            ),
+            Err(SelectedUtxosFeeError::UtxoNotFound {
+                outpoint: outpoint(4)
+            })
+        );
+        assert_eq!(
+            chosen_utxos(Some(&[outpoint(1)]), &available, &locked, &frozen),
+            Err(SelectedUtxosFeeError::UtxoLocked {
+                outpoint: outpoint(1)
+            })
+        );
+        assert_eq!(
+            chosen_utxos(Some(&[outpoint(2)]), &available, &locked, &frozen),
+            Err(SelectedUtxosFeeError::UtxoFrozen {
+                outpoint: outpoint(2)
+            })
+        );
+    }
+
+    #[test]
+    fn select_all_skips_uneconomic_utxos() {
+        let mut available = utxos(&[600, 50_000, 20_000]);
+
+        let (selection, amount_satoshis) = select_all(
+            &mut available,
+            FEE_RATE,
+            InputWeightPrediction::P2WPKH_MAX,
+            &[P2WPKH_SCRIPT_LEN],
+            294,
+        )
+        .expect("no selection");
+
+        // 2 inputs and 1 output.
+        assert_eq!(values(&selection.utxos), vec![50_000, 20_000]);
+        assert_eq!(selection.fee_satoshis, 1_780);
+        assert_eq!(amount_satoshis, 68_220);
+        assert_eq!(values(&available), vec![600]);
+    }
+
+    #[test]
+    fn select_all_fails_if_the_amount_is_dust() {
+        // 1 input and 1 output cost 1_100 satoshis, which leaves 200 satoshis.
+        let mut available = utxos(&[1_300]);
+
+        let selection = select_all(
+            &mut available,
+            FEE_RATE,
+            InputWeightPrediction::P2WPKH_MAX,
+            &[P2WPKH_SCRIPT_LEN],
+            294,
+        );
+
+        assert_eq!(selection, None);
+        assert_eq!(values(&available), vec![1_300]);
+
+        let mut available = vec![];
+        let selection = select_all(
+            &mut available,
+            FEE_RATE,
+            InputWeightPrediction::P2WPKH_MAX,
+            &[P2WPKH_SCRIPT_LEN],
+            294,
+        );
+
+        assert_eq!(selection, None);
+    }
+
+    /// Whether the effective value of the UTXOs covers the amount and the fee without change.
+    fn is_coverable(available: &[Utxo], target: SelectionTarget) -> bool {
+        let candidates = candidates(available, SelectionFees::input_fee(target));
+        let effective_value: u64 = candidates.iter().map(|(_, value)| value).sum();
+        effective_value
+            >= target.amount_satoshis + SelectionFees::new(target, candidates.len()).base
+    }
+
+    fn sorted(mut utxos: Vec<Utxo>) -> Vec<Utxo> {
+        utxos.sort_by_key(|utxo| utxo.outpoint.vout);
+        utxos
+    }
+
+    proptest! {
+        #[test]
+        fn selection_covers_amount_and_fee(
+            values in prop::collection::vec(1u64..10_000_000, 0..30),
+            amount_satoshis in 1u64..50_000_000,
+            fee_millisatoshi_per_vbyte in 1_000u64..100_000,
+            seed in any::<u64>(),
+            strategy in prop_oneof![
+                Just(BtcCoinSelectionStrategy::BranchAndBound),
+                Just(BtcCoinSelectionStrategy::SingleRandomDraw),
+            ],
+        ) {
+            let original = utxos(&values);
+            let mut available = original.clone();
+            let target = target(amount_satoshis, fee_millisatoshi_per_vbyte);
+            let coverable = is_coverable(&original, target);
+
+            let selection = select_utxos(strategy, &mut available, target, seed);
+
+            prop_assert_eq!(selection.is_some(), coverable);
+            match selection {
+                Some(selection) => {
+                    let total: u64 = selection.utxos.iter().map(|utxo| utxo.value).sum();
+                    let min_fee = target.fee(selection.utxos.len(), false).unwrap();
+                    prop_assert!(selection.fee_satoshis >= min_fee);
+                    prop_assert_eq!(
+                        total,
+                        amount_satoshis + selection.fee_satoshis + selection.change_satoshis
+                    );
+                    prop_assert!(
+                        selection.change_satoshis == 0
+                            || selection.change_satoshis >= P2WPKH_DUST_LIMIT
+                    );
+                    let mut remaining = available.clone();
+                    remaining.extend(selection.utxos);
+                    prop_assert_eq!(sorted(remaining), original);
+                }
+                None => prop_assert_eq!(available, original),
+            }
+        }
+
+        #[test]
+        fn greedy_selection_covers_amount_and_fee(
+            values in prop::collection::vec(1u64..10_000_000, 0..30),
+            amount_satoshis in 1u64..50_000_000,
+        ) {
+            let original = utxos(&values);
+            let mut available = original.clone();
+            let target = target(amount_satoshis, FEE_RATE);
+
+            let selection = select_utxos(
+                BtcCoinSelectionStrategy::Greedy,
+                &mut available,
+                target,
+                0,
+            );
+
+            if values.iter().sum::<u64>() >= required_satoshis(values.len(), target).unwrap() {
+                prop_assert!(selection.is_some());
+            }
+            match selection {
+                Some(selection) => {
+                    let total: u64 = selection.utxos.iter().map(|utxo| utxo.value).sum();
+                    prop_assert!(selection.fee_satoshis >= target.fee(selection.utxos.len(), false).unwrap());
+                    prop_assert_eq!(
+                        total,
+                        amount_satoshis + selection.fee_satoshis + selection.change_satoshis
+                    );
+                }
+                None => prop_assert_eq!(sorted(available), original),
+            }
+        }
+    }
+}
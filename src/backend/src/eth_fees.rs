@@ -0,0 +1,101 @@
+//! Suggested EIP-1559 fees, based on the fees paid in recent blocks.
+use crate::eth_transaction::u256_to_nat;
+use ethers_core::types::{FeeHistory, U256};
+use shared::types::ethereum::{EthEstimateFeesResponse, EvmRpcError};
+
+/// The number of recent blocks that the suggestion is based on.
+pub const FEE_HISTORY_BLOCKS: u64 = 5;
+/// The percentile of the priority fees paid in each block.
+pub const PRIORITY_FEE_PERCENTILE: u8 = 50;
+/// How long the fees suggested for a chain are reused, about the time between two Ethereum blocks.
+pub const FEE_ESTIMATE_TTL_NS: u64 = 12 * 1_000_000_000;
+
+/// Suggests the fees of a transaction in the next block.
+///
+/// The priority fee is the median of the priority fees paid in the recent blocks.  The maximum fee allows the base
+/// fee to double, which takes at least six full blocks, as it grows by at most 12.5% per block.
+///
+/// # Errors
+/// - `InvalidResponse` if the history has no base fee.
+pub fn suggest_fees(history: &FeeHistory) -> Result<EthEstimateFeesResponse, EvmRpcError> {
+    // The last base fee is the base fee of the block after the latest block.
+    let base_fee_per_gas =
+        *history
+            .base_fee_per_gas
+            .last()
+            .ok_or_else(|| EvmRpcError::InvalidResponse {
+                msg: "The fee history has no base fee".to_string(),
+            })?;
+    let mut priority_fees: Vec<U256> = history
+        .reward
+        .iter()
+        .filter_map(|rewards| rewards.first().copied())
+        .collect();
+    priority_fees.sort_unstable();
+    let max_priority_fee_per_gas = priority_fees
+        .get(priority_fees.len() / 2)
+        .copied()
+        .unwrap_or_default();
+    let max_fee_per_gas = base_fee_per_gas
+        .saturating_mul(U256::from(2))
+        .saturating_add(max_priority_fee_per_gas);
+    Ok(EthEstimateFeesResponse {
+        base_fee_per_gas: u256_to_nat(base_fee_per_gas),
+        max_priority_fee_per_gas: u256_to_nat(max_priority_fee_per_gas),
+        max_fee_per_gas: u256_to_nat(max_fee_per_gas),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use pretty_assertions::assert_eq;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    fn history(base_fees: &[u64], priority_fees: &[u64]) -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: base_fees.iter().map(|fee| U256::from(*fee)).collect(),
+            gas_used_ratio: vec![0.5; priority_fees.len()],
+            oldest_block: U256::from(100),
+            reward: priority_fees
+                .iter()
+                .map(|fee| vec![U256::from(*fee)])
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn fees_follow_the_next_base_fee_and_the_median_priority_fee() {
+        let history = history(&[GWEI, 3 * GWEI / 2, 2 * GWEI], &[3 * GWEI, GWEI, 2 * GWEI]);
+
+        assert_eq!(
+            suggest_fees(&history),
+            Ok(EthEstimateFeesResponse {
+                base_fee_per_gas: Nat::from(2 * GWEI),
+                max_priority_fee_per_gas: Nat::from(2 * GWEI),
+                max_fee_per_gas: Nat::from(6 * GWEI),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_priority_fees_are_zero() {
+        let history = history(&[GWEI], &[]);
+
+        assert_eq!(
+            suggest_fees(&history),
+            Ok(EthEstimateFeesResponse {
+                base_fee_per_gas: Nat::from(GWEI),
+                max_priority_fee_per_gas: Nat::from(0_u64),
+                max_fee_per_gas: Nat::from(2 * GWEI),
+            })
+        );
+    }
+
+    #[test]
+    fn a_base_fee_is_required() {
+        assert!(suggest_fees(&history(&[], &[GWEI])).is_err());
+    }
+}
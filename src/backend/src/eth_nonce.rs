@@ -0,0 +1,208 @@
+//! Nonces of the Ethereum transactions of a user on one chain.
+//!
+//! Clients that send several transactions in quick succession would otherwise compute the same nonce.  Each client
+//! reserves a nonce before signing, and the reservation is held until the chain has caught up with it, it is released
+//! after a failure, or it expires.
+//!
+//! An expired reservation may still belong to a transaction that was sent, so its nonce is only handed out again once
+//! the nonce of the chain has been reconciled after the expiry.
+use shared::types::ethereum::EthReserveNonceError;
+use std::collections::BTreeMap;
+
+/// The maximum number of nonces a user can reserve on one chain at a time.
+pub const MAX_RESERVATIONS: u32 = 32;
+/// How long a reservation is held if the chain does not catch up with it.
+pub const RESERVATION_TTL_NS: u64 = 10 * 60 * 1_000_000_000;
+
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+pub struct EthNonces {
+    /// The nonce of the next transaction according to the chain, if known.
+    on_chain_nonce: Option<u64>,
+    /// When the nonce of the chain was last reconciled.
+    reconciled_at_ns: u64,
+    /// The reserved nonces, with the time at which each reservation expires.
+    reservations: BTreeMap<u64, u64>,
+}
+
+impl EthNonces {
+    /// Reserves the lowest nonce that is neither used on the chain nor reserved.
+    ///
+    /// A nonce released after a failure is therefore handed out again before any higher nonce, so that no gap
+    /// blocks the later transactions.
+    ///
+    /// # Errors
+    /// - `UnknownOnChainNonce` if the nonce of the chain is not known.
+    /// - `TooManyReservations` if the user has reserved too many nonces.
+    pub fn reserve(&mut self, now_ns: u64) -> Result<(u64, u64), EthReserveNonceError> {
+        self.prune();
+        let on_chain_nonce = self
+            .on_chain_nonce
+            .ok_or(EthReserveNonceError::UnknownOnChainNonce)?;
+        if self.reservations.len() >= MAX_RESERVATIONS as usize {
+            return Err(EthReserveNonceError::TooManyReservations {
+                max_reservations: MAX_RESERVATIONS,
+            });
+        }
+        // One of the first `MAX_RESERVATIONS + 1` nonces is free, as fewer nonces are reserved.
+        let nonce = (on_chain_nonce..)
+            .take(MAX_RESERVATIONS as usize + 1)
+            .find(|nonce| !self.reservations.contains_key(nonce))
+            .unwrap_or(on_chain_nonce);
+        let expires_at = now_ns.saturating_add(RESERVATION_TTL_NS);
+        self.reservations.insert(nonce, expires_at);
+        Ok((nonce, expires_at))
+    }
+
+    /// Releases a reserved nonce.  Nonces that are not reserved are ignored.
+    pub fn release(&mut self, nonce: u64) {
+        self.reservations.remove(&nonce);
+    }
+
+    /// Sets the nonce of the next transaction according to the chain, including its pending transactions, e.g. as
+    /// reported by the client or fetched over RPC.  The reservations of the nonces that are now used on the chain are
+    /// dropped.
+    pub fn reconcile(&mut self, on_chain_nonce: u64, now_ns: u64) {
+        self.on_chain_nonce = Some(on_chain_nonce);
+        self.reconciled_at_ns = now_ns;
+        self.reservations = self.reservations.split_off(&on_chain_nonce);
+        self.prune();
+    }
+
+    /// Drops the reservations that had expired when the nonce of the chain was last reconciled: their transactions
+    /// were not pending on the chain, so their nonces are free.
+    fn prune(&mut self) {
+        let reconciled_at_ns = self.reconciled_at_ns;
+        self.reservations
+            .retain(|_, expires_at| *expires_at > reconciled_at_ns);
+    }
+
+    /// Whether a reservation has expired since the nonce of the chain was last reconciled, so that the nonce of the
+    /// chain is needed to know whether its nonce is free.
+    #[must_use]
+    pub fn needs_reconciliation(&self, now_ns: u64) -> bool {
+        self.on_chain_nonce.is_none()
+            || self
+                .reservations
+                .values()
+                .any(|expires_at| *expires_at <= now_ns && *expires_at > self.reconciled_at_ns)
+    }
+
+    /// Whether all reservations have expired, so the entry can be removed.  The nonce of the chain is then required
+    /// again, which avoids relying on an old value and reissuing the expired nonces before it is known.
+    #[must_use]
+    pub fn is_stale(&self, now_ns: u64) -> bool {
+        self.reservations
+            .values()
+            .all(|expires_at| *expires_at <= now_ns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn reserve(nonces: &mut EthNonces, now_ns: u64) -> u64 {
+        nonces.reserve(now_ns).expect("failed to reserve").0
+    }
+
+    #[test]
+    fn reservations_need_the_on_chain_nonce() {
+        let mut nonces = EthNonces::default();
+
+        assert_eq!(
+            nonces.reserve(0),
+            Err(EthReserveNonceError::UnknownOnChainNonce)
+        );
+
+        nonces.reconcile(7, 0);
+        assert_eq!(nonces.reserve(5), Ok((7, 5 + RESERVATION_TTL_NS)));
+    }
+
+    #[test]
+    fn released_nonces_are_reserved_again_first() {
+        let mut nonces = EthNonces::default();
+        nonces.reconcile(3, 0);
+
+        assert_eq!(
+            (0..3).map(|_| reserve(&mut nonces, 0)).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        nonces.release(4);
+        nonces.release(100);
+
+        assert_eq!(reserve(&mut nonces, 0), 4);
+        assert_eq!(reserve(&mut nonces, 0), 6);
+    }
+
+    #[test]
+    fn reconciling_drops_the_used_nonces() {
+        let mut nonces = EthNonces::default();
+        nonces.reconcile(0, 0);
+        for _ in 0..3 {
+            reserve(&mut nonces, 0);
+        }
+
+        nonces.reconcile(2, 0);
+
+        assert_eq!(
+            nonces.reservations.keys().copied().collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(reserve(&mut nonces, 0), 3);
+    }
+
+    #[test]
+    fn expired_reservations_are_held_until_reconciled() {
+        let mut nonces = EthNonces::default();
+        nonces.reconcile(0, 0);
+        reserve(&mut nonces, 0);
+        reserve(&mut nonces, 10);
+        assert!(!nonces.needs_reconciliation(RESERVATION_TTL_NS - 1));
+
+        // The transaction of the expired nonce may have been sent, so the nonce is not handed out again.
+        assert!(nonces.needs_reconciliation(RESERVATION_TTL_NS));
+        assert!(!nonces.is_stale(RESERVATION_TTL_NS));
+        assert_eq!(reserve(&mut nonces, RESERVATION_TTL_NS), 2);
+
+        // The chain has not seen the expired nonce, so it is free.
+        nonces.reconcile(0, RESERVATION_TTL_NS + 1);
+        assert!(!nonces.needs_reconciliation(RESERVATION_TTL_NS + 1));
+        assert_eq!(
+            nonces.reservations.keys().copied().collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(reserve(&mut nonces, RESERVATION_TTL_NS + 1), 0);
+
+        assert!(nonces.is_stale(3 * RESERVATION_TTL_NS));
+    }
+
+    #[test]
+    fn expired_reservations_used_on_chain_are_dropped() {
+        let mut nonces = EthNonces::default();
+        nonces.reconcile(0, 0);
+        reserve(&mut nonces, 0);
+        reserve(&mut nonces, 0);
+
+        nonces.reconcile(1, RESERVATION_TTL_NS);
+
+        assert_eq!(nonces.reservations.len(), 0);
+        assert_eq!(reserve(&mut nonces, RESERVATION_TTL_NS), 1);
+    }
+
+    #[test]
+    fn reservations_are_limited() {
+        let mut nonces = EthNonces::default();
+        nonces.reconcile(0, 0);
+        for _ in 0..MAX_RESERVATIONS {
+            reserve(&mut nonces, 0);
+        }
+
+        assert_eq!(
+            nonces.reserve(0),
+            Err(EthReserveNonceError::TooManyReservations {
+                max_reservations: MAX_RESERVATIONS
+            })
+        );
+    }
+}
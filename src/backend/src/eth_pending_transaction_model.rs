@@ -0,0 +1,373 @@
+use crate::types::{Candid, EthPendingTransactionMap, StoredPrincipal};
+use candid::Principal;
+use ethers_core::types::Address;
+use ethers_core::utils::to_checksum;
+use shared::types::ethereum::{
+    EthAddPendingTransactionError, EthAddPendingTransactionRequest, EthPendingTransaction,
+};
+use shared::types::token::ChainId;
+use std::str::FromStr;
+
+const MAX_PENDING_TRANSACTIONS: usize = 1000;
+const MAX_CHAIN_COUNT_PER_USER: usize = 20;
+const DAY_IN_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// How long transactions are kept after they were created, whether or not they were replaced.
+const RETENTION_IN_NS: u64 = 7 * DAY_IN_NS;
+
+/// The pending transaction of a request, with the hash in lower case and the addresses in checksum form.
+///
+/// # Errors
+/// - The hash is not 32 bytes hex encoded, or the recipient or token is not an address.
+pub fn pending_transaction(
+    request: EthAddPendingTransactionRequest,
+    created_at_timestamp_ns: u64,
+) -> Result<EthPendingTransaction, EthAddPendingTransactionError> {
+    let hash = request.hash.to_lowercase();
+    let bytes = hash
+        .strip_prefix("0x")
+        .and_then(|hex| hex::decode(hex).ok());
+    if bytes.map_or(true, |bytes| bytes.len() != 32) {
+        return Err(EthAddPendingTransactionError::InvalidHash { hash: request.hash });
+    }
+    let checksummed = |address: String| {
+        Address::from_str(&address)
+            .map(|parsed| to_checksum(&parsed, None))
+            .map_err(|_| EthAddPendingTransactionError::InvalidAddress { address })
+    };
+    Ok(EthPendingTransaction {
+        hash,
+        nonce: request.nonce,
+        to: checksummed(request.to)?,
+        value: request.value,
+        token: request.token.map(checksummed).transpose()?,
+        created_at_timestamp_ns,
+        replaced_by: None,
+    })
+}
+
+/// `EthPendingTransactionModel` should be used to access and manage the pending Ethereum transactions in the stable memory.
+///
+/// It mirrors `BtcPendingTransactionModel`, with transactions grouped by chain rather than by address, and replacements
+/// detected by nonce.
+pub struct EthPendingTransactionModel<'a> {
+    /// Map of (`user_principal`, `chain_id`) to the pending transactions on the chain.
+    pending_transaction_map: &'a mut EthPendingTransactionMap,
+    /// Maximum number of transactions that will be stored per `(principal, chain_id)` tuple.
+    max_pending_transactions: usize,
+    /// Maximum number of chains per user.
+    max_chains_per_user: usize,
+}
+
+impl<'a> EthPendingTransactionModel<'a> {
+    pub fn new(
+        pending_transaction_map: &'a mut EthPendingTransactionMap,
+        max_pending_txs: Option<usize>,
+        max_chains_per_user: Option<usize>,
+    ) -> EthPendingTransactionModel<'a> {
+        EthPendingTransactionModel {
+            pending_transaction_map,
+            max_pending_transactions: max_pending_txs.unwrap_or(MAX_PENDING_TRANSACTIONS),
+            max_chains_per_user: max_chains_per_user.unwrap_or(MAX_CHAIN_COUNT_PER_USER),
+        }
+    }
+
+    /// Returns the pending transactions of a specific principal on a chain that are not past retention.
+    pub fn get_pending_transactions(
+        &self,
+        principal: &Principal,
+        chain_id: ChainId,
+        now_ns: u64,
+    ) -> Vec<EthPendingTransaction> {
+        self.pending_transaction_map
+            .get(&key(*principal, chain_id))
+            .map_or_else(Vec::new, |transactions| transactions.0)
+            .into_iter()
+            .filter(|transaction| !is_past_retention(transaction, now_ns))
+            .collect()
+    }
+
+    /// Adds a pending transaction for a specific principal and chain.
+    /// It has a limit of storable transactions set on init.
+    ///
+    /// The pending transactions with the same nonce are marked as replaced by the new transaction.
+    /// Adding a transaction that is already stored changes nothing.
+    pub fn add_pending_transaction(
+        &mut self,
+        principal: Principal,
+        chain_id: ChainId,
+        new_transaction: EthPendingTransaction,
+    ) -> Result<(), String> {
+        let key = key(principal, chain_id);
+        let mut list = if let Some(list) = self.pending_transaction_map.get(&key) {
+            list.0
+        } else {
+            if self.chain_count(principal) >= self.max_chains_per_user {
+                return Err("Maximum chains per user reached".to_string());
+            }
+            Vec::new()
+        };
+        if list
+            .iter()
+            .any(|transaction| transaction.hash == new_transaction.hash)
+        {
+            return Ok(());
+        }
+        if list.len() >= self.max_pending_transactions {
+            return Err("Maximum pending transactions reached".to_string());
+        }
+        for transaction in &mut list {
+            if transaction.nonce == new_transaction.nonce && transaction.replaced_by.is_none() {
+                transaction.replaced_by = Some(new_transaction.hash.clone());
+            }
+        }
+        list.push(new_transaction);
+        self.pending_transaction_map.insert(key, Candid(list));
+        Ok(())
+    }
+
+    /// Prunes the transactions of a specific principal and chain that were created longer ago than the retention
+    /// period, and those with a nonce below the nonce of the chain, if given.
+    pub fn prune_pending_transactions(
+        &mut self,
+        principal: Principal,
+        chain_id: ChainId,
+        on_chain_nonce: Option<u64>,
+        now_ns: u64,
+    ) {
+        let key = key(principal, chain_id);
+        let Some(Candid(mut transactions)) = self.pending_transaction_map.get(&key) else {
+            return;
+        };
+        let count = transactions.len();
+        transactions.retain(|transaction| {
+            !is_past_retention(transaction, now_ns)
+                && on_chain_nonce.map_or(true, |nonce| transaction.nonce >= nonce)
+        });
+        if transactions.is_empty() {
+            self.pending_transaction_map.remove(&key);
+        } else if transactions.len() < count {
+            self.pending_transaction_map
+                .insert(key, Candid(transactions));
+        }
+    }
+
+    /// The number of chains on which a specific principal has pending transactions.
+    fn chain_count(&self, principal: Principal) -> usize {
+        let principal = StoredPrincipal(principal);
+        self.pending_transaction_map
+            .keys_range((principal, 0)..)
+            .take_while(|(key_principal, _)| *key_principal == principal)
+            .count()
+    }
+}
+
+fn is_past_retention(transaction: &EthPendingTransaction, now_ns: u64) -> bool {
+    transaction.created_at_timestamp_ns + RETENTION_IN_NS < now_ns
+}
+
+fn key(principal: Principal, chain_id: ChainId) -> (StoredPrincipal, ChainId) {
+    (StoredPrincipal(principal), chain_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+
+    const PRINCIPAL_TEXT_1: &str =
+        "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+    const PRINCIPAL_TEXT_2: &str =
+        "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+    const TO: &str = "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9";
+    const SEPOLIA: ChainId = 11_155_111;
+
+    fn prepare_btree() -> EthPendingTransactionMap {
+        const ETH_PENDING_TRANSACTION_MEMORY_ID: MemoryId = MemoryId::new(11);
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        let map =
+            EthPendingTransactionMap::new(memory.borrow().get(ETH_PENDING_TRANSACTION_MEMORY_ID));
+        map
+    }
+
+    fn transaction(
+        hash_byte: u8,
+        nonce: u64,
+        created_at_timestamp_ns: u64,
+    ) -> EthPendingTransaction {
+        pending_transaction(
+            EthAddPendingTransactionRequest {
+                chain_id: SEPOLIA,
+                hash: format!("0x{}", hex::encode([hash_byte; 32])),
+                nonce,
+                to: TO.to_lowercase(),
+                value: Nat::from(1_000_u32),
+                token: None,
+            },
+            created_at_timestamp_ns,
+        )
+        .expect("invalid transaction")
+    }
+
+    #[test]
+    fn test_pending_transaction_is_normalised() {
+        let transaction = transaction(0xab, 0, 5);
+
+        assert_eq!(transaction.hash, format!("0x{}", "ab".repeat(32)));
+        assert_eq!(transaction.to, TO);
+        assert_eq!(transaction.created_at_timestamp_ns, 5);
+
+        let request = EthAddPendingTransactionRequest {
+            chain_id: SEPOLIA,
+            hash: "0x1234".to_string(),
+            nonce: 0,
+            to: TO.to_string(),
+            value: Nat::from(0_u32),
+            token: None,
+        };
+        assert_eq!(
+            pending_transaction(request.clone(), 0),
+            Err(EthAddPendingTransactionError::InvalidHash {
+                hash: "0x1234".to_string()
+            })
+        );
+        assert_eq!(
+            pending_transaction(
+                EthAddPendingTransactionRequest {
+                    hash: format!("0x{}", "00".repeat(32)),
+                    token: Some("0x1234".to_string()),
+                    ..request
+                },
+                0
+            ),
+            Err(EthAddPendingTransactionError::InvalidAddress {
+                address: "0x1234".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_add_pending_transaction_per_chain() {
+        let mut map = prepare_btree();
+        let mut model = EthPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let other_principal = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        let tx = transaction(1, 0, 0);
+
+        assert_eq!(
+            model.add_pending_transaction(principal, SEPOLIA, tx.clone()),
+            Ok(())
+        );
+        // Adding the same transaction again changes nothing.
+        assert_eq!(
+            model.add_pending_transaction(principal, SEPOLIA, tx.clone()),
+            Ok(())
+        );
+
+        assert_eq!(
+            model.get_pending_transactions(&principal, SEPOLIA, 0),
+            vec![tx]
+        );
+        assert!(model.get_pending_transactions(&principal, 1, 0).is_empty());
+        assert!(model
+            .get_pending_transactions(&other_principal, SEPOLIA, 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_transactions_with_the_same_nonce_are_replaced() {
+        let mut map = prepare_btree();
+        let mut model = EthPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        for tx in [
+            transaction(1, 0, 0),
+            transaction(2, 1, 0),
+            transaction(3, 0, 1),
+        ] {
+            model
+                .add_pending_transaction(principal, SEPOLIA, tx)
+                .expect("failed to add");
+        }
+
+        let replaced_by: Vec<Option<String>> = model
+            .get_pending_transactions(&principal, SEPOLIA, 1)
+            .into_iter()
+            .map(|tx| tx.replaced_by)
+            .collect();
+        assert_eq!(
+            replaced_by,
+            vec![Some(transaction(3, 0, 1).hash), None, None]
+        );
+    }
+
+    #[test]
+    fn test_add_pending_transaction_limits() {
+        let mut map = prepare_btree();
+        let mut model = EthPendingTransactionModel::new(&mut map, Some(2), Some(2));
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+
+        for nonce in 0..2 {
+            model
+                .add_pending_transaction(principal, SEPOLIA, transaction(nonce as u8, nonce, 0))
+                .expect("failed to add");
+        }
+        assert_eq!(
+            model.add_pending_transaction(principal, SEPOLIA, transaction(2, 2, 0)),
+            Err("Maximum pending transactions reached".to_string())
+        );
+
+        model
+            .add_pending_transaction(principal, 1, transaction(3, 0, 0))
+            .expect("failed to add");
+        assert_eq!(
+            model.add_pending_transaction(principal, 10, transaction(4, 0, 0)),
+            Err("Maximum chains per user reached".to_string())
+        );
+
+        // The limit of chains is per user.
+        let other_principal = Principal::from_text(PRINCIPAL_TEXT_2).unwrap();
+        assert_eq!(
+            model.add_pending_transaction(other_principal, 10, transaction(4, 0, 0)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_prune_pending_transactions() {
+        let mut map = prepare_btree();
+        let mut model = EthPendingTransactionModel::new(&mut map, None, None);
+        let principal = Principal::from_text(PRINCIPAL_TEXT_1).unwrap();
+        let old = transaction(1, 5, 0);
+        let used = transaction(2, 6, RETENTION_IN_NS);
+        let pending = transaction(3, 7, RETENTION_IN_NS);
+        for tx in [old, used, pending.clone()] {
+            model
+                .add_pending_transaction(principal, SEPOLIA, tx)
+                .expect("failed to add");
+        }
+        let now_ns = RETENTION_IN_NS + 1;
+
+        // Transactions past retention are not listed, even before they are pruned.
+        assert_eq!(
+            model
+                .get_pending_transactions(&principal, SEPOLIA, now_ns)
+                .len(),
+            2
+        );
+
+        model.prune_pending_transactions(principal, SEPOLIA, Some(7), now_ns);
+        assert_eq!(
+            model.get_pending_transactions(&principal, SEPOLIA, now_ns),
+            vec![pending]
+        );
+
+        model.prune_pending_transactions(principal, SEPOLIA, None, 3 * RETENTION_IN_NS);
+        assert!(map.is_empty());
+    }
+}
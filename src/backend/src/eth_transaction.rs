@@ -0,0 +1,380 @@
+//! Encoding and signing of EIP-1559 Ethereum transactions, and signatures of messages.
+use ethers_core::{
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Bytes, Eip1559TransactionRequest,
+        NameOrAddress, Signature, U256,
+    },
+    utils::keccak256,
+};
+use k256::ecdsa::{RecoveryId, VerifyingKey};
+use shared::types::{ethereum::EthSignTransactionError, transaction::SignRequest};
+use std::str::FromStr;
+
+/// The EIP-2718 type of EIP-1559 transactions.
+const EIP1559_TX_ID: u8 = 2;
+
+/// Converts a candid number to a 256 bit number.
+///
+/// # Errors
+/// - `ValueOutOfRange` if the number is larger than `U256::MAX`.
+fn nat_to_u256(nat: &candid::Nat, field: &str) -> Result<U256, EthSignTransactionError> {
+    let bytes = nat.0.to_bytes_be();
+    if bytes.len() > 32 {
+        return Err(EthSignTransactionError::ValueOutOfRange {
+            field: field.to_string(),
+        });
+    }
+    Ok(U256::from_big_endian(&bytes))
+}
+
+/// Converts a 256 bit number to a candid number.
+#[must_use]
+pub fn u256_to_nat(value: U256) -> candid::Nat {
+    candid::Nat::parse(value.to_string().as_bytes())
+        .unwrap_or_else(|_| unreachable!("The decimal form of a U256 is a valid candid number"))
+}
+
+/// The EIP-1559 transaction of a signing request.
+///
+/// # Errors
+/// - The recipient is not an address, the data is not hex encoded or a number is out of range.
+pub fn eip1559_transaction(
+    request: &SignRequest,
+) -> Result<Eip1559TransactionRequest, EthSignTransactionError> {
+    let to =
+        Address::from_str(&request.to).map_err(|_| EthSignTransactionError::InvalidToAddress {
+            address: request.to.clone(),
+        })?;
+    let data = request
+        .data
+        .as_ref()
+        .map(|data| {
+            Bytes::from_str(data)
+                .map_err(|_| EthSignTransactionError::InvalidData { data: data.clone() })
+        })
+        .transpose()?;
+    let chain_id = u64::try_from(nat_to_u256(&request.chain_id, "chain_id")?).map_err(|_| {
+        EthSignTransactionError::ValueOutOfRange {
+            field: "chain_id".to_string(),
+        }
+    })?;
+    Ok(Eip1559TransactionRequest {
+        from: None,
+        to: Some(NameOrAddress::Address(to)),
+        gas: Some(nat_to_u256(&request.gas, "gas")?),
+        value: Some(nat_to_u256(&request.value, "value")?),
+        data,
+        nonce: Some(nat_to_u256(&request.nonce, "nonce")?),
+        access_list: Vec::new().into(),
+        max_priority_fee_per_gas: Some(nat_to_u256(
+            &request.max_priority_fee_per_gas,
+            "max_priority_fee_per_gas",
+        )?),
+        max_fee_per_gas: Some(nat_to_u256(&request.max_fee_per_gas, "max_fee_per_gas")?),
+        chain_id: Some(chain_id.into()),
+    })
+}
+
+/// The hash that is signed: the hash of the type byte followed by the RLP encoding of the unsigned transaction.
+#[must_use]
+pub fn signing_hash(transaction: &Eip1559TransactionRequest) -> [u8; 32] {
+    let mut unsigned = vec![EIP1559_TX_ID];
+    unsigned.extend_from_slice(&transaction.rlp());
+    keccak256(unsigned)
+}
+
+/// The Ethereum signature of a hash, from a threshold ECDSA signature of the hash.
+///
+/// The recovery id, which is the `v` of the signature, is found by recovering the public key from the signature.
+/// A signature with a high `s` is normalised first, as Ethereum only accepts signatures with a low `s`.
+///
+/// # Errors
+/// - The signature is not a valid signature of the hash by the given SEC1 encoded public key.
+pub fn recoverable_signature(
+    hash: &[u8; 32],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<Signature, String> {
+    let signature = k256::ecdsa::Signature::from_slice(signature)
+        .map_err(|_| "Invalid signature".to_string())?;
+    let signature = signature.normalize_s().unwrap_or(signature);
+    let public_key =
+        VerifyingKey::from_sec1_bytes(public_key).map_err(|_| "Invalid public key".to_string())?;
+    let v = [false, true]
+        .into_iter()
+        .find(|&is_y_odd| {
+            VerifyingKey::recover_from_prehash(hash, &signature, RecoveryId::new(is_y_odd, false))
+                .is_ok_and(|recovered| recovered == public_key)
+        })
+        .map(u64::from)
+        .ok_or("The signature does not match the public key".to_string())?;
+    let (r, s) = signature.split_bytes();
+    Ok(Signature {
+        r: U256::from_big_endian(&r),
+        s: U256::from_big_endian(&s),
+        v,
+    })
+}
+
+/// Decodes a signature as returned by the chain fusion signer: `r ‖ s`, optionally followed by `v`, hex encoded with
+/// an optional `0x` prefix.
+///
+/// The recovery id `v` is dropped, as it is found again from the public key.
+///
+/// # Errors
+/// - The signature is not hex encoded, or is neither 64 nor 65 bytes long.
+pub fn decode_signature(signature: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(|_| "The signature is not hex encoded".to_string())?;
+    match bytes.len() {
+        64 => {}
+        65 => bytes.truncate(64),
+        len => return Err(format!("The signature has {len} bytes instead of 64 or 65")),
+    }
+    Ok(bytes)
+}
+
+/// Assembles the signed transaction from a threshold ECDSA signature of its signing hash.
+///
+/// # Errors
+/// - The signature is not a valid signature of the hash by the given SEC1 encoded public key.
+pub fn signed_transaction(
+    transaction: &Eip1559TransactionRequest,
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<String, String> {
+    let signature = recoverable_signature(&signing_hash(transaction), signature, public_key)?;
+    let signed = TypedTransaction::Eip1559(transaction.clone()).rlp_signed(&signature);
+    Ok(format!("0x{}", hex::encode(signed)))
+}
+
+/// The signature of a message digest, as returned by `personal_sign` and `eth_signTypedData_v4`: `r ‖ s ‖ v`, hex
+/// encoded with a `0x` prefix, where `v` is 27 or 28.
+///
+/// # Errors
+/// - The signature is not a valid signature of the digest by the given SEC1 encoded public key.
+pub fn message_signature(
+    digest: &[u8; 32],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<String, String> {
+    let mut signature = recoverable_signature(digest, signature, public_key)?;
+    signature.v += 27;
+    Ok(format!("0x{}", hex::encode(signature.to_vec())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use ethers_core::utils::{rlp::Rlp, to_checksum};
+    use k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey};
+    use pretty_assertions::assert_eq;
+
+    const TO: &str = "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9";
+
+    fn request() -> SignRequest {
+        SignRequest {
+            chain_id: Nat::from(1_u32),
+            to: TO.to_string(),
+            gas: Nat::from(21_000_u32),
+            max_fee_per_gas: Nat::from(0_u32),
+            max_priority_fee_per_gas: Nat::from(0_u32),
+            value: Nat::from(0_u32),
+            nonce: Nat::from(0_u32),
+            data: None,
+        }
+    }
+
+    /// Signs the transaction with the given private key, as the chain fusion signer does with its threshold key.
+    fn sign(transaction: &Eip1559TransactionRequest, private_key: u8) -> String {
+        let mut key = [0_u8; 32];
+        key[31] = private_key;
+        let signing_key = SigningKey::from_slice(&key).unwrap();
+        let (signature, _) = signing_key
+            .sign_prehash_recoverable(&signing_hash(transaction))
+            .unwrap();
+        signed_transaction(
+            transaction,
+            &signature.to_bytes(),
+            &signing_key.verifying_key().to_sec1_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unsigned_transaction_is_rlp_encoded() {
+        let transaction = eip1559_transaction(&request()).unwrap();
+
+        let mut unsigned = vec![EIP1559_TX_ID];
+        unsigned.extend_from_slice(&transaction.rlp());
+
+        // [chain_id, nonce, max_priority_fee_per_gas, max_fee_per_gas, gas, to, value, data, access_list]
+        assert_eq!(
+            hex::encode(unsigned),
+            format!("02df0180808082520894{}8080c0", TO[2..].to_lowercase())
+        );
+    }
+
+    #[test]
+    fn signed_transaction_recovers_to_the_signer() {
+        let mut request = request();
+        request.data = Some("0xa9059cbb".to_string());
+        request.value = Nat::from(1_000_000_000_000_000_000_u64);
+        request.max_fee_per_gas = Nat::from(30_000_000_000_u64);
+        let transaction = eip1559_transaction(&request).unwrap();
+
+        for private_key in 1..=4 {
+            let signed = sign(&transaction, private_key);
+
+            let bytes = hex::decode(&signed[2..]).unwrap();
+            assert_eq!(bytes[0], EIP1559_TX_ID);
+            let (decoded, signature) = TypedTransaction::decode_signed(&Rlp::new(&bytes)).unwrap();
+            // Decoding recovers the sender from the signature.
+            let from = signature.recover(decoded.sighash()).unwrap();
+            let mut expected = transaction.clone();
+            expected.from = Some(from);
+            assert_eq!(decoded, TypedTransaction::Eip1559(expected));
+            if private_key == 1 {
+                assert_eq!(
+                    to_checksum(&from, None),
+                    "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+                );
+            }
+            assert!(signature.v <= 1);
+        }
+    }
+
+    #[test]
+    fn signature_of_another_key_is_rejected() {
+        let transaction = eip1559_transaction(&request()).unwrap();
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let other_key = SigningKey::from_slice(&[2; 32]).unwrap();
+        let (signature, _) = signing_key
+            .sign_prehash_recoverable(&signing_hash(&transaction))
+            .unwrap();
+
+        assert!(signed_transaction(
+            &transaction,
+            &signature.to_bytes(),
+            &other_key.verifying_key().to_sec1_bytes(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn signatures_are_decoded_with_or_without_recovery_id() {
+        let signature = [7_u8; 64];
+        for encoded in [
+            hex::encode(signature),
+            format!("0x{}", hex::encode(signature)),
+            format!("0x{}1b", hex::encode(signature)),
+        ] {
+            assert_eq!(decode_signature(&encoded), Ok(signature.to_vec()));
+        }
+        assert!(decode_signature("0xzz").is_err());
+        assert!(decode_signature(&hex::encode([7_u8; 63])).is_err());
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let mut invalid_to = request();
+        invalid_to.to = "0x1234".to_string();
+        assert_eq!(
+            eip1559_transaction(&invalid_to),
+            Err(EthSignTransactionError::InvalidToAddress {
+                address: "0x1234".to_string()
+            })
+        );
+
+        let mut invalid_data = request();
+        invalid_data.data = Some("0xzz".to_string());
+        assert_eq!(
+            eip1559_transaction(&invalid_data),
+            Err(EthSignTransactionError::InvalidData {
+                data: "0xzz".to_string()
+            })
+        );
+
+        let mut large_chain_id = request();
+        large_chain_id.chain_id = Nat::from(u128::from(u64::MAX) + 1);
+        assert_eq!(
+            eip1559_transaction(&large_chain_id),
+            Err(EthSignTransactionError::ValueOutOfRange {
+                field: "chain_id".to_string()
+            })
+        );
+
+        let mut large_value = request();
+        large_value.value = Nat::from(u128::MAX) * Nat::from(u128::MAX) * Nat::from(2_u32);
+        assert_eq!(
+            eip1559_transaction(&large_value),
+            Err(EthSignTransactionError::ValueOutOfRange {
+                field: "value".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn numbers_are_converted_both_ways() {
+        for value in [U256::zero(), U256::from(1_000_000_000_u64), U256::MAX] {
+            assert_eq!(nat_to_u256(&u256_to_nat(value), "value"), Ok(value));
+        }
+    }
+
+    #[test]
+    fn typed_data_signature_matches_the_eip_example() {
+        // The example of EIP-712, signed by the private key `keccak256("cow")`.
+        let typed_data = shared::eip712::TypedData::from_json(
+            r#"{
+                "types": {
+                    "EIP712Domain": [
+                        { "name": "name", "type": "string" },
+                        { "name": "version", "type": "string" },
+                        { "name": "chainId", "type": "uint256" },
+                        { "name": "verifyingContract", "type": "address" }
+                    ],
+                    "Person": [
+                        { "name": "name", "type": "string" },
+                        { "name": "wallet", "type": "address" }
+                    ],
+                    "Mail": [
+                        { "name": "from", "type": "Person" },
+                        { "name": "to", "type": "Person" },
+                        { "name": "contents", "type": "string" }
+                    ]
+                },
+                "primaryType": "Mail",
+                "domain": {
+                    "name": "Ether Mail",
+                    "version": "1",
+                    "chainId": 1,
+                    "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+                },
+                "message": {
+                    "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+                    "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+                    "contents": "Hello, Bob!"
+                }
+            }"#,
+        )
+        .unwrap();
+        let digest = typed_data.digest().unwrap();
+        let signing_key = SigningKey::from_slice(&keccak256("cow")).unwrap();
+        let signature: k256::ecdsa::Signature = signing_key.sign_prehash(&digest).unwrap();
+
+        assert_eq!(
+            message_signature(
+                &digest,
+                &signature.to_bytes(),
+                &signing_key.verifying_key().to_sec1_bytes()
+            ),
+            Ok(format!(
+                "0x{}{}{}",
+                "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d",
+                "07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562",
+                "1c"
+            ))
+        );
+    }
+}
@@ -0,0 +1,456 @@
+//! Client of the [EVM RPC canister](https://github.com/internet-computer-protocol/evm-rpc-canister), which reads the
+//! state of EVM chains.
+//!
+//! Every request is sent to several providers, and a result is only accepted if enough providers return it.  A
+//! single provider could otherwise report any balance or fee.
+//!
+//! The providers are rarely at the same block, so the state is read at a block number that they agree on rather
+//! than at the latest block, which would give different results.
+use crate::read_config;
+use candid::{CandidType, Deserialize, Principal, Reserved};
+use ethers_core::{
+    abi::{self, ParamType, Token},
+    types::{Bytes, FeeHistory, H160, U256, U64},
+    utils::id,
+};
+use futures::future::join_all;
+use ic_cdk::api::call::call_with_payment128;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use shared::types::{ethereum::EvmRpcError, token::ChainId};
+
+/// The providers that each request is sent to.
+const PROVIDERS: [Provider; 3] = [Provider::Ankr, Provider::BlockPi, Provider::PublicNode];
+/// The number of providers that must return the same result.
+const MIN_AGREEING_PROVIDERS: usize = 2;
+/// Cycles attached to each request.  The EVM RPC canister refunds the cycles that it does not charge.
+const CYCLES_PER_REQUEST: u128 = 10_000_000_000;
+/// The maximum size of a response in bytes.  The cost of a request grows with this limit.
+const MAX_RESPONSE_BYTES: u64 = 8_192;
+/// The number of requests a caller may send to the EVM RPC canister per window.
+pub const MAX_REQUESTS_PER_WINDOW: u32 = 30;
+/// The window over which the requests of a caller are counted.
+const REQUEST_WINDOW_NS: u64 = 60 * 1_000_000_000;
+
+/// A provider of the EVM RPC canister.
+///
+/// Note: The EVM RPC canister has one provider type per chain.  These providers are available on all the supported
+/// chains and have the same variant names in all of those types, so a single type can encode them.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+enum Provider {
+    Ankr,
+    BlockPi,
+    PublicNode,
+}
+
+/// The subset of the `RpcService` of the EVM RPC canister that is used here.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+enum RpcService {
+    EthMainnet(Provider),
+    EthSepolia(Provider),
+    ArbitrumOne(Provider),
+    BaseMainnet(Provider),
+    OptimismMainnet(Provider),
+}
+
+impl RpcService {
+    /// The service of a provider on a chain, or `None` if the chain is not supported.
+    fn new(chain_id: ChainId, provider: Provider) -> Option<Self> {
+        match chain_id {
+            1 => Some(Self::EthMainnet(provider)),
+            11_155_111 => Some(Self::EthSepolia(provider)),
+            42_161 => Some(Self::ArbitrumOne(provider)),
+            8_453 => Some(Self::BaseMainnet(provider)),
+            10 => Some(Self::OptimismMainnet(provider)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the state of one EVM chain.
+pub struct EvmRpcClient {
+    canister_id: Principal,
+    services: Vec<RpcService>,
+}
+
+impl EvmRpcClient {
+    /// Creates a client for a chain.
+    ///
+    /// # Errors
+    /// - `Disabled` if the EVM RPC canister is not configured.
+    /// - `UnsupportedChain` if the EVM RPC canister has no providers for the chain.
+    pub fn new(chain_id: ChainId) -> Result<Self, EvmRpcError> {
+        let canister_id = read_config(|s| s.evm_rpc_canister_id).ok_or(EvmRpcError::Disabled)?;
+        let services = PROVIDERS
+            .iter()
+            .map(|provider| RpcService::new(chain_id, *provider))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(EvmRpcError::UnsupportedChain { chain_id })?;
+        Ok(Self {
+            canister_id,
+            services,
+        })
+    }
+
+    /// The number of the latest block that enough providers have reached.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn block_number(&self) -> Result<U64, EvmRpcError> {
+        let results = self.responses("eth_blockNumber", json!([])).await;
+        median_block_number(results, MIN_AGREEING_PROVIDERS)
+    }
+
+    /// The balance of the native token of an address at the latest block, in wei.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn get_balance(&self, address: &H160) -> Result<U256, EvmRpcError> {
+        let block = self.block_number().await?;
+        self.request("eth_getBalance", json!([address, block]))
+            .await
+    }
+
+    /// The number of transactions sent from an address, including the pending ones, i.e. its next nonce.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn get_transaction_count(&self, address: &H160) -> Result<u64, EvmRpcError> {
+        self.request("eth_getTransactionCount", json!([address, "pending"]))
+            .await
+            .map(|count: U64| count.as_u64())
+    }
+
+    /// The fees of the latest blocks, with the given percentiles of the priority fees paid in each block.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn fee_history(
+        &self,
+        block_count: u64,
+        reward_percentiles: &[u8],
+    ) -> Result<FeeHistory, EvmRpcError> {
+        let block = self.block_number().await?;
+        self.request(
+            "eth_feeHistory",
+            json!([U64::from(block_count), block, reward_percentiles]),
+        )
+        .await
+    }
+
+    /// Calls a contract without sending a transaction.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn call(&self, to: &H160, data: Vec<u8>) -> Result<Vec<u8>, EvmRpcError> {
+        self.request(
+            "eth_call",
+            json!([{ "to": to, "data": Bytes::from(data) }, "latest"]),
+        )
+        .await
+        .map(|output: Bytes| output.to_vec())
+    }
+
+    /// The ERC20 `decimals()` of a token.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn erc20_decimals(&self, token: &H160) -> Result<u8, EvmRpcError> {
+        let output = self.call(token, id("decimals()").to_vec()).await?;
+        decode_decimals(&output).map_err(|msg| EvmRpcError::InvalidResponse { msg })
+    }
+
+    /// The ERC20 `symbol()` of a token.
+    ///
+    /// # Errors
+    /// Errors are enumerated by: `EvmRpcError`.
+    pub async fn erc20_symbol(&self, token: &H160) -> Result<String, EvmRpcError> {
+        let output = self.call(token, id("symbol()").to_vec()).await?;
+        decode_symbol(&output).map_err(|msg| EvmRpcError::InvalidResponse { msg })
+    }
+
+    /// Sends a JSON-RPC request to all the providers and returns the result that enough of them agree on.
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, EvmRpcError> {
+        let results = self.responses(method, params).await;
+        let result = consensus(results, MIN_AGREEING_PROVIDERS)?;
+        serde_json::from_value(result).map_err(|err| EvmRpcError::InvalidResponse {
+            msg: format!("Unexpected result of {method}: {err}"),
+        })
+    }
+
+    /// Sends a JSON-RPC request to all the providers and returns the result or error of each.
+    async fn responses(&self, method: &str, params: Value) -> Vec<Result<Value, String>> {
+        let request =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let responses = join_all(self.services.iter().map(|service| {
+            call_with_payment128::<_, (Result<String, Reserved>,)>(
+                self.canister_id,
+                "request",
+                (*service, &request, MAX_RESPONSE_BYTES),
+                CYCLES_PER_REQUEST,
+            )
+        }))
+        .await;
+        // The errors of the EVM RPC canister are not needed, so they are not decoded.
+        responses
+            .into_iter()
+            .map(|response| match response {
+                Ok((Ok(response),)) => json_rpc_result(&response),
+                Ok((Err(_),)) => Err("The provider failed to respond".to_string()),
+                Err((code, msg)) => Err(format!(
+                    "Failed to call the EVM RPC canister: {code:?} {msg}"
+                )),
+            })
+            .collect()
+    }
+}
+
+/// The requests that a caller has sent to the EVM RPC canister in the current window.
+///
+/// Each request costs the backend cycles, so callers may only send a limited number of them.
+#[derive(Clone, Default, Debug, Eq, PartialEq)]
+pub struct RequestQuota {
+    /// When the current window started.
+    window_start_ns: u64,
+    /// The number of requests sent in the current window.
+    requests: u32,
+}
+
+impl RequestQuota {
+    /// Counts a request, starting a new window if the current one is over.
+    ///
+    /// # Errors
+    /// - `TooManyRequests` if the caller has sent too many requests in the current window.
+    pub fn take(&mut self, now_ns: u64) -> Result<(), EvmRpcError> {
+        if self.is_stale(now_ns) {
+            *self = Self {
+                window_start_ns: now_ns,
+                requests: 0,
+            };
+        }
+        if self.requests >= MAX_REQUESTS_PER_WINDOW {
+            return Err(EvmRpcError::TooManyRequests {
+                max_requests: MAX_REQUESTS_PER_WINDOW,
+            });
+        }
+        self.requests += 1;
+        Ok(())
+    }
+
+    /// Whether the window is over, so that the quota no longer needs to be kept.
+    pub fn is_stale(&self, now_ns: u64) -> bool {
+        now_ns.saturating_sub(self.window_start_ns) >= REQUEST_WINDOW_NS
+    }
+}
+
+/// The `result` of a JSON-RPC response.
+///
+/// # Errors
+/// - The response is not JSON, or it is a JSON-RPC error.
+fn json_rpc_result(response: &str) -> Result<Value, String> {
+    let mut response: Value = serde_json::from_str(response)
+        .map_err(|err| format!("Invalid JSON-RPC response: {err}"))?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("JSON-RPC error: {error}"));
+    }
+    response
+        .get_mut("result")
+        .map(Value::take)
+        .ok_or_else(|| "JSON-RPC response without a result".to_string())
+}
+
+/// The result returned by at least `min_agreeing` providers.
+///
+/// # Errors
+/// - `InconsistentResults` if no result is returned by enough providers.
+fn consensus(
+    results: Vec<Result<Value, String>>,
+    min_agreeing: usize,
+) -> Result<Value, EvmRpcError> {
+    let agreed = results
+        .iter()
+        .flatten()
+        .find(|value| {
+            results
+                .iter()
+                .flatten()
+                .filter(|other| other == value)
+                .count()
+                >= min_agreeing
+        })
+        .cloned();
+    agreed.ok_or_else(|| EvmRpcError::InconsistentResults {
+        results: results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(Value::String).to_string())
+            .collect(),
+    })
+}
+
+/// The median of the block numbers returned by at least `min_responses` providers.
+///
+/// The median is a block that a majority of the providers have reached, and a single provider cannot move it past
+/// the blocks reported by the others.
+///
+/// # Errors
+/// - `InconsistentResults` if too few providers return a block number.
+fn median_block_number(
+    results: Vec<Result<Value, String>>,
+    min_responses: usize,
+) -> Result<U64, EvmRpcError> {
+    let mut block_numbers: Vec<U64> = results
+        .iter()
+        .flatten()
+        .filter_map(|value| serde_json::from_value(value.clone()).ok())
+        .collect();
+    if block_numbers.len() < min_responses {
+        return Err(EvmRpcError::InconsistentResults {
+            results: results
+                .into_iter()
+                .map(|result| result.unwrap_or_else(Value::String).to_string())
+                .collect(),
+        });
+    }
+    block_numbers.sort_unstable();
+    // With an even number of block numbers, the lower one is taken, which the providers are more likely to have.
+    Ok(block_numbers[(block_numbers.len() - 1) / 2])
+}
+
+/// Decodes the output of the ERC20 `decimals()`.
+fn decode_decimals(output: &[u8]) -> Result<u8, String> {
+    match abi::decode(&[ParamType::Uint(8)], output).as_deref() {
+        Ok([Token::Uint(decimals)]) => u8::try_from(*decimals).ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("Invalid decimals: 0x{}", hex::encode(output)))
+}
+
+/// Decodes the output of the ERC20 `symbol()`.
+///
+/// Some early tokens, e.g. MKR, return the symbol as `bytes32` padded with zeros instead of a `string`.
+fn decode_symbol(output: &[u8]) -> Result<String, String> {
+    if let Ok([Token::String(symbol)]) = abi::decode(&[ParamType::String], output).as_deref() {
+        return Ok(symbol.clone());
+    }
+    if output.len() == 32 {
+        if let Ok(symbol) = std::str::from_utf8(output) {
+            return Ok(symbol.trim_end_matches('\0').to_string());
+        }
+    }
+    Err(format!("Invalid symbol: 0x{}", hex::encode(output)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn json_rpc_results_and_errors_are_parsed() {
+        assert_eq!(
+            json_rpc_result(r#"{"jsonrpc":"2.0","id":1,"result":"0x2a"}"#),
+            Ok(json!("0x2a"))
+        );
+        assert_eq!(
+            json_rpc_result(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"oops"}}"#),
+            Err(r#"JSON-RPC error: {"code":-32000,"message":"oops"}"#.to_string())
+        );
+        assert!(json_rpc_result(r#"{"jsonrpc":"2.0","id":1}"#).is_err());
+        assert!(json_rpc_result("<html>").is_err());
+    }
+
+    #[test]
+    fn the_result_of_enough_providers_is_accepted() {
+        let results = vec![
+            Ok(json!("0x1")),
+            Err("timeout".to_string()),
+            Ok(json!("0x1")),
+        ];
+        assert_eq!(consensus(results, 2), Ok(json!("0x1")));
+
+        let results = vec![Ok(json!("0x1")), Ok(json!("0x2")), Ok(json!("0x2"))];
+        assert_eq!(consensus(results, 2), Ok(json!("0x2")));
+    }
+
+    #[test]
+    fn inconsistent_results_are_rejected() {
+        let results = vec![
+            Ok(json!("0x1")),
+            Ok(json!("0x2")),
+            Err("timeout".to_string()),
+        ];
+        assert_eq!(
+            consensus(results, 2),
+            Err(EvmRpcError::InconsistentResults {
+                results: vec![
+                    "\"0x1\"".to_string(),
+                    "\"0x2\"".to_string(),
+                    "\"timeout\"".to_string()
+                ]
+            })
+        );
+        // Agreeing errors are not a result.
+        let results = vec![Err("timeout".to_string()), Err("timeout".to_string())];
+        assert!(consensus(results, 2).is_err());
+    }
+
+    #[test]
+    fn the_median_block_number_is_taken() {
+        let results = vec![Ok(json!("0x12")), Ok(json!("0x10")), Ok(json!("0x11"))];
+        assert_eq!(median_block_number(results, 2), Ok(U64::from(0x11)));
+
+        let results = vec![
+            Ok(json!("0x12")),
+            Err("timeout".to_string()),
+            Ok(json!("0x11")),
+        ];
+        assert_eq!(median_block_number(results, 2), Ok(U64::from(0x11)));
+
+        let results = vec![
+            Ok(json!("0x12")),
+            Err("timeout".to_string()),
+            Ok(json!("oops")),
+        ];
+        assert!(median_block_number(results, 2).is_err());
+    }
+
+    #[test]
+    fn requests_per_caller_are_limited() {
+        let mut quota = RequestQuota::default();
+        let now_ns = REQUEST_WINDOW_NS;
+        for _ in 0..MAX_REQUESTS_PER_WINDOW {
+            assert_eq!(quota.take(now_ns), Ok(()));
+        }
+        assert_eq!(
+            quota.take(now_ns + 1),
+            Err(EvmRpcError::TooManyRequests {
+                max_requests: MAX_REQUESTS_PER_WINDOW
+            })
+        );
+        assert!(!quota.is_stale(now_ns + 1));
+
+        // A new window starts once the current one is over.
+        let later_ns = now_ns + REQUEST_WINDOW_NS;
+        assert!(quota.is_stale(later_ns));
+        assert_eq!(quota.take(later_ns), Ok(()));
+    }
+
+    #[test]
+    fn erc20_metadata_is_decoded() {
+        let decimals = abi::encode(&[Token::Uint(6.into())]);
+        assert_eq!(decode_decimals(&decimals), Ok(6));
+        let too_many_decimals = abi::encode(&[Token::Uint(256.into())]);
+        assert!(decode_decimals(&too_many_decimals).is_err());
+        assert!(decode_decimals(&[]).is_err());
+
+        let symbol = abi::encode(&[Token::String("USDC".to_string())]);
+        assert_eq!(decode_symbol(&symbol), Ok("USDC".to_string()));
+        let bytes32_symbol = abi::encode(&[Token::FixedBytes(b"MKR".to_vec())]);
+        assert_eq!(decode_symbol(&bytes32_symbol), Ok("MKR".to_string()));
+        assert!(decode_symbol(&[]).is_err());
+    }
+}
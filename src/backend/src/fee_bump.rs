@@ -0,0 +1,769 @@
+//! Acceleration of unconfirmed transactions.
+//!
+//! - Replacement by fee, following [BIP-125](https://github.com/bitcoin/bips/blob/master/bip-0125.mediawiki):
+//!   the replacement spends the same inputs and pays the same destinations.  The higher fee comes out of the change.
+//! - Child pays for parent: a child transaction spends an output of the parent and pays a fee that raises the fee rate
+//!   of both together.
+
+use crate::bitcoin_utils::{
+    decode_transaction, estimate_fee, estimate_tx_vsize, input_weight_prediction, spent_utxos,
+    MAX_FEE_MILLISATOSHI_PER_VBYTE,
+};
+use crate::btc_pending_transaction_model::StoredPendingTransaction;
+use bitcoin::{
+    hashes::Hash, Address, Amount, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use shared::types::bitcoin::{
+    BtcBumpFeeError, BtcCpfpError, BtcParentTransaction, BtcPendingTransactionStatus, BtcTxOutput,
+};
+
+/// The fee rate by which a replacement must pay for its own relay, in millisatoshi per vbyte.
+///
+/// This is the default `-incrementalrelayfee` of Bitcoin Core.
+const INCREMENTAL_RELAY_FEE_MILLISATOSHI_PER_VBYTE: u64 = 1_000;
+
+/// The lowest fee rate of a transaction that nodes relay, in millisatoshi per vbyte.
+///
+/// This is the default `-minrelaytxfee` of Bitcoin Core.
+const MIN_RELAY_FEE_MILLISATOSHI_PER_VBYTE: u64 = 1_000;
+
+/// An unsigned transaction that replaces a pending transaction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeeBump {
+    pub tx: Transaction,
+    /// The UTXO spent by each input.
+    pub utxos: Vec<Utxo>,
+    pub fee_satoshis: u64,
+    /// The value of the change output, or zero if the change is dust and is part of the fee.
+    pub change_satoshis: u64,
+    /// The index of the change output, unless the change is dust and is part of the fee.
+    pub change_output_index: Option<u32>,
+}
+
+/// Builds a replacement of the `original` transaction, which spends `utxos` of `source`, with a higher fee rate.
+///
+/// The change output, at `change_output_index` in the original, pays for the higher fee and is dropped
+/// if what is left would be dust.  Without change, only a fee rate that the original already pays could be reached.
+///
+/// The replacement must pay at least the fees of the original and of its descendants, such as a CPFP child,
+/// which are evicted with it, plus the incremental relay fee for its own size (BIP-125 rules 3 and 4).
+/// As both transactions spend the same inputs, which are already in the original,
+/// there are no new unconfirmed inputs (rule 2) and the fee rate of the replacement is higher (rule 6).
+///
+/// # Errors
+/// - The original does not signal replaceability, or spends UTXOs that are not in `utxos`.
+/// - The fee rate is too low or too high, or there is no change to pay for it.
+pub fn bump_fee(
+    original: &Transaction,
+    utxos: &[Utxo],
+    source: &Address,
+    change_output_index: Option<u32>,
+    descendants_fee_satoshis: u64,
+    fee_millisatoshi_per_vbyte: u64,
+) -> Result<FeeBump, BtcBumpFeeError> {
+    // Rule 1: the original signals replaceability explicitly.
+    if !original.is_explicitly_rbf() {
+        return Err(BtcBumpFeeError::NotReplaceable);
+    }
+    let fee_rate_too_high = BtcBumpFeeError::FeeRateTooHigh {
+        maximum_fee_rate_millisatoshi_per_vbyte: MAX_FEE_MILLISATOSHI_PER_VBYTE,
+    };
+    if fee_millisatoshi_per_vbyte > MAX_FEE_MILLISATOSHI_PER_VBYTE {
+        return Err(fee_rate_too_high);
+    }
+    let (utxos, original_fee) = spent_utxos_and_fee(original, utxos)
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+
+    let input = input_weight_prediction(source);
+    let mut outputs = original.output.clone();
+    let script_lens = |outputs: &[TxOut]| {
+        outputs
+            .iter()
+            .map(|output| output.script_pubkey.len())
+            .collect::<Vec<_>>()
+    };
+
+    let vsize = estimate_tx_vsize(input, utxos.len(), script_lens(&outputs));
+    let minimum_fee = original_fee
+        + descendants_fee_satoshis
+        + (vsize * INCREMENTAL_RELAY_FEE_MILLISATOSHI_PER_VBYTE).div_ceil(1000);
+    let fee = estimate_fee(
+        input,
+        utxos.len(),
+        fee_millisatoshi_per_vbyte,
+        script_lens(&outputs),
+    )
+    .ok_or(fee_rate_too_high.clone())?;
+    if fee < minimum_fee {
+        return Err(BtcBumpFeeError::FeeRateTooLow {
+            minimum_fee_rate_millisatoshi_per_vbyte: (minimum_fee * 1000).div_ceil(vsize),
+        });
+    }
+
+    let change_index = change_output_index
+        .and_then(|index| usize::try_from(index).ok())
+        .filter(|index| *index < outputs.len())
+        .ok_or(BtcBumpFeeError::InsufficientFunds)?;
+    // The change and the original fee are what the replacement can spend on its fee.
+    let available_satoshis = original_fee + outputs[change_index].value.to_sat();
+    let dust_limit = outputs[change_index]
+        .script_pubkey
+        .minimal_non_dust()
+        .to_sat();
+    let (fee_satoshis, change_satoshis, change_output_index) =
+        if fee + dust_limit <= available_satoshis {
+            let change_satoshis = available_satoshis - fee;
+            outputs[change_index].value = Amount::from_sat(change_satoshis);
+            (fee, change_satoshis, change_output_index)
+        } else {
+            outputs.remove(change_index);
+            let fee_without_change = estimate_fee(
+                input,
+                utxos.len(),
+                fee_millisatoshi_per_vbyte,
+                script_lens(&outputs),
+            )
+            .ok_or(fee_rate_too_high)?;
+            if outputs.is_empty() || available_satoshis < fee_without_change.max(minimum_fee) {
+                return Err(BtcBumpFeeError::InsufficientFunds);
+            }
+            (available_satoshis, 0, None)
+        };
+
+    let tx = Transaction {
+        version: original.version,
+        lock_time: original.lock_time,
+        input: original
+            .input
+            .iter()
+            .map(|input| TxIn {
+                previous_output: input.previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs,
+    };
+    Ok(FeeBump {
+        tx,
+        utxos,
+        fee_satoshis,
+        change_satoshis,
+        change_output_index,
+    })
+}
+
+/// Returns the total fee of the pending transactions that descend from the transaction `txid`, such as CPFP children.
+///
+/// A replacement evicts them from the mempool along with the original, so it has to pay for them too.
+///
+/// # Errors
+/// - A descendant was not built or submitted by this canister, or spends more than its inputs.
+pub fn descendants_fee(
+    txid: &[u8],
+    pending_transactions: &[StoredPendingTransaction],
+    now_ns: u64,
+) -> Result<u64, String> {
+    let mut ancestors: Vec<&[u8]> = vec![txid];
+    let mut fee_satoshis = 0;
+    // The descendants are created after their parent, so each one is found after its ancestors.
+    for pending_transaction in pending_transactions {
+        let is_descendant = pending_transaction.status(now_ns)
+            == BtcPendingTransactionStatus::Pending
+            && pending_transaction
+                .utxos
+                .iter()
+                .any(|utxo| ancestors.contains(&utxo.outpoint.txid.as_slice()));
+        if !is_descendant {
+            continue;
+        }
+        let tx = pending_transaction
+            .transaction
+            .as_deref()
+            .ok_or_else(|| "A descendant of the transaction is unknown".to_string())
+            .and_then(decode_transaction)?;
+        let (_, fee) = spent_utxos_and_fee(&tx, &pending_transaction.utxos)?;
+        fee_satoshis += fee;
+        ancestors.push(&pending_transaction.txid);
+    }
+    Ok(fee_satoshis)
+}
+
+/// Returns the UTXO spent by each input of a transaction and the fee it pays.
+///
+/// # Errors
+/// - The transaction spends UTXOs that are not in `utxos`, or more than its inputs.
+fn spent_utxos_and_fee(tx: &Transaction, utxos: &[Utxo]) -> Result<(Vec<Utxo>, u64), String> {
+    let utxos = spent_utxos(tx, utxos)
+        .map_err(|outpoint| format!("The transaction spends an unknown UTXO: {outpoint}"))?;
+    let input_satoshis: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let output_satoshis: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+    let fee = input_satoshis
+        .checked_sub(output_satoshis)
+        .ok_or("The transaction spends more than its inputs")?;
+    Ok((utxos, fee))
+}
+
+/// Returns the size and fee of a transaction that spends `utxos` of `source`.
+///
+/// The size of an unsigned transaction is estimated.
+///
+/// # Errors
+/// - The transaction spends UTXOs that are not in `utxos`, or more than its inputs.
+pub fn parent_transaction(
+    tx: &Transaction,
+    utxos: &[Utxo],
+    source: &Address,
+) -> Result<BtcParentTransaction, String> {
+    let (utxos, fee_satoshis) = spent_utxos_and_fee(tx, utxos)?;
+    let is_signed = tx
+        .input
+        .iter()
+        .all(|input| !input.witness.is_empty() || !input.script_sig.is_empty());
+    let vsize = if is_signed {
+        tx.vsize() as u64
+    } else {
+        estimate_tx_vsize(
+            input_weight_prediction(source),
+            utxos.len(),
+            tx.script_pubkey_lens(),
+        )
+    };
+    Ok(BtcParentTransaction {
+        vsize,
+        fee_satoshis,
+    })
+}
+
+/// An unsigned child transaction that accelerates its parent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CpfpChild {
+    /// The output of the parent spent by the child.
+    pub utxos: Vec<Utxo>,
+    pub outputs: Vec<BtcTxOutput>,
+    pub fee_satoshis: u64,
+}
+
+/// Returns the parent of a child, and its size and fee, from the pending transaction of the caller.
+///
+/// The size and fee default to those of the parent as it was built.
+///
+/// # Errors
+/// - `ParentUnknown` if the parent is not a pending transaction built or submitted by the caller.
+pub fn cpfp_parent(
+    pending_parent: Option<StoredPendingTransaction>,
+    parent: Option<BtcParentTransaction>,
+    source: &Address,
+) -> Result<(Transaction, BtcParentTransaction), BtcCpfpError> {
+    // The outputs of the parent are only known from the parent itself.
+    let Some(StoredPendingTransaction {
+        transaction: Some(transaction),
+        utxos,
+        ..
+    }) = pending_parent
+    else {
+        return Err(BtcCpfpError::ParentUnknown);
+    };
+    let parent_tx =
+        decode_transaction(&transaction).map_err(|msg| BtcCpfpError::InternalError { msg })?;
+    let parent = match parent {
+        Some(parent) => parent,
+        None => parent_transaction(&parent_tx, &utxos, source)
+            .map_err(|msg| BtcCpfpError::InternalError { msg })?,
+    };
+    Ok((parent_tx, parent))
+}
+
+/// Returns the outputs of a transaction that pay to `source`, as UTXOs that a child can spend.
+///
+/// The Bitcoin API does not return the outputs of unconfirmed transactions, so they are read from the transaction
+/// itself.  As they are not in a block yet, their height is zero.
+pub fn parent_utxos(parent: &Transaction, source: &Address) -> Vec<Utxo> {
+    let txid = parent.compute_txid().to_byte_array().to_vec();
+    let source_script = source.script_pubkey();
+    (0_u32..)
+        .zip(&parent.output)
+        .filter(|(_, output)| output.script_pubkey == source_script)
+        .map(|(vout, output)| Utxo {
+            outpoint: Outpoint {
+                txid: txid.clone(),
+                vout,
+            },
+            value: output.value.to_sat(),
+            height: 0,
+        })
+        .collect()
+}
+
+/// Builds a child that spends `utxo` of `source` to `destination`, and pays for the parent and itself
+/// at the given fee rate.
+///
+/// # Errors
+/// - `FeeRateTooHigh` if the fee rate is above `MAX_FEE_MILLISATOSHI_PER_VBYTE`.
+/// - `InsufficientFunds` if what is left of the UTXO after the fee is dust.
+pub fn cpfp_child(
+    utxo: Utxo,
+    parent: BtcParentTransaction,
+    source: &Address,
+    destination: &Address,
+    fee_millisatoshi_per_vbyte: u64,
+) -> Result<CpfpChild, BtcCpfpError> {
+    if fee_millisatoshi_per_vbyte > MAX_FEE_MILLISATOSHI_PER_VBYTE {
+        return Err(BtcCpfpError::FeeRateTooHigh {
+            maximum_fee_rate_millisatoshi_per_vbyte: MAX_FEE_MILLISATOSHI_PER_VBYTE,
+        });
+    }
+    let destination_script = destination.script_pubkey();
+    let child_vsize = estimate_tx_vsize(
+        input_weight_prediction(source),
+        1,
+        [destination_script.len()],
+    );
+    // A fee that overflows, for a parent of an impossible size, cannot be paid either.
+    let fee_satoshis = cpfp_child_fee(parent, child_vsize, fee_millisatoshi_per_vbyte)
+        .ok_or(BtcCpfpError::InsufficientFunds)?;
+    let sent_satoshis = utxo
+        .value
+        .checked_sub(fee_satoshis)
+        .filter(|sent_satoshis| *sent_satoshis >= destination_script.minimal_non_dust().to_sat())
+        .ok_or(BtcCpfpError::InsufficientFunds)?;
+    Ok(CpfpChild {
+        utxos: vec![utxo],
+        outputs: vec![BtcTxOutput {
+            destination_address: destination.to_string(),
+            sent_satoshis,
+        }],
+        fee_satoshis,
+    })
+}
+
+/// Returns the fee of a child of `child_vsize` vbytes that raises the fee rate of the parent and child together
+/// to the given fee rate.
+///
+/// The child pays at least the minimum relay fee, even if the parent already pays enough.
+/// Returns `None` if the fee overflows.
+pub fn cpfp_child_fee(
+    parent: BtcParentTransaction,
+    child_vsize: u64,
+    fee_millisatoshi_per_vbyte: u64,
+) -> Option<u64> {
+    let package_fee = parent
+        .vsize
+        .checked_add(child_vsize)?
+        .checked_mul(fee_millisatoshi_per_vbyte)?
+        .div_ceil(1000);
+    let minimum_fee = child_vsize
+        .checked_mul(MIN_RELAY_FEE_MILLISATOSHI_PER_VBYTE)?
+        .div_ceil(1000);
+    Some(
+        package_fee
+            .saturating_sub(parent.fee_satoshis)
+            .max(minimum_fee),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_utils::{build_psbt, parse_address};
+    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint};
+    use pretty_assertions::assert_eq;
+    use shared::types::bitcoin::BtcTxOutput;
+
+    const SOURCE: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    const DESTINATION: &str = "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c";
+
+    fn source() -> Address {
+        parse_address(SOURCE, BitcoinNetwork::Testnet).unwrap()
+    }
+
+    fn utxo() -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![1; 32],
+                vout: 0,
+            },
+            value: 100_000,
+            height: 100,
+        }
+    }
+
+    /// A transaction that spends one UTXO of 100_000 satoshi to the destination, with the given change.
+    fn original(sent_satoshis: u64, change_satoshis: u64) -> Transaction {
+        let outputs = [(DESTINATION, sent_satoshis), (SOURCE, change_satoshis)]
+            .into_iter()
+            .filter(|(_, sent_satoshis)| *sent_satoshis > 0)
+            .map(|(address, sent_satoshis)| BtcTxOutput {
+                destination_address: address.to_string(),
+                sent_satoshis,
+            })
+            .collect::<Vec<_>>();
+        build_psbt(&[utxo()], &source(), &outputs, BitcoinNetwork::Testnet)
+            .unwrap()
+            .unsigned_tx
+    }
+
+    fn output_values(tx: &Transaction) -> Vec<u64> {
+        tx.output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .collect()
+    }
+
+    #[test]
+    fn bump_fee_takes_higher_fee_from_change() {
+        let original = original(50_000, 49_000);
+
+        let bump = bump_fee(&original, &[utxo()], &source(), Some(1), 0, 10_000).unwrap();
+
+        // 153 vbytes at 10 satoshi per vbyte.
+        assert_eq!(bump.fee_satoshis, 1_530);
+        assert_eq!(bump.change_satoshis, 48_470);
+        assert_eq!(output_values(&bump.tx), vec![50_000, 48_470]);
+        assert_eq!(bump.utxos, vec![utxo()]);
+        assert_eq!(
+            bump.tx.input[0].previous_output,
+            original.input[0].previous_output
+        );
+        assert!(bump.tx.is_explicitly_rbf());
+        assert_ne!(bump.tx.compute_txid(), original.compute_txid());
+    }
+
+    #[test]
+    fn bump_fee_requires_incremental_relay_fee() {
+        // The original pays 1_000 satoshi, so the replacement has to pay 1_000 + 153 satoshi.
+        assert_eq!(
+            bump_fee(
+                &original(50_000, 49_000),
+                &[utxo()],
+                &source(),
+                Some(1),
+                0,
+                7_000
+            ),
+            Err(BtcBumpFeeError::FeeRateTooLow {
+                minimum_fee_rate_millisatoshi_per_vbyte: 7_536,
+            })
+        );
+        assert!(bump_fee(
+            &original(50_000, 49_000),
+            &[utxo()],
+            &source(),
+            Some(1),
+            0,
+            7_536
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn bump_fee_rejects_fee_rate_above_maximum() {
+        assert_eq!(
+            bump_fee(
+                &original(50_000, 49_000),
+                &[utxo()],
+                &source(),
+                Some(1),
+                0,
+                MAX_FEE_MILLISATOSHI_PER_VBYTE + 1
+            ),
+            Err(BtcBumpFeeError::FeeRateTooHigh {
+                maximum_fee_rate_millisatoshi_per_vbyte: MAX_FEE_MILLISATOSHI_PER_VBYTE,
+            })
+        );
+    }
+
+    #[test]
+    fn bump_fee_pays_for_descendants() {
+        // The original pays 1_000 satoshi and its child 2_000 satoshi, so the replacement has to pay
+        // 1_000 + 2_000 + 153 = 3_153 satoshi.
+        assert_eq!(
+            bump_fee(
+                &original(50_000, 49_000),
+                &[utxo()],
+                &source(),
+                Some(1),
+                2_000,
+                10_000
+            ),
+            Err(BtcBumpFeeError::FeeRateTooLow {
+                minimum_fee_rate_millisatoshi_per_vbyte: 20_608,
+            })
+        );
+        assert_eq!(
+            bump_fee(
+                &original(50_000, 49_000),
+                &[utxo()],
+                &source(),
+                Some(1),
+                2_000,
+                20_608
+            )
+            .map(|bump| bump.fee_satoshis),
+            Ok(3_153)
+        );
+    }
+
+    #[test]
+    fn descendants_fee_sums_the_fees_of_pending_children() {
+        let parent = original(50_000, 49_000);
+        let parent_txid = parent.compute_txid().to_byte_array().to_vec();
+        let pending = |tx: &Transaction, utxos: Vec<Utxo>| StoredPendingTransaction {
+            txid: tx.compute_txid().to_byte_array().to_vec(),
+            utxos,
+            created_at_timestamp_ns: 0,
+            transaction: Some(bitcoin::consensus::serialize(tx)),
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let child_utxos = parent_utxos(&parent, &source());
+        let child_outputs = [BtcTxOutput {
+            destination_address: SOURCE.to_string(),
+            sent_satoshis: 47_000,
+        }];
+        let child = build_psbt(
+            &child_utxos,
+            &source(),
+            &child_outputs,
+            BitcoinNetwork::Testnet,
+        )
+        .unwrap()
+        .unsigned_tx;
+        let grandchild_utxos = parent_utxos(&child, &source());
+        let grandchild_outputs = [BtcTxOutput {
+            destination_address: SOURCE.to_string(),
+            sent_satoshis: 46_500,
+        }];
+        let grandchild = build_psbt(
+            &grandchild_utxos,
+            &source(),
+            &grandchild_outputs,
+            BitcoinNetwork::Testnet,
+        )
+        .unwrap()
+        .unsigned_tx;
+        let pending_transactions = [
+            pending(&parent, vec![utxo()]),
+            pending(&child, child_utxos),
+            pending(&grandchild, grandchild_utxos),
+        ];
+
+        assert_eq!(
+            descendants_fee(&parent_txid, &pending_transactions, 0),
+            Ok(2_000 + 500)
+        );
+        assert_eq!(
+            descendants_fee(&pending_transactions[2].txid, &pending_transactions, 0),
+            Ok(0)
+        );
+        // Children that are no longer pending are not evicted by a replacement.
+        assert_eq!(
+            descendants_fee(
+                &parent_txid,
+                &pending_transactions,
+                2 * 24 * 60 * 60 * 1_000_000_000
+            ),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn bump_fee_drops_dust_change() {
+        let bump = bump_fee(
+            &original(98_400, 600),
+            &[utxo()],
+            &source(),
+            Some(1),
+            0,
+            10_000,
+        )
+        .unwrap();
+
+        assert_eq!(bump.fee_satoshis, 1_600);
+        assert_eq!(bump.change_satoshis, 0);
+        assert_eq!(bump.change_output_index, None);
+        assert_eq!(output_values(&bump.tx), vec![98_400]);
+    }
+
+    #[test]
+    fn bump_fee_fails_if_change_cannot_pay() {
+        assert_eq!(
+            bump_fee(
+                &original(98_400, 600),
+                &[utxo()],
+                &source(),
+                Some(1),
+                0,
+                20_000
+            ),
+            Err(BtcBumpFeeError::InsufficientFunds)
+        );
+        assert_eq!(
+            bump_fee(&original(99_000, 0), &[utxo()], &source(), None, 0, 10_000),
+            Err(BtcBumpFeeError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn bump_fee_takes_higher_fee_from_stored_change_output() {
+        // The user sends to its own address and receives the change at another address.
+        let outputs = [(SOURCE, 50_000), (DESTINATION, 49_000)]
+            .into_iter()
+            .map(|(address, sent_satoshis)| BtcTxOutput {
+                destination_address: address.to_string(),
+                sent_satoshis,
+            })
+            .collect::<Vec<_>>();
+        let original = build_psbt(&[utxo()], &source(), &outputs, BitcoinNetwork::Testnet)
+            .unwrap()
+            .unsigned_tx;
+
+        let bump = bump_fee(&original, &[utxo()], &source(), Some(1), 0, 10_000).unwrap();
+
+        assert_eq!(output_values(&bump.tx), vec![50_000, 48_470]);
+        assert_eq!(bump.change_output_index, Some(1));
+        assert_eq!(
+            bump_fee(&original, &[utxo()], &source(), None, 0, 10_000),
+            Err(BtcBumpFeeError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn bump_fee_requires_replaceable_original() {
+        let mut original = original(50_000, 49_000);
+        original.input[0].sequence = Sequence::MAX;
+
+        assert_eq!(
+            bump_fee(&original, &[utxo()], &source(), Some(1), 0, 10_000),
+            Err(BtcBumpFeeError::NotReplaceable)
+        );
+    }
+
+    #[test]
+    fn parent_transaction_estimates_size_of_unsigned_transaction() {
+        assert_eq!(
+            parent_transaction(&original(50_000, 49_000), &[utxo()], &source()),
+            Ok(BtcParentTransaction {
+                vsize: 153,
+                fee_satoshis: 1_000,
+            })
+        );
+        assert!(parent_transaction(&original(50_000, 49_000), &[], &source()).is_err());
+    }
+
+    #[test]
+    fn cpfp_parent_is_a_pending_transaction_built_here() {
+        let parent_tx = original(50_000, 49_000);
+        let pending_parent = |transaction| StoredPendingTransaction {
+            txid: parent_tx.compute_txid().to_byte_array().to_vec(),
+            utxos: vec![utxo()],
+            created_at_timestamp_ns: 0,
+            transaction,
+            change_output_index: Some(1),
+            replaced_by: None,
+            confirmed_at_height: None,
+        };
+        let size_and_fee = BtcParentTransaction {
+            vsize: 153,
+            fee_satoshis: 1_000,
+        };
+
+        assert_eq!(
+            cpfp_parent(
+                Some(pending_parent(Some(bitcoin::consensus::serialize(
+                    &parent_tx
+                )))),
+                None,
+                &source()
+            ),
+            Ok((parent_tx.clone(), size_and_fee))
+        );
+        assert_eq!(
+            cpfp_parent(Some(pending_parent(None)), Some(size_and_fee), &source()),
+            Err(BtcCpfpError::ParentUnknown)
+        );
+        assert_eq!(
+            cpfp_parent(None, Some(size_and_fee), &source()),
+            Err(BtcCpfpError::ParentUnknown)
+        );
+    }
+
+    #[test]
+    fn parent_utxos_are_the_outputs_to_source() {
+        let parent = original(50_000, 49_000);
+
+        assert_eq!(
+            parent_utxos(&parent, &source()),
+            vec![Utxo {
+                outpoint: Outpoint {
+                    txid: parent.compute_txid().to_byte_array().to_vec(),
+                    vout: 1,
+                },
+                value: 49_000,
+                height: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn cpfp_child_pays_for_parent_and_itself() {
+        let parent = BtcParentTransaction {
+            vsize: 153,
+            fee_satoshis: 153,
+        };
+        let utxo = Utxo {
+            value: 49_000,
+            ..utxo()
+        };
+
+        let child = cpfp_child(utxo.clone(), parent, &source(), &source(), 10_000).unwrap();
+
+        // 110 vbytes for the child.
+        assert_eq!(child.fee_satoshis, 2_477);
+        assert_eq!(child.utxos, vec![utxo.clone()]);
+        assert_eq!(
+            child.outputs,
+            vec![BtcTxOutput {
+                destination_address: SOURCE.to_string(),
+                sent_satoshis: 46_523,
+            }]
+        );
+        assert_eq!(
+            cpfp_child(utxo.clone(), parent, &source(), &source(), 200_000),
+            Err(BtcCpfpError::InsufficientFunds)
+        );
+        assert_eq!(
+            cpfp_child(
+                utxo,
+                parent,
+                &source(),
+                &source(),
+                MAX_FEE_MILLISATOSHI_PER_VBYTE + 1
+            ),
+            Err(BtcCpfpError::FeeRateTooHigh {
+                maximum_fee_rate_millisatoshi_per_vbyte: MAX_FEE_MILLISATOSHI_PER_VBYTE,
+            })
+        );
+    }
+
+    #[test]
+    fn cpfp_child_fee_raises_package_fee_rate() {
+        let parent = BtcParentTransaction {
+            vsize: 153,
+            fee_satoshis: 153,
+        };
+
+        // (153 + 110) vbytes at 10 satoshi per vbyte, minus what the parent pays.
+        assert_eq!(cpfp_child_fee(parent, 110, 10_000), Some(2_477));
+        // The parent pays enough, so the child only pays the minimum relay fee.
+        assert_eq!(cpfp_child_fee(parent, 110, 500), Some(110));
+        let huge_parent = BtcParentTransaction {
+            vsize: u64::MAX,
+            fee_satoshis: 0,
+        };
+        assert_eq!(cpfp_child_fee(huge_parent, 110, 10_000), None);
+    }
+}
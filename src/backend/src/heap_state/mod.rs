@@ -1,2 +0,0 @@
-pub mod btc_user_pending_tx_state;
-pub mod state;
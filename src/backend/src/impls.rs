@@ -1,5 +1,5 @@
 use crate::{
-    types::{Candid, StoredPrincipal},
+    types::{Candid, StoredBtcAddress, StoredPrincipal, StoredTxid},
     State,
 };
 use candid::{CandidType, Deserialize, Principal};
@@ -41,6 +41,11 @@ impl From<&State> for Stats {
             user_timestamps_count: state.user_profile_updated.len(),
             user_token_count: state.user_token.len(),
             custom_token_count: state.custom_token.len(),
+            user_profile_history_count: state.user_profile_history.len(),
+            principal_link_count: state.principal_link.len(),
+            btc_pending_transaction_count: state.btc_pending_transaction.len(),
+            btc_frozen_utxo_count: state.btc_frozen_utxo.len(),
+            eth_pending_transaction_count: state.eth_pending_transaction.len(),
         }
     }
 }
@@ -63,3 +68,51 @@ impl Storable for StoredPrincipal {
         ))
     }
 }
+
+impl StoredBtcAddress {
+    pub const MAX_LENGTH: u32 = 90;
+}
+
+impl Storable for StoredBtcAddress {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: Self::MAX_LENGTH,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        assert!(
+            self.0.len() <= Self::MAX_LENGTH as usize,
+            "bitcoin address length should not exceed {} bytes",
+            Self::MAX_LENGTH
+        );
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(String::from_utf8(bytes.into_owned()).expect("bitcoin address should be utf-8"))
+    }
+}
+
+impl StoredTxid {
+    pub const MAX_LENGTH: u32 = 32;
+}
+
+impl Storable for StoredTxid {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: Self::MAX_LENGTH,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        assert!(
+            self.0.len() <= Self::MAX_LENGTH as usize,
+            "txid length should not exceed {} bytes",
+            Self::MAX_LENGTH
+        );
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
@@ -0,0 +1,155 @@
+//! Offline derivation of threshold public keys, [as done by the IC](https://github.com/dfinity/ic/blob/master/rs/crypto/secp256k1/src/lib.rs).
+//!
+//! The IC derives the threshold keys with a generalisation of non-hardened BIP-32, in which every element of the
+//! derivation path is an arbitrary byte string rather than a 4 byte index.  The public key of a derivation path can
+//! therefore be computed from the public key and chain code of a parent, without calling the management canister.
+
+use bitcoin::{
+    hashes::{hmac, sha512, Hash, HashEngine},
+    secp256k1::{PublicKey, Scalar, Secp256k1},
+};
+
+/// A public key together with the chain code for deriving its child keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtendedPublicKey {
+    pub public_key: PublicKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedPublicKey {
+    /// Parses a SEC1 encoded public key and a chain code, as returned by the management canister.
+    ///
+    /// # Errors
+    /// - The public key is not a valid secp256k1 key or the chain code is not 32 bytes long.
+    pub fn from_slices(public_key: &[u8], chain_code: &[u8]) -> Result<Self, String> {
+        Ok(ExtendedPublicKey {
+            public_key: PublicKey::from_slice(public_key)
+                .map_err(|_| "Invalid public key".to_string())?,
+            chain_code: chain_code
+                .try_into()
+                .map_err(|_| "Invalid chain code".to_string())?,
+        })
+    }
+
+    /// Derives the key of the given path, relative to this key.
+    #[must_use]
+    pub fn derive(&self, derivation_path: &[Vec<u8>]) -> Self {
+        derivation_path
+            .iter()
+            .fold(*self, |parent, index| parent.derive_child(index))
+    }
+
+    /// Derives the child key with the given index.
+    fn derive_child(&self, index: &[u8]) -> Self {
+        let secp = Secp256k1::verification_only();
+        let mut input = self.public_key.serialize().to_vec();
+        loop {
+            let (chain_code, tweak) = child_tweak(index, &input, &self.chain_code);
+            match self.public_key.add_exp_tweak(&secp, &tweak) {
+                Ok(public_key) => {
+                    return ExtendedPublicKey {
+                        public_key,
+                        chain_code,
+                    }
+                }
+                // The child key would be the point at infinity, so the IC tries again with the next input.
+                Err(_) => input = [&[1_u8][..], &chain_code].concat(),
+            }
+        }
+    }
+}
+
+/// The chain code of a child key and the tweak that is added to the parent key.
+///
+/// If the tweak is not a valid scalar, the IC tries again with the next input, as in SLIP-10.
+fn child_tweak(index: &[u8], input: &[u8], chain_code: &[u8; 32]) -> ([u8; 32], Scalar) {
+    let mut input = input.to_vec();
+    loop {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(chain_code);
+        engine.input(&input);
+        engine.input(index);
+        let output = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+        let (mut tweak, mut next_chain_code) = ([0_u8; 32], [0_u8; 32]);
+        tweak.copy_from_slice(&output[..32]);
+        next_chain_code.copy_from_slice(&output[32..]);
+        match Scalar::from_be_bytes(tweak) {
+            Ok(tweak) => return (next_chain_code, tweak),
+            Err(_) => input = [&[1_u8][..], &next_chain_code].concat(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bip32::{ChildNumber, Xpub};
+    use pretty_assertions::assert_eq;
+    use std::str::FromStr;
+
+    /// The master key of the first BIP-32 test vector.
+    const BIP32_MASTER_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    fn master() -> (Xpub, ExtendedPublicKey) {
+        let xpub = Xpub::from_str(BIP32_MASTER_XPUB).expect("invalid xpub");
+        let key = ExtendedPublicKey {
+            public_key: xpub.public_key,
+            chain_code: xpub.chain_code.to_bytes(),
+        };
+        (xpub, key)
+    }
+
+    #[test]
+    fn four_byte_indices_derive_as_non_hardened_bip32() {
+        let (xpub, key) = master();
+        let secp = Secp256k1::verification_only();
+
+        for path in [vec![0_u32], vec![1, 2], vec![2, 1_000_000_000, 7]] {
+            let expected = xpub
+                .derive_pub(
+                    &secp,
+                    &path
+                        .iter()
+                        .map(|&index| ChildNumber::Normal { index })
+                        .collect::<Vec<_>>(),
+                )
+                .expect("failed to derive");
+            let derived = key.derive(
+                &path
+                    .iter()
+                    .map(|index| index.to_be_bytes().to_vec())
+                    .collect::<Vec<_>>(),
+            );
+
+            assert_eq!(derived.public_key, expected.public_key);
+            assert_eq!(derived.chain_code, expected.chain_code.to_bytes());
+        }
+    }
+
+    #[test]
+    fn derivation_is_step_by_step() {
+        let (_, key) = master();
+        let schema = vec![0_u8];
+        let principal = vec![7_u8; 29];
+
+        assert_eq!(
+            key.derive(&[schema.clone(), principal.clone()]),
+            key.derive(&[schema.clone()]).derive(&[principal.clone()])
+        );
+        assert_eq!(key.derive(&[]), key);
+        assert_ne!(
+            key.derive(&[schema, principal.clone()]),
+            key.derive(&[vec![1_u8], principal])
+        );
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(ExtendedPublicKey::from_slices(&[2; 32], &[0; 32]).is_err());
+        let (_, key) = master();
+        assert!(ExtendedPublicKey::from_slices(&key.public_key.serialize(), &[0; 31]).is_err());
+        assert_eq!(
+            ExtendedPublicKey::from_slices(&key.public_key.serialize(), &key.chain_code),
+            Ok(key)
+        );
+    }
+}
@@ -1,13 +1,20 @@
-use crate::assertions::{assert_token_enabled_is_some, assert_token_symbol_length};
+use crate::assertions::{
+    assert_token_enabled_is_some, assert_token_matches_contract, assert_token_symbol_length,
+};
 use crate::guards::{caller_is_allowed, may_read_user_data, may_write_user_data};
 use crate::token::{add_to_user_token, remove_from_user_token};
 use crate::user_profile::add_hidden_dapp_id;
-use bitcoin_utils::estimate_fee;
+use bitcoin::{hashes::Hash, Address};
+use bitcoin_api::AddressUtxos;
+use btc_pending_transaction_model::{BtcPendingTransactionModel, StoredPendingTransaction};
 use candid::Principal;
+use coin_selection::{CoinSelection, SelectionTarget};
 use config::find_credential_config;
+use eth_nonce::EthNonces;
+use eth_pending_transaction_model::EthPendingTransactionModel;
 use ethers_core::abi::ethereum_types::H160;
-use heap_state::btc_user_pending_tx_state::StoredPendingTransaction;
-use heap_state::state::with_btc_pending_transactions;
+use evm_rpc::{EvmRpcClient, RequestQuota};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
 use ic_cdk::api::time;
 use ic_cdk::eprintln;
 use ic_cdk_macros::{export_candid, init, post_upgrade, query, update};
@@ -18,32 +25,67 @@ use ic_stable_structures::{
 };
 use ic_verifiable_credentials::validate_ii_presentation_and_claims;
 use oisy_user::oisy_users;
+use principal_link_model::{primary_principal, PrincipalLinkModel};
 use serde_bytes::ByteBuf;
+use shared::eip191;
+use shared::eip712::TypedData;
 use shared::http::{HttpRequest, HttpResponse};
 use shared::metrics::get_metrics;
 use shared::std_canister_status;
 use shared::types::bitcoin::{
-    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcGetPendingTransactionsError,
-    BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsRequest, PendingTransaction,
-    SelectedUtxosFeeError, SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
+    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcBuildTransactionError,
+    BtcBuildTransactionRequest, BtcBuildTransactionResponse, BtcBumpFeeError, BtcBumpFeeRequest,
+    BtcCpfpError, BtcCpfpRequest, BtcFeeTierPercentiles, BtcFreezeUtxosError,
+    BtcFreezeUtxosRequest, BtcFrozenUtxo, BtcGetAddressError, BtcGetAddressRequest,
+    BtcGetAddressResponse, BtcGetBalanceError, BtcGetBalanceRequest, BtcGetBalanceResponse,
+    BtcGetFeeTiersError, BtcGetFeeTiersRequest, BtcGetFeeTiersResponse,
+    BtcGetPendingTransactionsError, BtcGetPendingTransactionsReponse,
+    BtcGetPendingTransactionsRequest, BtcPendingTransactionStatus, BtcSubmitTransactionError,
+    BtcSubmitTransactionRequest, BtcSubmitTransactionResponse, BtcTxOutput,
+    BtcUnfreezeUtxosRequest, PendingTransaction, SelectedUtxosFeeError, SelectedUtxosFeeRequest,
+    SelectedUtxosFeeResponse,
 };
 use shared::types::custom_token::{CustomToken, CustomTokenId};
 use shared::types::dapp::{AddDappSettingsError, AddHiddenDappIdRequest};
+use shared::types::ethereum::{
+    EthAddPendingTransactionError, EthAddPendingTransactionRequest, EthAddressError,
+    EthAddressResponse, EthEstimateFeesResponse, EthGetBalanceRequest, EthGetBalanceResponse,
+    EthGetPendingTransactionsRequest, EthGetPendingTransactionsResponse,
+    EthGetTransactionCountRequest, EthGetTransactionCountResponse, EthPersonalSignRequest,
+    EthPrunePendingTransactionsRequest, EthReconcileNonceRequest, EthReleaseNonceRequest,
+    EthReserveNonceError, EthReserveNonceRequest, EthReserveNonceResponse, EthSignMessageError,
+    EthSignMessageResponse, EthSignTransactionError, EthSignTransactionRequest,
+    EthSignTransactionResponse, EthSignTypedDataRequest, EthSigningHashResponse, EvmRpcError,
+};
+use shared::types::principal_link::{
+    ConfirmPrincipalLinkRequest, LinkedPrincipals, PrincipalLinkError, StartPrincipalLinkRequest,
+    StartPrincipalLinkResponse, UnlinkPrincipalRequest,
+};
 use shared::types::signer::topup::{TopUpCyclesLedgerRequest, TopUpCyclesLedgerResult};
-use shared::types::token::{UserToken, UserTokenId};
+use shared::types::token::{ChainId, UserToken, UserTokenId};
+use shared::types::transaction::SignRequest;
 use shared::types::user_profile::{
-    AddUserCredentialError, AddUserCredentialRequest, GetUserProfileError, ListUsersRequest,
-    ListUsersResponse, OisyUser, UserProfile,
+    AcceptAgreementsError, AcceptAgreementsRequest, AddUserCredentialError,
+    AddUserCredentialRequest, GetUserProfileError, GetUserProfileHistoryError,
+    GetUserProfileHistoryRequest, GetUserProfileHistoryResponse, ListUsersRequest,
+    ListUsersResponse, UserProfile,
 };
 use shared::types::{
-    Arg, Config, Guards, InitArg, Migration, MigrationProgress, MigrationReport, Stats,
+    AgreementKind, AgreementVersion, Arg, Config, Guards, InitArg, Migration, MigrationProgress,
+    MigrationReport, SetRequiredAgreementVersionRequest, Stats, Timestamp,
+    UserProfileHistoryConfig,
+};
+use signer::{
+    btc_principal_to_address, eth_principal_to_address, eth_public_key, AllowSigningError,
 };
-use signer::{btc_principal_to_p2wpkh_address, AllowSigningError};
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::time::Duration;
 use types::{
-    Candid, ConfigCell, CustomTokenMap, StoredPrincipal, UserProfileMap, UserProfileUpdatedMap,
-    UserTokenMap,
+    BtcFrozenUtxoMap, BtcPendingTransactionMap, Candid, ConfigCell, CustomTokenMap,
+    EthPendingTransactionMap, PrincipalLinkChallengeMap, PrincipalLinkGroupMap,
+    PrincipalLinkInitiatorChallengeMap, PrincipalLinkMap, StoredPrincipal, UserProfileHistoryMap,
+    UserProfileMap, UserProfileUpdatedMap, UserTokenMap,
 };
 use user_profile::{add_credential, create_profile, find_profile};
 use user_profile_model::UserProfileModel;
@@ -51,12 +93,23 @@ use user_profile_model::UserProfileModel;
 mod assertions;
 mod bitcoin_api;
 mod bitcoin_utils;
+mod btc_frozen_utxo;
+mod btc_pending_transaction_model;
+mod coin_selection;
 mod config;
+mod eth_fees;
+mod eth_nonce;
+mod eth_pending_transaction_model;
+mod eth_transaction;
+mod evm_rpc;
+mod fee_bump;
 mod guards;
-mod heap_state;
 mod impls;
+mod key_derivation;
 mod migrate;
 mod oisy_user;
+mod principal_link;
+mod principal_link_model;
 pub mod signer;
 mod state;
 mod token;
@@ -69,8 +122,18 @@ const USER_TOKEN_MEMORY_ID: MemoryId = MemoryId::new(1);
 const USER_CUSTOM_TOKEN_MEMORY_ID: MemoryId = MemoryId::new(2);
 const USER_PROFILE_MEMORY_ID: MemoryId = MemoryId::new(3);
 const USER_PROFILE_UPDATED_MEMORY_ID: MemoryId = MemoryId::new(4);
+const USER_PROFILE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(5);
+const PRINCIPAL_LINK_MEMORY_ID: MemoryId = MemoryId::new(6);
+const PRINCIPAL_LINK_GROUP_MEMORY_ID: MemoryId = MemoryId::new(7);
+const PRINCIPAL_LINK_CHALLENGE_MEMORY_ID: MemoryId = MemoryId::new(8);
+const BTC_PENDING_TRANSACTION_MEMORY_ID: MemoryId = MemoryId::new(9);
+const BTC_FROZEN_UTXO_MEMORY_ID: MemoryId = MemoryId::new(10);
+const ETH_PENDING_TRANSACTION_MEMORY_ID: MemoryId = MemoryId::new(11);
+const PRINCIPAL_LINK_INITIATOR_CHALLENGE_MEMORY_ID: MemoryId = MemoryId::new(12);
 
 const MAX_SYMBOL_LENGTH: usize = 20;
+/// The number of `(principal, address)` entries of pending Bitcoin transactions refreshed per housekeeping run.
+const BTC_PENDING_TRANSACTION_BATCH_SIZE: usize = 20;
 
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -85,6 +148,21 @@ thread_local! {
             // Use `UserProfileModel` to access and manage access to these states
             user_profile: UserProfileMap::init(mm.borrow().get(USER_PROFILE_MEMORY_ID)),
             user_profile_updated: UserProfileUpdatedMap::init(mm.borrow().get(USER_PROFILE_UPDATED_MEMORY_ID)),
+            user_profile_history: UserProfileHistoryMap::init(mm.borrow().get(USER_PROFILE_HISTORY_MEMORY_ID)),
+            // Use `PrincipalLinkModel` to access and manage access to these states
+            principal_link: PrincipalLinkMap::init(mm.borrow().get(PRINCIPAL_LINK_MEMORY_ID)),
+            principal_link_group: PrincipalLinkGroupMap::init(mm.borrow().get(PRINCIPAL_LINK_GROUP_MEMORY_ID)),
+            principal_link_challenge: PrincipalLinkChallengeMap::init(mm.borrow().get(PRINCIPAL_LINK_CHALLENGE_MEMORY_ID)),
+            principal_link_initiator_challenge: PrincipalLinkInitiatorChallengeMap::init(mm.borrow().get(PRINCIPAL_LINK_INITIATOR_CHALLENGE_MEMORY_ID)),
+            principal_link_challenge_cursor: None,
+            // Use `BtcPendingTransactionModel` to access and manage access to this state
+            btc_pending_transaction: BtcPendingTransactionMap::init(mm.borrow().get(BTC_PENDING_TRANSACTION_MEMORY_ID)),
+            btc_pending_transaction_cursor: None,
+            btc_frozen_utxo: BtcFrozenUtxoMap::init(mm.borrow().get(BTC_FROZEN_UTXO_MEMORY_ID)),
+            eth_pending_transaction: EthPendingTransactionMap::init(mm.borrow().get(ETH_PENDING_TRANSACTION_MEMORY_ID)),
+            eth_nonces: BTreeMap::new(),
+            evm_rpc_quotas: BTreeMap::new(),
+            eth_fee_estimates: BTreeMap::new(),
             migration: None,
         })
     );
@@ -112,6 +190,24 @@ fn read_config<R>(f: impl FnOnce(&Config) -> R) -> R {
     })
 }
 
+/// The principal whose tokens and settings are used by the caller.
+///
+/// This is the caller itself, unless the caller has been linked to the group of another principal.
+fn caller_primary_principal() -> StoredPrincipal {
+    let caller = StoredPrincipal(ic_cdk::caller());
+    read_state(|s| primary_principal(&s.principal_link, caller))
+}
+
+/// Reads the retention limits for the user profile history.
+fn read_user_profile_history_config() -> UserProfileHistoryConfig {
+    read_config(|config| config.user_profile_history.unwrap_or_default())
+}
+
+/// Reads the agreement versions that users are currently required to accept.
+fn read_required_agreements() -> BTreeMap<AgreementKind, AgreementVersion> {
+    read_config(|config| config.required_agreements.clone().unwrap_or_default())
+}
+
 /// Modifies `state.config` with the provided function.
 fn modify_state_config(state: &mut State, f: impl FnOnce(&mut Config)) {
     let config: &Candid<Config> = state
@@ -136,9 +232,56 @@ pub struct State {
     custom_token: CustomTokenMap,
     user_profile: UserProfileMap,
     user_profile_updated: UserProfileUpdatedMap,
+    /// Append-only, bounded log of changes to each user profile.
+    user_profile_history: UserProfileHistoryMap,
+    /// Groups of principals that share one wallet account.
+    principal_link: PrincipalLinkMap,
+    principal_link_group: PrincipalLinkGroupMap,
+    /// Pending invitations to join a group of linked principals.
+    principal_link_challenge: PrincipalLinkChallengeMap,
+    principal_link_initiator_challenge: PrincipalLinkInitiatorChallengeMap,
+    /// The last invitation examined by the principal link challenge housekeeping.
+    principal_link_challenge_cursor: Option<(StoredPrincipal, StoredPrincipal)>,
+    /// Bitcoin transactions sent by the users, whose UTXOs must not be spent again.
+    btc_pending_transaction: BtcPendingTransactionMap,
+    /// The last entry refreshed by the pending Bitcoin transaction housekeeping.
+    btc_pending_transaction_cursor: Option<(Principal, String)>,
+    /// UTXOs the users have excluded from automatic coin selection.
+    btc_frozen_utxo: BtcFrozenUtxoMap,
+    /// Ethereum transactions sent by the users, shown in the activity until the chain has caught up with them.
+    eth_pending_transaction: EthPendingTransactionMap,
+    /// Ethereum nonces reserved by the users, per chain.
+    ///
+    /// Reservations are short lived and the nonce of the chain is reported again after an upgrade,
+    /// so they are not kept in stable memory.
+    eth_nonces: BTreeMap<(Principal, ChainId), EthNonces>,
+    /// The requests that each caller has recently sent to the EVM RPC canister.
+    evm_rpc_quotas: BTreeMap<Principal, RequestQuota>,
+    /// The fees last suggested for each EVM chain, with the time at which they were computed.
+    eth_fee_estimates: BTreeMap<ChainId, (Timestamp, EthEstimateFeesResponse)>,
     migration: Option<Migration>,
 }
 
+fn with_btc_pending_transactions<R>(f: impl FnOnce(&mut BtcPendingTransactionModel) -> R) -> R {
+    mutate_state(|s| {
+        f(&mut BtcPendingTransactionModel::new(
+            &mut s.btc_pending_transaction,
+            None,
+            None,
+        ))
+    })
+}
+
+fn with_eth_pending_transactions<R>(f: impl FnOnce(&mut EthPendingTransactionModel) -> R) -> R {
+    mutate_state(|s| {
+        f(&mut EthPendingTransactionModel::new(
+            &mut s.eth_pending_transaction,
+            None,
+            None,
+        ))
+    })
+}
+
 fn set_config(arg: InitArg) {
     let config = Config::from(arg);
     mutate_state(|state| {
@@ -151,6 +294,11 @@ fn set_config(arg: InitArg) {
 
 /// Runs housekeeping tasks immediately, then periodically:
 /// - `hourly_housekeeping_tasks`
+///
+/// Also refreshes pending Bitcoin transactions and drops expired nonce reservations and link invitations periodically:
+/// - `btc_pending_transaction_housekeeping`
+/// - `eth_nonce_housekeeping`
+/// - `principal_link_challenge_housekeeping`
 fn start_periodic_housekeeping_timers() {
     // Run housekeeping tasks once, immediately but asynchronously.
     let immediate = Duration::ZERO;
@@ -159,6 +307,15 @@ fn start_periodic_housekeeping_timers() {
     // Then periodically:
     let hour = Duration::from_secs(60 * 60);
     let _ = set_timer_interval(hour, || ic_cdk::spawn(hourly_housekeeping_tasks()));
+
+    // Each run refreshes one batch of pending Bitcoin transactions, so it runs more often.
+    let ten_minutes = Duration::from_secs(10 * 60);
+    let _ = set_timer_interval(ten_minutes, || {
+        ic_cdk::spawn(btc_pending_transaction_housekeeping());
+        eth_nonce_housekeeping();
+        evm_rpc_quota_housekeeping();
+        principal_link_challenge_housekeeping();
+    });
 }
 
 /// Runs hourly housekeeping tasks:
@@ -174,6 +331,89 @@ async fn hourly_housekeeping_tasks() {
     }
 }
 
+/// Drops the Ethereum nonce entries whose reservations have all expired.
+fn eth_nonce_housekeeping() {
+    let now_ns = time();
+    mutate_state(|s| {
+        s.eth_nonces.retain(|_, nonces| !nonces.is_stale(now_ns));
+    });
+}
+
+/// Drops the EVM RPC request quotas of the callers that have not sent requests recently.
+fn evm_rpc_quota_housekeeping() {
+    let now_ns = time();
+    mutate_state(|s| {
+        s.evm_rpc_quotas.retain(|_, quota| !quota.is_stale(now_ns));
+    });
+}
+
+/// Drops the invitations to link principals that nobody confirmed.
+///
+/// Successive runs examine the invitations in batches, so that a run does not scan all of them.
+fn principal_link_challenge_housekeeping() {
+    let now_ns = time();
+    mutate_state(|s| {
+        let cursor = s.principal_link_challenge_cursor.take();
+        let mut principal_link_model = PrincipalLinkModel::new(
+            &mut s.principal_link,
+            &mut s.principal_link_group,
+            &mut s.principal_link_challenge,
+            &mut s.principal_link_initiator_challenge,
+        );
+        s.principal_link_challenge_cursor =
+            principal_link::prune_challenges(now_ns, cursor, &mut principal_link_model);
+    });
+}
+
+/// Refreshes the status of the next batch of pending Bitcoin transactions, and prunes the transactions past retention.
+///
+/// Successive runs walk all the `(principal, address)` entries in turn,
+/// so that the transactions of users who do not call the Bitcoin endpoints are refreshed too.
+/// The utxos are only fetched for the addresses with transactions still pending:
+/// the others are only pruned.
+async fn btc_pending_transaction_housekeeping() {
+    let cursor = read_state(|s| s.btc_pending_transaction_cursor.clone());
+    let batch = with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.addresses_after(cursor.as_ref(), BTC_PENDING_TRANSACTION_BATCH_SIZE)
+    });
+    mutate_state(|s| {
+        // Start again from the first entry once the last one has been reached.
+        s.btc_pending_transaction_cursor = if batch.len() < BTC_PENDING_TRANSACTION_BATCH_SIZE {
+            None
+        } else {
+            batch.last().cloned()
+        };
+    });
+
+    for (principal, address) in batch {
+        let has_pending_transactions = with_btc_pending_transactions(|pending_transactions| {
+            pending_transactions.has_pending_transactions(&principal, &address, time())
+        });
+        let network = bitcoin_utils::address_network(&address).filter(|_| has_pending_transactions);
+        let current = match network {
+            // All utxos are needed: a transaction is taken as confirmed when its utxos are gone.
+            Some(network) => bitcoin_api::get_address_utxos(network, address.clone(), Some(0))
+                .await
+                .map_err(|msg| eprintln!("Failed to get the utxos of a Bitcoin address: {msg}"))
+                .ok(),
+            None => None,
+        };
+        let now_ns = time();
+        with_btc_pending_transactions(|pending_transactions| match current {
+            Some(current) => pending_transactions.refresh_pending_transactions(
+                principal,
+                &address,
+                &current.utxos,
+                current.tip_height,
+                now_ns,
+            ),
+            // Without pending transactions, or without the utxos of the address,
+            // only the transactions past retention can be pruned.
+            None => pending_transactions.prune_pending_transactions(principal, &address, now_ns),
+        });
+    }
+}
+
 #[init]
 pub fn init(arg: Arg) {
     match arg {
@@ -220,6 +460,18 @@ pub async fn top_up_cycles_ledger(
     signer::top_up_cycles_ledger(request.unwrap_or_default()).await
 }
 
+/// Checks that the signer public keys of a principal, which are derived offline, match those of the management canister.
+///
+/// Only the integration tests use this endpoint, so it is not part of the release builds.
+///
+/// # Errors
+/// - A key could not be fetched or the keys differ.
+#[cfg(feature = "test-endpoints")]
+#[update(guard = "caller_is_allowed")]
+pub async fn check_signer_key_derivation(principal: Principal) -> Result<(), String> {
+    signer::check_key_derivation(&principal).await
+}
+
 /// Processes external HTTP requests.
 #[query]
 #[allow(clippy::needless_pass_by_value)]
@@ -249,15 +501,22 @@ fn parse_eth_address(address: &str) -> [u8; 20] {
     }
 }
 
+/// Adds or updates a token of the caller.
+///
+/// The decimals and the symbol are checked against the token contract if the EVM RPC canister can read the chain.
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
-pub fn set_user_token(token: UserToken) {
+pub async fn set_user_token(token: UserToken) {
     assert_token_symbol_length(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
     assert_token_enabled_is_some(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
 
     let addr = parse_eth_address(&token.contract_address);
 
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
+
+    assert_token_matches_contract(&token, &H160(addr))
+        .await
+        .unwrap_or_else(|e| ic_cdk::trap(&e));
 
     let find = |t: &UserToken| {
         t.chain_id == token.chain_id && parse_eth_address(&t.contract_address) == addr
@@ -266,16 +525,25 @@ pub fn set_user_token(token: UserToken) {
     mutate_state(|s| add_to_user_token(stored_principal, &mut s.user_token, &token, &find));
 }
 
+/// Adds or updates several tokens of the caller.
+///
+/// As with `set_user_token`, the decimals and the symbol are checked against the token contracts.  No token is stored
+/// if a check fails.
 #[update(guard = "may_write_user_data")]
-pub fn set_many_user_tokens(tokens: Vec<UserToken>) {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+pub async fn set_many_user_tokens(tokens: Vec<UserToken>) {
+    let stored_principal = caller_primary_principal();
+
+    for token in &tokens {
+        assert_token_symbol_length(token).unwrap_or_else(|e| ic_cdk::trap(&e));
+        assert_token_enabled_is_some(token).unwrap_or_else(|e| ic_cdk::trap(&e));
+        let addr = parse_eth_address(&token.contract_address);
+        assert_token_matches_contract(token, &H160(addr))
+            .await
+            .unwrap_or_else(|e| ic_cdk::trap(&e));
+    }
 
     mutate_state(|s| {
         for token in tokens {
-            assert_token_symbol_length(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
-            assert_token_enabled_is_some(&token).unwrap_or_else(|e| ic_cdk::trap(&e));
-            parse_eth_address(&token.contract_address);
-
             let find = |t: &UserToken| {
                 t.chain_id == token.chain_id && (t.contract_address == token.contract_address)
             };
@@ -289,7 +557,7 @@ pub fn set_many_user_tokens(tokens: Vec<UserToken>) {
 #[allow(clippy::needless_pass_by_value)]
 pub fn remove_user_token(token_id: UserTokenId) {
     let addr = parse_eth_address(&token_id.contract_address);
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
 
     let find = |t: &UserToken| {
         t.chain_id == token_id.chain_id && parse_eth_address(&t.contract_address) == addr
@@ -301,7 +569,7 @@ pub fn remove_user_token(token_id: UserTokenId) {
 #[query(guard = "may_read_user_data")]
 #[must_use]
 pub fn list_user_tokens() -> Vec<UserToken> {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
     read_state(|s| s.user_token.get(&stored_principal).unwrap_or_default().0)
 }
 
@@ -309,7 +577,7 @@ pub fn list_user_tokens() -> Vec<UserToken> {
 #[update(guard = "may_write_user_data")]
 #[allow(clippy::needless_pass_by_value)]
 pub fn set_custom_token(token: CustomToken) {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
 
     let find = |t: &CustomToken| -> bool {
         CustomTokenId::from(&t.token) == CustomTokenId::from(&token.token)
@@ -320,7 +588,7 @@ pub fn set_custom_token(token: CustomToken) {
 
 #[update(guard = "may_write_user_data")]
 pub fn set_many_custom_tokens(tokens: Vec<CustomToken>) {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
 
     mutate_state(|s| {
         for token in tokens {
@@ -336,12 +604,455 @@ pub fn set_many_custom_tokens(tokens: Vec<CustomToken>) {
 #[query(guard = "may_read_user_data")]
 #[must_use]
 pub fn list_custom_tokens() -> Vec<CustomToken> {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
     read_state(|s| s.custom_token.get(&stored_principal).unwrap_or_default().0)
 }
 
+/// Returns the caller's Ethereum address, derived from the chain fusion signer key.
+///
+/// # Errors
+/// Errors are enumerated by: `EthAddressError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_address_of_caller() -> Result<EthAddressResponse, EthAddressError> {
+    eth_address_of_principal(&ic_cdk::caller()).await
+}
+
+/// Returns the Ethereum address of the given principal, derived from the chain fusion signer key.
+///
+/// # Errors
+/// Errors are enumerated by: `EthAddressError`.
+#[update(guard = "caller_is_allowed")]
+pub async fn eth_address_of(principal: Principal) -> Result<EthAddressResponse, EthAddressError> {
+    eth_address_of_principal(&principal).await
+}
+
+async fn eth_address_of_principal(
+    principal: &Principal,
+) -> Result<EthAddressResponse, EthAddressError> {
+    let address = eth_principal_to_address(principal)
+        .await
+        .map_err(|msg| EthAddressError::InternalError { msg })?;
+    Ok(EthAddressResponse { address })
+}
+
+/// Returns the hash of an EIP-1559 Ethereum transaction that the caller signs to send it.
+///
+/// The caller signs the hash with `eth_sign_prehash` of the chain fusion signer, which uses the key of
+/// `eth_address_of_caller`, paying with the allowance of `allow_signing`.  The signature is then passed to
+/// `eth_assemble_signed_transaction`.
+///
+/// # Errors
+/// Errors are enumerated by: `EthSignTransactionError`.
+#[query(guard = "may_read_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn eth_transaction_signing_hash(
+    request: SignRequest,
+) -> Result<EthSigningHashResponse, EthSignTransactionError> {
+    let transaction = eth_transaction::eip1559_transaction(&request)?;
+    Ok(signing_hash_response(&eth_transaction::signing_hash(
+        &transaction,
+    )))
+}
+
+fn signing_hash_response(hash: &[u8; 32]) -> EthSigningHashResponse {
+    EthSigningHashResponse {
+        hash: format!("0x{}", hex::encode(hash)),
+    }
+}
+
+/// Assembles an EIP-1559 Ethereum transaction signed by the caller, ready to be sent.
+///
+/// The backend does not sign: the chain fusion signer signs with the key of the principal that calls it, so only the
+/// caller can obtain a signature by its own key, and the backend holds no authority over the keys of the users.  The
+/// signature must be a signature of the hash of `eth_transaction_signing_hash` by the key of `eth_address_of_caller`,
+/// so the transaction is sent from that address.  The backend checks the signature and encodes the transaction.
+///
+/// # Errors
+/// Errors are enumerated by: `EthSignTransactionError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_assemble_signed_transaction(
+    request: EthSignTransactionRequest,
+) -> Result<EthSignTransactionResponse, EthSignTransactionError> {
+    let transaction = eth_transaction::eip1559_transaction(&request.transaction)?;
+    let signature = eth_transaction::decode_signature(&request.signature)
+        .map_err(|_| EthSignTransactionError::InvalidSignature)?;
+    let public_key = eth_public_key(&ic_cdk::caller())
+        .await
+        .map_err(|msg| EthSignTransactionError::InternalError { msg })?;
+    let signed_transaction =
+        eth_transaction::signed_transaction(&transaction, &signature, &public_key)
+            .map_err(|_| EthSignTransactionError::InvalidSignature)?;
+    Ok(EthSignTransactionResponse { signed_transaction })
+}
+
+/// Returns the hash of a message that the caller signs as `personal_sign` does (EIP-191).
+///
+/// As with `eth_transaction_signing_hash`, the caller signs the hash with the chain fusion signer and passes the
+/// signature to `eth_assemble_personal_sign`.
+///
+/// # Arguments
+/// * `message` - The message, as in `personal_sign`: hex encoded bytes with a `0x` prefix, or else UTF-8 text.
+#[query(guard = "may_read_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+#[must_use]
+pub fn eth_personal_sign_hash(message: String) -> EthSigningHashResponse {
+    signing_hash_response(&eip191::digest(&eip191::personal_message(&message)))
+}
+
+/// Returns the signature of a message by the caller in the format of `personal_sign` (EIP-191).
+///
+/// The backend does not sign, as with `eth_assemble_signed_transaction`: the caller signs the hash of
+/// `eth_personal_sign_hash` with the chain fusion signer, by the key of `eth_address_of_caller`.  The backend checks
+/// the signature and adds the recovery id.
+///
+/// # Errors
+/// Errors are enumerated by: `EthSignMessageError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_assemble_personal_sign(
+    request: EthPersonalSignRequest,
+) -> Result<EthSignMessageResponse, EthSignMessageError> {
+    let digest = eip191::digest(&eip191::personal_message(&request.message));
+    eth_message_signature(&digest, &request.signature).await
+}
+
+/// Returns the hash of typed data that the caller signs as `eth_signTypedData_v4` does (EIP-712).
+///
+/// As with `eth_transaction_signing_hash`, the caller signs the hash with the chain fusion signer and passes the
+/// signature to `eth_assemble_typed_data_signature`.
+///
+/// # Arguments
+/// * `typed_data` - The typed data as JSON, as in `eth_signTypedData_v4`.
+///
+/// # Errors
+/// Errors are enumerated by: `EthSignMessageError`.
+#[query(guard = "may_read_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn eth_sign_typed_data_hash(
+    typed_data: String,
+) -> Result<EthSigningHashResponse, EthSignMessageError> {
+    Ok(signing_hash_response(&typed_data_digest(&typed_data)?))
+}
+
+/// Returns the signature of typed data by the caller in the format of `eth_signTypedData_v4` (EIP-712).
+///
+/// The backend does not sign, as with `eth_assemble_signed_transaction`: the caller signs the hash of
+/// `eth_sign_typed_data_hash` with the chain fusion signer, by the key of `eth_address_of_caller`.  The backend
+/// checks the signature and adds the recovery id.
+///
+/// # Errors
+/// Errors are enumerated by: `EthSignMessageError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_assemble_typed_data_signature(
+    request: EthSignTypedDataRequest,
+) -> Result<EthSignMessageResponse, EthSignMessageError> {
+    let digest = typed_data_digest(&request.typed_data)?;
+    eth_message_signature(&digest, &request.signature).await
+}
+
+fn typed_data_digest(typed_data: &str) -> Result<[u8; 32], EthSignMessageError> {
+    TypedData::from_json(typed_data)
+        .and_then(|typed_data| typed_data.digest())
+        .map_err(|msg| EthSignMessageError::InvalidTypedData { msg })
+}
+
+/// Checks that the signature of a digest is by the caller's Ethereum key, and adds the recovery id.
+async fn eth_message_signature(
+    digest: &[u8; 32],
+    signature: &str,
+) -> Result<EthSignMessageResponse, EthSignMessageError> {
+    let signature = eth_transaction::decode_signature(signature)
+        .map_err(|_| EthSignMessageError::InvalidSignature)?;
+    let public_key = eth_public_key(&ic_cdk::caller())
+        .await
+        .map_err(|msg| EthSignMessageError::InternalError { msg })?;
+    let signature = eth_transaction::message_signature(digest, &signature, &public_key)
+        .map_err(|_| EthSignMessageError::InvalidSignature)?;
+    Ok(EthSignMessageResponse { signature })
+}
+
+/// Adds an Ethereum transaction sent by the caller, so that it is shown in the activity on all devices.
+///
+/// A pending transaction with the same nonce is marked as replaced by the new transaction.
+///
+/// # Errors
+/// Errors are enumerated by: `EthAddPendingTransactionError`.
+#[update(guard = "may_write_user_data")]
+pub fn eth_add_pending_transaction(
+    request: EthAddPendingTransactionRequest,
+) -> Result<(), EthAddPendingTransactionError> {
+    let principal = ic_cdk::caller();
+    let now_ns = time();
+    let chain_id = request.chain_id;
+    let transaction = eth_pending_transaction_model::pending_transaction(request, now_ns)?;
+    with_eth_pending_transactions(|pending_transactions| {
+        pending_transactions.prune_pending_transactions(principal, chain_id, None, now_ns);
+        pending_transactions
+            .add_pending_transaction(principal, chain_id, transaction)
+            .map_err(|msg| EthAddPendingTransactionError::InternalError { msg })
+    })
+}
+
+/// Returns the Ethereum transactions sent by the caller on the given chain.
+///
+/// Transactions are kept for a week after they were created, unless they are pruned earlier.
+#[query(guard = "may_read_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+#[must_use]
+pub fn eth_get_pending_transactions(
+    request: EthGetPendingTransactionsRequest,
+) -> EthGetPendingTransactionsResponse {
+    let transactions = with_eth_pending_transactions(|pending_transactions| {
+        pending_transactions.get_pending_transactions(&ic_cdk::caller(), request.chain_id, time())
+    });
+    EthGetPendingTransactionsResponse { transactions }
+}
+
+/// Prunes the Ethereum transactions of the caller on the given chain that the chain has caught up with, and those
+/// past retention.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn eth_prune_pending_transactions(request: EthPrunePendingTransactionsRequest) {
+    let principal = ic_cdk::caller();
+    let now_ns = time();
+    with_eth_pending_transactions(|pending_transactions| {
+        pending_transactions.prune_pending_transactions(
+            principal,
+            request.chain_id,
+            request.on_chain_nonce,
+            now_ns,
+        );
+    });
+}
+
+/// Reserves the next nonce of the caller's Ethereum transactions on the given chain.
+///
+/// If it is provided, the nonce of the chain replaces the nonce known to the backend.  Otherwise, the backend fetches
+/// it when it is needed, i.e. on the first reservation and when a reservation has expired: the nonce of an expired
+/// reservation is only reserved again if no transaction with that nonce is pending on the chain.
+///
+/// # Errors
+/// Errors are enumerated by: `EthReserveNonceError`.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn eth_reserve_nonce(
+    request: EthReserveNonceRequest,
+) -> Result<EthReserveNonceResponse, EthReserveNonceError> {
+    let principal = ic_cdk::caller();
+    let key = (principal, request.chain_id);
+    let on_chain_nonce = match request.on_chain_nonce {
+        Some(on_chain_nonce) => Some(on_chain_nonce),
+        None if read_state(|s| {
+            s.eth_nonces
+                .get(&key)
+                .map_or(true, |nonces| nonces.needs_reconciliation(time()))
+        }) =>
+        {
+            // Without the nonce of the chain, the expired reservations are held.
+            eth_pending_nonce_of(&principal, request.chain_id)
+                .await
+                .map_err(|msg| eprintln!("Failed to get the nonce of the chain: {msg}"))
+                .ok()
+        }
+        None => None,
+    };
+    let now_ns = time();
+    mutate_state(|s| {
+        let nonces = s.eth_nonces.entry(key).or_default();
+        if let Some(on_chain_nonce) = on_chain_nonce {
+            nonces.reconcile(on_chain_nonce, now_ns);
+        }
+        let (nonce, expires_at) = nonces.reserve(now_ns)?;
+        Ok(EthReserveNonceResponse { nonce, expires_at })
+    })
+}
+
+/// The nonce of the next transaction of a principal on an EVM chain, including the pending transactions.
+async fn eth_pending_nonce_of(principal: &Principal, chain_id: ChainId) -> Result<u64, String> {
+    let address = eth_principal_to_address(principal).await?;
+    let address = parse_evm_address(&address).map_err(|err| format!("{err:?}"))?;
+    EvmRpcClient::new(chain_id)
+        .map_err(|err| format!("{err:?}"))?
+        .get_transaction_count(&address)
+        .await
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Releases a nonce reserved by the caller, e.g. because signing or sending the transaction failed.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn eth_release_nonce(request: EthReleaseNonceRequest) {
+    let key = (ic_cdk::caller(), request.chain_id);
+    mutate_state(|s| {
+        if let Some(nonces) = s.eth_nonces.get_mut(&key) {
+            nonces.release(request.nonce);
+        }
+    });
+}
+
+/// Sets the nonce of the caller's next Ethereum transaction according to the chain, including its pending
+/// transactions, which drops the reservations of the nonces used on the chain.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn eth_reconcile_nonce(request: EthReconcileNonceRequest) {
+    let key = (ic_cdk::caller(), request.chain_id);
+    let now_ns = time();
+    mutate_state(|s| {
+        s.eth_nonces
+            .entry(key)
+            .or_default()
+            .reconcile(request.on_chain_nonce, now_ns);
+    });
+}
+
+/// Counts a request of the caller to the EVM RPC canister.
+///
+/// # Errors
+/// - `TooManyRequests` if the caller has sent too many requests recently.
+fn take_evm_rpc_quota() -> Result<(), EvmRpcError> {
+    let principal = ic_cdk::caller();
+    let now_ns = time();
+    mutate_state(|s| s.evm_rpc_quotas.entry(principal).or_default().take(now_ns))
+}
+
+/// Returns the balance of the native token of an address on an EVM chain, as agreed by several providers.
+///
+/// # Errors
+/// Errors are enumerated by: `EvmRpcError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_get_balance(
+    request: EthGetBalanceRequest,
+) -> Result<EthGetBalanceResponse, EvmRpcError> {
+    let address = parse_evm_address(&request.address)?;
+    take_evm_rpc_quota()?;
+    let balance = EvmRpcClient::new(request.chain_id)?
+        .get_balance(&address)
+        .await?;
+    Ok(EthGetBalanceResponse {
+        balance: eth_transaction::u256_to_nat(balance),
+    })
+}
+
+/// Returns the number of transactions sent from an address on an EVM chain, including the pending ones, i.e. the
+/// nonce of its next transaction.
+///
+/// # Errors
+/// Errors are enumerated by: `EvmRpcError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_get_transaction_count(
+    request: EthGetTransactionCountRequest,
+) -> Result<EthGetTransactionCountResponse, EvmRpcError> {
+    let address = parse_evm_address(&request.address)?;
+    take_evm_rpc_quota()?;
+    let transaction_count = EvmRpcClient::new(request.chain_id)?
+        .get_transaction_count(&address)
+        .await?;
+    Ok(EthGetTransactionCountResponse { transaction_count })
+}
+
+/// Suggests the EIP-1559 fees of a transaction on an EVM chain, based on the fees paid in recent blocks.
+///
+/// The suggestion for a chain is reused for about a block, so that it is only computed once for all the callers.
+///
+/// # Errors
+/// Errors are enumerated by: `EvmRpcError`.
+#[update(guard = "may_read_user_data")]
+pub async fn eth_estimate_fees(chain_id: ChainId) -> Result<EthEstimateFeesResponse, EvmRpcError> {
+    let cached = read_state(|s| {
+        s.eth_fee_estimates
+            .get(&chain_id)
+            .filter(|(computed_at_ns, _)| {
+                time().saturating_sub(*computed_at_ns) < eth_fees::FEE_ESTIMATE_TTL_NS
+            })
+            .map(|(_, fees)| fees.clone())
+    });
+    if let Some(fees) = cached {
+        return Ok(fees);
+    }
+    let client = EvmRpcClient::new(chain_id)?;
+    take_evm_rpc_quota()?;
+    let history = client
+        .fee_history(
+            eth_fees::FEE_HISTORY_BLOCKS,
+            &[eth_fees::PRIORITY_FEE_PERCENTILE],
+        )
+        .await?;
+    let fees = eth_fees::suggest_fees(&history)?;
+    let now_ns = time();
+    mutate_state(|s| s.eth_fee_estimates.insert(chain_id, (now_ns, fees.clone())));
+    Ok(fees)
+}
+
+fn parse_evm_address(address: &str) -> Result<H160, EvmRpcError> {
+    address.parse().map_err(|_| EvmRpcError::InvalidAddress {
+        address: address.to_string(),
+    })
+}
+
 const MIN_CONFIRMATIONS_ACCEPTED_BTC_TX: u32 = 6;
 
+/// Returns the caller's Bitcoin address of the requested type.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcGetAddressError`.
+#[update(guard = "may_read_user_data")]
+pub async fn btc_get_address(
+    params: BtcGetAddressRequest,
+) -> Result<BtcGetAddressResponse, BtcGetAddressError> {
+    let address = btc_principal_to_address(
+        params.network,
+        &ic_cdk::caller(),
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcGetAddressError::InternalError { msg })?;
+    Ok(BtcGetAddressResponse { address })
+}
+
+/// Returns the balance of the caller's Bitcoin address of the requested type.
+///
+/// The Bitcoin API only knows about transactions in blocks, so the unconfirmed incoming amount is
+/// the value of the UTXOs that do not have the requested number of confirmations yet.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcGetBalanceError`.
+#[update(guard = "may_read_user_data")]
+pub async fn btc_get_balance(
+    params: BtcGetBalanceRequest,
+) -> Result<BtcGetBalanceResponse, BtcGetBalanceError> {
+    let principal = ic_cdk::caller();
+    let address = btc_principal_to_address(
+        params.network,
+        &principal,
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcGetBalanceError::InternalError { msg })?;
+    let current = bitcoin_api::get_address_utxos(params.network, address.clone(), Some(0))
+        .await
+        .map_err(|msg| BtcGetBalanceError::InternalError { msg })?;
+    let now_ns = time();
+
+    let locked_utxos = with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &address,
+            &current.utxos,
+            current.tip_height,
+            now_ns,
+        );
+        pending_transactions.get_locked_utxos(&principal, &address, now_ns)
+    });
+
+    Ok(bitcoin_utils::utxo_balance(
+        &current.utxos,
+        &locked_utxos,
+        current.tip_height,
+        params
+            .min_confirmations
+            .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
+    ))
+}
+
 /// Selects the user's UTXOs and calculates the fee for a Bitcoin transaction.
 ///
 /// # Errors
@@ -351,61 +1062,641 @@ pub async fn btc_select_user_utxos_fee(
     params: SelectedUtxosFeeRequest,
 ) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError> {
     let principal = ic_cdk::caller();
-    let source_address = btc_principal_to_p2wpkh_address(params.network, &principal)
-        .await
-        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
-    let all_utxos = bitcoin_api::get_all_utxos(
+    let source_address = btc_principal_to_address(
         params.network,
-        source_address.clone(),
-        Some(
-            params
-                .min_confirmations
-                .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-        ),
+        &principal,
+        params.address_type.unwrap_or_default(),
     )
     .await
     .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
+    btc_select_utxos(principal, &params, &source_address, None).await
+}
+
+/// Selects UTXOs of the source address and calculates the fee, paying the change to `change`.
+///
+/// The change goes back to the source address if no other change address is given.
+async fn btc_select_utxos(
+    principal: Principal,
+    params: &SelectedUtxosFeeRequest,
+    source_address: &str,
+    change: Option<&Address>,
+) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError> {
+    // The pending transactions are refreshed from all utxos, only the selection needs `min_confirmations`.
+    let AddressUtxos {
+        utxos: current_utxos,
+        tip_height,
+    } = bitcoin_api::get_address_utxos(params.network, source_address.to_string(), Some(0))
+        .await
+        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
     let now_ns = time();
 
-    let has_pending_transactions = with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &all_utxos, now_ns);
-        !pending_transactions
-            .get_pending_transactions(&principal, &source_address)
-            .is_empty()
-    });
+    let (has_pending_transactions, locked_utxos) =
+        with_btc_pending_transactions(|pending_transactions| {
+            pending_transactions.refresh_pending_transactions(
+                principal,
+                source_address,
+                &current_utxos,
+                tip_height,
+                now_ns,
+            );
+            (
+                pending_transactions.has_pending_transactions(&principal, source_address, now_ns),
+                pending_transactions.get_locked_utxos(&principal, source_address, now_ns),
+            )
+        });
+    let min_confirmations = params
+        .min_confirmations
+        .unwrap_or(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX);
+    let all_utxos: Vec<Utxo> = current_utxos
+        .into_iter()
+        .filter(|utxo| bitcoin_utils::has_confirmations(utxo, tip_height, min_confirmations))
+        .collect();
+
+    let frozen_utxos = btc_frozen_utxos(principal);
+    // UTXOs chosen by the user are spent as they are, even while other transactions are pending.
+    let chosen_utxos = coin_selection::chosen_utxos(
+        params.outpoints.as_deref(),
+        &all_utxos,
+        &locked_utxos,
+        &frozen_utxos,
+    )?;
+
+    let fee_millisatoshi_per_vbyte = btc_fee_rate(params).await?;
+    // The inputs spend from the source address, which also receives the change by default.
+    let source = bitcoin_utils::parse_address(source_address, params.network)
+        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
+    let change = change.unwrap_or(&source);
+
+    let available_utxos = btc_frozen_utxo::spendable_utxos(all_utxos, &locked_utxos, &frozen_utxos);
 
-    if has_pending_transactions {
+    if params.sweep.unwrap_or(false) {
+        let sweep_utxos = chosen_utxos.unwrap_or(available_utxos);
+        return btc_sweep_utxos(params, sweep_utxos, fee_millisatoshi_per_vbyte, &source);
+    }
+
+    if has_pending_transactions && chosen_utxos.is_none() {
         return Err(SelectedUtxosFeeError::PendingTransactions);
     }
 
-    let median_fee_millisatoshi_per_vbyte = bitcoin_api::get_fee_per_byte(params.network)
-        .await
-        .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
-    // We support sending to one destination only.
-    // Therefore, the outputs are the destination and the source address for the change.
-    let output_count = 2;
-    let mut available_utxos = all_utxos.clone();
-    let selected_utxos =
-        bitcoin_utils::utxos_selection(params.amount_satoshis, &mut available_utxos, output_count);
-
-    // Fee calculation might still take into account default tx size and expected output.
-    // But if there are no selcted utxos, no tx is possible. Therefore, no fee should be present.
-    if selected_utxos.is_empty() {
+    let change_script = change.script_pubkey();
+    let output_script_lens = bitcoin_utils::output_script_lens(
+        params.outputs.as_deref(),
+        params.amount_satoshis,
+        &source,
+        params.network,
+    )?;
+    let target = SelectionTarget {
+        amount_satoshis: params.amount_satoshis,
+        fee_millisatoshi_per_vbyte,
+        input: bitcoin_utils::input_weight_prediction(&source),
+        output_script_lens: &output_script_lens,
+        change_script_len: change_script.len(),
+        change_dust_limit: change_script.minimal_non_dust().to_sat(),
+    };
+    let selection = coin_selection::select(
+        chosen_utxos,
+        available_utxos,
+        params.coin_selection.unwrap_or_default(),
+        target,
+        now_ns,
+    );
+
+    // If there are no selected utxos, no tx is possible. Therefore, no fee should be present.
+    let Some(CoinSelection {
+        utxos: selected_utxos,
+        fee_satoshis,
+        change_satoshis,
+    }) = selection
+    else {
         return Ok(SelectedUtxosFeeResponse {
-            utxos: selected_utxos,
+            utxos: vec![],
             fee_satoshis: 0,
+            amount_satoshis: 0,
+            change_satoshis: 0,
+            outputs: vec![],
         });
-    }
+    };
 
-    let fee_satoshis = estimate_fee(
-        selected_utxos.len() as u64,
-        median_fee_millisatoshi_per_vbyte,
-        output_count as u64,
-    );
+    let mut outputs = params.outputs.clone().unwrap_or_default();
+    if change_satoshis > 0 {
+        outputs.push(BtcTxOutput {
+            destination_address: change.to_string(),
+            sent_satoshis: change_satoshis,
+        });
+    }
 
     Ok(SelectedUtxosFeeResponse {
         utxos: selected_utxos,
         fee_satoshis,
+        amount_satoshis: params.amount_satoshis,
+        change_satoshis,
+        outputs,
+    })
+}
+
+/// Builds an unsigned transaction from the caller's UTXOs to the given outputs.
+///
+/// The spent UTXOs are locked by a pending transaction until the transaction is confirmed or expires.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcBuildTransactionError`.
+#[update(guard = "may_write_user_data")]
+pub async fn btc_build_transaction(
+    params: BtcBuildTransactionRequest,
+) -> Result<BtcBuildTransactionResponse, BtcBuildTransactionError> {
+    let principal = ic_cdk::caller();
+    let change = params
+        .change_address
+        .as_ref()
+        .map(|address| {
+            bitcoin_utils::parse_address(address, params.network).map_err(|_| {
+                BtcBuildTransactionError::InvalidChangeAddress {
+                    address: address.clone(),
+                }
+            })
+        })
+        .transpose()?;
+    let source_address = btc_principal_to_address(
+        params.network,
+        &principal,
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcBuildTransactionError::InternalError { msg })?;
+    let selection_params = SelectedUtxosFeeRequest {
+        amount_satoshis: params
+            .outputs
+            .iter()
+            .try_fold(0_u64, |sum, output| sum.checked_add(output.sent_satoshis))
+            .ok_or(BtcBuildTransactionError::SelectUtxos(
+                SelectedUtxosFeeError::InvalidOutputs {
+                    msg: "The outputs overflow".to_string(),
+                },
+            ))?,
+        network: params.network,
+        min_confirmations: params.min_confirmations,
+        fee_rate_millisatoshi_per_vbyte: params.fee_rate_millisatoshi_per_vbyte,
+        fee_tier: params.fee_tier,
+        outputs: Some(params.outputs),
+        coin_selection: params.coin_selection,
+        sweep: None,
+        address_type: params.address_type,
+        outpoints: params.outpoints,
+    };
+    let selection = btc_select_utxos(
+        principal,
+        &selection_params,
+        &source_address,
+        change.as_ref(),
+    )
+    .await
+    .map_err(BtcBuildTransactionError::SelectUtxos)?;
+    // The UTXOs are locked for a day, so they must pay for the outputs and the reported fee.
+    if selection.utxos.is_empty() || !bitcoin_utils::is_funded(&selection) {
+        return Err(BtcBuildTransactionError::InsufficientFunds);
+    }
+
+    let source = bitcoin_utils::parse_address(&source_address, params.network)
+        .map_err(|msg| BtcBuildTransactionError::InternalError { msg })?;
+    let psbt = bitcoin_utils::build_psbt(
+        &selection.utxos,
+        &source,
+        &selection.outputs,
+        params.network,
+    )
+    .map_err(|msg| BtcBuildTransactionError::InternalError { msg })?;
+    let txid = psbt.unsigned_tx.compute_txid().to_byte_array().to_vec();
+    let unsigned_tx = bitcoin::consensus::serialize(&psbt.unsigned_tx);
+
+    with_btc_pending_transactions(|pending_transactions| {
+        // Another call may have locked some of the UTXOs while the fee rate was fetched.
+        let locked_utxos =
+            pending_transactions.get_locked_utxos(&principal, &source_address, time());
+        if selection
+            .utxos
+            .iter()
+            .any(|utxo| bitcoin_utils::is_locked(&locked_utxos, utxo))
+        {
+            return Err(BtcBuildTransactionError::SelectUtxos(
+                SelectedUtxosFeeError::PendingTransactions,
+            ));
+        }
+        pending_transactions
+            .add_pending_transaction(
+                principal,
+                &source_address,
+                StoredPendingTransaction {
+                    txid: txid.clone(),
+                    utxos: selection.utxos.clone(),
+                    created_at_timestamp_ns: time(),
+                    transaction: Some(unsigned_tx.clone()),
+                    change_output_index: bitcoin_utils::change_output_index(&selection),
+                    replaced_by: None,
+                    confirmed_at_height: None,
+                },
+            )
+            .map_err(|msg| BtcBuildTransactionError::InternalError { msg })
+    })?;
+
+    Ok(BtcBuildTransactionResponse {
+        unsigned_tx,
+        psbt: psbt.serialize(),
+        txid,
+        utxos: selection.utxos,
+        fee_satoshis: selection.fee_satoshis,
+        change_satoshis: selection.change_satoshis,
+        outputs: selection.outputs,
+    })
+}
+
+/// Builds an unsigned transaction that replaces a pending transaction of the caller with a higher fee (BIP-125).
+///
+/// The replacement spends the same UTXOs and pays the same destinations.  It also pays for the pending descendants
+/// of the original, such as a CPFP child, which it evicts.  It is tracked as a pending transaction
+/// and the original is marked as replaced.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcBumpFeeError`.
+#[update(guard = "may_write_user_data")]
+pub async fn btc_bump_fee(
+    params: BtcBumpFeeRequest,
+) -> Result<BtcBuildTransactionResponse, BtcBumpFeeError> {
+    let principal = ic_cdk::caller();
+    let source_address = btc_principal_to_address(
+        params.network,
+        &principal,
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let current = bitcoin_api::get_address_utxos(params.network, source_address.clone(), None)
+        .await
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let now_ns = time();
+
+    let (original, descendants_fee) = with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &source_address,
+            &current.utxos,
+            current.tip_height,
+            now_ns,
+        );
+        let transactions =
+            pending_transactions.get_pending_transactions(&principal, &source_address);
+        (
+            transactions
+                .iter()
+                .find(|pending_transaction| pending_transaction.txid == params.txid)
+                .cloned(),
+            fee_bump::descendants_fee(&params.txid, &transactions, now_ns),
+        )
+    });
+    let original = original.ok_or(BtcBumpFeeError::TransactionNotFound)?;
+    match (original.status(now_ns), original.replaced_by.clone()) {
+        (BtcPendingTransactionStatus::Replaced, Some(replaced_by)) => {
+            return Err(BtcBumpFeeError::AlreadyReplaced { replaced_by });
+        }
+        (BtcPendingTransactionStatus::Pending, _) => {}
+        // Confirmed and expired transactions cannot be replaced.
+        _ => return Err(BtcBumpFeeError::TransactionNotFound),
+    }
+    let original_tx = original
+        .transaction
+        .as_deref()
+        .map(bitcoin_utils::decode_transaction)
+        .ok_or(BtcBumpFeeError::TransactionNotFound)?
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+
+    let source = bitcoin_utils::parse_address(&source_address, params.network)
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let bump = fee_bump::bump_fee(
+        &original_tx,
+        &original.utxos,
+        &source,
+        original.change_output_index,
+        descendants_fee.map_err(|msg| BtcBumpFeeError::InternalError { msg })?,
+        params.fee_rate_millisatoshi_per_vbyte,
+    )?;
+    let outputs = bitcoin_utils::tx_outputs(&bump.tx, params.network)
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+    let txid = bump.tx.compute_txid().to_byte_array().to_vec();
+    let unsigned_tx = bitcoin::consensus::serialize(&bump.tx);
+    let psbt = bitcoin_utils::psbt_from_unsigned_tx(bump.tx, &bump.utxos, &source)
+        .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.replace_pending_transaction(
+            principal,
+            &source_address,
+            &params.txid,
+            StoredPendingTransaction {
+                txid: txid.clone(),
+                utxos: original.utxos,
+                created_at_timestamp_ns: now_ns,
+                transaction: Some(unsigned_tx.clone()),
+                change_output_index: bump.change_output_index,
+                replaced_by: None,
+                confirmed_at_height: None,
+            },
+        )
+    })
+    .map_err(|msg| BtcBumpFeeError::InternalError { msg })?;
+
+    Ok(BtcBuildTransactionResponse {
+        psbt: psbt.serialize(),
+        unsigned_tx,
+        txid,
+        utxos: bump.utxos,
+        fee_satoshis: bump.fee_satoshis,
+        change_satoshis: bump.change_satoshis,
+        outputs,
+    })
+}
+
+/// Builds an unsigned child transaction that accelerates a pending transaction of the caller, for example a payment
+/// that is stuck, by paying a fee for both (CPFP).
+///
+/// The child spends the largest output of the parent to the caller's address.  It is tracked as a pending transaction.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcCpfpError`.
+#[update(guard = "may_write_user_data")]
+pub async fn btc_accelerate_with_cpfp(
+    params: BtcCpfpRequest,
+) -> Result<BtcBuildTransactionResponse, BtcCpfpError> {
+    let principal = ic_cdk::caller();
+    let source_address = btc_principal_to_address(
+        params.network,
+        &principal,
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcCpfpError::InternalError { msg })?;
+    let source = bitcoin_utils::parse_address(&source_address, params.network)
+        .map_err(|msg| BtcCpfpError::InternalError { msg })?;
+    let destination = match &params.destination_address {
+        Some(address) => bitcoin_utils::parse_address(address, params.network).map_err(|_| {
+            BtcCpfpError::InvalidDestinationAddress {
+                address: address.clone(),
+            }
+        })?,
+        None => source.clone(),
+    };
+    let AddressUtxos {
+        utxos: current_utxos,
+        tip_height,
+    } = bitcoin_api::get_address_utxos(params.network, source_address.clone(), None)
+        .await
+        .map_err(|msg| BtcCpfpError::InternalError { msg })?;
+    let now_ns = time();
+
+    let (pending_parent, locked_utxos) = with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &source_address,
+            &current_utxos,
+            tip_height,
+            now_ns,
+        );
+        (
+            pending_transactions
+                .get_pending_transactions(&principal, &source_address)
+                .into_iter()
+                .find(|pending_transaction| {
+                    pending_transaction.txid == params.parent_txid
+                        && pending_transaction.status(now_ns)
+                            == BtcPendingTransactionStatus::Pending
+                }),
+            pending_transactions.get_locked_utxos(&principal, &source_address, now_ns),
+        )
+    });
+    let (parent_tx, parent) = fee_bump::cpfp_parent(pending_parent, params.parent, &source)?;
+    let frozen_utxos = btc_frozen_utxos(principal);
+    let utxo = fee_bump::parent_utxos(&parent_tx, &source)
+        .into_iter()
+        .filter(|utxo| {
+            !bitcoin_utils::is_locked(&locked_utxos, utxo)
+                && !btc_frozen_utxo::is_frozen(&frozen_utxos, &utxo.outpoint)
+        })
+        .max_by_key(|utxo| utxo.value)
+        .ok_or(BtcCpfpError::UtxoNotFound)?;
+    let child = fee_bump::cpfp_child(
+        utxo,
+        parent,
+        &source,
+        &destination,
+        params.fee_rate_millisatoshi_per_vbyte,
+    )?;
+    let psbt = bitcoin_utils::build_psbt(&child.utxos, &source, &child.outputs, params.network)
+        .map_err(|msg| BtcCpfpError::InternalError { msg })?;
+    let txid = psbt.unsigned_tx.compute_txid().to_byte_array().to_vec();
+    let unsigned_tx = bitcoin::consensus::serialize(&psbt.unsigned_tx);
+
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.add_pending_transaction(
+            principal,
+            &source_address,
+            StoredPendingTransaction {
+                txid: txid.clone(),
+                utxos: child.utxos.clone(),
+                created_at_timestamp_ns: now_ns,
+                transaction: Some(unsigned_tx.clone()),
+                // The child sends everything to the destination.
+                change_output_index: None,
+                replaced_by: None,
+                confirmed_at_height: None,
+            },
+        )
+    })
+    .map_err(|msg| BtcCpfpError::InternalError { msg })?;
+
+    Ok(BtcBuildTransactionResponse {
+        psbt: psbt.serialize(),
+        unsigned_tx,
+        txid,
+        utxos: child.utxos,
+        fee_satoshis: child.fee_satoshis,
+        change_satoshis: 0,
+        outputs: child.outputs,
+    })
+}
+
+/// Verifies that a signed transaction spends the caller's UTXOs, sends it to the Bitcoin network
+/// and tracks it as a pending transaction.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcSubmitTransactionError`.
+#[update(guard = "may_write_user_data")]
+pub async fn btc_submit_transaction(
+    params: BtcSubmitTransactionRequest,
+) -> Result<BtcSubmitTransactionResponse, BtcSubmitTransactionError> {
+    let principal = ic_cdk::caller();
+    let tx = bitcoin_utils::decode_transaction(&params.raw_tx)
+        .map_err(|msg| BtcSubmitTransactionError::InvalidTransaction { msg })?;
+    let source_address = btc_principal_to_address(
+        params.network,
+        &principal,
+        params.address_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|msg| BtcSubmitTransactionError::InternalError { msg })?;
+    // Every input must spend an output of the caller's address that has not been spent yet.
+    let AddressUtxos {
+        utxos: current_utxos,
+        tip_height,
+    } = bitcoin_api::get_address_utxos(params.network, source_address.clone(), None)
+        .await
+        .map_err(|msg| BtcSubmitTransactionError::InternalError { msg })?;
+    let spent_utxos = bitcoin_utils::spent_utxos(&tx, &current_utxos).map_err(|outpoint| {
+        BtcSubmitTransactionError::ForeignInput {
+            txid: outpoint.txid.to_byte_array().to_vec(),
+            vout: outpoint.vout,
+        }
+    })?;
+
+    bitcoin_api::send_transaction(params.network, params.raw_tx.clone())
+        .await
+        .map_err(|msg| BtcSubmitTransactionError::SendFailed { msg })?;
+
+    let txid = tx.compute_txid().to_byte_array().to_vec();
+    let now_ns = time();
+    with_btc_pending_transactions(|pending_transactions| {
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &source_address,
+            &current_utxos,
+            tip_height,
+            now_ns,
+        );
+        // A transaction built by `btc_build_transaction` is already pending.
+        if pending_transactions
+            .get_pending_transactions(&principal, &source_address)
+            .iter()
+            .any(|pending_transaction| pending_transaction.txid == txid)
+        {
+            return Ok(());
+        }
+        pending_transactions.add_pending_transaction(
+            principal,
+            &source_address,
+            StoredPendingTransaction {
+                txid: txid.clone(),
+                utxos: spent_utxos,
+                created_at_timestamp_ns: now_ns,
+                transaction: Some(params.raw_tx),
+                // The change of a transaction built elsewhere is not known.
+                change_output_index: None,
+                replaced_by: None,
+                confirmed_at_height: None,
+            },
+        )
+    })
+    .map_err(|msg| BtcSubmitTransactionError::InternalError { msg })?;
+
+    Ok(BtcSubmitTransactionResponse { txid })
+}
+
+/// Selects all spendable UTXOs for a transaction to one destination without change.
+///
+/// # Errors
+/// - Returns `Err` if there is more than one destination or the destination is invalid.
+/// - Returns `Err` if the UTXOs cannot pay for the fee and an amount above the dust limit.
+fn btc_sweep_utxos(
+    params: &SelectedUtxosFeeRequest,
+    mut available_utxos: Vec<Utxo>,
+    fee_millisatoshi_per_vbyte: u64,
+    source: &Address,
+) -> Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError> {
+    let destination_script = match params.outputs.as_deref() {
+        None => source.script_pubkey(),
+        Some([output]) => bitcoin_utils::parse_address(&output.destination_address, params.network)
+            .map_err(|_| SelectedUtxosFeeError::InvalidDestinationAddress {
+                address: output.destination_address.clone(),
+            })?
+            .script_pubkey(),
+        Some(_) => {
+            return Err(SelectedUtxosFeeError::InvalidOutputs {
+                msg: "A sweep has exactly one destination".to_string(),
+            })
+        }
+    };
+    let (selection, amount_satoshis) = coin_selection::select_all(
+        &mut available_utxos,
+        fee_millisatoshi_per_vbyte,
+        bitcoin_utils::input_weight_prediction(source),
+        &[destination_script.len()],
+        destination_script.minimal_non_dust().to_sat(),
+    )
+    .ok_or(SelectedUtxosFeeError::InsufficientFunds)?;
+
+    Ok(SelectedUtxosFeeResponse {
+        utxos: selection.utxos,
+        fee_satoshis: selection.fee_satoshis,
+        amount_satoshis,
+        change_satoshis: 0,
+        outputs: params
+            .outputs
+            .iter()
+            .flatten()
+            .map(|output| BtcTxOutput {
+                destination_address: output.destination_address.clone(),
+                sent_satoshis: amount_satoshis,
+            })
+            .collect(),
+    })
+}
+
+/// Reads the network fee percentiles used for the Bitcoin fee tiers.
+fn read_btc_fee_tier_percentiles() -> BtcFeeTierPercentiles {
+    read_config(|config| config.btc_fee_tier_percentiles.unwrap_or_default())
+}
+
+/// The fee rate requested by the user, either given explicitly or by fee tier.
+async fn btc_fee_rate(params: &SelectedUtxosFeeRequest) -> Result<u64, SelectedUtxosFeeError> {
+    match (params.fee_rate_millisatoshi_per_vbyte, params.fee_tier) {
+        (Some(_), Some(_)) => Err(SelectedUtxosFeeError::InvalidFeeRequest {
+            msg: "Either a fee rate or a fee tier may be given, not both".to_string(),
+        }),
+        (Some(0), None) => Err(SelectedUtxosFeeError::InvalidFeeRequest {
+            msg: "The fee rate must be positive".to_string(),
+        }),
+        (Some(fee_rate), None) if fee_rate > bitcoin_utils::MAX_FEE_MILLISATOSHI_PER_VBYTE => {
+            Err(SelectedUtxosFeeError::InvalidFeeRequest {
+                msg: format!(
+                    "The fee rate must be at most {} millisatoshi per vbyte",
+                    bitcoin_utils::MAX_FEE_MILLISATOSHI_PER_VBYTE
+                ),
+            })
+        }
+        (Some(fee_rate), None) => Ok(fee_rate),
+        (None, tier) => {
+            let percentile = read_btc_fee_tier_percentiles().percentile(tier.unwrap_or_default());
+            let fee_percentiles = bitcoin_api::get_current_fee_percentiles(params.network)
+                .await
+                .map_err(|msg| SelectedUtxosFeeError::InternalError { msg })?;
+            Ok(bitcoin_utils::fee_rate_at_percentile(
+                &fee_percentiles,
+                percentile,
+            ))
+        }
+    }
+}
+
+/// Returns the current fee rate of each Bitcoin fee tier.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcGetFeeTiersError`.
+#[update(guard = "may_read_user_data")]
+pub async fn btc_get_fee_tiers(
+    params: BtcGetFeeTiersRequest,
+) -> Result<BtcGetFeeTiersResponse, BtcGetFeeTiersError> {
+    let tier_percentiles = read_btc_fee_tier_percentiles();
+    let fee_percentiles = bitcoin_api::get_current_fee_percentiles(params.network)
+        .await
+        .map_err(|msg| BtcGetFeeTiersError::InternalError { msg })?;
+    Ok(BtcGetFeeTiersResponse {
+        tiers: bitcoin_utils::fee_tiers(&fee_percentiles, tier_percentiles),
     })
 }
 
@@ -418,29 +1709,37 @@ pub async fn btc_add_pending_transaction(
     params: BtcAddPendingTransactionRequest,
 ) -> Result<(), BtcAddPendingTransactionError> {
     let principal = ic_cdk::caller();
-    let current_utxos = bitcoin_api::get_all_utxos(
-        params.network,
-        params.address.clone(),
-        Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-    )
-    .await
-    .map_err(|msg| BtcAddPendingTransactionError::InternalError { msg })?;
+    let current = bitcoin_api::get_address_utxos(params.network, params.address.clone(), Some(0))
+        .await
+        .map_err(|msg| BtcAddPendingTransactionError::InternalError { msg })?;
     let now_ns = time();
 
     with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &current_utxos, now_ns);
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &params.address,
+            &current.utxos,
+            current.tip_height,
+            now_ns,
+        );
         let current_pending_transaction = StoredPendingTransaction {
             txid: params.txid,
             utxos: params.utxos,
             created_at_timestamp_ns: now_ns,
+            transaction: None,
+            change_output_index: None,
+            replaced_by: None,
+            confirmed_at_height: None,
         };
         pending_transactions
-            .add_pending_transaction(principal, params.address, current_pending_transaction)
+            .add_pending_transaction(principal, &params.address, current_pending_transaction)
             .map_err(|msg| BtcAddPendingTransactionError::InternalError { msg })
     })
 }
 
-/// Returns the pending Bitcoin transactions for the caller.
+/// Returns the Bitcoin transactions sent by the caller from the given address, with their status.
+///
+/// Transactions are kept for a week after they were created, whether they are confirmed, replaced or expired.
 ///
 /// # Errors
 /// Errors are enumerated by: `BtcGetPendingTransactionsError`.
@@ -451,26 +1750,28 @@ pub async fn btc_get_pending_transactions(
     let principal = ic_cdk::caller();
     let now_ns = time();
 
-    let current_utxos = bitcoin_api::get_all_utxos(
-        params.network,
-        params.address.clone(),
-        Some(MIN_CONFIRMATIONS_ACCEPTED_BTC_TX),
-    )
-    .await
-    .map_err(|msg| BtcGetPendingTransactionsError::InternalError { msg })?;
+    let current = bitcoin_api::get_address_utxos(params.network, params.address.clone(), Some(0))
+        .await
+        .map_err(|msg| BtcGetPendingTransactionsError::InternalError { msg })?;
 
     let stored_transactions = with_btc_pending_transactions(|pending_transactions| {
-        pending_transactions.prune_pending_transactions(principal, &current_utxos, now_ns);
-        pending_transactions
-            .get_pending_transactions(&principal, &params.address)
-            .clone()
+        pending_transactions.refresh_pending_transactions(
+            principal,
+            &params.address,
+            &current.utxos,
+            current.tip_height,
+            now_ns,
+        );
+        pending_transactions.get_pending_transactions(&principal, &params.address)
     });
 
     let pending_transactions = stored_transactions
-        .iter()
+        .into_iter()
         .map(|tx| PendingTransaction {
-            txid: tx.txid.clone(),
-            utxos: tx.utxos.clone(),
+            status: tx.status(now_ns),
+            confirmations: tx.confirmations(current.tip_height),
+            txid: tx.txid,
+            utxos: tx.utxos,
         })
         .collect();
 
@@ -479,6 +1780,60 @@ pub async fn btc_get_pending_transactions(
     })
 }
 
+/// The UTXOs frozen by the user.
+fn btc_frozen_utxos(principal: Principal) -> Vec<BtcFrozenUtxo> {
+    read_state(|s| {
+        s.btc_frozen_utxo
+            .get(&StoredPrincipal(principal))
+            .unwrap_or_default()
+            .0
+    })
+}
+
+/// Freezes UTXOs of the caller, so that they are never selected to fund a transaction.
+///
+/// Freezing a UTXO that is already frozen replaces its label.
+///
+/// # Errors
+/// Errors are enumerated by: `BtcFreezeUtxosError`.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn btc_freeze_utxos(params: BtcFreezeUtxosRequest) -> Result<(), BtcFreezeUtxosError> {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    mutate_state(|s| {
+        let Candid(mut frozen_utxos) = s.btc_frozen_utxo.get(&stored_principal).unwrap_or_default();
+        btc_frozen_utxo::freeze(&mut frozen_utxos, params.utxos)?;
+        s.btc_frozen_utxo
+            .insert(stored_principal, Candid(frozen_utxos));
+        Ok(())
+    })
+}
+
+/// Unfreezes UTXOs of the caller.  Outpoints that are not frozen are ignored.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn btc_unfreeze_utxos(params: BtcUnfreezeUtxosRequest) {
+    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    mutate_state(|s| {
+        if let Some(Candid(mut frozen_utxos)) = s.btc_frozen_utxo.get(&stored_principal) {
+            btc_frozen_utxo::unfreeze(&mut frozen_utxos, &params.outpoints);
+            if frozen_utxos.is_empty() {
+                s.btc_frozen_utxo.remove(&stored_principal);
+            } else {
+                s.btc_frozen_utxo
+                    .insert(stored_principal, Candid(frozen_utxos));
+            }
+        }
+    });
+}
+
+/// Lists the UTXOs frozen by the caller.
+#[query(guard = "may_read_user_data")]
+#[must_use]
+pub fn btc_list_frozen_utxos() -> Vec<BtcFrozenUtxo> {
+    btc_frozen_utxos(ic_cdk::caller())
+}
+
 /// Adds a verifiable credential to the user profile.
 ///
 /// # Errors
@@ -489,12 +1844,13 @@ pub fn add_user_credential(
     request: AddUserCredentialRequest,
 ) -> Result<(), AddUserCredentialError> {
     let user_principal = ic_cdk::caller();
-    let stored_principal = StoredPrincipal(user_principal);
+    let stored_principal = caller_primary_principal();
     let current_time_ns = u128::from(time());
 
     let (vc_flow_signers, root_pk_raw, credential_type, derivation_origin) =
         read_config(|config| find_credential_config(&request, config))
             .ok_or(AddUserCredentialError::ConfigurationError)?;
+    let history_config = read_user_profile_history_config();
 
     match validate_ii_presentation_and_claims(
         &request.credential_jwt,
@@ -506,8 +1862,12 @@ pub fn add_user_credential(
         current_time_ns,
     ) {
         Ok(()) => mutate_state(|s| {
-            let mut user_profile_model =
-                UserProfileModel::new(&mut s.user_profile, &mut s.user_profile_updated);
+            let mut user_profile_model = UserProfileModel::new(
+                &mut s.user_profile,
+                &mut s.user_profile_updated,
+                &mut s.user_profile_history,
+                history_config,
+            );
             add_credential(
                 stored_principal,
                 request.current_user_version,
@@ -535,12 +1895,16 @@ pub fn add_user_hidden_dapp_id(
     request: AddHiddenDappIdRequest,
 ) -> Result<(), AddDappSettingsError> {
     request.check()?;
-    let user_principal = ic_cdk::caller();
-    let stored_principal = StoredPrincipal(user_principal);
+    let stored_principal = caller_primary_principal();
+    let history_config = read_user_profile_history_config();
 
     mutate_state(|s| {
-        let mut user_profile_model =
-            UserProfileModel::new(&mut s.user_profile, &mut s.user_profile_updated);
+        let mut user_profile_model = UserProfileModel::new(
+            &mut s.user_profile,
+            &mut s.user_profile_updated,
+            &mut s.user_profile_history,
+            history_config,
+        );
         add_hidden_dapp_id(
             stored_principal,
             request.current_user_version,
@@ -555,11 +1919,16 @@ pub fn add_user_hidden_dapp_id(
 #[update(guard = "may_write_user_data")]
 #[must_use]
 pub fn create_user_profile() -> UserProfile {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
+    let history_config = read_user_profile_history_config();
 
     mutate_state(|s| {
-        let mut user_profile_model =
-            UserProfileModel::new(&mut s.user_profile, &mut s.user_profile_updated);
+        let mut user_profile_model = UserProfileModel::new(
+            &mut s.user_profile,
+            &mut s.user_profile_updated,
+            &mut s.user_profile_history,
+            history_config,
+        );
         let stored_user = create_profile(stored_principal, &mut user_profile_model);
         UserProfile::from(&stored_user)
     })
@@ -574,11 +1943,16 @@ pub fn create_user_profile() -> UserProfile {
 /// - If the caller is anonymous.  See: `may_read_user_data`.
 #[query(guard = "may_read_user_data")]
 pub fn get_user_profile() -> Result<UserProfile, GetUserProfileError> {
-    let stored_principal = StoredPrincipal(ic_cdk::caller());
+    let stored_principal = caller_primary_principal();
+    let history_config = read_user_profile_history_config();
 
     mutate_state(|s| {
-        let mut user_profile_model =
-            UserProfileModel::new(&mut s.user_profile, &mut s.user_profile_updated);
+        let mut user_profile_model = UserProfileModel::new(
+            &mut s.user_profile,
+            &mut s.user_profile_updated,
+            &mut s.user_profile_history,
+            history_config,
+        );
         match find_profile(stored_principal, &mut user_profile_model) {
             Ok(stored_user) => Ok(UserProfile::from(&stored_user)),
             Err(err) => Err(err),
@@ -586,6 +1960,175 @@ pub fn get_user_profile() -> Result<UserProfile, GetUserProfileError> {
     })
 }
 
+/// Returns the change history of a user profile, oldest first.
+///
+/// By default this is the history of the caller's own profile.  Allowed callers may also
+/// request the history of any other user.
+///
+/// # Errors
+/// Errors are enumerated by: `GetUserProfileHistoryError`.
+#[query(guard = "may_read_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_user_profile_history(
+    request: GetUserProfileHistoryRequest,
+) -> Result<GetUserProfileHistoryResponse, GetUserProfileHistoryError> {
+    let caller = ic_cdk::caller();
+    let user_principal = match request.principal {
+        Some(principal) if principal != caller => {
+            caller_is_allowed().map_err(|_| GetUserProfileHistoryError::NotAllowed)?;
+            StoredPrincipal(principal)
+        }
+        _ => caller_primary_principal(),
+    };
+    let history_config = read_user_profile_history_config();
+
+    mutate_state(|s| {
+        let user_profile_model = UserProfileModel::new(
+            &mut s.user_profile,
+            &mut s.user_profile_updated,
+            &mut s.user_profile_history,
+            history_config,
+        );
+        Ok(GetUserProfileHistoryResponse {
+            entries: user_profile_model.history(user_principal),
+        })
+    })
+}
+
+/// Records that the caller has accepted the given agreement versions, e.g. of the terms of service.
+///
+/// # Errors
+/// - Returns `Err` if the user profile is not found, the user profile version is not up-to-date,
+///   or an accepted agreement version is not the currently required one.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn accept_agreements(request: AcceptAgreementsRequest) -> Result<(), AcceptAgreementsError> {
+    let stored_principal = caller_primary_principal();
+    let required_agreements = read_required_agreements();
+    let history_config = read_user_profile_history_config();
+
+    mutate_state(|s| {
+        let mut user_profile_model = UserProfileModel::new(
+            &mut s.user_profile,
+            &mut s.user_profile_updated,
+            &mut s.user_profile_history,
+            history_config,
+        );
+        user_profile::accept_agreements(
+            stored_principal,
+            request.current_user_version,
+            &request.agreements,
+            &required_agreements,
+            &mut user_profile_model,
+        )
+    })
+}
+
+/// Invites another principal to join the caller's group of linked principals.
+///
+/// The invited principal has to confirm the link with `confirm_principal_link`.
+///
+/// # Errors
+/// Errors are enumerated by: `PrincipalLinkError`.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn start_principal_link(
+    request: StartPrincipalLinkRequest,
+) -> Result<StartPrincipalLinkResponse, PrincipalLinkError> {
+    let initiator = StoredPrincipal(ic_cdk::caller());
+    let invited = StoredPrincipal(request.principal);
+
+    mutate_state(|s| {
+        let mut principal_link_model = PrincipalLinkModel::new(
+            &mut s.principal_link,
+            &mut s.principal_link_group,
+            &mut s.principal_link_challenge,
+            &mut s.principal_link_initiator_challenge,
+        );
+        let expires_timestamp =
+            principal_link::start_link(initiator, invited, time(), &mut principal_link_model)?;
+        Ok(StartPrincipalLinkResponse { expires_timestamp })
+    })
+}
+
+/// Accepts an invitation to join the group of linked principals of the initiator.
+///
+/// From then on, the caller uses the tokens and settings of the group's primary principal.
+///
+/// # Errors
+/// Errors are enumerated by: `PrincipalLinkError`.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn confirm_principal_link(
+    request: ConfirmPrincipalLinkRequest,
+) -> Result<LinkedPrincipals, PrincipalLinkError> {
+    let invited = StoredPrincipal(ic_cdk::caller());
+    let initiator = StoredPrincipal(request.initiator);
+
+    mutate_state(|s| {
+        let invited_has_data = s.user_token.contains_key(&invited)
+            || s.custom_token.contains_key(&invited)
+            || s.user_profile_updated.contains_key(&invited);
+        let mut principal_link_model = PrincipalLinkModel::new(
+            &mut s.principal_link,
+            &mut s.principal_link_group,
+            &mut s.principal_link_challenge,
+            &mut s.principal_link_initiator_challenge,
+        );
+        principal_link::confirm_link(
+            invited,
+            initiator,
+            invited_has_data,
+            time(),
+            &mut principal_link_model,
+        )
+    })
+}
+
+/// Removes a principal from the caller's group of linked principals.
+///
+/// # Errors
+/// Errors are enumerated by: `PrincipalLinkError`.
+#[update(guard = "may_write_user_data")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn unlink_principal(
+    request: UnlinkPrincipalRequest,
+) -> Result<LinkedPrincipals, PrincipalLinkError> {
+    let caller = ic_cdk::caller();
+    let principal = StoredPrincipal(request.principal.unwrap_or(caller));
+
+    mutate_state(|s| {
+        let mut principal_link_model = PrincipalLinkModel::new(
+            &mut s.principal_link,
+            &mut s.principal_link_group,
+            &mut s.principal_link_challenge,
+            &mut s.principal_link_initiator_challenge,
+        );
+        principal_link::unlink(
+            StoredPrincipal(caller),
+            principal,
+            &mut principal_link_model,
+        )
+    })
+}
+
+/// Lists the principals that are linked to the caller.
+#[query(guard = "may_read_user_data")]
+#[must_use]
+pub fn get_linked_principals() -> LinkedPrincipals {
+    let caller = StoredPrincipal(ic_cdk::caller());
+
+    mutate_state(|s| {
+        let principal_link_model = PrincipalLinkModel::new(
+            &mut s.principal_link,
+            &mut s.principal_link_group,
+            &mut s.principal_link_challenge,
+            &mut s.principal_link_initiator_challenge,
+        );
+        principal_link::linked_principals(caller, &principal_link_model)
+    })
+}
+
 /// An endpoint to be called by users on first login, to enable them to
 /// use the chain fusion signer together with Oisy.
 ///
@@ -607,13 +2150,8 @@ pub async fn allow_signing() -> Result<(), AllowSigningError> {
 pub fn list_users(request: ListUsersRequest) -> ListUsersResponse {
     // WARNING: The value `DEFAULT_LIMIT_LIST_USERS_RESPONSE` must also be determined by the cycles consumption when reading BTreeMap.
 
-    let (users, matches_max_length): (Vec<OisyUser>, u64) =
-        read_state(|s| oisy_users(&request, &s.user_profile));
-
-    ListUsersResponse {
-        users,
-        matches_max_length,
-    }
+    let required_agreements = read_required_agreements();
+    read_state(|s| oisy_users(&request, &s.user_profile, &required_agreements))
 }
 
 /// API method to get cycle balance and burn rate.
@@ -635,6 +2173,32 @@ pub fn set_guards(guards: Guards) {
     mutate_state(|state| modify_state_config(state, |config| config.api = Some(guards)));
 }
 
+/// Publishes a new version of an agreement that users are required to accept.
+///
+/// # Panics
+/// - If the version is lower than the required version, as users who accepted the required version would otherwise
+///   have accepted a newer version than the one that they are asked to accept.
+#[update(guard = "caller_is_allowed")]
+#[allow(clippy::needless_pass_by_value)]
+pub fn set_required_agreement_version(request: SetRequiredAgreementVersionRequest) {
+    if let Some(required) = read_required_agreements().get(&request.agreement) {
+        if request.version < *required {
+            ic_cdk::trap(&format!(
+                "The required version {required} of {:?} cannot be lowered to {}",
+                request.agreement, request.version
+            ));
+        }
+    }
+    mutate_state(|state| {
+        modify_state_config(state, |config| {
+            config
+                .required_agreements
+                .get_or_insert_with(BTreeMap::new)
+                .insert(request.agreement, request.version);
+        });
+    });
+}
+
 /// Gets statistics about the canister.
 ///
 /// Note: This is a private method, restricted to authorized users, as some stats may not be suitable for public consumption.
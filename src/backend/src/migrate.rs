@@ -1,6 +1,9 @@
 use crate::{
-    mutate_state, read_state,
-    types::{Candid, StoredPrincipal},
+    btc_pending_transaction_model::StoredPendingTransaction,
+    mutate_state,
+    principal_link_model::PrincipalLinkModel,
+    read_state,
+    types::{BtcPendingTransactionMap, Candid, StoredBtcAddress, StoredPrincipal, StoredTxid},
 };
 use candid::{decode_one, encode_one, CandidType, Principal};
 use ic_cdk::eprintln;
@@ -9,7 +12,12 @@ use serde::Deserialize;
 use shared::{
     backend_api::Service,
     types::{
-        custom_token::CustomToken, token::UserToken, user_profile::StoredUserProfile,
+        bitcoin::BtcFrozenUtxo,
+        custom_token::CustomToken,
+        ethereum::EthPendingTransaction,
+        principal_link::PrincipalLink,
+        token::{ChainId, UserToken},
+        user_profile::{StoredUserProfile, UserProfileHistoryEntry},
         MigrationError, MigrationProgress, Timestamp,
     },
 };
@@ -20,6 +28,12 @@ use steps::{
 };
 pub mod steps;
 
+/// The pending Bitcoin transactions of a user, per address.
+type BtcAddressPendingTransactions = Vec<(String, Vec<StoredPendingTransaction>)>;
+
+/// The pending Ethereum transactions of a user, per chain.
+type EthChainPendingTransactions = Vec<(ChainId, Vec<EthPendingTransaction>)>;
+
 /// A chunk of data to be migrated.
 ///
 /// Note: Given that the migration moves data types that may be private, data is transferred with candid type `Vec<u8>`
@@ -30,6 +44,11 @@ pub enum MigrationChunk {
     CustomToken(Vec<(Principal, Vec<CustomToken>)>),
     UserProfile(Vec<((Timestamp, Principal), StoredUserProfile)>),
     UserProfileUpdated(Vec<(Principal, Timestamp)>),
+    UserProfileHistory(Vec<((Principal, u64), UserProfileHistoryEntry)>),
+    PrincipalLink(Vec<(Principal, PrincipalLink)>),
+    BtcPendingTransaction(Vec<(Principal, BtcAddressPendingTransactions)>),
+    BtcFrozenUtxo(Vec<(Principal, Vec<BtcFrozenUtxo>)>),
+    EthPendingTransaction(Vec<(Principal, EthChainPendingTransactions)>),
 }
 
 /// Bulk uploads data to this canister.
@@ -74,6 +93,81 @@ pub fn bulk_up(data: &[u8]) {
                 }
             });
         }
+        MigrationChunk::UserProfileHistory(entries) => {
+            mutate_state(|state| {
+                for ((principal, sequence_number), entry) in entries {
+                    state
+                        .user_profile_history
+                        .insert((StoredPrincipal(principal), sequence_number), Candid(entry));
+                }
+            });
+        }
+        MigrationChunk::PrincipalLink(links) => {
+            mutate_state(|state| {
+                let mut principal_link_model = PrincipalLinkModel::new(
+                    &mut state.principal_link,
+                    &mut state.principal_link_group,
+                    &mut state.principal_link_challenge,
+                    &mut state.principal_link_initiator_challenge,
+                );
+                for (principal, link) in links {
+                    principal_link_model.link(
+                        StoredPrincipal(principal),
+                        StoredPrincipal(link.primary),
+                        link.linked_timestamp,
+                    );
+                }
+            });
+        }
+        MigrationChunk::BtcPendingTransaction(pending_transactions) => {
+            mutate_state(|state| {
+                import_btc_pending_transactions(
+                    &mut state.btc_pending_transaction,
+                    pending_transactions,
+                );
+            });
+        }
+        MigrationChunk::BtcFrozenUtxo(frozen_utxos) => {
+            mutate_state(|state| {
+                for (principal, utxos) in frozen_utxos {
+                    state
+                        .btc_frozen_utxo
+                        .insert(StoredPrincipal(principal), Candid(utxos));
+                }
+            });
+        }
+        MigrationChunk::EthPendingTransaction(pending_transactions) => {
+            mutate_state(|state| {
+                for (principal, chains) in pending_transactions {
+                    for (chain_id, transactions) in chains {
+                        state
+                            .eth_pending_transaction
+                            .insert((StoredPrincipal(principal), chain_id), Candid(transactions));
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Stores the pending Bitcoin transactions of a chunk, each under its own key.
+fn import_btc_pending_transactions(
+    map: &mut BtcPendingTransactionMap,
+    pending_transactions: Vec<(Principal, BtcAddressPendingTransactions)>,
+) {
+    for (principal, addresses) in pending_transactions {
+        for (address, transactions) in addresses {
+            for transaction in transactions {
+                map.insert(
+                    (
+                        StoredPrincipal(principal),
+                        StoredBtcAddress(address.clone()),
+                        StoredTxid(transaction.txid.clone()),
+                    ),
+                    Candid(transaction),
+                );
+            }
+        }
     }
 }
 
@@ -153,6 +247,153 @@ fn next_user_timestamp_chunk(user_maybe: Option<Principal>) -> Vec<(Principal, T
     })
 }
 
+/// The next chunk of user profile history entries to be migrated.
+fn next_user_profile_history_chunk(
+    last_entry: Option<(Principal, u64)>,
+) -> Vec<((Principal, u64), UserProfileHistoryEntry)> {
+    let chunk_size = 50;
+    let range = last_entry.map_or(
+        (Bound::Unbounded, Bound::Unbounded),
+        |(principal, sequence_number)| {
+            (
+                Bound::Excluded((StoredPrincipal(principal), sequence_number)),
+                Bound::Unbounded,
+            )
+        },
+    );
+    read_state(|state| {
+        state
+            .user_profile_history
+            .range(range)
+            .take(chunk_size)
+            .map(|((stored_principal, sequence_number), entry)| {
+                ((stored_principal.0, sequence_number), entry.0)
+            })
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The next chunk of principal links to be migrated.
+///
+/// Note: Pending link challenges are short-lived and are not migrated.
+fn next_principal_link_chunk(last_principal: Option<Principal>) -> Vec<(Principal, PrincipalLink)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Excluded(StoredPrincipal(principal)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        state
+            .principal_link
+            .range(range)
+            .take(chunk_size)
+            .map(|(stored_principal, link)| (stored_principal.0, link.0))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The next chunk of pending Bitcoin transactions to be migrated.
+///
+/// Note: The pending transactions of a user are migrated together, whatever the number of their addresses.
+fn next_btc_pending_transaction_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, BtcAddressPendingTransactions)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Included((
+                StoredPrincipal(principal),
+                StoredBtcAddress(String::new()),
+                StoredTxid(Vec::new()),
+            )),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        let mut chunk: Vec<(Principal, BtcAddressPendingTransactions)> = Vec::new();
+        for ((stored_principal, address, _), transaction) in state
+            .btc_pending_transaction
+            .range(range)
+            .skip_while(|((stored_principal, _, _), _)| Some(stored_principal.0) == last_principal)
+        {
+            if let Some((principal, addresses)) = chunk.last_mut() {
+                if *principal == stored_principal.0 {
+                    match addresses.last_mut() {
+                        Some((last_address, transactions)) if *last_address == address.0 => {
+                            transactions.push(transaction.0);
+                        }
+                        _ => addresses.push((address.0, vec![transaction.0])),
+                    }
+                    continue;
+                }
+            }
+            if chunk.len() == chunk_size {
+                break;
+            }
+            chunk.push((stored_principal.0, vec![(address.0, vec![transaction.0])]));
+        }
+        chunk
+    })
+}
+
+/// The next chunk of frozen Bitcoin UTXOs to be migrated.
+fn next_btc_frozen_utxo_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, Vec<BtcFrozenUtxo>)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Excluded(StoredPrincipal(principal)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        state
+            .btc_frozen_utxo
+            .range(range)
+            .take(chunk_size)
+            .map(|(stored_principal, utxos)| (stored_principal.0, utxos.0))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// The next chunk of pending Ethereum transactions to be migrated.
+///
+/// Note: The pending transactions of a user are migrated together, whatever the number of their chains.
+fn next_eth_pending_transaction_chunk(
+    last_principal: Option<Principal>,
+) -> Vec<(Principal, EthChainPendingTransactions)> {
+    let chunk_size = 5;
+    let range = last_principal.map_or((Bound::Unbounded, Bound::Unbounded), |principal| {
+        (
+            Bound::Included((StoredPrincipal(principal), 0)),
+            Bound::Unbounded,
+        )
+    });
+    read_state(|state| {
+        let mut chunk: Vec<(Principal, EthChainPendingTransactions)> = Vec::new();
+        for ((stored_principal, chain_id), transactions) in state
+            .eth_pending_transaction
+            .range(range)
+            .skip_while(|((stored_principal, _), _)| Some(stored_principal.0) == last_principal)
+        {
+            if let Some((principal, chains)) = chunk.last_mut() {
+                if *principal == stored_principal.0 {
+                    chains.push((chain_id, transactions.0));
+                    continue;
+                }
+            }
+            if chunk.len() == chunk_size {
+                break;
+            }
+            chunk.push((stored_principal.0, vec![(chain_id, transactions.0)]));
+        }
+        chunk
+    })
+}
+
 /// Migrates a chunk of data.
 ///
 /// # Returns
@@ -180,6 +421,7 @@ macro_rules! migrate {
 }
 pub(crate) use migrate;
 
+#[allow(clippy::too_many_lines)] // One arm per step of the migration.
 pub async fn step_migration() -> Result<MigrationProgress, MigrationError> {
     fn set_progress(progress: MigrationProgress) {
         mutate_state(|state| {
@@ -224,6 +466,41 @@ pub async fn step_migration() -> Result<MigrationProgress, MigrationError> {
                 let chunk = next_user_profile_chunk(last_user_profile);
                 migrate!(migration, chunk, MigratedUserProfilesUpTo, UserProfile)
             }
+            MigrationProgress::MigratedUserProfileHistoryUpTo(last_entry) => {
+                let chunk = next_user_profile_history_chunk(last_entry);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedUserProfileHistoryUpTo,
+                    UserProfileHistory
+                )
+            }
+            MigrationProgress::MigratedPrincipalLinksUpTo(last_principal) => {
+                let chunk = next_principal_link_chunk(last_principal);
+                migrate!(migration, chunk, MigratedPrincipalLinksUpTo, PrincipalLink)
+            }
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(last_principal) => {
+                let chunk = next_btc_pending_transaction_chunk(last_principal);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedBtcPendingTransactionsUpTo,
+                    BtcPendingTransaction
+                )
+            }
+            MigrationProgress::MigratedBtcFrozenUtxosUpTo(last_principal) => {
+                let chunk = next_btc_frozen_utxo_chunk(last_principal);
+                migrate!(migration, chunk, MigratedBtcFrozenUtxosUpTo, BtcFrozenUtxo)
+            }
+            MigrationProgress::MigratedEthPendingTransactionsUpTo(last_principal) => {
+                let chunk = next_eth_pending_transaction_chunk(last_principal);
+                migrate!(
+                    migration,
+                    chunk,
+                    MigratedEthPendingTransactionsUpTo,
+                    EthPendingTransaction
+                )
+            }
             MigrationProgress::CheckingDataMigration => {
                 assert_target_has_all_data(&migration).await?;
                 migration.progress.next()
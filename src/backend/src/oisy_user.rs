@@ -1,9 +1,10 @@
 use crate::{types::UserProfileMap, StoredPrincipal};
 use candid::Principal;
 use shared::types::{
-    user_profile::{ListUsersRequest, OisyUser},
-    Timestamp,
+    user_profile::{ListUsersRequest, ListUsersResponse, OisyUser, StoredUserProfile},
+    AgreementKind, AgreementVersion, Timestamp,
 };
+use std::collections::BTreeMap;
 use std::ops::Bound;
 
 const DEFAULT_LIMIT_LIST_USERS_RESPONSE: usize = 10_000;
@@ -18,21 +19,189 @@ fn limit_users_size(request: &ListUsersRequest) -> usize {
         .unwrap_or(DEFAULT_LIMIT_LIST_USERS_RESPONSE)
 }
 
+/// The maximum number of users scanned in one request.  This bounds the cost of a request when few users match the
+/// filter.
+const MAX_SCANNED_USERS: usize = 100_000;
+
 pub fn oisy_users(
     request: &ListUsersRequest,
     user_profile_map: &UserProfileMap,
-) -> (Vec<OisyUser>, u64) {
+    required_agreements: &BTreeMap<AgreementKind, AgreementVersion>,
+) -> ListUsersResponse {
+    scan_oisy_users(
+        request,
+        user_profile_map,
+        required_agreements,
+        MAX_SCANNED_USERS,
+    )
+}
+
+/// Lists the matching users among at most `max_scanned` users, in the order in which they were updated.
+///
+/// If users are left unscanned, the response has the last scanned user, after which to continue.  The cursor has the
+/// principal as well as the timestamp, as any number of users may have been updated at the same time.
+fn scan_oisy_users(
+    request: &ListUsersRequest,
+    user_profile_map: &UserProfileMap,
+    required_agreements: &BTreeMap<AgreementKind, AgreementVersion>,
+    max_scanned: usize,
+) -> ListUsersResponse {
     let limit_users_size: usize = limit_users_size(request);
 
-    let start_bound: Bound<(Timestamp, StoredPrincipal)> = match request.updated_after_timestamp {
-        Some(updated) => Bound::Included((updated, StoredPrincipal(PRINCIPAL_MIN))),
-        None => Bound::Unbounded,
+    let start_bound: Bound<(Timestamp, StoredPrincipal)> = match (
+        request.updated_after_timestamp,
+        request.updated_after_principal,
+    ) {
+        (Some(updated), Some(principal)) => Bound::Excluded((updated, StoredPrincipal(principal))),
+        (Some(updated), None) => Bound::Included((updated, StoredPrincipal(PRINCIPAL_MIN))),
+        (None, _) => Bound::Unbounded,
     };
-    let users: Vec<OisyUser> = user_profile_map
+    let is_match = |profile: &StoredUserProfile| match request.agreement_not_accepted {
+        // If no version is required, every user is up to date.
+        Some(agreement) => required_agreements
+            .get(&agreement)
+            .is_some_and(|version| !profile.has_accepted(agreement, *version)),
+        None => true,
+    };
+    let mut users: Vec<OisyUser> = Vec::new();
+    let mut next_updated_after = None;
+    let mut entries = user_profile_map
         .range((start_bound, Bound::Unbounded))
-        .take(limit_users_size)
-        .map(|((_, principal), profile)| OisyUser::from_profile(&profile, principal.0))
-        .collect();
+        .peekable();
+    let mut scanned = 0;
+    // At least one user is scanned, so that every request makes progress.
+    while let Some(((updated_timestamp, principal), profile)) = entries.next() {
+        scanned += 1;
+        if users.len() < limit_users_size && is_match(&profile) {
+            users.push(OisyUser::from_profile(&profile, principal.0));
+        }
+        if (scanned >= max_scanned || users.len() >= limit_users_size) && entries.peek().is_some() {
+            next_updated_after = Some((updated_timestamp, principal.0));
+            break;
+        }
+    }
+
+    ListUsersResponse {
+        users,
+        matches_max_length: limit_users_size as u64,
+        next_updated_after_timestamp: next_updated_after.map(|(timestamp, _)| timestamp),
+        next_updated_after_principal: next_updated_after.map(|(_, principal)| principal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Candid;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use pretty_assertions::assert_eq;
+    use shared::types::user_profile::AcceptedAgreement;
+    use std::cell::RefCell;
+
+    fn prepare_btree(accepted: &[bool]) -> UserProfileMap {
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        let mut map = UserProfileMap::new(memory.borrow().get(MemoryId::new(3)));
+        for (i, accepted) in accepted.iter().enumerate() {
+            let timestamp = i as Timestamp;
+            let mut profile = StoredUserProfile::from_timestamp(timestamp);
+            if *accepted {
+                profile.agreements = Some(BTreeMap::from([(
+                    AgreementKind::TermsOfService,
+                    AcceptedAgreement {
+                        version: 1,
+                        accepted_timestamp: timestamp,
+                    },
+                )]));
+            }
+            let principal = Principal::from_slice(&[u8::try_from(i).unwrap()]);
+            map.insert((timestamp, StoredPrincipal(principal)), Candid(profile));
+        }
+        map
+    }
+
+    fn principals(response: &ListUsersResponse) -> Vec<Principal> {
+        response.users.iter().map(|user| user.principal).collect()
+    }
+
+    #[test]
+    fn the_scan_is_bounded_and_can_be_continued() {
+        let map = prepare_btree(&[true, true, false, true, false, true]);
+        let required_agreements = BTreeMap::from([(AgreementKind::TermsOfService, 1)]);
+        let mut request = ListUsersRequest {
+            updated_after_timestamp: None,
+            updated_after_principal: None,
+            matches_max_length: None,
+            agreement_not_accepted: Some(AgreementKind::TermsOfService),
+        };
+
+        let response = scan_oisy_users(&request, &map, &required_agreements, 3);
+        assert_eq!(principals(&response), vec![Principal::from_slice(&[2])]);
+        assert_eq!(response.next_updated_after_timestamp, Some(2));
+        assert_eq!(
+            response.next_updated_after_principal,
+            Some(Principal::from_slice(&[2]))
+        );
+
+        request.updated_after_timestamp = response.next_updated_after_timestamp;
+        request.updated_after_principal = response.next_updated_after_principal;
+        let response = scan_oisy_users(&request, &map, &required_agreements, 3);
+        assert_eq!(principals(&response), vec![Principal::from_slice(&[4])]);
+        assert_eq!(response.next_updated_after_timestamp, None);
+        assert_eq!(response.next_updated_after_principal, None);
+    }
+
+    #[test]
+    fn users_updated_at_the_same_time_can_be_continued() {
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        let mut map = UserProfileMap::new(memory.borrow().get(MemoryId::new(3)));
+        for i in 0..5 {
+            let principal = Principal::from_slice(&[i]);
+            map.insert(
+                (7, StoredPrincipal(principal)),
+                Candid(StoredUserProfile::from_timestamp(7)),
+            );
+        }
+        let mut request = ListUsersRequest {
+            updated_after_timestamp: None,
+            updated_after_principal: None,
+            matches_max_length: Some(2),
+            agreement_not_accepted: None,
+        };
+
+        let mut listed = Vec::new();
+        loop {
+            let response = scan_oisy_users(&request, &map, &BTreeMap::new(), MAX_SCANNED_USERS);
+            listed.extend(principals(&response));
+            if response.next_updated_after_timestamp.is_none() {
+                break;
+            }
+            request.updated_after_timestamp = response.next_updated_after_timestamp;
+            request.updated_after_principal = response.next_updated_after_principal;
+        }
+
+        assert_eq!(
+            listed,
+            (0..5)
+                .map(|i| Principal::from_slice(&[i]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn a_full_page_can_be_continued() {
+        let map = prepare_btree(&[false, false, false]);
+        let request = ListUsersRequest {
+            updated_after_timestamp: None,
+            updated_after_principal: None,
+            matches_max_length: Some(2),
+            agreement_not_accepted: None,
+        };
 
-    (users, limit_users_size as u64)
+        let response = scan_oisy_users(&request, &map, &BTreeMap::new(), MAX_SCANNED_USERS);
+        assert_eq!(response.users.len(), 2);
+        assert_eq!(response.next_updated_after_timestamp, Some(1));
+    }
 }
@@ -0,0 +1,179 @@
+use crate::{principal_link_model::PrincipalLinkModel, StoredPrincipal};
+use shared::types::{
+    principal_link::{LinkedPrincipals, PrincipalLinkChallenge, PrincipalLinkError},
+    Timestamp,
+};
+
+/// The maximum number of principals in a linked group, including the primary principal.
+pub const MAX_LINKED_GROUP_SIZE: usize = 5;
+/// How long an invited principal has to confirm a link.
+const LINK_CHALLENGE_TTL_NS: u64 = 10 * 60 * 1_000_000_000;
+/// The maximum number of pending invitations per initiator.  A group cannot take in more principals anyway.
+pub const MAX_CHALLENGES_PER_INITIATOR: usize = MAX_LINKED_GROUP_SIZE - 1;
+/// How long expired invitations are kept, so that a late confirmation is told that the invitation expired.
+const EXPIRED_LINK_CHALLENGE_RETENTION_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// The number of invitations examined per housekeeping run.
+pub const LINK_CHALLENGE_PRUNE_BATCH_SIZE: usize = 100;
+
+/// Invites a principal to join the initiator's group.
+///
+/// Only the primary principal of a group may invite principals into it.
+///
+/// # Arguments
+/// * `initiator` - The principal that starts the link.
+/// * `invited` - The principal that is invited to join the initiator's group.
+/// * `now` - The current time.
+/// * `principal_link_model` - The principal link model.
+///
+/// # Returns
+/// - The time by which the invited principal has to confirm the link.
+///
+/// # Errors
+/// - Returns `Err` if the initiator is a linked member rather than the primary principal of its group.
+/// - Returns `Err` if the invited principal is the initiator, already belongs to a group, or the group is full.
+/// - Returns `Err` if the initiator has too many pending invitations.
+pub fn start_link(
+    initiator: StoredPrincipal,
+    invited: StoredPrincipal,
+    now: Timestamp,
+    principal_link_model: &mut PrincipalLinkModel,
+) -> Result<Timestamp, PrincipalLinkError> {
+    if principal_link_model.primary_of(initiator) != initiator {
+        return Err(PrincipalLinkError::NotAllowed);
+    }
+    check_can_join(initiator, invited, principal_link_model)?;
+    // Expired invitations do not count towards the limit.  A new invitation of the same principal replaces the
+    // earlier one.
+    let mut pending = 0;
+    for (other_invited, expires_timestamp) in principal_link_model.challenges_by(initiator) {
+        if expires_timestamp < now {
+            principal_link_model.take_challenge(other_invited, initiator);
+        } else if other_invited != invited {
+            pending += 1;
+        }
+    }
+    if pending >= MAX_CHALLENGES_PER_INITIATOR {
+        return Err(PrincipalLinkError::TooManyChallenges {
+            max_challenges: MAX_CHALLENGES_PER_INITIATOR as u64,
+        });
+    }
+    let expires_timestamp = now.saturating_add(LINK_CHALLENGE_TTL_NS);
+    principal_link_model.store_challenge(
+        invited,
+        PrincipalLinkChallenge {
+            initiator: initiator.0,
+            expires_timestamp,
+        },
+    );
+    Ok(expires_timestamp)
+}
+
+/// Confirms an invitation, adding the invited principal to the initiator's group.
+///
+/// The invited principal must not have tokens or a profile of its own: once linked, it uses those of the group's
+/// primary principal, so its own would be lost.
+///
+/// # Errors
+/// - Returns `Err` if there is no matching, unexpired invitation, or if the link is no longer possible.
+/// - Returns `Err` if the initiator has since joined another group.
+/// - Returns `Err` if the invited principal has data of its own.
+pub fn confirm_link(
+    invited: StoredPrincipal,
+    initiator: StoredPrincipal,
+    invited_has_data: bool,
+    now: Timestamp,
+    principal_link_model: &mut PrincipalLinkModel,
+) -> Result<LinkedPrincipals, PrincipalLinkError> {
+    let challenge = principal_link_model
+        .take_challenge(invited, initiator)
+        .ok_or(PrincipalLinkError::ChallengeNotFound)?;
+    if challenge.expires_timestamp < now {
+        return Err(PrincipalLinkError::ChallengeExpired);
+    }
+    if invited_has_data {
+        return Err(PrincipalLinkError::InvitedPrincipalHasData);
+    }
+    // The initiator's group may have changed since the link was started.
+    if principal_link_model.primary_of(initiator) != initiator {
+        return Err(PrincipalLinkError::NotAllowed);
+    }
+    check_can_join(initiator, invited, principal_link_model)?;
+    principal_link_model.link(invited, initiator, now);
+    Ok(linked_principals(invited, principal_link_model))
+}
+
+/// Removes the invitations that expired more than a day ago among the next batch of invitations following `after`.
+///
+/// Returns the cursor from which the next run continues, or `None` once all the invitations have been examined.
+pub fn prune_challenges(
+    now: Timestamp,
+    after: Option<(StoredPrincipal, StoredPrincipal)>,
+    principal_link_model: &mut PrincipalLinkModel,
+) -> Option<(StoredPrincipal, StoredPrincipal)> {
+    principal_link_model.prune_challenges(
+        now.saturating_sub(EXPIRED_LINK_CHALLENGE_RETENTION_NS),
+        after,
+        LINK_CHALLENGE_PRUNE_BATCH_SIZE,
+    )
+}
+
+/// Removes a principal from its group.
+///
+/// Any member may leave its group, and the primary principal may remove any member.
+///
+/// # Errors
+/// - Returns `Err` if the principal is not linked, is the primary of its group, or may not be removed by the caller.
+pub fn unlink(
+    caller: StoredPrincipal,
+    principal: StoredPrincipal,
+    principal_link_model: &mut PrincipalLinkModel,
+) -> Result<LinkedPrincipals, PrincipalLinkError> {
+    if !principal_link_model.is_linked(principal) {
+        return Err(PrincipalLinkError::NotLinked);
+    }
+    let primary = principal_link_model.primary_of(principal);
+    if primary == principal {
+        return Err(PrincipalLinkError::CannotUnlinkPrimary);
+    }
+    if caller != principal && caller != primary {
+        return Err(PrincipalLinkError::NotAllowed);
+    }
+    principal_link_model.unlink(principal);
+    Ok(linked_principals(caller, principal_link_model))
+}
+
+/// The group that the given principal belongs to.
+pub fn linked_principals(
+    principal: StoredPrincipal,
+    principal_link_model: &PrincipalLinkModel,
+) -> LinkedPrincipals {
+    let primary = principal_link_model.primary_of(principal);
+    LinkedPrincipals {
+        primary: primary.0,
+        principals: principal_link_model
+            .group_of(primary)
+            .into_iter()
+            .map(|principal| principal.0)
+            .collect(),
+    }
+}
+
+/// Checks that the invited principal may join the group of the given primary principal.
+fn check_can_join(
+    primary: StoredPrincipal,
+    invited: StoredPrincipal,
+    principal_link_model: &PrincipalLinkModel,
+) -> Result<(), PrincipalLinkError> {
+    if primary == invited {
+        return Err(PrincipalLinkError::CannotLinkSelf);
+    }
+    if principal_link_model.is_linked(invited) {
+        return Err(PrincipalLinkError::AlreadyLinked);
+    }
+    if principal_link_model.group_of(primary).len() >= MAX_LINKED_GROUP_SIZE {
+        return Err(PrincipalLinkError::GroupFull {
+            max_group_size: MAX_LINKED_GROUP_SIZE as u64,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,393 @@
+use crate::types::{
+    Candid, PrincipalLinkChallengeMap, PrincipalLinkGroupMap, PrincipalLinkInitiatorChallengeMap,
+    PrincipalLinkMap, StoredPrincipal,
+};
+use candid::Principal;
+use shared::types::{
+    principal_link::{PrincipalLink, PrincipalLinkChallenge},
+    Timestamp,
+};
+use std::ops::{Bound, RangeInclusive};
+
+const PRINCIPAL_MIN: Principal = Principal::from_slice(&[]);
+const PRINCIPAL_MAX: Principal = Principal::from_slice(&[0xff; 29]);
+
+pub struct PrincipalLinkModel<'a> {
+    principal_link_map: &'a mut PrincipalLinkMap,
+    principal_link_group_map: &'a mut PrincipalLinkGroupMap,
+    principal_link_challenge_map: &'a mut PrincipalLinkChallengeMap,
+    principal_link_initiator_challenge_map: &'a mut PrincipalLinkInitiatorChallengeMap,
+}
+
+/// `PrincipalLinkModel` should be used to access and manage the groups of linked principals in the stable memory.
+///
+/// Every linked principal has an entry in the link map pointing to the primary principal of its group,
+/// and an entry in the group map, keyed by the primary principal.  The primary principal itself has no entries.
+///
+/// Likewise, every invitation has an entry in the challenge map, keyed by the invited principal,
+/// and an entry in the initiator challenge map, keyed by the initiator.
+impl<'a> PrincipalLinkModel<'a> {
+    pub fn new(
+        principal_link_map: &'a mut PrincipalLinkMap,
+        principal_link_group_map: &'a mut PrincipalLinkGroupMap,
+        principal_link_challenge_map: &'a mut PrincipalLinkChallengeMap,
+        principal_link_initiator_challenge_map: &'a mut PrincipalLinkInitiatorChallengeMap,
+    ) -> PrincipalLinkModel<'a> {
+        PrincipalLinkModel {
+            principal_link_map,
+            principal_link_group_map,
+            principal_link_challenge_map,
+            principal_link_initiator_challenge_map,
+        }
+    }
+
+    /// The principal whose tokens and settings are used by the given principal.
+    pub fn primary_of(&self, principal: StoredPrincipal) -> StoredPrincipal {
+        primary_principal(self.principal_link_map, principal)
+    }
+
+    /// Whether the principal is linked to other principals, either as a member or as the primary.
+    pub fn is_linked(&self, principal: StoredPrincipal) -> bool {
+        self.principal_link_map.contains_key(&principal)
+            || self
+                .principal_link_group_map
+                .keys_range(group_range(principal))
+                .next()
+                .is_some()
+    }
+
+    /// All principals in a group, primary first.
+    pub fn group_of(&self, primary: StoredPrincipal) -> Vec<StoredPrincipal> {
+        std::iter::once(primary)
+            .chain(
+                self.principal_link_group_map
+                    .keys_range(group_range(primary))
+                    .map(|(_, member)| member),
+            )
+            .collect()
+    }
+
+    /// Adds a principal to the group of the given primary principal.
+    pub fn link(&mut self, principal: StoredPrincipal, primary: StoredPrincipal, now: Timestamp) {
+        self.principal_link_map.insert(
+            principal,
+            Candid(PrincipalLink {
+                primary: primary.0,
+                linked_timestamp: now,
+            }),
+        );
+        self.principal_link_group_map
+            .insert((primary, principal), now);
+    }
+
+    /// Removes a principal from its group.
+    pub fn unlink(&mut self, principal: StoredPrincipal) {
+        if let Some(link) = self.principal_link_map.remove(&principal) {
+            self.principal_link_group_map
+                .remove(&(StoredPrincipal(link.primary), principal));
+        }
+    }
+
+    /// Stores an invitation for the given principal to join a group, replacing any earlier invitation by the same
+    /// initiator.
+    pub fn store_challenge(&mut self, invited: StoredPrincipal, challenge: PrincipalLinkChallenge) {
+        let initiator = StoredPrincipal(challenge.initiator);
+        self.principal_link_challenge_map
+            .insert((invited, initiator), Candid(challenge));
+        self.principal_link_initiator_challenge_map
+            .insert((initiator, invited), challenge.expires_timestamp);
+    }
+
+    /// Removes and returns the invitation by the given initiator for the given principal, if any.
+    pub fn take_challenge(
+        &mut self,
+        invited: StoredPrincipal,
+        initiator: StoredPrincipal,
+    ) -> Option<PrincipalLinkChallenge> {
+        self.principal_link_initiator_challenge_map
+            .remove(&(initiator, invited));
+        self.principal_link_challenge_map
+            .remove(&(invited, initiator))
+            .map(|challenge| challenge.0)
+    }
+
+    /// The principals invited by the given initiator, with the time by which they have to confirm the link.
+    pub fn challenges_by(&self, initiator: StoredPrincipal) -> Vec<(StoredPrincipal, Timestamp)> {
+        self.principal_link_initiator_challenge_map
+            .range(group_range(initiator))
+            .map(|((_, invited), expires_timestamp)| (invited, expires_timestamp))
+            .collect()
+    }
+
+    /// Removes the invitations that expired before the given time among the next `count` invitations following
+    /// `after` in (initiator, invited) order.
+    ///
+    /// Returns the last invitation examined, from which the next call should continue, or `None` once all the
+    /// invitations have been examined.
+    pub fn prune_challenges(
+        &mut self,
+        expired_before: Timestamp,
+        after: Option<(StoredPrincipal, StoredPrincipal)>,
+        count: usize,
+    ) -> Option<(StoredPrincipal, StoredPrincipal)> {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let batch: Vec<_> = self
+            .principal_link_initiator_challenge_map
+            .range((start, Bound::Unbounded))
+            .take(count)
+            .collect();
+        for ((initiator, invited), expires_timestamp) in &batch {
+            if *expires_timestamp < expired_before {
+                self.take_challenge(*invited, *initiator);
+            }
+        }
+        if batch.len() < count {
+            None
+        } else {
+            batch.last().map(|(key, _)| *key)
+        }
+    }
+
+    #[cfg(test)]
+    fn assert_consistent(&self) {
+        pretty_assertions::assert_eq!(
+            self.principal_link_map.len(),
+            self.principal_link_group_map.len()
+        );
+        pretty_assertions::assert_eq!(
+            self.principal_link_challenge_map.len(),
+            self.principal_link_initiator_challenge_map.len()
+        );
+        for ((invited, initiator), challenge) in self.principal_link_challenge_map.iter() {
+            pretty_assertions::assert_eq!(
+                self.principal_link_initiator_challenge_map
+                    .get(&(initiator, invited)),
+                Some(challenge.expires_timestamp)
+            );
+        }
+        for (principal, link) in self.principal_link_map.iter() {
+            let primary = StoredPrincipal(link.primary);
+            pretty_assertions::assert_eq!(
+                self.principal_link_group_map.get(&(primary, principal)),
+                Some(link.linked_timestamp)
+            );
+            pretty_assertions::assert_eq!(
+                self.principal_link_map
+                    .get(&primary)
+                    .map(|link| link.primary),
+                None
+            );
+        }
+    }
+}
+
+/// The principal whose tokens and settings are used by the given principal.
+///
+/// Unlinked principals are their own primary.
+pub fn primary_principal(
+    principal_link_map: &PrincipalLinkMap,
+    principal: StoredPrincipal,
+) -> StoredPrincipal {
+    principal_link_map
+        .get(&principal)
+        .map_or(principal, |link| StoredPrincipal(link.primary))
+}
+
+/// The range of keys in the group map that belong to the given primary principal.
+///
+/// Also the range of keys in the initiator challenge map that belong to the given initiator.
+fn group_range(primary: StoredPrincipal) -> RangeInclusive<(StoredPrincipal, StoredPrincipal)> {
+    (primary, StoredPrincipal(PRINCIPAL_MIN))..=(primary, StoredPrincipal(PRINCIPAL_MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::{
+        memory_manager::{MemoryId, MemoryManager},
+        DefaultMemoryImpl,
+    };
+    use pretty_assertions::assert_eq;
+    use std::cell::RefCell;
+
+    const USER_1: &str = "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
+    const USER_2: &str = "ufjdl-kewp5-bgfaq-d7k34-e5w62-nyad4-7r3s5-m2pt2-owqga-kcr5z-jae";
+    const USER_3: &str = "7blps-itamd-lzszp-7lbda-4nngn-fev5u-2jvpn-6y3ap-eunp7-kz57e-fqe";
+
+    fn prepare_btrees() -> (
+        PrincipalLinkMap,
+        PrincipalLinkGroupMap,
+        PrincipalLinkChallengeMap,
+        PrincipalLinkInitiatorChallengeMap,
+    ) {
+        const PRINCIPAL_LINK_MEMORY_ID: MemoryId = MemoryId::new(6);
+        const PRINCIPAL_LINK_GROUP_MEMORY_ID: MemoryId = MemoryId::new(7);
+        const PRINCIPAL_LINK_CHALLENGE_MEMORY_ID: MemoryId = MemoryId::new(8);
+        const PRINCIPAL_LINK_INITIATOR_CHALLENGE_MEMORY_ID: MemoryId = MemoryId::new(12);
+        let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+        let principal_link_map =
+            PrincipalLinkMap::new(memory.borrow().get(PRINCIPAL_LINK_MEMORY_ID));
+        let principal_link_group_map =
+            PrincipalLinkGroupMap::new(memory.borrow().get(PRINCIPAL_LINK_GROUP_MEMORY_ID));
+        let principal_link_challenge_map =
+            PrincipalLinkChallengeMap::new(memory.borrow().get(PRINCIPAL_LINK_CHALLENGE_MEMORY_ID));
+        let principal_link_initiator_challenge_map = PrincipalLinkInitiatorChallengeMap::new(
+            memory
+                .borrow()
+                .get(PRINCIPAL_LINK_INITIATOR_CHALLENGE_MEMORY_ID),
+        );
+
+        (
+            principal_link_map,
+            principal_link_group_map,
+            principal_link_challenge_map,
+            principal_link_initiator_challenge_map,
+        )
+    }
+
+    fn stored_principal(text: &str) -> StoredPrincipal {
+        StoredPrincipal(Principal::from_text(text).expect("invalid user principal"))
+    }
+
+    #[test]
+    fn test_unlinked_principal_is_its_own_primary() {
+        let (mut link_map, mut group_map, mut challenge_map, mut initiator_map) = prepare_btrees();
+        let model = PrincipalLinkModel::new(
+            &mut link_map,
+            &mut group_map,
+            &mut challenge_map,
+            &mut initiator_map,
+        );
+        let user_1 = stored_principal(USER_1);
+
+        assert_eq!(model.primary_of(user_1), user_1);
+        assert_eq!(model.group_of(user_1), vec![user_1]);
+        assert!(!model.is_linked(user_1));
+    }
+
+    #[test]
+    fn test_link_and_unlink() {
+        let (mut link_map, mut group_map, mut challenge_map, mut initiator_map) = prepare_btrees();
+        let mut model = PrincipalLinkModel::new(
+            &mut link_map,
+            &mut group_map,
+            &mut challenge_map,
+            &mut initiator_map,
+        );
+        let user_1 = stored_principal(USER_1);
+        let user_2 = stored_principal(USER_2);
+        let user_3 = stored_principal(USER_3);
+
+        model.link(user_2, user_1, 10);
+        model.link(user_3, user_1, 20);
+        model.assert_consistent();
+
+        assert_eq!(model.primary_of(user_2), user_1);
+        assert_eq!(model.primary_of(user_3), user_1);
+        assert_eq!(model.primary_of(user_1), user_1);
+        assert!(model.is_linked(user_1));
+        assert_eq!(model.group_of(user_1).len(), 3);
+        assert_eq!(model.group_of(user_1)[0], user_1);
+
+        model.unlink(user_2);
+        model.assert_consistent();
+
+        assert_eq!(model.primary_of(user_2), user_2);
+        assert!(!model.is_linked(user_2));
+        assert_eq!(model.group_of(user_1), vec![user_1, user_3]);
+    }
+
+    #[test]
+    fn test_challenge_can_be_taken_once() {
+        let (mut link_map, mut group_map, mut challenge_map, mut initiator_map) = prepare_btrees();
+        let mut model = PrincipalLinkModel::new(
+            &mut link_map,
+            &mut group_map,
+            &mut challenge_map,
+            &mut initiator_map,
+        );
+        let user_1 = stored_principal(USER_1);
+        let user_2 = stored_principal(USER_2);
+        let challenge = PrincipalLinkChallenge {
+            initiator: user_1.0,
+            expires_timestamp: 100,
+        };
+
+        model.store_challenge(user_2, challenge);
+        model.assert_consistent();
+
+        assert_eq!(model.take_challenge(user_2, user_1), Some(challenge));
+        assert_eq!(model.take_challenge(user_2, user_1), None);
+        model.assert_consistent();
+    }
+
+    #[test]
+    fn test_challenges_of_different_initiators_are_kept_apart() {
+        let (mut link_map, mut group_map, mut challenge_map, mut initiator_map) = prepare_btrees();
+        let mut model = PrincipalLinkModel::new(
+            &mut link_map,
+            &mut group_map,
+            &mut challenge_map,
+            &mut initiator_map,
+        );
+        let user_1 = stored_principal(USER_1);
+        let user_2 = stored_principal(USER_2);
+        let user_3 = stored_principal(USER_3);
+        let challenge_by = |initiator: StoredPrincipal, expires_timestamp| PrincipalLinkChallenge {
+            initiator: initiator.0,
+            expires_timestamp,
+        };
+
+        model.store_challenge(user_2, challenge_by(user_1, 100));
+        model.store_challenge(user_2, challenge_by(user_3, 200));
+        model.assert_consistent();
+
+        assert_eq!(model.challenges_by(user_1), vec![(user_2, 100)]);
+        assert_eq!(model.challenges_by(user_3), vec![(user_2, 200)]);
+
+        assert_eq!(model.prune_challenges(150, None, 10), None);
+        model.assert_consistent();
+
+        assert_eq!(model.take_challenge(user_2, user_1), None);
+        assert_eq!(
+            model.take_challenge(user_2, user_3),
+            Some(challenge_by(user_3, 200))
+        );
+    }
+
+    #[test]
+    fn test_challenges_are_pruned_in_batches() {
+        let (mut link_map, mut group_map, mut challenge_map, mut initiator_map) = prepare_btrees();
+        let mut model = PrincipalLinkModel::new(
+            &mut link_map,
+            &mut group_map,
+            &mut challenge_map,
+            &mut initiator_map,
+        );
+        let user_1 = stored_principal(USER_1);
+        let user_2 = stored_principal(USER_2);
+        let user_3 = stored_principal(USER_3);
+        let challenge_by = |initiator: StoredPrincipal| PrincipalLinkChallenge {
+            initiator: initiator.0,
+            expires_timestamp: 100,
+        };
+        model.store_challenge(user_2, challenge_by(user_1));
+        model.store_challenge(user_3, challenge_by(user_1));
+        model.store_challenge(user_1, challenge_by(user_3));
+
+        let cursor = model.prune_challenges(150, None, 2);
+        model.assert_consistent();
+
+        assert!(cursor.is_some());
+        assert_eq!(
+            model.challenges_by(user_1).len() + model.challenges_by(user_3).len(),
+            1
+        );
+
+        assert_eq!(model.prune_challenges(150, cursor, 2), None);
+        model.assert_consistent();
+
+        assert_eq!(model.challenges_by(user_1), vec![]);
+        assert_eq!(model.challenges_by(user_3), vec![]);
+    }
+}
@@ -1,12 +1,17 @@
 //! Code for interacting with the chain fusion signer.
 use crate::{
+    key_derivation::ExtendedPublicKey,
     read_config,
     state::{CYCLES_LEDGER, SIGNER},
 };
-use bitcoin::{Address, CompressedPublicKey, Network};
+use bitcoin::{secp256k1, Address, CompressedPublicKey, Network};
 use candid::{CandidType, Deserialize, Nat, Principal};
+use ethers_core::{
+    types::H160,
+    utils::{keccak256, to_checksum},
+};
 use ic_cdk::api::{
-    call::call_with_payment128,
+    call::{call, call_with_payment128},
     management_canister::{
         bitcoin::BitcoinNetwork,
         ecdsa::{ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument},
@@ -17,10 +22,12 @@ use ic_cycles_ledger_client::{
 };
 use ic_ledger_types::Subaccount;
 use serde_bytes::ByteBuf;
+use shared::types::bitcoin::BtcAddressType;
 use shared::types::signer::topup::{
     TopUpCyclesLedgerError, TopUpCyclesLedgerRequest, TopUpCyclesLedgerResponse,
     TopUpCyclesLedgerResult,
 };
+use std::{cell::RefCell, collections::BTreeMap, future::Future};
 
 #[derive(CandidType, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub enum AllowSigningError {
@@ -103,35 +110,197 @@ pub fn principal2account(principal: &Principal) -> ByteBuf {
         .into()
 }
 
-/// Computes the public key of the specified principal.
-// TODO: Cache CFS pubkey and derive it offline as in [ckBTC minter](https://github.com/dfinity/ic/blob/35153c7cb7b9d1da60472ca7e94c693e418f87bd/rs/bitcoin/ckbtc/minter/src/address.rs#L101-L101)
-async fn cfs_ecdsa_pubkey_of(principal: &Principal) -> Result<Vec<u8>, String> {
-    let (ecdsa_key_name, maybe_cfs_canister_id) =
+/// The schemas of the derivation paths of the chain fusion signer keys.
+///
+/// As set in [CFS](https://github.com/dfinity/chain-fusion-signer/blob/26b683c6de9971fdbf7bd4cebc04d427d1753289/src/signer/canister/src/derivation_path.rs#L6)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DerivationSchema {
+    Btc = 0,
+    Eth = 1,
+}
+
+/// The derivation path of the keys of the specified principal for the given chain.
+fn cfs_derivation_path(schema: DerivationSchema, principal: &Principal) -> Vec<Vec<u8>> {
+    vec![vec![schema as u8], principal.as_slice().to_vec()]
+}
+
+/// The threshold signature algorithms of the chain fusion signer keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum KeyAlgorithm {
+    Ecdsa,
+    Schnorr,
+}
+
+/// The kind of an address of a user.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum AddressKind {
+    Btc(BitcoinNetwork, BtcAddressType),
+    Eth,
+}
+
+/// The maximum number of addresses kept in the address cache.
+const MAX_CACHED_ADDRESSES: usize = 10_000;
+
+thread_local! {
+    /// The public keys and chain codes of the chain fusion signer canister, from which the keys of the users are
+    /// derived offline, as in the [ckBTC minter](https://github.com/dfinity/ic/blob/35153c7cb7b9d1da60472ca7e94c693e418f87bd/rs/bitcoin/ckbtc/minter/src/address.rs#L101-L101).
+    ///
+    /// The keys never change for a given configuration.  They are kept on the heap, so they are fetched again after
+    /// an upgrade, which is the only time the configuration can change.
+    static CFS_ROOT_KEYS: RefCell<BTreeMap<KeyAlgorithm, ExtendedPublicKey>> = RefCell::default();
+    /// The addresses of the users, by principal and kind of address.
+    static ADDRESSES: RefCell<BTreeMap<(Principal, AddressKind), String>> = RefCell::default();
+}
+
+/// Gets the public key and chain code of the given derivation path of the chain fusion signer from the management canister.
+///
+/// The threshold Schnorr keys have the same names as the threshold ECDSA keys, so the configured ECDSA key name is used.
+/// Schnorr keys are not available on subnets without threshold Schnorr support.
+async fn fetch_cfs_public_key(
+    algorithm: KeyAlgorithm,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<ExtendedPublicKey, String> {
+    let (key_name, maybe_cfs_canister_id) =
         read_config(|s| (s.ecdsa_key_name.clone(), s.cfs_canister_id));
-    // As set in [CFS](https://github.com/dfinity/chain-fusion-signer/blob/26b683c6de9971fdbf7bd4cebc04d427d1753289/src/signer/canister/src/derivation_path.rs#L6)
-    // 0 is for BTC
-    // 1 is for Eth
-    // 0xff is generic
-    let btc_schema = vec![0_u8];
-    let derivation_path = vec![btc_schema, principal.as_slice().to_vec()];
     let cfs_canister_id = maybe_cfs_canister_id.ok_or("Missing CFS canister id")?;
-    if let Ok((key,)) = ecdsa_public_key(EcdsaPublicKeyArgument {
-        canister_id: Some(cfs_canister_id),
-        derivation_path,
-        key_id: EcdsaKeyId {
-            curve: EcdsaCurve::Secp256k1,
-            name: ecdsa_key_name,
-        },
-    })
-    .await
-    {
-        Ok(key.public_key)
-    } else {
-        Err("Failed to get ecdsa public key".to_string())
+    let (public_key, chain_code) = match algorithm {
+        KeyAlgorithm::Ecdsa => {
+            let (key,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+                canister_id: Some(cfs_canister_id),
+                derivation_path,
+                key_id: EcdsaKeyId {
+                    curve: EcdsaCurve::Secp256k1,
+                    name: key_name,
+                },
+            })
+            .await
+            .map_err(|_| "Failed to get ecdsa public key".to_string())?;
+            (key.public_key, key.chain_code)
+        }
+        KeyAlgorithm::Schnorr => {
+            let arg = SchnorrPublicKeyArgument {
+                canister_id: Some(cfs_canister_id),
+                derivation_path,
+                key_id: SchnorrKeyId {
+                    algorithm: SchnorrAlgorithm::Bip340Secp256k1,
+                    name: key_name,
+                },
+            };
+            let (key,): (SchnorrPublicKeyResponse,) = call(
+                Principal::management_canister(),
+                "schnorr_public_key",
+                (arg,),
+            )
+            .await
+            .map_err(|_| "Failed to get schnorr public key".to_string())?;
+            (key.public_key, key.chain_code)
+        }
+    };
+    ExtendedPublicKey::from_slices(&public_key, &chain_code)
+}
+
+/// The public key and chain code of the chain fusion signer canister, fetched once.
+async fn cfs_root_key(algorithm: KeyAlgorithm) -> Result<ExtendedPublicKey, String> {
+    if let Some(key) = CFS_ROOT_KEYS.with(|keys| keys.borrow().get(&algorithm).copied()) {
+        return Ok(key);
     }
+    let key = fetch_cfs_public_key(algorithm, vec![]).await?;
+    CFS_ROOT_KEYS.with(|keys| keys.borrow_mut().insert(algorithm, key));
+    Ok(key)
+}
+
+/// Computes the SEC1 compressed public key of the specified principal for the given chain, offline.
+async fn cfs_pubkey_of(
+    principal: &Principal,
+    schema: DerivationSchema,
+    algorithm: KeyAlgorithm,
+) -> Result<Vec<u8>, String> {
+    let root_key = cfs_root_key(algorithm).await?;
+    Ok(root_key
+        .derive(&cfs_derivation_path(schema, principal))
+        .public_key
+        .serialize()
+        .to_vec())
+}
+
+/// Checks that the public keys of the principal that are derived offline match those of the management canister.
+///
+/// # Errors
+/// - A key could not be fetched or the keys differ.
+#[cfg(feature = "test-endpoints")]
+pub async fn check_key_derivation(principal: &Principal) -> Result<(), String> {
+    for (schema, algorithm) in [
+        (DerivationSchema::Btc, KeyAlgorithm::Ecdsa),
+        (DerivationSchema::Btc, KeyAlgorithm::Schnorr),
+        (DerivationSchema::Eth, KeyAlgorithm::Ecdsa),
+    ] {
+        let derived = cfs_pubkey_of(principal, schema, algorithm).await?;
+        let fetched =
+            fetch_cfs_public_key(algorithm, cfs_derivation_path(schema, principal)).await?;
+        if derived != fetched.public_key.serialize() {
+            return Err(format!(
+                "The derived {schema:?} {algorithm:?} public key {} differs from the public key {} of the management canister",
+                hex::encode(derived),
+                hex::encode(fetched.public_key.serialize())
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The cached address of the given kind of a principal, or the newly computed address, which is then cached.
+async fn cached_address(
+    principal: &Principal,
+    kind: AddressKind,
+    address: impl Future<Output = Result<String, String>>,
+) -> Result<String, String> {
+    let cache_key = (*principal, kind);
+    if let Some(address) = ADDRESSES.with(|addresses| addresses.borrow().get(&cache_key).cloned()) {
+        return Ok(address);
+    }
+    let address = address.await?;
+    ADDRESSES.with(|addresses| {
+        let mut addresses = addresses.borrow_mut();
+        if addresses.len() >= MAX_CACHED_ADDRESSES {
+            addresses.clear();
+        }
+        addresses.insert(cache_key, address.clone());
+    });
+    Ok(address)
+}
+
+/// Threshold Schnorr algorithm, as in the [management canister API](https://internetcomputer.org/docs/current/references/ic-interface-spec/#ic-schnorr_public_key).
+///
+/// Note: `ic-cdk` 0.16 has no bindings for threshold Schnorr yet.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum SchnorrAlgorithm {
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrKeyId {
+    algorithm: SchnorrAlgorithm,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyArgument {
+    canister_id: Option<Principal>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SchnorrPublicKeyResponse {
+    /// A BIP-340 public key in SEC1 compressed form.
+    public_key: Vec<u8>,
+    chain_code: Vec<u8>,
 }
 
-fn transform_network(network: BitcoinNetwork) -> Network {
+/// Converts the management canister network to the `bitcoin` crate network.
+#[must_use]
+pub fn transform_network(network: BitcoinNetwork) -> Network {
     match network {
         BitcoinNetwork::Mainnet => Network::Bitcoin,
         BitcoinNetwork::Testnet => Network::Testnet,
@@ -147,7 +316,7 @@ pub async fn btc_principal_to_p2wpkh_address(
     network: BitcoinNetwork,
     principal: &Principal,
 ) -> Result<String, String> {
-    let ecdsa_pubkey = cfs_ecdsa_pubkey_of(principal).await?;
+    let ecdsa_pubkey = cfs_pubkey_of(principal, DerivationSchema::Btc, KeyAlgorithm::Ecdsa).await?;
     if let Ok(compressed_public_key) = CompressedPublicKey::from_slice(&ecdsa_pubkey) {
         Ok(Address::p2wpkh(&compressed_public_key, transform_network(network)).to_string())
     } else {
@@ -155,6 +324,93 @@ pub async fn btc_principal_to_p2wpkh_address(
     }
 }
 
+/// Converts a BIP-340 public key to a P2TR key-path address, with no script path, as in BIP-86.
+///
+/// # Errors
+/// - The public key is not a valid SEC1 compressed key.
+fn p2tr_address(schnorr_pubkey: &[u8], network: BitcoinNetwork) -> Result<String, String> {
+    let public_key = secp256k1::PublicKey::from_slice(schnorr_pubkey)
+        .map_err(|_| "Error getting P2TR from public key".to_string())?;
+    let (internal_key, _parity) = public_key.x_only_public_key();
+    Ok(Address::p2tr(
+        &secp256k1::Secp256k1::verification_only(),
+        internal_key,
+        None,
+        transform_network(network),
+    )
+    .to_string())
+}
+
+/// Converts the Schnorr public key of a principal to a P2TR key-path address.
+///
+/// # Errors
+/// - The signer does not support threshold Schnorr or it was not possible to get the P2TR from the public key.
+pub async fn btc_principal_to_p2tr_address(
+    network: BitcoinNetwork,
+    principal: &Principal,
+) -> Result<String, String> {
+    let schnorr_pubkey =
+        cfs_pubkey_of(principal, DerivationSchema::Btc, KeyAlgorithm::Schnorr).await?;
+    p2tr_address(&schnorr_pubkey, network)
+}
+
+/// Computes the Bitcoin address of the given type of a principal.
+///
+/// The addresses are cached.
+///
+/// # Errors
+/// - It was not possible to get the address from the public key.
+pub async fn btc_principal_to_address(
+    network: BitcoinNetwork,
+    principal: &Principal,
+    address_type: BtcAddressType,
+) -> Result<String, String> {
+    cached_address(
+        principal,
+        AddressKind::Btc(network, address_type),
+        async move {
+            match address_type {
+                BtcAddressType::P2wpkh => btc_principal_to_p2wpkh_address(network, principal).await,
+                BtcAddressType::P2tr => btc_principal_to_p2tr_address(network, principal).await,
+            }
+        },
+    )
+    .await
+}
+
+/// Converts a SEC1 encoded secp256k1 public key to an Ethereum address, in EIP-55 checksum form.
+///
+/// # Errors
+/// - The public key is not a valid secp256k1 key.
+fn eth_address(ecdsa_pubkey: &[u8]) -> Result<String, String> {
+    let public_key = secp256k1::PublicKey::from_slice(ecdsa_pubkey)
+        .map_err(|_| "Error getting Ethereum address from public key".to_string())?;
+    // The address is the last 20 bytes of the hash of the uncompressed key, without the SEC1 tag.
+    let hash = keccak256(&public_key.serialize_uncompressed()[1..]);
+    Ok(to_checksum(&H160::from_slice(&hash[12..]), None))
+}
+
+/// Computes the Ethereum address of a principal, in EIP-55 checksum form.
+///
+/// The addresses are cached.
+///
+/// # Errors
+/// - It was not possible to get the address from the public key.
+pub async fn eth_principal_to_address(principal: &Principal) -> Result<String, String> {
+    cached_address(principal, AddressKind::Eth, async {
+        eth_address(&eth_public_key(principal).await?)
+    })
+    .await
+}
+
+/// The SEC1 compressed Ethereum public key of a principal, which is the key of `eth_principal_to_address`.
+///
+/// # Errors
+/// - The key of the chain fusion signer could not be fetched.
+pub async fn eth_public_key(principal: &Principal) -> Result<Vec<u8>, String> {
+    cfs_pubkey_of(principal, DerivationSchema::Eth, KeyAlgorithm::Ecdsa).await
+}
+
 /// Tops up the cycles ledger.
 ///
 /// # Errors
@@ -209,3 +465,40 @@ pub async fn top_up_cycles_ledger(request: TopUpCyclesLedgerRequest) -> TopUpCyc
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn p2tr_address_matches_bip86_test_vector() {
+        // The internal key of m/86'/0'/0'/0/0 in the BIP-86 test vectors, with either parity.
+        let x_only_key = "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115";
+        for prefix in ["02", "03"] {
+            let public_key = hex::decode(format!("{prefix}{x_only_key}")).unwrap();
+            assert_eq!(
+                p2tr_address(&public_key, BitcoinNetwork::Mainnet),
+                Ok("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn eth_address_is_checksummed() {
+        // The key of private key 1, which is the generator point.
+        let public_key =
+            hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap();
+        assert_eq!(
+            eth_address(&public_key),
+            Ok("0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf".to_string())
+        );
+        assert!(eth_address(&[2; 32]).is_err());
+    }
+
+    #[test]
+    fn p2tr_address_rejects_invalid_public_key() {
+        assert!(p2tr_address(&[2; 32], BitcoinNetwork::Mainnet).is_err());
+    }
+}
@@ -1,10 +1,17 @@
+use crate::btc_pending_transaction_model::StoredPendingTransaction;
 use candid::{CandidType, Deserialize, Principal};
 use ic_stable_structures::{
     memory_manager::VirtualMemory, DefaultMemoryImpl, StableBTreeMap, StableCell,
 };
 use shared::types::Config;
 use shared::types::{
-    custom_token::CustomToken, token::UserToken, user_profile::StoredUserProfile, Timestamp,
+    bitcoin::BtcFrozenUtxo,
+    custom_token::CustomToken,
+    ethereum::EthPendingTransaction,
+    principal_link::{PrincipalLink, PrincipalLinkChallenge},
+    token::{ChainId, UserToken},
+    user_profile::{StoredUserProfile, UserProfileHistoryEntry},
+    Timestamp,
 };
 
 pub type VMem = VirtualMemory<DefaultMemoryImpl>;
@@ -16,6 +23,31 @@ pub type UserProfileMap =
     StableBTreeMap<(Timestamp, StoredPrincipal), Candid<StoredUserProfile>, VMem>;
 /// Map of `user_principal` to `updated_timestamp` (in `UserProfile`)
 pub type UserProfileUpdatedMap = StableBTreeMap<StoredPrincipal, Timestamp, VMem>;
+/// Map of (`user_principal`, `sequence_number`) to an entry in the user's profile change history
+pub type UserProfileHistoryMap =
+    StableBTreeMap<(StoredPrincipal, u64), Candid<UserProfileHistoryEntry>, VMem>;
+/// Map of `linked_principal` to the primary principal of its group
+pub type PrincipalLinkMap = StableBTreeMap<StoredPrincipal, Candid<PrincipalLink>, VMem>;
+/// Map of (`primary_principal`, `linked_principal`) to `linked_timestamp`
+pub type PrincipalLinkGroupMap =
+    StableBTreeMap<(StoredPrincipal, StoredPrincipal), Timestamp, VMem>;
+/// Map of (`invited_principal`, `initiator_principal`) to a pending invitation to join a group of linked principals
+pub type PrincipalLinkChallengeMap =
+    StableBTreeMap<(StoredPrincipal, StoredPrincipal), Candid<PrincipalLinkChallenge>, VMem>;
+/// Map of (`initiator_principal`, `invited_principal`) to `expires_timestamp` of the pending invitations
+pub type PrincipalLinkInitiatorChallengeMap =
+    StableBTreeMap<(StoredPrincipal, StoredPrincipal), Timestamp, VMem>;
+/// Map of (`user_principal`, `bitcoin_address`, `txid`) to a pending transaction spending from the address
+pub type BtcPendingTransactionMap = StableBTreeMap<
+    (StoredPrincipal, StoredBtcAddress, StoredTxid),
+    Candid<StoredPendingTransaction>,
+    VMem,
+>;
+/// Map of `user_principal` to the UTXOs the user has frozen
+pub type BtcFrozenUtxoMap = StableBTreeMap<StoredPrincipal, Candid<Vec<BtcFrozenUtxo>>, VMem>;
+/// Map of (`user_principal`, `chain_id`) to the Ethereum transactions sent by the user on the chain
+pub type EthPendingTransactionMap =
+    StableBTreeMap<(StoredPrincipal, ChainId), Candid<Vec<EthPendingTransaction>>, VMem>;
 
 #[derive(Default)]
 pub struct Candid<T>(pub T)
@@ -24,3 +56,13 @@ where
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StoredPrincipal(pub Principal);
+
+/// A Bitcoin address, as used in stable map keys.
+///
+/// Bech32 addresses are at most 90 characters long; base58 addresses are shorter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredBtcAddress(pub String);
+
+/// A Bitcoin txid, as used in stable map keys.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoredTxid(pub Vec<u8>);
@@ -2,9 +2,13 @@ use crate::{user_profile_model::UserProfileModel, StoredPrincipal};
 use ic_cdk::api::time;
 use shared::types::dapp::AddDappSettingsError;
 use shared::types::{
-    user_profile::{AddUserCredentialError, GetUserProfileError, StoredUserProfile},
-    CredentialType, Version,
+    user_profile::{
+        AcceptAgreementsError, AddUserCredentialError, GetUserProfileError, StoredUserProfile,
+        UserProfileChangeKind,
+    },
+    AgreementKind, AgreementVersion, CredentialType, Version,
 };
+use std::collections::BTreeMap;
 
 pub fn find_profile(
     principal: StoredPrincipal,
@@ -26,7 +30,12 @@ pub fn create_profile(
     } else {
         let now = time();
         let default_profile = StoredUserProfile::from_timestamp(now);
-        user_profile_model.store_new(principal, now, &default_profile);
+        user_profile_model.store_new(
+            principal,
+            now,
+            &default_profile,
+            UserProfileChangeKind::Created,
+        );
         default_profile
     }
 }
@@ -43,7 +52,12 @@ pub fn add_credential(
         if let Ok(new_profile) =
             user_profile.add_credential(profile_version, now, credential_type, issuer)
         {
-            user_profile_model.store_new(principal, now, &new_profile);
+            user_profile_model.store_new(
+                principal,
+                now,
+                &new_profile,
+                UserProfileChangeKind::CredentialAdded,
+            );
             Ok(())
         } else {
             Err(AddUserCredentialError::VersionMismatch)
@@ -76,6 +90,43 @@ pub fn add_hidden_dapp_id(
         .map_err(|_| AddDappSettingsError::UserNotFound)?;
     let now = time();
     let new_profile = user_profile.add_hidden_dapp_id(profile_version, now, dapp_id)?;
-    user_profile_model.store_new(principal, now, &new_profile);
+    user_profile_model.store_new(
+        principal,
+        now,
+        &new_profile,
+        UserProfileChangeKind::HiddenDappIdAdded,
+    );
+    Ok(())
+}
+
+/// Records that the user has accepted the given agreement versions.
+///
+/// # Arguments
+/// * `principal` - The principal of the user.
+/// * `profile_version` - The version of the user's profile.
+/// * `accepted` - The agreement versions that the user has accepted.
+/// * `required` - The agreement versions that users are currently required to accept.
+/// * `user_profile_model` - The user profile model.
+///
+/// # Errors
+/// - Returns `Err` if the user profile is not found, the user profile version is not up-to-date,
+///   or an accepted agreement version is not the currently required one.
+pub fn accept_agreements(
+    principal: StoredPrincipal,
+    profile_version: Option<Version>,
+    accepted: &BTreeMap<AgreementKind, AgreementVersion>,
+    required: &BTreeMap<AgreementKind, AgreementVersion>,
+    user_profile_model: &mut UserProfileModel,
+) -> Result<(), AcceptAgreementsError> {
+    let user_profile = find_profile(principal, user_profile_model)
+        .map_err(|_| AcceptAgreementsError::UserNotFound)?;
+    let now = time();
+    let new_profile = user_profile.accept_agreements(profile_version, now, accepted, required)?;
+    user_profile_model.store_new(
+        principal,
+        now,
+        &new_profile,
+        UserProfileChangeKind::AgreementsAccepted,
+    );
     Ok(())
 }
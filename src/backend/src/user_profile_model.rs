@@ -1,9 +1,20 @@
-use crate::types::{Candid, StoredPrincipal, UserProfileMap, UserProfileUpdatedMap};
-use shared::types::{user_profile::StoredUserProfile, Timestamp};
+use crate::types::{
+    Candid, StoredPrincipal, UserProfileHistoryMap, UserProfileMap, UserProfileUpdatedMap,
+};
+use shared::types::{
+    user_profile::{StoredUserProfile, UserProfileChangeKind, UserProfileHistoryEntry},
+    Timestamp, UserProfileHistoryConfig,
+};
+use std::ops::RangeInclusive;
+
+/// The number of history entries kept per user, if not set in the config.
+const DEFAULT_MAX_HISTORY_ENTRIES_PER_USER: u64 = 100;
 
 pub struct UserProfileModel<'a> {
     user_profile_map: &'a mut UserProfileMap,
     user_profile_updated_map: &'a mut UserProfileUpdatedMap,
+    user_profile_history_map: &'a mut UserProfileHistoryMap,
+    history_config: UserProfileHistoryConfig,
 }
 
 /// `UserProfileModel` should be used to access and manage the state to user profiles in the stable memory
@@ -11,10 +22,14 @@ impl<'a> UserProfileModel<'a> {
     pub fn new(
         user_profile_map: &'a mut UserProfileMap,
         user_profile_updated_map: &'a mut UserProfileUpdatedMap,
+        user_profile_history_map: &'a mut UserProfileHistoryMap,
+        history_config: UserProfileHistoryConfig,
     ) -> UserProfileModel<'a> {
         UserProfileModel {
             user_profile_map,
             user_profile_updated_map,
+            user_profile_history_map,
+            history_config,
         }
     }
 
@@ -28,12 +43,16 @@ impl<'a> UserProfileModel<'a> {
         }
     }
 
+    /// Stores a new version of the user profile, replacing the previous one, and records the
+    /// change in the user's profile history.
     pub fn store_new(
         &mut self,
         user_principal: StoredPrincipal,
         timestamp: Timestamp,
         new_user: &StoredUserProfile,
+        kind: UserProfileChangeKind,
     ) {
+        let old_user = self.find_by_principal(user_principal);
         if let Some(old_updated) = self.user_profile_updated_map.get(&user_principal) {
             // Clean up old entries
             self.user_profile_map.remove(&(old_updated, user_principal));
@@ -42,6 +61,64 @@ impl<'a> UserProfileModel<'a> {
             .insert(user_principal, timestamp);
         self.user_profile_map
             .insert((timestamp, user_principal), Candid(new_user.clone()));
+
+        let changes = new_user.changes_since(old_user.as_ref());
+        // Storing an unchanged profile, e.g. re-hiding a hidden dApp, is not worth recording.
+        if old_user.is_none() || !changes.is_empty() {
+            self.append_history(
+                user_principal,
+                UserProfileHistoryEntry {
+                    timestamp,
+                    version: new_user.version,
+                    kind,
+                    changes,
+                },
+            );
+        }
+    }
+
+    /// Returns the change history of a user profile, oldest first.
+    pub fn history(&self, user_principal: StoredPrincipal) -> Vec<UserProfileHistoryEntry> {
+        self.user_profile_history_map
+            .range(history_range(user_principal))
+            .map(|(_, entry)| entry.0)
+            .collect()
+    }
+
+    /// Appends an entry to the user's history, then drops entries that exceed the retention limits.
+    fn append_history(&mut self, user_principal: StoredPrincipal, entry: UserProfileHistoryEntry) {
+        let next_sequence_number = self
+            .user_profile_history_map
+            .keys_range(history_range(user_principal))
+            .next_back()
+            .map_or(0, |(_, sequence_number)| sequence_number + 1);
+        let oldest_allowed_timestamp = self
+            .history_config
+            .max_age_ns
+            .map(|max_age_ns| entry.timestamp.saturating_sub(max_age_ns));
+        self.user_profile_history_map
+            .insert((user_principal, next_sequence_number), Candid(entry));
+
+        let max_entries = self
+            .history_config
+            .max_entries_per_user
+            .unwrap_or(DEFAULT_MAX_HISTORY_ENTRIES_PER_USER);
+        let entries: Vec<((StoredPrincipal, u64), Timestamp)> = self
+            .user_profile_history_map
+            .range(history_range(user_principal))
+            .map(|(key, entry)| (key, entry.timestamp))
+            .collect();
+        let excess = u64::try_from(entries.len())
+            .unwrap_or(u64::MAX)
+            .saturating_sub(max_entries);
+        for (index, (key, timestamp)) in (0..).zip(entries) {
+            let is_excess = index < excess;
+            let is_expired = oldest_allowed_timestamp.is_some_and(|oldest| timestamp < oldest);
+            if !is_excess && !is_expired {
+                break;
+            }
+            self.user_profile_history_map.remove(&key);
+        }
     }
 
     #[cfg(test)]
@@ -62,6 +139,11 @@ impl<'a> UserProfileModel<'a> {
     }
 }
 
+/// The range of keys in the history map that belong to the given user.
+fn history_range(user_principal: StoredPrincipal) -> RangeInclusive<(StoredPrincipal, u64)> {
+    (user_principal, 0)..=(user_principal, u64::MAX)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,26 +152,38 @@ mod tests {
         memory_manager::{MemoryId, MemoryManager},
         DefaultMemoryImpl,
     };
-    use shared::types::{user_profile::StoredUserProfile, Timestamp};
+    use pretty_assertions::assert_eq;
+    use shared::types::{
+        user_profile::{StoredUserProfile, UserProfileChange},
+        Timestamp,
+    };
     use std::cell::RefCell;
 
     const USER_1: &str = "xzg7k-thc6c-idntg-knmtz-2fbhh-utt3e-snqw6-5xph3-54pbp-7axl5-tae";
     const USER_2: &str = "ufjdl-kewp5-bgfaq-d7k34-e5w62-nyad4-7r3s5-m2pt2-owqga-kcr5z-jae";
 
-    fn prepare_btrees() -> (UserProfileMap, UserProfileUpdatedMap) {
+    fn prepare_btrees() -> (UserProfileMap, UserProfileUpdatedMap, UserProfileHistoryMap) {
         const USER_PROFILE_MEMORY_ID: MemoryId = MemoryId::new(3);
         const USER_PROFILE_UPDATED_MEMORY_ID: MemoryId = MemoryId::new(4);
+        const USER_PROFILE_HISTORY_MEMORY_ID: MemoryId = MemoryId::new(5);
         let memory = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
         let user_profile_map = UserProfileMap::new(memory.borrow().get(USER_PROFILE_MEMORY_ID));
         let user_profile_updated_map =
             UserProfileUpdatedMap::new(memory.borrow().get(USER_PROFILE_UPDATED_MEMORY_ID));
+        let user_profile_history_map =
+            UserProfileHistoryMap::new(memory.borrow().get(USER_PROFILE_HISTORY_MEMORY_ID));
 
-        (user_profile_map, user_profile_updated_map)
+        (
+            user_profile_map,
+            user_profile_updated_map,
+            user_profile_history_map,
+        )
     }
 
     #[test]
     fn test_find_by_principal_returns_profiles() {
-        let (mut user_profile_map, mut user_profile_updated_map) = prepare_btrees();
+        let (mut user_profile_map, mut user_profile_updated_map, mut user_profile_history_map) =
+            prepare_btrees();
 
         let user_principal =
             StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
@@ -108,8 +202,12 @@ mod tests {
         );
         user_profile_updated_map.insert(user_principal_2, another_now);
 
-        let user_profile_model =
-            UserProfileModel::new(&mut user_profile_map, &mut user_profile_updated_map);
+        let user_profile_model = UserProfileModel::new(
+            &mut user_profile_map,
+            &mut user_profile_updated_map,
+            &mut user_profile_history_map,
+            UserProfileHistoryConfig::default(),
+        );
 
         assert_eq!(
             user_profile_model
@@ -129,7 +227,8 @@ mod tests {
 
     #[test]
     fn test_store_new_saves_profiles() {
-        let (mut user_profile_map, mut user_profile_updated_map) = prepare_btrees();
+        let (mut user_profile_map, mut user_profile_updated_map, mut user_profile_history_map) =
+            prepare_btrees();
 
         let user_principal =
             StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
@@ -148,13 +247,22 @@ mod tests {
         );
         user_profile_updated_map.insert(user_principal_2, another_now);
 
-        let mut user_profile_model =
-            UserProfileModel::new(&mut user_profile_map, &mut user_profile_updated_map);
+        let mut user_profile_model = UserProfileModel::new(
+            &mut user_profile_map,
+            &mut user_profile_updated_map,
+            &mut user_profile_history_map,
+            UserProfileHistoryConfig::default(),
+        );
 
         let mut user_profile_2_updated = user_profile_2.clone();
         let later_timestamp = another_now + 400000000;
         user_profile_2_updated.updated_timestamp = later_timestamp;
-        user_profile_model.store_new(user_principal_2, later_timestamp, &user_profile_2_updated);
+        user_profile_model.store_new(
+            user_principal_2,
+            later_timestamp,
+            &user_profile_2_updated,
+            UserProfileChangeKind::HiddenDappIdAdded,
+        );
 
         assert_eq!(
             user_profile_model
@@ -179,4 +287,187 @@ mod tests {
         );
         user_profile_model.assert_consistent();
     }
+
+    fn history_test_model(
+        maps: &mut (UserProfileMap, UserProfileUpdatedMap, UserProfileHistoryMap),
+        history_config: UserProfileHistoryConfig,
+    ) -> UserProfileModel<'_> {
+        let (user_profile_map, user_profile_updated_map, user_profile_history_map) = maps;
+        UserProfileModel::new(
+            user_profile_map,
+            user_profile_updated_map,
+            user_profile_history_map,
+            history_config,
+        )
+    }
+
+    #[test]
+    fn test_store_new_records_history() {
+        let mut maps = prepare_btrees();
+        let mut user_profile_model =
+            history_test_model(&mut maps, UserProfileHistoryConfig::default());
+        let user_principal =
+            StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
+        let now: Timestamp = 12345667788223;
+        let profile = StoredUserProfile::from_timestamp(now);
+        user_profile_model.store_new(
+            user_principal,
+            now,
+            &profile,
+            UserProfileChangeKind::Created,
+        );
+        let later = now + 1000;
+        let updated_profile = profile
+            .add_hidden_dapp_id(profile.version, later, "test_dapp_id".to_string())
+            .unwrap();
+        user_profile_model.store_new(
+            user_principal,
+            later,
+            &updated_profile,
+            UserProfileChangeKind::HiddenDappIdAdded,
+        );
+
+        assert_eq!(
+            user_profile_model.history(user_principal),
+            vec![
+                UserProfileHistoryEntry {
+                    timestamp: now,
+                    version: None,
+                    kind: UserProfileChangeKind::Created,
+                    changes: vec![],
+                },
+                UserProfileHistoryEntry {
+                    timestamp: later,
+                    version: Some(1),
+                    kind: UserProfileChangeKind::HiddenDappIdAdded,
+                    changes: vec![UserProfileChange::HiddenDappIdAdded {
+                        dapp_id: "test_dapp_id".to_string()
+                    }],
+                },
+            ]
+        );
+        let user_principal_2 =
+            StoredPrincipal(Principal::from_text(USER_2).expect("invalid user principal"));
+        assert!(user_profile_model.history(user_principal_2).is_empty());
+        user_profile_model.assert_consistent();
+    }
+
+    #[test]
+    fn test_store_new_does_not_record_unchanged_profile() {
+        let mut maps = prepare_btrees();
+        let mut user_profile_model =
+            history_test_model(&mut maps, UserProfileHistoryConfig::default());
+        let user_principal =
+            StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
+        let now: Timestamp = 12345667788223;
+        let profile = StoredUserProfile::from_timestamp(now);
+        user_profile_model.store_new(
+            user_principal,
+            now,
+            &profile,
+            UserProfileChangeKind::Created,
+        );
+        user_profile_model.store_new(
+            user_principal,
+            now + 1000,
+            &profile,
+            UserProfileChangeKind::HiddenDappIdAdded,
+        );
+
+        assert_eq!(user_profile_model.history(user_principal).len(), 1);
+    }
+
+    #[test]
+    fn test_history_is_bounded_by_max_entries() {
+        let mut maps = prepare_btrees();
+        let mut user_profile_model = history_test_model(
+            &mut maps,
+            UserProfileHistoryConfig {
+                max_entries_per_user: Some(3),
+                max_age_ns: None,
+            },
+        );
+        let user_principal =
+            StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
+        let mut now: Timestamp = 12345667788223;
+        let mut profile = StoredUserProfile::from_timestamp(now);
+        user_profile_model.store_new(
+            user_principal,
+            now,
+            &profile,
+            UserProfileChangeKind::Created,
+        );
+        for i in 0..5 {
+            now += 1000;
+            profile = profile
+                .add_hidden_dapp_id(profile.version, now, format!("dapp_{i}"))
+                .unwrap();
+            user_profile_model.store_new(
+                user_principal,
+                now,
+                &profile,
+                UserProfileChangeKind::HiddenDappIdAdded,
+            );
+        }
+
+        let history = user_profile_model.history(user_principal);
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| entry.version)
+                .collect::<Vec<_>>(),
+            vec![Some(3), Some(4), Some(5)]
+        );
+    }
+
+    #[test]
+    fn test_history_drops_expired_entries() {
+        let mut maps = prepare_btrees();
+        let mut user_profile_model = history_test_model(
+            &mut maps,
+            UserProfileHistoryConfig {
+                max_entries_per_user: None,
+                max_age_ns: Some(1500),
+            },
+        );
+        let user_principal =
+            StoredPrincipal(Principal::from_text(USER_1).expect("invalid user principal"));
+        let now: Timestamp = 12345667788223;
+        let profile = StoredUserProfile::from_timestamp(now);
+        user_profile_model.store_new(
+            user_principal,
+            now,
+            &profile,
+            UserProfileChangeKind::Created,
+        );
+        let profile = profile
+            .add_hidden_dapp_id(profile.version, now + 1000, "dapp_1".to_string())
+            .unwrap();
+        user_profile_model.store_new(
+            user_principal,
+            now + 1000,
+            &profile,
+            UserProfileChangeKind::HiddenDappIdAdded,
+        );
+        assert_eq!(user_profile_model.history(user_principal).len(), 2);
+
+        let profile = profile
+            .add_hidden_dapp_id(profile.version, now + 2000, "dapp_2".to_string())
+            .unwrap();
+        user_profile_model.store_new(
+            user_principal,
+            now + 2000,
+            &profile,
+            UserProfileChangeKind::HiddenDappIdAdded,
+        );
+
+        let history = user_profile_model.history(user_principal);
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| entry.timestamp)
+                .collect::<Vec<_>>(),
+            vec![now + 1000, now + 2000]
+        );
+    }
 }
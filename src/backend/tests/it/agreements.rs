@@ -0,0 +1,149 @@
+use crate::utils::{
+    mock::{CALLER, USER_1},
+    pocketic::{setup, PicCanisterTrait},
+};
+use candid::Principal;
+use pretty_assertions::assert_eq;
+use shared::types::{
+    user_profile::{
+        AcceptAgreementsError, AcceptAgreementsRequest, ListUsersRequest, ListUsersResponse,
+        UserProfile,
+    },
+    AgreementKind, SetRequiredAgreementVersionRequest,
+};
+use std::collections::BTreeMap;
+
+fn publish_terms_of_service(pic_setup: &impl PicCanisterTrait, version: u64) {
+    let request = SetRequiredAgreementVersionRequest {
+        agreement: AgreementKind::TermsOfService,
+        version,
+    };
+    pic_setup
+        .update::<()>(
+            Principal::from_text(CALLER).unwrap(),
+            "set_required_agreement_version",
+            request,
+        )
+        .expect("Failed to publish agreement version");
+}
+
+fn list_users_without_terms_of_service(pic_setup: &impl PicCanisterTrait) -> Vec<Principal> {
+    let request = ListUsersRequest {
+        updated_after_timestamp: None,
+        updated_after_principal: None,
+        matches_max_length: None,
+        agreement_not_accepted: Some(AgreementKind::TermsOfService),
+    };
+    pic_setup
+        .query::<ListUsersResponse>(Principal::from_text(CALLER).unwrap(), "list_users", request)
+        .expect("Failed to list users")
+        .users
+        .into_iter()
+        .map(|user| user.principal)
+        .collect()
+}
+
+#[test]
+fn test_only_allowed_callers_can_publish_agreement_versions() {
+    let pic_setup = setup();
+
+    let request = SetRequiredAgreementVersionRequest {
+        agreement: AgreementKind::TermsOfService,
+        version: 1,
+    };
+    let response = pic_setup.update::<()>(
+        Principal::from_text(USER_1).unwrap(),
+        "set_required_agreement_version",
+        request,
+    );
+
+    assert!(response.is_err());
+}
+
+#[test]
+fn test_required_agreement_version_cannot_be_lowered() {
+    let pic_setup = setup();
+    publish_terms_of_service(&pic_setup, 2);
+
+    let request = SetRequiredAgreementVersionRequest {
+        agreement: AgreementKind::TermsOfService,
+        version: 1,
+    };
+    let response = pic_setup.update::<()>(
+        Principal::from_text(CALLER).unwrap(),
+        "set_required_agreement_version",
+        request,
+    );
+
+    assert!(response.unwrap_err().contains("cannot be lowered to 1"));
+    // Publishing the same version again is harmless.
+    publish_terms_of_service(&pic_setup, 2);
+}
+
+#[test]
+fn test_accept_agreements_records_accepted_version() {
+    let pic_setup = setup();
+    let caller = Principal::from_text(USER_1).unwrap();
+    publish_terms_of_service(&pic_setup, 1);
+
+    let profile = pic_setup
+        .update::<UserProfile>(caller, "create_user_profile", ())
+        .expect("Create failed");
+    assert_eq!(
+        list_users_without_terms_of_service(&pic_setup),
+        vec![caller]
+    );
+
+    let request = AcceptAgreementsRequest {
+        agreements: BTreeMap::from([(AgreementKind::TermsOfService, 1)]),
+        current_user_version: profile.version,
+    };
+    pic_setup
+        .update::<Result<(), AcceptAgreementsError>>(caller, "accept_agreements", request)
+        .expect("Call to accept agreements failed")
+        .expect("Accept agreements failed");
+
+    let profile = pic_setup
+        .update::<UserProfile>(caller, "create_user_profile", ())
+        .expect("Get failed");
+    let accepted = profile
+        .agreements
+        .and_then(|agreements| agreements.get(&AgreementKind::TermsOfService).copied())
+        .expect("Terms of service should have been accepted");
+    assert_eq!(accepted.version, 1);
+    assert_eq!(list_users_without_terms_of_service(&pic_setup), vec![]);
+
+    // Publishing a new version requires the user to accept it again.
+    publish_terms_of_service(&pic_setup, 2);
+    assert_eq!(
+        list_users_without_terms_of_service(&pic_setup),
+        vec![caller]
+    );
+}
+
+#[test]
+fn test_accept_agreements_rejects_outdated_version() {
+    let pic_setup = setup();
+    let caller = Principal::from_text(USER_1).unwrap();
+    publish_terms_of_service(&pic_setup, 2);
+
+    let profile = pic_setup
+        .update::<UserProfile>(caller, "create_user_profile", ())
+        .expect("Create failed");
+
+    let request = AcceptAgreementsRequest {
+        agreements: BTreeMap::from([(AgreementKind::TermsOfService, 1)]),
+        current_user_version: profile.version,
+    };
+    let response = pic_setup
+        .update::<Result<(), AcceptAgreementsError>>(caller, "accept_agreements", request)
+        .expect("Call to accept agreements failed");
+
+    assert_eq!(
+        response,
+        Err(AcceptAgreementsError::AgreementVersionMismatch {
+            agreement: AgreementKind::TermsOfService,
+            required_version: Some(2),
+        })
+    );
+}
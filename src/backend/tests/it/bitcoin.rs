@@ -1,17 +1,82 @@
+use bitcoin::{
+    absolute, hashes::Hash, transaction, Address, Amount, OutPoint, Transaction, TxIn, TxOut, Txid,
+};
 use candid::Principal;
 use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
+use pretty_assertions::assert_eq;
 use shared::types::bitcoin::{
-    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcGetPendingTransactionsError,
-    BtcGetPendingTransactionsReponse, BtcGetPendingTransactionsRequest, SelectedUtxosFeeError,
-    SelectedUtxosFeeRequest, SelectedUtxosFeeResponse,
+    BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcAddressType,
+    BtcBuildTransactionError, BtcBuildTransactionRequest, BtcBuildTransactionResponse,
+    BtcBumpFeeError, BtcBumpFeeRequest, BtcCpfpError, BtcCpfpRequest, BtcFeeTier,
+    BtcFreezeUtxosError, BtcFreezeUtxosRequest, BtcFrozenUtxo, BtcGetAddressError,
+    BtcGetAddressRequest, BtcGetAddressResponse, BtcGetBalanceError, BtcGetBalanceRequest,
+    BtcGetBalanceResponse, BtcGetFeeTiersError, BtcGetFeeTiersRequest, BtcGetFeeTiersResponse,
+    BtcGetPendingTransactionsError, BtcGetPendingTransactionsReponse,
+    BtcGetPendingTransactionsRequest, BtcParentTransaction, BtcPendingTransactionStatus,
+    BtcSubmitTransactionError, BtcSubmitTransactionRequest, BtcSubmitTransactionResponse,
+    BtcTxOutput, BtcUnfreezeUtxosRequest, SelectedUtxosFeeError, SelectedUtxosFeeRequest,
+    SelectedUtxosFeeResponse,
 };
+use std::str::FromStr;
 
 use crate::utils::{
-    mock::CALLER,
+    mock::{CALLER, USER_1},
     pocketic::{setup, PicCanisterTrait},
 };
 
-const MOCK_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+pub const MOCK_ADDRESS: &str = "bcrt1qpg7udjvq7gx2fp480pgt4hnhj3qc4nhrkstc33";
+
+#[test]
+fn test_btc_get_address_returns_address_of_each_type() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for (address_type, prefix) in [
+        (None, "bcrt1q"),
+        (Some(BtcAddressType::P2wpkh), "bcrt1q"),
+        (Some(BtcAddressType::P2tr), "bcrt1p"),
+    ] {
+        let request = BtcGetAddressRequest {
+            network: BitcoinNetwork::Regtest,
+            address_type,
+        };
+        let response = pic_setup
+            .update::<Result<BtcGetAddressResponse, BtcGetAddressError>>(
+                caller,
+                "btc_get_address",
+                request,
+            )
+            .expect("Call failed")
+            .expect("Request was not successful");
+
+        assert!(response.address.starts_with(prefix));
+    }
+}
+
+#[test]
+fn test_btc_get_balance_of_empty_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for address_type in [None, Some(BtcAddressType::P2tr)] {
+        let request = BtcGetBalanceRequest {
+            network: BitcoinNetwork::Regtest,
+            address_type,
+            min_confirmations: None,
+        };
+        let response = pic_setup
+            .update::<Result<BtcGetBalanceResponse, BtcGetBalanceError>>(
+                caller,
+                "btc_get_balance",
+                request,
+            )
+            .expect("Call failed");
+
+        assert_eq!(response, Ok(BtcGetBalanceResponse::default()));
+    }
+}
 
 #[test]
 fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
@@ -23,6 +88,13 @@ fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
         amount_satoshis: 100_000_000u64,
         network: BitcoinNetwork::Regtest,
         min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        outputs: None,
+        coin_selection: None,
+        sweep: None,
+        address_type: None,
+        outpoints: None,
     };
     let response = pic_setup.update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
         caller,
@@ -38,6 +110,450 @@ fn test_select_user_utxos_fee_returns_zero_when_user_has_insufficient_funds() {
 
     assert_eq!(response.utxos.len(), 0);
     assert_eq!(response.fee_satoshis, 0);
+    assert_eq!(response.change_satoshis, 0);
+    assert_eq!(response.outputs, vec![]);
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_fee_rate_and_tier_together() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 100_000_000u64,
+        network: BitcoinNetwork::Regtest,
+        min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: Some(5_000),
+        fee_tier: Some(BtcFeeTier::Fast),
+        outputs: None,
+        coin_selection: None,
+        sweep: None,
+        address_type: None,
+        outpoints: None,
+    };
+    let response = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
+            caller,
+            "btc_select_user_utxos_fee",
+            request,
+        )
+        .expect("Call failed");
+
+    assert!(matches!(
+        response,
+        Err(SelectedUtxosFeeError::InvalidFeeRequest { .. })
+    ));
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_address_of_other_network() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let mainnet_address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string();
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 3_000u64,
+        network: BitcoinNetwork::Regtest,
+        min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        outputs: Some(vec![
+            BtcTxOutput {
+                destination_address: MOCK_ADDRESS.to_string(),
+                sent_satoshis: 1_000,
+            },
+            BtcTxOutput {
+                destination_address: mainnet_address.clone(),
+                sent_satoshis: 2_000,
+            },
+        ]),
+        coin_selection: None,
+        sweep: None,
+        address_type: None,
+        outpoints: None,
+    };
+    let response = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
+            caller,
+            "btc_select_user_utxos_fee",
+            request,
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Err(SelectedUtxosFeeError::InvalidDestinationAddress {
+            address: mainnet_address
+        })
+    );
+}
+
+#[test]
+fn test_select_user_utxos_fee_sweep_without_funds_is_insufficient() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 0u64,
+        network: BitcoinNetwork::Regtest,
+        min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        outputs: Some(vec![BtcTxOutput {
+            destination_address: MOCK_ADDRESS.to_string(),
+            sent_satoshis: 0,
+        }]),
+        coin_selection: None,
+        sweep: Some(true),
+        address_type: None,
+        outpoints: None,
+    };
+    let response = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
+            caller,
+            "btc_select_user_utxos_fee",
+            request,
+        )
+        .expect("Call failed");
+
+    assert_eq!(response, Err(SelectedUtxosFeeError::InsufficientFunds));
+}
+
+#[test]
+fn test_select_user_utxos_fee_rejects_unknown_outpoint() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let outpoint = Outpoint {
+        txid: vec![1; 32],
+        vout: 0,
+    };
+
+    let request = SelectedUtxosFeeRequest {
+        amount_satoshis: 10_000u64,
+        network: BitcoinNetwork::Regtest,
+        min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        outputs: None,
+        coin_selection: None,
+        sweep: None,
+        address_type: None,
+        outpoints: Some(vec![outpoint.clone()]),
+    };
+    let response = pic_setup
+        .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
+            caller,
+            "btc_select_user_utxos_fee",
+            request,
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Err(SelectedUtxosFeeError::UtxoNotFound { outpoint })
+    );
+}
+
+#[test]
+fn test_freeze_and_unfreeze_utxos() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let frozen_utxo = |vout: u32, label: Option<&str>| BtcFrozenUtxo {
+        outpoint: Outpoint {
+            txid: vec![1; 32],
+            vout,
+        },
+        label: label.map(ToString::to_string),
+    };
+
+    let before = pic_setup.query::<Vec<BtcFrozenUtxo>>(caller, "btc_list_frozen_utxos", ());
+    assert_eq!(before, Ok(vec![]));
+
+    for utxos in [
+        vec![frozen_utxo(0, Some("inscription")), frozen_utxo(1, None)],
+        vec![frozen_utxo(1, Some("rare sat"))],
+    ] {
+        pic_setup
+            .update::<Result<(), BtcFreezeUtxosError>>(
+                caller,
+                "btc_freeze_utxos",
+                BtcFreezeUtxosRequest { utxos },
+            )
+            .expect("Call failed")
+            .expect("Failed to freeze UTXOs");
+    }
+    let frozen = pic_setup.query::<Vec<BtcFrozenUtxo>>(caller, "btc_list_frozen_utxos", ());
+    assert_eq!(
+        frozen,
+        Ok(vec![
+            frozen_utxo(0, Some("inscription")),
+            frozen_utxo(1, Some("rare sat"))
+        ])
+    );
+
+    let too_long = pic_setup
+        .update::<Result<(), BtcFreezeUtxosError>>(
+            caller,
+            "btc_freeze_utxos",
+            BtcFreezeUtxosRequest {
+                utxos: vec![frozen_utxo(2, Some(&"a".repeat(101)))],
+            },
+        )
+        .expect("Call failed");
+    assert_eq!(
+        too_long,
+        Err(BtcFreezeUtxosError::LabelTooLong {
+            max_label_length: 100
+        })
+    );
+
+    pic_setup
+        .update::<()>(
+            caller,
+            "btc_unfreeze_utxos",
+            BtcUnfreezeUtxosRequest {
+                outpoints: vec![frozen_utxo(0, None).outpoint],
+            },
+        )
+        .expect("Call failed");
+    let after = pic_setup.query::<Vec<BtcFrozenUtxo>>(caller, "btc_list_frozen_utxos", ());
+    assert_eq!(after, Ok(vec![frozen_utxo(1, Some("rare sat"))]));
+
+    // Other users have their own frozen UTXOs.
+    let other = pic_setup.query::<Vec<BtcFrozenUtxo>>(
+        Principal::from_text(USER_1).unwrap(),
+        "btc_list_frozen_utxos",
+        (),
+    );
+    assert_eq!(other, Ok(vec![]));
+}
+
+fn build_transaction_request(change_address: Option<String>) -> BtcBuildTransactionRequest {
+    BtcBuildTransactionRequest {
+        network: BitcoinNetwork::Regtest,
+        outputs: vec![BtcTxOutput {
+            destination_address: MOCK_ADDRESS.to_string(),
+            sent_satoshis: 10_000,
+        }],
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        change_address,
+        address_type: None,
+        min_confirmations: None,
+        coin_selection: None,
+        outpoints: None,
+    }
+}
+
+#[test]
+fn test_build_transaction_without_funds_fails() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<BtcBuildTransactionResponse, BtcBuildTransactionError>>(
+            caller,
+            "btc_build_transaction",
+            build_transaction_request(None),
+        )
+        .expect("Call failed");
+
+    assert_eq!(response, Err(BtcBuildTransactionError::InsufficientFunds));
+}
+
+#[test]
+fn test_build_transaction_rejects_invalid_change_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let mainnet_address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq".to_string();
+
+    let response = pic_setup
+        .update::<Result<BtcBuildTransactionResponse, BtcBuildTransactionError>>(
+            caller,
+            "btc_build_transaction",
+            build_transaction_request(Some(mainnet_address.clone())),
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Err(BtcBuildTransactionError::InvalidChangeAddress {
+            address: mainnet_address
+        })
+    );
+}
+
+#[test]
+fn test_bump_fee_of_unknown_transaction_fails() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<BtcBuildTransactionResponse, BtcBumpFeeError>>(
+            caller,
+            "btc_bump_fee",
+            BtcBumpFeeRequest {
+                network: BitcoinNetwork::Regtest,
+                txid: vec![7; 32],
+                fee_rate_millisatoshi_per_vbyte: 10_000,
+                address_type: None,
+            },
+        )
+        .expect("Call failed");
+
+    assert_eq!(response, Err(BtcBumpFeeError::TransactionNotFound));
+}
+
+fn cpfp_request(parent: Option<BtcParentTransaction>) -> BtcCpfpRequest {
+    BtcCpfpRequest {
+        network: BitcoinNetwork::Regtest,
+        parent_txid: vec![7; 32],
+        parent,
+        fee_rate_millisatoshi_per_vbyte: 10_000,
+        destination_address: None,
+        address_type: None,
+    }
+}
+
+#[test]
+fn test_cpfp_requires_size_and_fee_of_unknown_parent() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<BtcBuildTransactionResponse, BtcCpfpError>>(
+            caller,
+            "btc_accelerate_with_cpfp",
+            cpfp_request(None),
+        )
+        .expect("Call failed");
+
+    assert_eq!(response, Err(BtcCpfpError::ParentUnknown));
+}
+
+#[test]
+fn test_cpfp_of_unknown_parent_fails_even_with_size_and_fee() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<BtcBuildTransactionResponse, BtcCpfpError>>(
+            caller,
+            "btc_accelerate_with_cpfp",
+            cpfp_request(Some(BtcParentTransaction {
+                vsize: 141,
+                fee_satoshis: 141,
+            })),
+        )
+        .expect("Call failed");
+
+    // The outputs of an unconfirmed transaction are only known if the caller sent it.
+    assert_eq!(response, Err(BtcCpfpError::ParentUnknown));
+}
+
+#[test]
+fn test_submit_transaction_rejects_invalid_transaction() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<BtcSubmitTransactionResponse, BtcSubmitTransactionError>>(
+            caller,
+            "btc_submit_transaction",
+            BtcSubmitTransactionRequest {
+                network: BitcoinNetwork::Regtest,
+                raw_tx: vec![1, 2, 3],
+                address_type: None,
+            },
+        )
+        .expect("Call failed");
+
+    assert!(matches!(
+        response,
+        Err(BtcSubmitTransactionError::InvalidTransaction { .. })
+    ));
+}
+
+#[test]
+fn test_submit_transaction_rejects_inputs_of_others() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let foreign_outpoint = OutPoint {
+        txid: Txid::from_byte_array([7; 32]),
+        vout: 1,
+    };
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: foreign_outpoint,
+            ..TxIn::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: Address::from_str(MOCK_ADDRESS)
+                .unwrap()
+                .assume_checked()
+                .script_pubkey(),
+        }],
+    };
+
+    let response = pic_setup
+        .update::<Result<BtcSubmitTransactionResponse, BtcSubmitTransactionError>>(
+            caller,
+            "btc_submit_transaction",
+            BtcSubmitTransactionRequest {
+                network: BitcoinNetwork::Regtest,
+                raw_tx: bitcoin::consensus::serialize(&tx),
+                address_type: None,
+            },
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Err(BtcSubmitTransactionError::ForeignInput {
+            txid: vec![7; 32],
+            vout: 1,
+        })
+    );
+}
+
+#[test]
+fn test_get_fee_tiers_returns_all_tiers() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let request = BtcGetFeeTiersRequest {
+        network: BitcoinNetwork::Regtest,
+    };
+    let response = pic_setup
+        .update::<Result<BtcGetFeeTiersResponse, BtcGetFeeTiersError>>(
+            caller,
+            "btc_get_fee_tiers",
+            request,
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+
+    let tiers: Vec<BtcFeeTier> = response.tiers.iter().map(|tier| tier.tier).collect();
+    assert_eq!(
+        tiers,
+        vec![BtcFeeTier::Slow, BtcFeeTier::Standard, BtcFeeTier::Fast]
+    );
 }
 
 const UTXO_1: Utxo = Utxo {
@@ -78,6 +594,13 @@ fn test_select_user_utxos_fee_pending_transaction_error() {
         amount_satoshis: 100_000_000u64,
         network: BitcoinNetwork::Regtest,
         min_confirmations: None,
+        fee_rate_millisatoshi_per_vbyte: None,
+        fee_tier: None,
+        outputs: None,
+        coin_selection: None,
+        sweep: None,
+        address_type: None,
+        outpoints: None,
     };
     let select_response = pic_setup
         .update::<Result<SelectedUtxosFeeResponse, SelectedUtxosFeeError>>(
@@ -143,13 +666,15 @@ fn test_add_and_read_pending_transactions() {
         .expect("Call failed")
         .expect("Request was not successful");
 
-    // Call to `btc_get_pending_transactions` cleans up the pending transactoins
-    // because the get_utxos call returns an empty list.
-    // Therefore, the pending transaction utxos are not available,
-    // which means it's not a pending transaction anymore and it gets pruned.
-    assert_eq!(data.transactions.len(), 0);
-
-    // I tried removing the call to prune and the test returns the pending transaction as expected.
+    // The get_utxos call returns an empty list, so the utxos of the pending transaction are spent.
+    // Therefore, the transaction is confirmed by the time of the chain tip, and is returned as such.
     // Ideally, we mock the call to get_utxos to return the pending utxo
-    // and the pending transaction won't be pruned.
+    // and the transaction stays pending.
+    assert_eq!(data.transactions.len(), 1);
+    assert_eq!(data.transactions[0].txid, txid);
+    assert!(matches!(
+        data.transactions[0].status,
+        BtcPendingTransactionStatus::Confirmed { .. }
+    ));
+    assert_eq!(data.transactions[0].confirmations, 1);
 }
@@ -0,0 +1,508 @@
+use candid::{Nat, Principal};
+use ethers_core::{types::H160, utils::to_checksum};
+use pretty_assertions::assert_eq;
+use shared::types::{
+    ethereum::{
+        EthAddPendingTransactionError, EthAddPendingTransactionRequest, EthAddressError,
+        EthAddressResponse, EthGetPendingTransactionsRequest, EthGetPendingTransactionsResponse,
+        EthPersonalSignRequest, EthPrunePendingTransactionsRequest, EthReconcileNonceRequest,
+        EthReleaseNonceRequest, EthReserveNonceError, EthReserveNonceRequest,
+        EthReserveNonceResponse, EthSignMessageError, EthSignMessageResponse,
+        EthSignTransactionError, EthSignTransactionRequest, EthSignTransactionResponse,
+        EthSignTypedDataRequest, EthSigningHashResponse,
+    },
+    transaction::SignRequest,
+};
+
+use crate::utils::{
+    mock::{CALLER, USER_1},
+    pocketic::{controller, setup, PicBackend, PicCanisterTrait},
+};
+
+#[test]
+fn test_eth_address_of_caller_is_checksummed() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<EthAddressResponse, EthAddressError>>(caller, "eth_address_of_caller", ())
+        .expect("Call failed")
+        .expect("Request was not successful");
+
+    let address: H160 = response.address.parse().expect("Not an Ethereum address");
+    assert_eq!(response.address, to_checksum(&address, None));
+}
+
+#[test]
+fn test_eth_address_of_principal_matches_address_of_caller() {
+    let pic_setup = setup();
+
+    for user in [CALLER, USER_1] {
+        let user = Principal::from_text(user).unwrap();
+        let own = pic_setup
+            .update::<Result<EthAddressResponse, EthAddressError>>(
+                user,
+                "eth_address_of_caller",
+                (),
+            )
+            .expect("Call failed");
+        let of_principal = pic_setup
+            .update::<Result<EthAddressResponse, EthAddressError>>(
+                controller(),
+                "eth_address_of",
+                user,
+            )
+            .expect("Call failed");
+
+        assert_eq!(own, of_principal);
+    }
+}
+
+#[test]
+fn test_eth_address_of_cannot_be_called_if_not_allowed() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup.update::<Result<EthAddressResponse, EthAddressError>>(
+        caller,
+        "eth_address_of",
+        Principal::from_text(USER_1).unwrap(),
+    );
+
+    assert_eq!(response, Err("Caller is not allowed.".to_string()));
+}
+
+#[test]
+fn test_anonymous_cannot_get_eth_address() {
+    let pic_setup = setup();
+
+    let response = pic_setup.update::<Result<EthAddressResponse, EthAddressError>>(
+        Principal::anonymous(),
+        "eth_address_of_caller",
+        (),
+    );
+
+    assert_eq!(
+        response,
+        Err("Anonymous caller not authorized.".to_string())
+    );
+}
+
+fn sign_request(to: &str) -> SignRequest {
+    SignRequest {
+        chain_id: Nat::from(11_155_111_u32),
+        to: to.to_string(),
+        gas: Nat::from(21_000_u32),
+        max_fee_per_gas: Nat::from(30_000_000_000_u64),
+        max_priority_fee_per_gas: Nat::from(1_000_000_000_u64),
+        value: Nat::from(1_000_u32),
+        nonce: Nat::from(0_u32),
+        data: None,
+    }
+}
+
+fn sign_transaction_request(to: &str, signature: &str) -> EthSignTransactionRequest {
+    EthSignTransactionRequest {
+        transaction: sign_request(to),
+        signature: signature.to_string(),
+    }
+}
+
+#[test]
+fn test_eth_transaction_signing_hash() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .query::<Result<EthSigningHashResponse, EthSignTransactionError>>(
+            caller,
+            "eth_transaction_signing_hash",
+            sign_request("0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9"),
+        )
+        .expect("Call failed")
+        .expect("Request was not successful");
+
+    assert!(response.hash.starts_with("0x"));
+    assert_eq!(
+        hex::decode(&response.hash[2..]).map(|hash| hash.len()),
+        Ok(32)
+    );
+}
+
+#[test]
+fn test_eth_assemble_signed_transaction_rejects_invalid_to_address() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let hash_response = pic_setup
+        .query::<Result<EthSigningHashResponse, EthSignTransactionError>>(
+            caller,
+            "eth_transaction_signing_hash",
+            sign_request("0x1234"),
+        )
+        .expect("Call failed");
+    let response = pic_setup
+        .update::<Result<EthSignTransactionResponse, EthSignTransactionError>>(
+            caller,
+            "eth_assemble_signed_transaction",
+            sign_transaction_request("0x1234", &"01".repeat(64)),
+        )
+        .expect("Call failed");
+
+    let expected = EthSignTransactionError::InvalidToAddress {
+        address: "0x1234".to_string(),
+    };
+    assert_eq!(hash_response, Err(expected.clone()));
+    assert_eq!(response, Err(expected));
+}
+
+#[test]
+fn test_eth_assemble_signed_transaction_rejects_signatures_not_by_the_caller() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    // Neither a malformed signature nor a signature by another key is accepted.
+    for signature in ["0x1234".to_string(), format!("0x{}", "01".repeat(64))] {
+        let response = pic_setup
+            .update::<Result<EthSignTransactionResponse, EthSignTransactionError>>(
+                caller,
+                "eth_assemble_signed_transaction",
+                sign_transaction_request("0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9", &signature),
+            )
+            .expect("Call failed");
+
+        assert_eq!(response, Err(EthSignTransactionError::InvalidSignature));
+    }
+}
+
+#[test]
+fn test_anonymous_cannot_assemble_signed_eth_transaction() {
+    let pic_setup = setup();
+
+    let response = pic_setup.update::<Result<EthSignTransactionResponse, EthSignTransactionError>>(
+        Principal::anonymous(),
+        "eth_assemble_signed_transaction",
+        sign_transaction_request(
+            "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9",
+            &"01".repeat(64),
+        ),
+    );
+
+    assert_eq!(
+        response,
+        Err("Anonymous caller not authorized.".to_string())
+    );
+}
+
+const SEPOLIA: u64 = 11_155_111;
+
+fn reserve_nonce(
+    pic_setup: &PicBackend,
+    caller: Principal,
+    chain_id: u64,
+    on_chain_nonce: Option<u64>,
+) -> Result<u64, EthReserveNonceError> {
+    pic_setup
+        .update::<Result<EthReserveNonceResponse, EthReserveNonceError>>(
+            caller,
+            "eth_reserve_nonce",
+            EthReserveNonceRequest {
+                chain_id,
+                on_chain_nonce,
+            },
+        )
+        .expect("Call failed")
+        .map(|response| response.nonce)
+}
+
+#[test]
+fn test_eth_reserve_nonce_requires_the_on_chain_nonce() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    assert_eq!(
+        reserve_nonce(&pic_setup, caller, SEPOLIA, None),
+        Err(EthReserveNonceError::UnknownOnChainNonce)
+    );
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, Some(5)), Ok(5));
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, None), Ok(6));
+}
+
+#[test]
+fn test_eth_nonces_are_reserved_per_principal_and_chain() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let user = Principal::from_text(USER_1).unwrap();
+
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, Some(0)), Ok(0));
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, None), Ok(1));
+    assert_eq!(reserve_nonce(&pic_setup, caller, 1, Some(0)), Ok(0));
+    assert_eq!(reserve_nonce(&pic_setup, user, SEPOLIA, Some(0)), Ok(0));
+}
+
+#[test]
+fn test_eth_released_and_reconciled_nonces() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for expected in 0..3 {
+        assert_eq!(
+            reserve_nonce(&pic_setup, caller, SEPOLIA, (expected == 0).then_some(0)),
+            Ok(expected)
+        );
+    }
+
+    // A released nonce is reserved again before any higher nonce.
+    pic_setup
+        .update::<()>(
+            caller,
+            "eth_release_nonce",
+            EthReleaseNonceRequest {
+                chain_id: SEPOLIA,
+                nonce: 1,
+            },
+        )
+        .expect("Call failed");
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, None), Ok(1));
+
+    // The chain has caught up with nonces 0 to 3, e.g. because another wallet sent a transaction.
+    pic_setup
+        .update::<()>(
+            caller,
+            "eth_reconcile_nonce",
+            EthReconcileNonceRequest {
+                chain_id: SEPOLIA,
+                on_chain_nonce: 4,
+            },
+        )
+        .expect("Call failed");
+    assert_eq!(reserve_nonce(&pic_setup, caller, SEPOLIA, None), Ok(4));
+}
+
+#[test]
+fn test_anonymous_cannot_reserve_eth_nonce() {
+    let pic_setup = setup();
+
+    let response = pic_setup.update::<Result<EthReserveNonceResponse, EthReserveNonceError>>(
+        Principal::anonymous(),
+        "eth_reserve_nonce",
+        EthReserveNonceRequest {
+            chain_id: SEPOLIA,
+            on_chain_nonce: Some(0),
+        },
+    );
+
+    assert_eq!(
+        response,
+        Err("Anonymous caller not authorized.".to_string())
+    );
+}
+
+#[test]
+fn test_eth_typed_data_endpoints_reject_invalid_typed_data() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    for typed_data in [
+        "not json",
+        r#"{ "types": { "T": [{ "name": "a", "type": "uint8" }] }, "primaryType": "T", "domain": {}, "message": { "a": 256 } }"#,
+    ] {
+        let hash_response = pic_setup
+            .query::<Result<EthSigningHashResponse, EthSignMessageError>>(
+                caller,
+                "eth_sign_typed_data_hash",
+                typed_data.to_string(),
+            )
+            .expect("Call failed");
+        let response = pic_setup
+            .update::<Result<EthSignMessageResponse, EthSignMessageError>>(
+                caller,
+                "eth_assemble_typed_data_signature",
+                EthSignTypedDataRequest {
+                    typed_data: typed_data.to_string(),
+                    signature: "01".repeat(64),
+                },
+            )
+            .expect("Call failed");
+
+        assert!(matches!(
+            hash_response,
+            Err(EthSignMessageError::InvalidTypedData { .. })
+        ));
+        assert!(matches!(
+            response,
+            Err(EthSignMessageError::InvalidTypedData { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_eth_personal_sign_hash_follows_eip_191() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .query::<EthSigningHashResponse>(
+            caller,
+            "eth_personal_sign_hash",
+            "Hello World".to_string(),
+        )
+        .expect("Call failed");
+
+    // The hash of "\x19Ethereum Signed Message:\n11Hello World".
+    assert_eq!(
+        response.hash,
+        "0xa1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+    );
+}
+
+#[test]
+fn test_eth_assemble_personal_sign_rejects_signatures_not_by_the_caller() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    // Neither a malformed signature nor a signature by another key is accepted.
+    for signature in ["0x1234".to_string(), format!("0x{}", "01".repeat(64))] {
+        let response = pic_setup
+            .update::<Result<EthSignMessageResponse, EthSignMessageError>>(
+                caller,
+                "eth_assemble_personal_sign",
+                EthPersonalSignRequest {
+                    message: "Hello World".to_string(),
+                    signature,
+                },
+            )
+            .expect("Call failed");
+
+        assert_eq!(response, Err(EthSignMessageError::InvalidSignature));
+    }
+}
+
+#[test]
+fn test_anonymous_cannot_assemble_eth_message_signatures() {
+    let pic_setup = setup();
+
+    let response = pic_setup.update::<Result<EthSignMessageResponse, EthSignMessageError>>(
+        Principal::anonymous(),
+        "eth_assemble_personal_sign",
+        EthPersonalSignRequest {
+            message: "Hello World".to_string(),
+            signature: "01".repeat(64),
+        },
+    );
+
+    assert_eq!(
+        response,
+        Err("Anonymous caller not authorized.".to_string())
+    );
+}
+
+fn add_pending_transaction(
+    pic_setup: &PicBackend,
+    caller: Principal,
+    hash_byte: u8,
+    nonce: u64,
+) -> Result<(), EthAddPendingTransactionError> {
+    pic_setup
+        .update::<Result<(), EthAddPendingTransactionError>>(
+            caller,
+            "eth_add_pending_transaction",
+            EthAddPendingTransactionRequest {
+                chain_id: SEPOLIA,
+                hash: format!("0x{}", hex::encode([hash_byte; 32])),
+                nonce,
+                to: "0x7439e9bb6d8a84dd3a23fe621a30f95403f87fb9".to_string(),
+                value: Nat::from(1_000_u32),
+                token: Some("0x1c7d4b196cb0c7b01d743fbc6116a902379c7238".to_string()),
+            },
+        )
+        .expect("Call failed")
+}
+
+fn pending_transactions(pic_setup: &PicBackend, caller: Principal) -> Vec<(u64, bool)> {
+    pic_setup
+        .query::<EthGetPendingTransactionsResponse>(
+            caller,
+            "eth_get_pending_transactions",
+            EthGetPendingTransactionsRequest { chain_id: SEPOLIA },
+        )
+        .expect("Call failed")
+        .transactions
+        .into_iter()
+        .map(|transaction| (transaction.nonce, transaction.replaced_by.is_some()))
+        .collect()
+}
+
+#[test]
+fn test_eth_pending_transactions_are_added_replaced_and_pruned() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+    let user = Principal::from_text(USER_1).unwrap();
+
+    assert_eq!(add_pending_transaction(&pic_setup, caller, 1, 0), Ok(()));
+    assert_eq!(add_pending_transaction(&pic_setup, caller, 2, 1), Ok(()));
+    // Sped up with the same nonce.
+    assert_eq!(add_pending_transaction(&pic_setup, caller, 3, 1), Ok(()));
+
+    assert_eq!(
+        pending_transactions(&pic_setup, caller),
+        vec![(0, false), (1, true), (1, false)]
+    );
+    assert_eq!(pending_transactions(&pic_setup, user), vec![]);
+
+    pic_setup
+        .update::<()>(
+            caller,
+            "eth_prune_pending_transactions",
+            EthPrunePendingTransactionsRequest {
+                chain_id: SEPOLIA,
+                on_chain_nonce: Some(1),
+            },
+        )
+        .expect("Call failed");
+    assert_eq!(
+        pending_transactions(&pic_setup, caller),
+        vec![(1, true), (1, false)]
+    );
+}
+
+#[test]
+fn test_eth_add_pending_transaction_rejects_invalid_hash() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let response = pic_setup
+        .update::<Result<(), EthAddPendingTransactionError>>(
+            caller,
+            "eth_add_pending_transaction",
+            EthAddPendingTransactionRequest {
+                chain_id: SEPOLIA,
+                hash: "0x1234".to_string(),
+                nonce: 0,
+                to: "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9".to_string(),
+                value: Nat::from(0_u32),
+                token: None,
+            },
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Err(EthAddPendingTransactionError::InvalidHash {
+            hash: "0x1234".to_string()
+        })
+    );
+}
@@ -0,0 +1,261 @@
+use candid::{Nat, Principal};
+use pretty_assertions::assert_eq;
+use shared::types::{
+    ethereum::{
+        EthEstimateFeesResponse, EthGetBalanceRequest, EthGetBalanceResponse,
+        EthGetTransactionCountRequest, EthGetTransactionCountResponse, EvmRpcError,
+    },
+    token::{ChainId, UserToken},
+};
+
+use crate::utils::{
+    mock::{CALLER, SEPOLIA_CHAIN_ID, WEENUS_CONTRACT_ADDRESS},
+    pocketic::{setup, BackendBuilder, PicBackend, PicCanisterTrait},
+};
+
+const GWEI: u64 = 1_000_000_000;
+/// An address that the EVM RPC stand-in reports a different balance for on every request.
+const INCONSISTENT_ADDRESS: &str = "0x000000000000000000000000000000000000dEaD";
+const ADDRESS: &str = "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9";
+
+fn setup_with_evm_rpc() -> PicBackend {
+    BackendBuilder::default().with_evm_rpc_stub().deploy()
+}
+
+fn estimate_fees(
+    pic_setup: &PicBackend,
+    caller: Principal,
+    chain_id: ChainId,
+) -> Result<Result<EthEstimateFeesResponse, EvmRpcError>, String> {
+    pic_setup.update::<Result<EthEstimateFeesResponse, EvmRpcError>>(
+        caller,
+        "eth_estimate_fees",
+        chain_id,
+    )
+}
+
+fn get_balance(
+    pic_setup: &PicBackend,
+    chain_id: ChainId,
+    address: &str,
+) -> Result<EthGetBalanceResponse, EvmRpcError> {
+    pic_setup
+        .update::<Result<EthGetBalanceResponse, EvmRpcError>>(
+            Principal::from_text(CALLER).unwrap(),
+            "eth_get_balance",
+            EthGetBalanceRequest {
+                chain_id,
+                address: address.to_string(),
+            },
+        )
+        .expect("Call failed")
+}
+
+/// A USDC token, as reported by the EVM RPC stand-in for any contract.
+fn usdc() -> UserToken {
+    UserToken {
+        chain_id: SEPOLIA_CHAIN_ID,
+        contract_address: WEENUS_CONTRACT_ADDRESS.to_string(),
+        decimals: Some(6),
+        symbol: Some("USDC".to_string()),
+        version: None,
+        enabled: Some(true),
+    }
+}
+
+#[test]
+fn test_eth_estimate_fees_suggests_eip1559_fees() {
+    let pic_setup = setup_with_evm_rpc();
+
+    let response = estimate_fees(
+        &pic_setup,
+        Principal::from_text(CALLER).unwrap(),
+        SEPOLIA_CHAIN_ID,
+    );
+
+    // The next base fee is 2 gwei and the median priority fee is 1.5 gwei.
+    assert_eq!(
+        response,
+        Ok(Ok(EthEstimateFeesResponse {
+            base_fee_per_gas: Nat::from(2 * GWEI),
+            max_priority_fee_per_gas: Nat::from(3 * GWEI / 2),
+            max_fee_per_gas: Nat::from(11 * GWEI / 2),
+        }))
+    );
+}
+
+#[test]
+fn test_eth_estimate_fees_rejects_unsupported_chains() {
+    let pic_setup = setup_with_evm_rpc();
+
+    let response = estimate_fees(&pic_setup, Principal::from_text(CALLER).unwrap(), 31_337);
+
+    assert_eq!(
+        response,
+        Ok(Err(EvmRpcError::UnsupportedChain { chain_id: 31_337 }))
+    );
+}
+
+#[test]
+fn test_eth_estimate_fees_needs_the_evm_rpc_canister() {
+    let pic_setup = setup();
+
+    let response = estimate_fees(
+        &pic_setup,
+        Principal::from_text(CALLER).unwrap(),
+        SEPOLIA_CHAIN_ID,
+    );
+
+    assert_eq!(response, Ok(Err(EvmRpcError::Disabled)));
+}
+
+#[test]
+fn test_anonymous_cannot_estimate_eth_fees() {
+    let pic_setup = setup_with_evm_rpc();
+
+    let response = estimate_fees(&pic_setup, Principal::anonymous(), SEPOLIA_CHAIN_ID);
+
+    assert_eq!(
+        response,
+        Err("Anonymous caller not authorized.".to_string())
+    );
+}
+
+#[test]
+fn test_eth_get_balance_returns_the_agreed_balance() {
+    let pic_setup = setup_with_evm_rpc();
+
+    assert_eq!(
+        get_balance(&pic_setup, SEPOLIA_CHAIN_ID, ADDRESS),
+        Ok(EthGetBalanceResponse {
+            balance: Nat::from(1_000_000_000_000_000_000_u64),
+        })
+    );
+    assert_eq!(
+        get_balance(&pic_setup, SEPOLIA_CHAIN_ID, "0x1234"),
+        Err(EvmRpcError::InvalidAddress {
+            address: "0x1234".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_eth_get_balance_rejects_inconsistent_results() {
+    let pic_setup = setup_with_evm_rpc();
+
+    let response = get_balance(&pic_setup, SEPOLIA_CHAIN_ID, INCONSISTENT_ADDRESS);
+
+    assert!(
+        matches!(response, Err(EvmRpcError::InconsistentResults { ref results }) if results.len() == 3),
+        "Unexpected response: {response:?}"
+    );
+}
+
+#[test]
+fn test_eth_requests_per_caller_are_limited() {
+    let pic_setup = setup_with_evm_rpc();
+
+    for _ in 0..30 {
+        assert!(get_balance(&pic_setup, SEPOLIA_CHAIN_ID, ADDRESS).is_ok());
+    }
+
+    assert_eq!(
+        get_balance(&pic_setup, SEPOLIA_CHAIN_ID, ADDRESS),
+        Err(EvmRpcError::TooManyRequests { max_requests: 30 })
+    );
+}
+
+#[test]
+fn test_eth_get_transaction_count() {
+    let pic_setup = setup_with_evm_rpc();
+
+    let response = pic_setup
+        .update::<Result<EthGetTransactionCountResponse, EvmRpcError>>(
+            Principal::from_text(CALLER).unwrap(),
+            "eth_get_transaction_count",
+            EthGetTransactionCountRequest {
+                chain_id: SEPOLIA_CHAIN_ID,
+                address: ADDRESS.to_string(),
+            },
+        )
+        .expect("Call failed");
+
+    assert_eq!(
+        response,
+        Ok(EthGetTransactionCountResponse {
+            transaction_count: 42
+        })
+    );
+}
+
+#[test]
+fn test_set_user_token_checks_the_token_contract() {
+    let pic_setup = setup_with_evm_rpc();
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let result = pic_setup.update::<()>(caller, "set_user_token", usdc());
+    assert_eq!(result, Ok(()));
+
+    let wrong_decimals = UserToken {
+        decimals: Some(18),
+        ..usdc()
+    };
+    let result = pic_setup.update::<()>(caller, "set_user_token", wrong_decimals);
+    assert!(result
+        .unwrap_err()
+        .contains("Token decimals 18 do not match the contract decimals 6"));
+
+    let wrong_symbol = UserToken {
+        symbol: Some("USDT".to_string()),
+        ..usdc()
+    };
+    let result = pic_setup.update::<()>(caller, "set_user_token", wrong_symbol);
+    assert!(result
+        .unwrap_err()
+        .contains("Token symbol USDT does not match the contract symbol USDC"));
+}
+
+#[test]
+fn test_set_many_user_tokens_checks_the_token_contracts() {
+    let pic_setup = setup_with_evm_rpc();
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let other_chain = UserToken {
+        chain_id: 31_337,
+        ..usdc()
+    };
+    let wrong_decimals = UserToken {
+        decimals: Some(18),
+        ..usdc()
+    };
+    let result = pic_setup.update::<()>(
+        caller,
+        "set_many_user_tokens",
+        vec![other_chain.clone(), wrong_decimals],
+    );
+    assert!(result
+        .unwrap_err()
+        .contains("Token decimals 18 do not match the contract decimals 6"));
+    assert_eq!(
+        pic_setup.query::<Vec<UserToken>>(caller, "list_user_tokens", ()),
+        Ok(vec![])
+    );
+
+    let result = pic_setup.update::<()>(caller, "set_many_user_tokens", vec![other_chain, usdc()]);
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn test_set_user_token_skips_the_check_on_unsupported_chains() {
+    let pic_setup = setup_with_evm_rpc();
+    let caller = Principal::from_text(CALLER).unwrap();
+
+    let token = UserToken {
+        chain_id: 31_337,
+        decimals: Some(18),
+        ..usdc()
+    };
+    let result = pic_setup.update::<()>(caller, "set_user_token", token);
+
+    assert_eq!(result, Ok(()));
+}
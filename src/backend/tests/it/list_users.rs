@@ -21,6 +21,8 @@ fn test_list_users_cannot_be_called_if_not_allowed() {
     let arg = ListUsersRequest {
         matches_max_length: None,
         updated_after_timestamp: None,
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
 
@@ -38,6 +40,8 @@ fn test_list_users_returns_users() {
     let arg = ListUsersRequest {
         matches_max_length: None,
         updated_after_timestamp: None,
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
 
@@ -106,6 +110,8 @@ fn test_list_users_returns_filtered_users_by_updated() {
     let arg = ListUsersRequest {
         matches_max_length: None,
         updated_after_timestamp: Some(timestamp_nanos_1 as u64),
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
 
@@ -148,6 +154,8 @@ fn test_list_users_returns_requested_users_count() {
     let arg = ListUsersRequest {
         matches_max_length: Some(requested_count as u64),
         updated_after_timestamp: Some(timestamp_nanos as u64),
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let expected_users = &users_after_expected_timestamp[0..requested_count];
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
@@ -170,6 +178,8 @@ fn test_list_users_returns_less_than_requested_users_count() {
     let arg = ListUsersRequest {
         matches_max_length: Some(requested_count as u64),
         updated_after_timestamp: None,
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
 
@@ -226,6 +236,8 @@ fn test_list_users_returns_pouh_credential() {
     let arg = ListUsersRequest {
         matches_max_length: None,
         updated_after_timestamp: None,
+        updated_after_principal: None,
+        agreement_not_accepted: None,
     };
     let list_users_response = pic_setup.query::<ListUsersResponse>(caller, "list_users", arg);
 
@@ -1,9 +1,13 @@
+mod agreements;
 mod bitcoin;
 mod config;
 mod custom_token;
+mod ethereum;
+mod evm_rpc;
 mod guard;
 mod list_users;
 mod migration;
+mod principal_link;
 mod settings;
 mod signer;
 mod stats;
@@ -3,13 +3,24 @@
 use std::sync::Arc;
 
 use crate::{
+    bitcoin::MOCK_ADDRESS,
     user_token::{ANOTHER_TOKEN, MOCK_TOKEN},
     utils::pocketic::{controller, setup, BackendBuilder, PicBackend, PicCanisterTrait},
 };
-use candid::Principal;
+use candid::{Nat, Principal};
+use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
 use pocket_ic::PocketIcBuilder;
 use shared::types::{
+    bitcoin::{
+        BtcAddPendingTransactionError, BtcAddPendingTransactionRequest, BtcFreezeUtxosError,
+        BtcFreezeUtxosRequest, BtcFrozenUtxo,
+    },
     custom_token::{CustomToken, IcrcToken, Token},
+    ethereum::{EthAddPendingTransactionError, EthAddPendingTransactionRequest},
+    principal_link::{
+        ConfirmPrincipalLinkRequest, LinkedPrincipals, PrincipalLinkError,
+        StartPrincipalLinkRequest, StartPrincipalLinkResponse,
+    },
     ApiEnabled, Guards, MigrationProgress, MigrationReport, Stats,
 };
 
@@ -60,6 +71,11 @@ impl MigrationTestEnv {
             user_timestamps_count,
             user_token_count,
             custom_token_count,
+            user_profile_history_count: _,
+            principal_link_count,
+            btc_pending_transaction_count,
+            btc_frozen_utxo_count,
+            eth_pending_transaction_count,
         } = stats;
         assert_eq!(user_profile_count, user_timestamps_count, "Test setup failure: Stats indicate that the database is inconsistent.  Doesn't affect the migration but should be fixed.");
         // Create users
@@ -95,6 +111,114 @@ impl MigrationTestEnv {
                 .update::<()>(user.principal, "set_many_custom_tokens", &custom_tokens)
                 .expect("Test setup error: Failed to set user tokens");
         }
+        // Link principals without data of their own to users.
+        for (i, user) in expected_users
+            .iter()
+            .take(*principal_link_count as usize)
+            .enumerate()
+        {
+            let (primary, linked) = (
+                user.principal,
+                Principal::self_authenticating(format!("linked principal {i}")),
+            );
+            pic_setup
+                .old_backend
+                .update::<Result<StartPrincipalLinkResponse, PrincipalLinkError>>(
+                    primary,
+                    "start_principal_link",
+                    StartPrincipalLinkRequest { principal: linked },
+                )
+                .expect("Test setup error: Failed to call start_principal_link")
+                .expect("Test setup error: Failed to start principal link");
+            pic_setup
+                .old_backend
+                .update::<Result<LinkedPrincipals, PrincipalLinkError>>(
+                    linked,
+                    "confirm_principal_link",
+                    ConfirmPrincipalLinkRequest { initiator: primary },
+                )
+                .expect("Test setup error: Failed to call confirm_principal_link")
+                .expect("Test setup error: Failed to confirm principal link");
+        }
+        // Add pending Bitcoin transactions.
+        for (vout, user) in (0_u32..).zip(
+            expected_users
+                .iter()
+                .take(*btc_pending_transaction_count as usize),
+        ) {
+            let request = BtcAddPendingTransactionRequest {
+                txid: vout.to_be_bytes().to_vec(),
+                utxos: vec![Utxo {
+                    outpoint: Outpoint {
+                        txid: vec![1, 2, 3],
+                        vout,
+                    },
+                    value: 1000,
+                    height: 100,
+                }],
+                address: MOCK_ADDRESS.to_string(),
+                network: BitcoinNetwork::Regtest,
+            };
+            pic_setup
+                .old_backend
+                .update::<Result<(), BtcAddPendingTransactionError>>(
+                    user.principal,
+                    "btc_add_pending_transaction",
+                    request,
+                )
+                .expect("Test setup error: Failed to call btc_add_pending_transaction")
+                .expect("Test setup error: Failed to add pending transaction");
+        }
+        // Freeze Bitcoin UTXOs.
+        for (vout, user) in (0_u32..).zip(
+            expected_users
+                .iter()
+                .rev()
+                .take(*btc_frozen_utxo_count as usize),
+        ) {
+            let request = BtcFreezeUtxosRequest {
+                utxos: vec![BtcFrozenUtxo {
+                    outpoint: Outpoint {
+                        txid: vec![4, 5, 6],
+                        vout,
+                    },
+                    label: Some("inscription".to_string()),
+                }],
+            };
+            pic_setup
+                .old_backend
+                .update::<Result<(), BtcFreezeUtxosError>>(
+                    user.principal,
+                    "btc_freeze_utxos",
+                    request,
+                )
+                .expect("Test setup error: Failed to call btc_freeze_utxos")
+                .expect("Test setup error: Failed to freeze UTXOs");
+        }
+        // Add pending Ethereum transactions.
+        for (nonce, user) in (0_u64..).zip(
+            expected_users
+                .iter()
+                .take(*eth_pending_transaction_count as usize),
+        ) {
+            let request = EthAddPendingTransactionRequest {
+                chain_id: 11_155_111,
+                hash: format!("0x{:064x}", nonce),
+                nonce,
+                to: "0x7439E9Bb6D8a84dd3A23fe621A30F95403F87fB9".to_string(),
+                value: Nat::from(1_000_u32),
+                token: None,
+            };
+            pic_setup
+                .old_backend
+                .update::<Result<(), EthAddPendingTransactionError>>(
+                    user.principal,
+                    "eth_add_pending_transaction",
+                    request,
+                )
+                .expect("Test setup error: Failed to call eth_add_pending_transaction")
+                .expect("Test setup error: Failed to add pending transaction");
+        }
         pic_setup
     }
 
@@ -169,6 +293,11 @@ fn test_migration() {
         user_timestamps_count: 20,
         user_token_count: 10,
         custom_token_count: 5,
+        user_profile_history_count: 20,
+        principal_link_count: 3,
+        btc_pending_transaction_count: 7,
+        btc_frozen_utxo_count: 6,
+        eth_pending_transaction_count: 4,
     };
     let pic_setup = MigrationTestEnv::new(&stats);
     // Test the migration.
@@ -320,6 +449,81 @@ fn test_migration() {
             pic_setup.step_migration();
         }
     }
+    // Should have started the user profile history migration.
+    {
+        pic_setup
+            .assert_migration_progress_is(MigrationProgress::MigratedUserProfileHistoryUpTo(None));
+    }
+    // Keep stepping until the user profile history has been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedUserProfileHistoryUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the principal link migration.
+    {
+        pic_setup.assert_migration_progress_is(MigrationProgress::MigratedPrincipalLinksUpTo(None));
+    }
+    // Keep stepping until the principal links have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedPrincipalLinksUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the pending Bitcoin transaction migration.
+    {
+        pic_setup.assert_migration_progress_is(
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(None),
+        );
+    }
+    // Keep stepping until the pending Bitcoin transactions have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedBtcPendingTransactionsUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the frozen Bitcoin UTXO migration.
+    {
+        pic_setup.assert_migration_progress_is(MigrationProgress::MigratedBtcFrozenUtxosUpTo(None));
+    }
+    // Keep stepping until the frozen Bitcoin UTXOs have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedBtcFrozenUtxosUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
+    // Should have started the pending Ethereum transaction migration.
+    {
+        pic_setup.assert_migration_progress_is(
+            MigrationProgress::MigratedEthPendingTransactionsUpTo(None),
+        );
+    }
+    // Keep stepping until the pending Ethereum transactions have been migrated.
+    {
+        while let Some(MigrationReport {
+            progress: shared::types::MigrationProgress::MigratedEthPendingTransactionsUpTo(_),
+            ..
+        }) = pic_setup.migration_state()
+        {
+            pic_setup.step_migration();
+        }
+    }
     // Should be checking the migration.
     {
         pic_setup.assert_migration_progress_is(MigrationProgress::CheckingDataMigration);
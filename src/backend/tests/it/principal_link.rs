@@ -0,0 +1,226 @@
+use crate::{
+    user_token::MOCK_TOKEN,
+    utils::pocketic::{setup, PicBackend, PicCanisterTrait},
+};
+use candid::Principal;
+use pretty_assertions::assert_eq;
+use shared::types::{
+    principal_link::{
+        ConfirmPrincipalLinkRequest, LinkedPrincipals, PrincipalLinkError,
+        StartPrincipalLinkRequest, StartPrincipalLinkResponse, UnlinkPrincipalRequest,
+    },
+    token::UserToken,
+};
+use std::time::Duration;
+
+fn user(i: u8) -> Principal {
+    Principal::self_authenticating(format!("principal link user {i}"))
+}
+
+fn start_link(
+    pic_setup: &PicBackend,
+    initiator: Principal,
+    principal: Principal,
+) -> Result<StartPrincipalLinkResponse, PrincipalLinkError> {
+    pic_setup
+        .update(
+            initiator,
+            "start_principal_link",
+            StartPrincipalLinkRequest { principal },
+        )
+        .expect("Call to start_principal_link failed")
+}
+
+fn confirm_link(
+    pic_setup: &PicBackend,
+    invited: Principal,
+    initiator: Principal,
+) -> Result<LinkedPrincipals, PrincipalLinkError> {
+    pic_setup
+        .update(
+            invited,
+            "confirm_principal_link",
+            ConfirmPrincipalLinkRequest { initiator },
+        )
+        .expect("Call to confirm_principal_link failed")
+}
+
+fn link(pic_setup: &PicBackend, primary: Principal, invited: Principal) -> LinkedPrincipals {
+    start_link(pic_setup, primary, invited).expect("Failed to start link");
+    confirm_link(pic_setup, invited, primary).expect("Failed to confirm link")
+}
+
+#[test]
+fn test_linked_principal_uses_tokens_of_primary() {
+    let pic_setup = setup();
+    let (primary, linked) = (user(1), user(2));
+
+    pic_setup
+        .update::<()>(primary, "set_user_token", MOCK_TOKEN.clone())
+        .expect("Failed to set user token");
+
+    let group = link(&pic_setup, primary, linked);
+    assert_eq!(group.primary, primary);
+    assert_eq!(group.principals, vec![primary, linked]);
+
+    let tokens = pic_setup
+        .query::<Vec<UserToken>>(linked, "list_user_tokens", ())
+        .expect("Failed to list user tokens");
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].contract_address, MOCK_TOKEN.contract_address);
+
+    // After unlinking, the principal has its own, empty, token list again.
+    pic_setup
+        .update::<Result<LinkedPrincipals, PrincipalLinkError>>(
+            linked,
+            "unlink_principal",
+            UnlinkPrincipalRequest { principal: None },
+        )
+        .expect("Call to unlink_principal failed")
+        .expect("Failed to unlink principal");
+    let tokens = pic_setup
+        .query::<Vec<UserToken>>(linked, "list_user_tokens", ())
+        .expect("Failed to list user tokens");
+    assert_eq!(tokens, vec![]);
+}
+
+#[test]
+fn test_confirm_link_requires_matching_challenge() {
+    let pic_setup = setup();
+    let (primary, invited, other) = (user(1), user(2), user(3));
+
+    assert_eq!(
+        confirm_link(&pic_setup, invited, primary),
+        Err(PrincipalLinkError::ChallengeNotFound)
+    );
+
+    start_link(&pic_setup, primary, invited).expect("Failed to start link");
+    assert_eq!(
+        confirm_link(&pic_setup, invited, other),
+        Err(PrincipalLinkError::ChallengeNotFound)
+    );
+}
+
+#[test]
+fn test_confirm_link_fails_after_challenge_expires() {
+    let pic_setup = setup();
+    let (primary, invited) = (user(1), user(2));
+
+    start_link(&pic_setup, primary, invited).expect("Failed to start link");
+    pic_setup.pic().advance_time(Duration::from_secs(60 * 60));
+
+    assert_eq!(
+        confirm_link(&pic_setup, invited, primary),
+        Err(PrincipalLinkError::ChallengeExpired)
+    );
+}
+
+#[test]
+fn test_linked_group_size_is_limited() {
+    let pic_setup = setup();
+    let primary = user(0);
+
+    for i in 1..5 {
+        link(&pic_setup, primary, user(i));
+    }
+
+    assert_eq!(
+        start_link(&pic_setup, primary, user(5)),
+        Err(PrincipalLinkError::GroupFull { max_group_size: 5 })
+    );
+}
+
+#[test]
+fn test_only_primary_can_invite_principals() {
+    let pic_setup = setup();
+    let (primary, linked, invited) = (user(1), user(2), user(3));
+    link(&pic_setup, primary, linked);
+
+    assert_eq!(
+        start_link(&pic_setup, linked, invited),
+        Err(PrincipalLinkError::NotAllowed)
+    );
+}
+
+#[test]
+fn test_pending_challenges_per_initiator_are_limited() {
+    let pic_setup = setup();
+    let initiator = user(0);
+
+    for i in 1..5 {
+        start_link(&pic_setup, initiator, user(i)).expect("Failed to start link");
+    }
+    // Inviting a principal again replaces its invitation.
+    start_link(&pic_setup, initiator, user(1)).expect("Failed to start link");
+    assert_eq!(
+        start_link(&pic_setup, initiator, user(5)),
+        Err(PrincipalLinkError::TooManyChallenges { max_challenges: 4 })
+    );
+
+    // Expired invitations do not count.
+    pic_setup.pic().advance_time(Duration::from_secs(60 * 60));
+    start_link(&pic_setup, initiator, user(5)).expect("Failed to start link");
+}
+
+#[test]
+fn test_invitations_by_different_initiators_do_not_replace_each_other() {
+    let pic_setup = setup();
+    let (initiator, other, invited) = (user(1), user(2), user(3));
+
+    start_link(&pic_setup, initiator, invited).expect("Failed to start link");
+    start_link(&pic_setup, other, invited).expect("Failed to start link");
+
+    assert_eq!(
+        confirm_link(&pic_setup, invited, initiator).map(|group| group.primary),
+        Ok(initiator)
+    );
+}
+
+#[test]
+fn test_invited_principal_with_data_cannot_link() {
+    let pic_setup = setup();
+    let (primary, invited) = (user(1), user(2));
+    pic_setup
+        .update::<()>(invited, "set_user_token", MOCK_TOKEN.clone())
+        .expect("Failed to set user token");
+
+    start_link(&pic_setup, primary, invited).expect("Failed to start link");
+
+    assert_eq!(
+        confirm_link(&pic_setup, invited, primary),
+        Err(PrincipalLinkError::InvitedPrincipalHasData)
+    );
+}
+
+#[test]
+fn test_only_primary_can_unlink_other_principals() {
+    let pic_setup = setup();
+    let (primary, linked_1, linked_2) = (user(1), user(2), user(3));
+    link(&pic_setup, primary, linked_1);
+    link(&pic_setup, primary, linked_2);
+
+    let unlink = |caller: Principal, principal: Principal| {
+        pic_setup
+            .update::<Result<LinkedPrincipals, PrincipalLinkError>>(
+                caller,
+                "unlink_principal",
+                UnlinkPrincipalRequest {
+                    principal: Some(principal),
+                },
+            )
+            .expect("Call to unlink_principal failed")
+    };
+
+    assert_eq!(
+        unlink(linked_1, linked_2),
+        Err(PrincipalLinkError::NotAllowed)
+    );
+    assert_eq!(
+        unlink(primary, primary),
+        Err(PrincipalLinkError::CannotUnlinkPrimary)
+    );
+    assert_eq!(
+        unlink(primary, linked_2).map(|group| group.principals),
+        Ok(vec![primary, linked_1])
+    );
+}
@@ -2,7 +2,10 @@
 
 use crate::utils::pocketic::controller;
 use crate::utils::pocketic::pic_canister::PicCanisterTrait;
-use crate::utils::{mock::VC_HOLDER, pocketic::setup};
+use crate::utils::{
+    mock::{CALLER, USER_1, VC_HOLDER},
+    pocketic::{setup, BackendBuilder},
+};
 use candid::Principal;
 use shared::types::signer::topup::{
     TopUpCyclesLedgerError, TopUpCyclesLedgerRequest, TopUpCyclesLedgerResult, MAX_PERCENTAGE,
@@ -48,3 +51,43 @@ fn test_topup_fails_for_percentage_out_of_bounds() {
         );
     }
 }
+
+#[test]
+fn test_offline_key_derivation_matches_management_canister() {
+    let pic_setup = BackendBuilder::default().with_test_endpoints().deploy();
+
+    for principal in [CALLER, USER_1, VC_HOLDER] {
+        let principal = Principal::from_text(principal).unwrap();
+        let response = pic_setup.update::<Result<(), String>>(
+            controller(),
+            "check_signer_key_derivation",
+            principal,
+        );
+
+        assert_eq!(response, Ok(Ok(())), "for principal {principal}");
+    }
+}
+
+#[test]
+fn test_check_signer_key_derivation_cannot_be_called_if_not_allowed() {
+    let pic_setup = BackendBuilder::default().with_test_endpoints().deploy();
+    let caller = Principal::from_text(VC_HOLDER).unwrap();
+
+    let response =
+        pic_setup.update::<Result<(), String>>(caller, "check_signer_key_derivation", caller);
+
+    assert_eq!(response, Err("Caller is not allowed.".to_string()));
+}
+
+#[test]
+fn test_check_signer_key_derivation_is_not_in_release_builds() {
+    let pic_setup = setup();
+
+    let response = pic_setup.update::<Result<(), String>>(
+        controller(),
+        "check_signer_key_derivation",
+        controller(),
+    );
+
+    assert!(response.is_err_and(|err| err.contains("check_signer_key_derivation")));
+}
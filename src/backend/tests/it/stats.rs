@@ -30,6 +30,11 @@ fn stats_returns_correct_number_of_users() {
         user_timestamps_count: expected_users.len() as u64,
         user_token_count: NUM_USERS_WITH_TOKENS as u64,
         custom_token_count: 0,
+        user_profile_history_count: expected_users.len() as u64,
+        principal_link_count: 0,
+        btc_pending_transaction_count: 0,
+        btc_frozen_utxo_count: 0,
+        eth_pending_transaction_count: 0,
     };
 
     let caller = controller();
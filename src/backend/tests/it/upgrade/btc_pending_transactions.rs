@@ -0,0 +1,47 @@
+use crate::bitcoin::MOCK_ADDRESS;
+use crate::utils::mock::CALLER;
+use crate::utils::pocketic::{controller, setup, PicCanisterTrait};
+use candid::Principal;
+use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
+use pretty_assertions::assert_eq;
+use shared::types::bitcoin::{BtcAddPendingTransactionError, BtcAddPendingTransactionRequest};
+use shared::types::Stats;
+
+#[test]
+fn test_upgrade_keeps_btc_pending_transactions() {
+    let pic_setup = setup();
+
+    // Add a pending transaction
+    let caller = Principal::from_text(CALLER).unwrap();
+    let request = BtcAddPendingTransactionRequest {
+        txid: vec![1, 2, 3],
+        utxos: vec![Utxo {
+            outpoint: Outpoint {
+                txid: vec![4, 5, 6],
+                vout: 0,
+            },
+            value: 1000,
+            height: 100,
+        }],
+        address: MOCK_ADDRESS.to_string(),
+        network: BitcoinNetwork::Regtest,
+    };
+    let result = pic_setup.update::<Result<(), BtcAddPendingTransactionError>>(
+        caller,
+        "btc_add_pending_transaction",
+        request,
+    );
+    assert_eq!(result, Ok(Ok(())));
+
+    // Upgrade canister with new wasm
+    pic_setup
+        .upgrade_latest_wasm(None)
+        .unwrap_or_else(|e| panic!("Upgrade canister failed with error: {}", e));
+
+    // The pending transaction is still stored.  Reading it with `btc_get_pending_transactions` would prune it,
+    // as the regtest bitcoin canister does not know its utxos, so the stats are checked instead.
+    let stats = pic_setup
+        .query::<Stats>(controller(), "stats", ())
+        .expect("Failed to get stats");
+    assert_eq!(stats.btc_pending_transaction_count, 1);
+}
@@ -1,3 +1,4 @@
+mod btc_pending_transactions;
 mod constants;
 mod impls;
 mod token_enabled;
@@ -1,9 +1,13 @@
 use crate::utils::{
-    mock::CALLER,
+    mock::{CALLER, USER_1},
     pocketic::{setup, PicCanisterTrait},
 };
 use candid::Principal;
-use shared::types::user_profile::{GetUserProfileError, UserProfile};
+use pretty_assertions::assert_eq;
+use shared::types::user_profile::{
+    GetUserProfileError, GetUserProfileHistoryError, GetUserProfileHistoryRequest,
+    GetUserProfileHistoryResponse, UserProfile, UserProfileChangeKind,
+};
 use std::time::Duration;
 
 #[test]
@@ -86,3 +90,66 @@ fn test_get_user_profile_returns_not_found() {
         GetUserProfileError::NotFound,
     );
 }
+
+#[test]
+fn test_get_user_profile_history_records_creation() {
+    let pic_setup = setup();
+
+    let caller = Principal::from_text(USER_1).unwrap();
+
+    let _ = pic_setup
+        .update::<UserProfile>(caller, "create_user_profile", ())
+        .expect("Create failed");
+
+    let response = pic_setup
+        .query::<Result<GetUserProfileHistoryResponse, GetUserProfileHistoryError>>(
+            caller,
+            "get_user_profile_history",
+            GetUserProfileHistoryRequest::default(),
+        )
+        .expect("Call to get profile history failed")
+        .expect("Get profile history failed");
+
+    assert_eq!(response.entries.len(), 1);
+    assert_eq!(response.entries[0].kind, UserProfileChangeKind::Created);
+}
+
+#[test]
+fn test_get_user_profile_history_of_another_user_requires_allowed_caller() {
+    let pic_setup = setup();
+
+    let user = Principal::from_text(USER_1).unwrap();
+    let allowed_caller = Principal::from_text(CALLER).unwrap();
+
+    let _ = pic_setup
+        .update::<UserProfile>(user, "create_user_profile", ())
+        .expect("Create failed");
+    let _ = pic_setup
+        .update::<UserProfile>(allowed_caller, "create_user_profile", ())
+        .expect("Create failed");
+
+    let request = GetUserProfileHistoryRequest {
+        principal: Some(allowed_caller),
+    };
+    let forbidden = pic_setup
+        .query::<Result<GetUserProfileHistoryResponse, GetUserProfileHistoryError>>(
+            user,
+            "get_user_profile_history",
+            request,
+        )
+        .expect("Call to get profile history failed");
+    assert_eq!(forbidden, Err(GetUserProfileHistoryError::NotAllowed));
+
+    let request = GetUserProfileHistoryRequest {
+        principal: Some(user),
+    };
+    let allowed = pic_setup
+        .query::<Result<GetUserProfileHistoryResponse, GetUserProfileHistoryError>>(
+            allowed_caller,
+            "get_user_profile_history",
+            request,
+        )
+        .expect("Call to get profile history failed")
+        .expect("Get profile history failed");
+    assert_eq!(allowed.entries.len(), 1);
+}
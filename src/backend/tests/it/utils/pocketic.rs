@@ -22,6 +22,12 @@ use super::mock::{
 const BACKEND_WASM: &str = "../../target/wasm32-unknown-unknown/release/backend.wasm";
 const DEFAULT_BITCOIN_WASM: &str = "../../ic-btc-canister.wasm.gz";
 const BITCOIN_CANISTER_ID: &str = "g4xu7-jiaaa-aaaan-aaaaq-cai";
+const DEFAULT_EVM_RPC_STUB_WASM: &str =
+    "../../target/wasm32-unknown-unknown/release/evm_rpc_stub.wasm";
+const DEFAULT_TEST_ENDPOINTS_BACKEND_WASM: &str =
+    "../../target/test-endpoints/wasm32-unknown-unknown/release/backend.wasm";
+/// The stand-in for the EVM RPC canister is deployed with the ID of the mainnet EVM RPC canister.
+pub const EVM_RPC_CANISTER_ID: &str = "7hfb6-caaaa-aaaar-qadga-cai";
 
 // This is necessary to deploy the bitcoin canister.
 // This is a struct based on the `InitConfig` from the Bitcoin canister.
@@ -80,6 +86,8 @@ pub struct BackendBuilder {
     wasm_path: String,
     /// Path to the bitcoin canister wasm file.
     bitcoin_wasm_path: String,
+    /// Path to the wasm file of the stand-in for the EVM RPC canister.  If set, the stand-in is deployed.
+    evm_rpc_stub_wasm_path: Option<String>,
     /// Argument to pass to the backend canister.
     arg: Vec<u8>,
     /// Controllers of the backend canister.
@@ -107,6 +115,19 @@ impl BackendBuilder {
     pub fn default_bitcoin_wasm_path() -> String {
         env::var("BITCOIN_CANISTER_WASM_FILE").unwrap_or_else(|_| DEFAULT_BITCOIN_WASM.to_string())
     }
+    /// The default Wasm file to deploy the stand-in for the EVM RPC canister:
+    /// - If the environment variable `EVM_RPC_STUB_WASM_PATH` is set, it will use that path.
+    /// - Otherwise, it will use the `DEFAULT_EVM_RPC_STUB_WASM` constant.
+    pub fn default_evm_rpc_stub_wasm_path() -> String {
+        env::var("EVM_RPC_STUB_WASM_PATH").unwrap_or_else(|_| DEFAULT_EVM_RPC_STUB_WASM.to_string())
+    }
+    /// The default Wasm file of the backend with the endpoints that only the tests use:
+    /// - If the environment variable `TEST_ENDPOINTS_BACKEND_WASM_PATH` is set, it will use that path.
+    /// - Otherwise, it will use the `DEFAULT_TEST_ENDPOINTS_BACKEND_WASM` constant.
+    pub fn default_test_endpoints_wasm_path() -> String {
+        env::var("TEST_ENDPOINTS_BACKEND_WASM_PATH")
+            .unwrap_or_else(|_| DEFAULT_TEST_ENDPOINTS_BACKEND_WASM.to_string())
+    }
     /// The default arguments to deploy the bitcoin canister.
     pub fn default_bitcoin_arg() -> Vec<u8> {
         let init_config = BitcoinInitConfig {
@@ -146,6 +167,7 @@ impl Default for BackendBuilder {
             cycles: Self::DEFAULT_CYCLES,
             wasm_path: Self::default_wasm_path(),
             bitcoin_wasm_path: Self::default_bitcoin_wasm_path(),
+            evm_rpc_stub_wasm_path: None,
             arg: Self::default_arg(),
             controllers: Self::default_controllers(),
         }
@@ -163,6 +185,24 @@ impl BackendBuilder {
         self.wasm_path = wasm_path.to_string();
         self
     }
+    /// Deploys the backend built with the `test-endpoints` feature, which has the endpoints that only the tests use.
+    pub fn with_test_endpoints(mut self) -> Self {
+        self.wasm_path = Self::default_test_endpoints_wasm_path();
+        self
+    }
+    /// Deploys a stand-in for the EVM RPC canister, which returns canned responses, and configures the backend to
+    /// use it.
+    pub fn with_evm_rpc_stub(mut self) -> Self {
+        self.evm_rpc_stub_wasm_path = Some(Self::default_evm_rpc_stub_wasm_path());
+        let Arg::Init(mut arg) = init_arg() else {
+            unreachable!("The default argument is an init argument")
+        };
+        arg.evm_rpc_canister_id = Some(
+            Principal::from_text(EVM_RPC_CANISTER_ID).expect("Unexpected EVM RPC canister id"),
+        );
+        self.arg = encode_one(Arg::Init(arg)).unwrap();
+        self
+    }
 }
 // Get parameters
 impl BackendBuilder {
@@ -219,6 +259,19 @@ impl BackendBuilder {
         let wasm_bytes = self.bitcoin_wasm_bytes();
         pic.install_canister(canister_id, wasm_bytes, Self::default_bitcoin_arg(), None);
     }
+    /// Install the stand-in for the EVM RPC canister, if configured.
+    fn install_evm_rpc_stub(&mut self, pic: &PocketIc) {
+        let Some(wasm_path) = self.evm_rpc_stub_wasm_path.clone() else {
+            return;
+        };
+        let canister_id =
+            Principal::from_text(EVM_RPC_CANISTER_ID).expect("Unexpected EVM RPC canister id");
+        pic.create_canister_with_id(None, None, canister_id)
+            .expect("Failed creating EVM RPC canister");
+        let wasm_bytes = read(&wasm_path)
+            .unwrap_or_else(|_| panic!("Could not find the EVM RPC stub wasm: {wasm_path}"));
+        pic.install_canister(canister_id, wasm_bytes, encode_one(()).unwrap(), None);
+    }
     /// Set controllers of the backend canister.
     fn set_controllers(&mut self, pic: &PocketIc) {
         let canister_id = self.canister_id(pic);
@@ -235,6 +288,7 @@ impl BackendBuilder {
     /// Setup the backend canister.
     pub fn deploy_to(&mut self, pic: &PocketIc) -> Principal {
         self.install_bitcoin(pic);
+        self.install_evm_rpc_stub(pic);
         self.deploy_backend(pic)
     }
     /// Deploy to a new pic.
@@ -320,9 +374,13 @@ pub(crate) fn init_arg() -> Arg {
             credential_type: CredentialType::ProofOfUniqueness,
         }]),
         api: None,
+        user_profile_history: None,
+        required_agreements: None,
+        btc_fee_tier_percentiles: None,
         cfs_canister_id: Some(
             Principal::from_text(SIGNER_CANISTER_ID.to_string()).expect("wrong cfs canister id"),
         ),
+        evm_rpc_canister_id: None,
         derivation_origin: Some(VC_DERIVATION_ORIGIN.to_string()),
     })
 }
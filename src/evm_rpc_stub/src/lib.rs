@@ -0,0 +1,80 @@
+//! A stand-in for the [EVM RPC canister](https://github.com/internet-computer-protocol/evm-rpc-canister) in tests.
+//!
+//! Only the raw `request` endpoint is provided.  It returns canned JSON-RPC responses, whatever the provider:
+//! - `eth_getBalance`: 1 ETH, except for [`INCONSISTENT_ADDRESS`], whose balance differs on every request, so that
+//!   the providers never agree.
+//! - `eth_blockNumber`: a block number that advances on every request, as the providers are rarely at the same block.
+//! - `eth_getTransactionCount`: 42.
+//! - `eth_feeHistory`: 5 blocks with a next base fee of 2 gwei and priority fees of 1, 1.5 and 2 gwei.
+//! - `eth_call`: the ERC20 `decimals()` and `symbol()` of USDC, i.e. 6 and "USDC", for any contract.
+//!
+//! Any other method returns a JSON-RPC error.
+use candid::Reserved;
+use ic_cdk::update;
+use serde_json::{json, Value};
+use std::cell::Cell;
+
+/// An address whose balance differs on every request.
+const INCONSISTENT_ADDRESS: &str = "0x000000000000000000000000000000000000dead";
+/// The selector of the ERC20 `decimals()`.
+const DECIMALS_SELECTOR: &str = "0x313ce567";
+/// The selector of the ERC20 `symbol()`.
+const SYMBOL_SELECTOR: &str = "0x95d89b41";
+/// The ABI encoding of the `uint8` 6.
+const DECIMALS: &str = "0x0000000000000000000000000000000000000000000000000000000000000006";
+/// The ABI encoding of the `string` "USDC".
+const SYMBOL: &str = "0x\
+    0000000000000000000000000000000000000000000000000000000000000020\
+    0000000000000000000000000000000000000000000000000000000000000004\
+    5553444300000000000000000000000000000000000000000000000000000000";
+
+thread_local! {
+    static REQUESTS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Answers a JSON-RPC request with a canned response.
+///
+/// The cycles attached to the call are not accepted, so they are all refunded.
+#[update]
+#[allow(clippy::needless_pass_by_value)]
+fn request(_: Reserved, json: String, _: u64) -> Result<String, String> {
+    let request: Value =
+        serde_json::from_str(&json).map_err(|err| format!("Invalid JSON-RPC request: {err}"))?;
+    let count = REQUESTS.with(|requests| {
+        requests.set(requests.get() + 1);
+        requests.get()
+    });
+    let params = &request["params"];
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "eth_getBalance" => {
+            let address = params[0].as_str().unwrap_or_default().to_lowercase();
+            if address == INCONSISTENT_ADDRESS {
+                json!(format!("{count:#x}"))
+            } else {
+                json!("0xde0b6b3a7640000")
+            }
+        }
+        "eth_blockNumber" => json!(format!("{:#x}", 0x100 + count)),
+        "eth_getTransactionCount" => json!("0x2a"),
+        "eth_feeHistory" => json!({
+            "oldestBlock": "0x100",
+            "baseFeePerGas": ["0x3b9aca00", "0x4190ab00", "0x47868c00", "0x4d7c6d00", "0x53724e00", "0x77359400"],
+            "gasUsedRatio": [0.5, 0.6, 0.7, 0.8, 0.9],
+            "reward": [["0x3b9aca00"], ["0x59682f00"], ["0x77359400"], ["0x3b9aca00"], ["0x59682f00"]],
+        }),
+        "eth_call" => match params[0]["data"].as_str().unwrap_or_default() {
+            DECIMALS_SELECTOR => json!(DECIMALS),
+            SYMBOL_SELECTOR => json!(SYMBOL),
+            _ => json!("0x"),
+        },
+        method => {
+            return Ok(json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": format!("Method not found: {method}") },
+            })
+            .to_string())
+        }
+    };
+    Ok(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string())
+}
@@ -0,0 +1,44 @@
+//! Hashing of [EIP-191](https://eips.ethereum.org/EIPS/eip-191) messages, as signed by `personal_sign`.
+use ethers_core::utils::keccak256;
+
+/// The prefix of version `0x45` messages, which is followed by the length of the message in decimal.
+const PREFIX: &str = "\x19Ethereum Signed Message:\n";
+
+/// The digest that is signed: `keccak256("\x19Ethereum Signed Message:\n" ‖ len(message) ‖ message)`.
+#[must_use]
+pub fn digest(message: &[u8]) -> [u8; 32] {
+    let mut prefixed = format!("{PREFIX}{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    keccak256(prefixed)
+}
+
+/// The message of a `personal_sign` request.
+///
+/// Wallets accept both hex encoded bytes with a `0x` prefix and plain text, so anything that is not valid hex is
+/// signed as UTF-8 text.
+#[must_use]
+pub fn personal_message(message: &str) -> Vec<u8> {
+    message
+        .strip_prefix("0x")
+        .and_then(|hex| hex::decode(hex).ok())
+        .unwrap_or_else(|| message.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn hello_world() {
+        assert_eq!(
+            hex::encode(digest(b"Hello World")),
+            "a1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+        assert_eq!(
+            digest(&personal_message("0x48656c6c6f20576f726c64")),
+            digest(&personal_message("Hello World"))
+        );
+        assert_eq!(personal_message("0xzz"), b"0xzz".to_vec());
+    }
+}
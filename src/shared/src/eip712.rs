@@ -0,0 +1,484 @@
+//! Hashing of [EIP-712](https://eips.ethereum.org/EIPS/eip-712) typed data, as signed by `eth_signTypedData_v4`.
+//!
+//! The typed data is the JSON document passed to `eth_signTypedData_v4`, with the `types`, `primaryType`, `domain` and
+//! `message` fields.  Arrays, both dynamic and fixed size, and nested struct types are encoded as in version 4 of
+//! the `MetaMask` implementation, which is the de facto standard.
+use ethers_core::{
+    types::{Address, I256, U256},
+    utils::keccak256,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The name of the type of the domain.
+pub const DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// A field of a struct type.
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+/// The struct types, by name.
+pub type Types = BTreeMap<String, Vec<Field>>;
+
+/// The typed data of `eth_signTypedData_v4`.
+#[derive(Deserialize, Clone, Eq, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: Types,
+    pub primary_type: String,
+    pub domain: Map<String, Value>,
+    pub message: Map<String, Value>,
+}
+
+impl TypedData {
+    /// Parses the JSON typed data of `eth_signTypedData_v4`.
+    ///
+    /// # Errors
+    /// - The typed data is not valid JSON or lacks a field.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|err| format!("Invalid typed data: {err}"))
+    }
+
+    /// The types, with the domain type inferred from the fields of the domain if it is not given.
+    fn types_with_domain(&self) -> Types {
+        let mut types = self.types.clone();
+        types.entry(DOMAIN_TYPE.to_string()).or_insert_with(|| {
+            [
+                ("name", "string"),
+                ("version", "string"),
+                ("chainId", "uint256"),
+                ("verifyingContract", "address"),
+                ("salt", "bytes32"),
+            ]
+            .into_iter()
+            .filter(|(name, _)| self.domain.contains_key(*name))
+            .map(|(name, r#type)| Field {
+                name: name.to_string(),
+                r#type: r#type.to_string(),
+            })
+            .collect()
+        });
+        types
+    }
+
+    /// The hash of the domain.
+    ///
+    /// # Errors
+    /// - The domain does not match its type.
+    pub fn domain_separator(&self) -> Result<[u8; 32], String> {
+        hash_struct(&self.types_with_domain(), DOMAIN_TYPE, &self.domain)
+    }
+
+    /// The digest that is signed: `keccak256(0x19 0x01 ‖ domainSeparator ‖ hashStruct(message))`.
+    ///
+    /// If the primary type is the domain type, the message is not part of the digest.
+    ///
+    /// # Errors
+    /// - The domain or the message does not match its type.
+    pub fn digest(&self) -> Result<[u8; 32], String> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(&self.domain_separator()?);
+        if self.primary_type != DOMAIN_TYPE {
+            encoded.extend_from_slice(&hash_struct(
+                &self.types_with_domain(),
+                &self.primary_type,
+                &self.message,
+            )?);
+        }
+        Ok(keccak256(encoded))
+    }
+}
+
+/// The element type of an array type, e.g. `Person` for `Person[]` or `uint8[2]` for `uint8[2][3]`.
+fn array_element_type(r#type: &str) -> Option<&str> {
+    r#type
+        .strip_suffix(']')
+        .and_then(|r#type| r#type.rfind('[').map(|index| &r#type[..index]))
+}
+
+/// The struct type of a field type, without any array suffixes.
+fn base_type(r#type: &str) -> &str {
+    r#type.split('[').next().unwrap_or(r#type)
+}
+
+/// The struct types that a struct type references, directly or indirectly, including itself.
+fn dependencies<'a>(types: &'a Types, r#type: &'a str, found: &mut BTreeSet<&'a str>) {
+    if found.contains(r#type) {
+        return;
+    }
+    if let Some(fields) = types.get(r#type) {
+        found.insert(r#type);
+        for field in fields {
+            dependencies(types, base_type(&field.r#type), found);
+        }
+    }
+}
+
+/// The encoding of a struct type: the type itself, followed by the types it references, sorted by name.
+///
+/// # Errors
+/// - The type is not defined.
+pub fn encode_type(types: &Types, primary_type: &str) -> Result<String, String> {
+    if !types.contains_key(primary_type) {
+        return Err(format!("Undefined type: {primary_type}"));
+    }
+    let mut found = BTreeSet::new();
+    dependencies(types, primary_type, &mut found);
+    found.remove(primary_type);
+    let mut encoded = String::new();
+    for r#type in std::iter::once(primary_type).chain(found) {
+        let fields = types[r#type]
+            .iter()
+            .map(|field| format!("{} {}", field.r#type, field.name))
+            .collect::<Vec<_>>()
+            .join(",");
+        encoded.push_str(&format!("{type}({fields})"));
+    }
+    Ok(encoded)
+}
+
+/// The hash of the encoding of a struct type.
+///
+/// # Errors
+/// - The type is not defined.
+pub fn type_hash(types: &Types, primary_type: &str) -> Result<[u8; 32], String> {
+    encode_type(types, primary_type).map(keccak256)
+}
+
+/// The hash of a struct: `keccak256(typeHash ‖ encodeData(data))`.
+///
+/// # Errors
+/// - The data does not match the type.
+pub fn hash_struct(
+    types: &Types,
+    primary_type: &str,
+    data: &Map<String, Value>,
+) -> Result<[u8; 32], String> {
+    let mut encoded = type_hash(types, primary_type)?.to_vec();
+    for field in &types[primary_type] {
+        let value = data
+            .get(&field.name)
+            .ok_or_else(|| format!("Missing field {} of {primary_type}", field.name))?;
+        encoded.extend_from_slice(&encode_value(types, &field.r#type, value)?);
+    }
+    Ok(keccak256(encoded))
+}
+
+/// Encodes a value as a 32 byte word.
+///
+/// Structs, arrays and dynamic types are encoded as the hash of their contents.
+fn encode_value(types: &Types, r#type: &str, value: &Value) -> Result<[u8; 32], String> {
+    let invalid = || format!("Invalid {type} value: {value}");
+    if let Some(element_type) = array_element_type(r#type) {
+        let elements = value.as_array().ok_or_else(invalid)?;
+        let length = &r#type[element_type.len() + 1..r#type.len() - 1];
+        if !length.is_empty() && length.parse::<usize>().ok() != Some(elements.len()) {
+            return Err(invalid());
+        }
+        let mut encoded = Vec::with_capacity(32 * elements.len());
+        for element in elements {
+            encoded.extend_from_slice(&encode_value(types, element_type, element)?);
+        }
+        return Ok(keccak256(encoded));
+    }
+    if types.contains_key(r#type) {
+        return hash_struct(types, r#type, value.as_object().ok_or_else(invalid)?);
+    }
+    match r#type {
+        "string" => Ok(keccak256(value.as_str().ok_or_else(invalid)?)),
+        "bytes" => Ok(keccak256(parse_hex(value).ok_or_else(invalid)?)),
+        "bool" => Ok(word(U256::from(u8::from(
+            value.as_bool().ok_or_else(invalid)?,
+        )))),
+        "address" => {
+            let address: Address = value
+                .as_str()
+                .and_then(|address| address.parse().ok())
+                .ok_or_else(invalid)?;
+            let mut encoded = [0; 32];
+            encoded[12..].copy_from_slice(address.as_bytes());
+            Ok(encoded)
+        }
+        _ => {
+            if let Some(size) = type_size(r#type, "bytes") {
+                let bytes = parse_hex(value)
+                    .filter(|bytes| (1..=32).contains(&size) && bytes.len() <= size);
+                let bytes = bytes.ok_or_else(invalid)?;
+                let mut encoded = [0; 32];
+                encoded[..bytes.len()].copy_from_slice(&bytes);
+                Ok(encoded)
+            } else if let Some(bits) = type_size(r#type, "uint") {
+                let number = parse_uint(value).ok_or_else(invalid)?;
+                if !valid_bits(bits) || number.bits() > bits {
+                    return Err(invalid());
+                }
+                Ok(word(number))
+            } else if let Some(bits) = type_size(r#type, "int") {
+                let number = parse_int(value).ok_or_else(invalid)?;
+                // The number fits if it is within [-2^(bits - 1), 2^(bits - 1)).
+                let limit = I256::from_raw(U256::one() << (bits - 1));
+                if !valid_bits(bits) || (bits < 256 && (number < -limit || number >= limit)) {
+                    return Err(invalid());
+                }
+                Ok(word(number.into_raw()))
+            } else {
+                Err(format!("Undefined type: {type}"))
+            }
+        }
+    }
+}
+
+/// The size of a sized type such as `uint64` or `bytes4`, if the type has the given prefix.
+fn type_size(r#type: &str, prefix: &str) -> Option<usize> {
+    r#type.strip_prefix(prefix)?.parse().ok()
+}
+
+/// Whether an integer type has a valid number of bits.
+fn valid_bits(bits: usize) -> bool {
+    (8..=256).contains(&bits) && bits % 8 == 0
+}
+
+/// The big endian encoding of a number.
+fn word(number: U256) -> [u8; 32] {
+    let mut encoded = [0; 32];
+    number.to_big_endian(&mut encoded);
+    encoded
+}
+
+/// Parses hex encoded bytes with a `0x` prefix.
+fn parse_hex(value: &Value) -> Option<Vec<u8>> {
+    hex::decode(value.as_str()?.strip_prefix("0x")?).ok()
+}
+
+/// Parses an unsigned number given as a JSON number, a decimal string or a hex string with a `0x` prefix.
+fn parse_uint(value: &Value) -> Option<U256> {
+    match value {
+        Value::Number(number) => number.as_u64().map(U256::from),
+        Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_dec_str(string).ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Parses a signed number given as a JSON number or a decimal string.
+fn parse_int(value: &Value) -> Option<I256> {
+    match value {
+        Value::Number(number) => number.as_i64().map(I256::from),
+        Value::String(string) => I256::from_dec_str(string).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// The example of the EIP: <https://github.com/ethereum/EIPs/blob/master/assets/eip-712/Example.js>
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "version", "type": "string" },
+                { "name": "chainId", "type": "uint256" },
+                { "name": "verifyingContract", "type": "address" }
+            ],
+            "Person": [
+                { "name": "name", "type": "string" },
+                { "name": "wallet", "type": "address" }
+            ],
+            "Mail": [
+                { "name": "from", "type": "Person" },
+                { "name": "to", "type": "Person" },
+                { "name": "contents", "type": "string" }
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    /// The example with arrays of the `MetaMask` implementation: <https://github.com/MetaMask/eth-sig-util/blob/main/src/sign-typed-data.test.ts>
+    const MAIL_WITH_ARRAYS: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "version", "type": "string" },
+                { "name": "chainId", "type": "uint256" },
+                { "name": "verifyingContract", "type": "address" }
+            ],
+            "Group": [
+                { "name": "name", "type": "string" },
+                { "name": "members", "type": "Person[]" }
+            ],
+            "Person": [
+                { "name": "name", "type": "string" },
+                { "name": "wallets", "type": "address[]" }
+            ],
+            "Mail": [
+                { "name": "from", "type": "Person" },
+                { "name": "to", "type": "Person[]" },
+                { "name": "contents", "type": "string" }
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {
+                "name": "Cow",
+                "wallets": [
+                    "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826",
+                    "0xDeaDbeefdEAdbeefdEadbEEFdeadbeEFdEaDbeeF"
+                ]
+            },
+            "to": [
+                {
+                    "name": "Bob",
+                    "wallets": [
+                        "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB",
+                        "0xB0BdaBea57B0BDABeA57b0bdABEA57b0BDabEa57",
+                        "0xB0B0b0b0b0b0B000000000000000000000000000"
+                    ]
+                }
+            ],
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    fn message_hash(typed_data: &TypedData) -> String {
+        hex::encode(
+            hash_struct(
+                &typed_data.types,
+                &typed_data.primary_type,
+                &typed_data.message,
+            )
+            .expect("failed to hash the message"),
+        )
+    }
+
+    #[test]
+    fn mail_example_of_the_eip() {
+        let typed_data = TypedData::from_json(MAIL).expect("invalid typed data");
+
+        assert_eq!(
+            encode_type(&typed_data.types, "Mail"),
+            Ok(
+                "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            hex::encode(type_hash(&typed_data.types, "Mail").unwrap()),
+            "a0cedeb2dc280ba39b857546d74f5549c3a1d7bdc2dd96bf881f76108e23dac2"
+        );
+        assert_eq!(
+            message_hash(&typed_data),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+    }
+
+    /// The domain separator and the digest to sign that the EIP gives for its example.
+    #[test]
+    fn mail_test_vectors_of_the_eip() {
+        let typed_data = TypedData::from_json(MAIL).expect("invalid typed data");
+
+        assert_eq!(
+            typed_data.domain_separator().map(hex::encode),
+            Ok("f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f".to_string())
+        );
+        assert_eq!(
+            typed_data.digest().map(hex::encode),
+            Ok("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2".to_string())
+        );
+    }
+
+    #[test]
+    fn mail_example_with_arrays() {
+        let typed_data = TypedData::from_json(MAIL_WITH_ARRAYS).expect("invalid typed data");
+
+        assert_eq!(
+            encode_type(&typed_data.types, "Mail"),
+            Ok("Mail(Person from,Person[] to,string contents)Person(string name,address[] wallets)".to_string())
+        );
+        assert_eq!(
+            encode_type(&typed_data.types, "Group"),
+            Ok(
+                "Group(string name,Person[] members)Person(string name,address[] wallets)"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            hex::encode(type_hash(&typed_data.types, "Mail").unwrap()),
+            "4bd8a9a2b93427bb184aca81e24beb30ffa3c747e2a33d4225ec08bf12e2e753"
+        );
+        assert_eq!(
+            message_hash(&typed_data),
+            "eb4221181ff3f1a83ea7313993ca9218496e424604ba9492bb4052c03d5c3df8"
+        );
+        assert_eq!(
+            hex::encode(typed_data.digest().unwrap()),
+            "a85c2e2b118698e88db68a8105b794a8cc7cec074e89ef991cb4f5f533819cc2"
+        );
+    }
+
+    #[test]
+    fn domain_type_is_inferred() {
+        let mut typed_data = TypedData::from_json(MAIL).expect("invalid typed data");
+        let expected = typed_data.digest();
+
+        typed_data.types.remove(DOMAIN_TYPE);
+
+        assert_eq!(typed_data.digest(), expected);
+    }
+
+    #[test]
+    fn values_must_match_their_types() {
+        let types: Types = serde_json::from_str(
+            r#"{ "T": [
+                { "name": "a", "type": "uint8" },
+                { "name": "b", "type": "int16" },
+                { "name": "c", "type": "bytes4" },
+                { "name": "d", "type": "bool[2]" }
+            ] }"#,
+        )
+        .unwrap();
+        let hash = |data: &str| hash_struct(&types, "T", &serde_json::from_str(data).unwrap());
+
+        assert!(
+            hash(r#"{ "a": 255, "b": "-32768", "c": "0x01020304", "d": [true, false] }"#).is_ok()
+        );
+        assert!(hash(r#"{ "a": "0xff", "b": 32767, "c": "0x01", "d": [true, false] }"#).is_ok());
+        assert!(hash(r#"{ "a": 256, "b": 0, "c": "0x01020304", "d": [true, false] }"#).is_err());
+        assert!(
+            hash(r#"{ "a": 0, "b": "-32769", "c": "0x01020304", "d": [true, false] }"#).is_err()
+        );
+        assert!(hash(r#"{ "a": 0, "b": 0, "c": "0x0102030405", "d": [true, false] }"#).is_err());
+        assert!(hash(r#"{ "a": 0, "b": 0, "c": "0x01020304", "d": [true] }"#).is_err());
+        assert!(hash(r#"{ "a": 0, "b": 0, "c": "0x01020304" }"#).is_err());
+        assert_eq!(
+            hash_struct(&types, "U", &Map::new()),
+            Err("Undefined type: U".to_string())
+        );
+    }
+}
@@ -1,13 +1,15 @@
+use crate::types::bitcoin::{BtcFeeTier, BtcFeeTierPercentiles};
 use crate::types::custom_token::{CustomToken, CustomTokenId, Token};
 use crate::types::dapp::{AddDappSettingsError, DappCarouselSettings, DappSettings};
 use crate::types::settings::Settings;
 use crate::types::token::UserToken;
 use crate::types::user_profile::{
-    AddUserCredentialError, OisyUser, StoredUserProfile, UserCredential, UserProfile,
+    AcceptAgreementsError, AcceptedAgreement, AddUserCredentialError, OisyUser, StoredUserProfile,
+    UserCredential, UserProfile, UserProfileChange,
 };
 use crate::types::{
-    ApiEnabled, Config, CredentialType, InitArg, Migration, MigrationProgress, MigrationReport,
-    Timestamp, TokenVersion, Version,
+    AgreementKind, AgreementVersion, ApiEnabled, Config, CredentialType, InitArg, Migration,
+    MigrationProgress, MigrationReport, Timestamp, TokenVersion, Version,
 };
 use candid::Principal;
 use ic_canister_sig_creation::{extract_raw_root_pk_from_der, IC_ROOT_PK_DER};
@@ -47,6 +49,7 @@ impl From<InitArg> for Config {
     ///
     /// # Panics
     /// - If the root key cannot be parsed.
+    /// - If the Bitcoin fee tier percentiles are not valid.
     fn from(arg: InitArg) -> Self {
         let InitArg {
             ecdsa_key_name,
@@ -55,7 +58,11 @@ impl From<InitArg> for Config {
             ic_root_key_der,
             api,
             cfs_canister_id,
+            evm_rpc_canister_id,
             derivation_origin,
+            user_profile_history,
+            required_agreements,
+            btc_fee_tier_percentiles,
         } = arg;
         let ic_root_key_raw = match extract_raw_root_pk_from_der(
             &ic_root_key_der.unwrap_or_else(|| IC_ROOT_PK_DER.to_vec()),
@@ -63,14 +70,21 @@ impl From<InitArg> for Config {
             Ok(root_key) => root_key,
             Err(msg) => panic!("{}", format!("Error parsing root key: {msg}")),
         };
+        if let Some(Err(msg)) = btc_fee_tier_percentiles.map(|percentiles| percentiles.validate()) {
+            panic!("{}", format!("Invalid Bitcoin fee tier percentiles: {msg}"));
+        }
         Config {
             ecdsa_key_name,
             allowed_callers,
             cfs_canister_id,
+            evm_rpc_canister_id,
             supported_credentials,
             ic_root_key_raw: Some(ic_root_key_raw),
             api,
             derivation_origin,
+            user_profile_history,
+            required_agreements,
+            btc_fee_tier_percentiles,
         }
     }
 }
@@ -136,6 +150,7 @@ impl StoredUserProfile {
             created_timestamp: now,
             updated_timestamp: now,
             version: None,
+            agreements: None,
         }
     }
 
@@ -203,6 +218,168 @@ impl StoredUserProfile {
         new_profile.updated_timestamp = now;
         Ok(new_profile)
     }
+
+    /// Records that the user has accepted the given agreement versions.
+    ///
+    /// # Errors
+    ///
+    /// Will return Err if there is a profile version mismatch, or if an accepted agreement version
+    /// is not the version that users are currently required to accept.
+    pub fn accept_agreements(
+        &self,
+        profile_version: Option<Version>,
+        now: Timestamp,
+        accepted: &BTreeMap<AgreementKind, AgreementVersion>,
+        required: &BTreeMap<AgreementKind, AgreementVersion>,
+    ) -> Result<StoredUserProfile, AcceptAgreementsError> {
+        if profile_version != self.version {
+            return Err(AcceptAgreementsError::VersionMismatch);
+        }
+        for (agreement, version) in accepted {
+            let required_version = required.get(agreement).copied();
+            if required_version != Some(*version) {
+                return Err(AcceptAgreementsError::AgreementVersionMismatch {
+                    agreement: *agreement,
+                    required_version,
+                });
+            }
+        }
+
+        let mut new_profile = self.clone_with_incremented_version();
+        let mut new_agreements = new_profile.agreements.clone().unwrap_or_default();
+        for (agreement, version) in accepted {
+            new_agreements.insert(
+                *agreement,
+                AcceptedAgreement {
+                    version: *version,
+                    accepted_timestamp: now,
+                },
+            );
+        }
+        new_profile.agreements = Some(new_agreements);
+        new_profile.updated_timestamp = now;
+        Ok(new_profile)
+    }
+
+    /// Whether the user has accepted the given version of an agreement, or a later one.
+    #[must_use]
+    pub fn has_accepted(&self, agreement: AgreementKind, version: AgreementVersion) -> bool {
+        self.agreements
+            .as_ref()
+            .and_then(|agreements| agreements.get(&agreement))
+            .is_some_and(|accepted| accepted.version >= version)
+    }
+
+    /// Lists the field-level changes from this profile to a newer version of it.
+    ///
+    /// Pass `None` as the previous profile to list the contents of a newly created profile.
+    #[must_use]
+    pub fn changes_since(&self, previous: Option<&StoredUserProfile>) -> Vec<UserProfileChange> {
+        fn hidden_dapp_ids(profile: Option<&StoredUserProfile>) -> Vec<String> {
+            profile
+                .and_then(|profile| profile.settings.as_ref())
+                .map(|settings| settings.dapp.dapp_carousel.hidden_dapp_ids.clone())
+                .unwrap_or_default()
+        }
+        let empty_credentials = BTreeMap::new();
+        let previous_credentials = previous.map_or(&empty_credentials, |p| &p.credentials);
+
+        let mut changes = Vec::new();
+        for (credential_type, credential) in &self.credentials {
+            if previous_credentials.get(credential_type) != Some(credential) {
+                changes.push(UserProfileChange::CredentialAdded {
+                    credential_type: credential_type.clone(),
+                    issuer: credential.issuer.clone(),
+                });
+            }
+        }
+        for credential_type in previous_credentials.keys() {
+            if !self.credentials.contains_key(credential_type) {
+                changes.push(UserProfileChange::CredentialRemoved {
+                    credential_type: credential_type.clone(),
+                });
+            }
+        }
+
+        let previous_dapp_ids = hidden_dapp_ids(previous);
+        let current_dapp_ids = hidden_dapp_ids(Some(self));
+        for dapp_id in &current_dapp_ids {
+            if !previous_dapp_ids.contains(dapp_id) {
+                changes.push(UserProfileChange::HiddenDappIdAdded {
+                    dapp_id: dapp_id.clone(),
+                });
+            }
+        }
+        for dapp_id in previous_dapp_ids {
+            if !current_dapp_ids.contains(&dapp_id) {
+                changes.push(UserProfileChange::HiddenDappIdRemoved { dapp_id });
+            }
+        }
+
+        let previous_agreements = previous.and_then(|p| p.agreements.as_ref());
+        for (agreement, accepted) in self.agreements.iter().flatten() {
+            if previous_agreements.and_then(|agreements| agreements.get(agreement))
+                != Some(accepted)
+            {
+                changes.push(UserProfileChange::AgreementAccepted {
+                    agreement: *agreement,
+                    version: accepted.version,
+                });
+            }
+        }
+        changes
+    }
+}
+
+#[test]
+fn test_accept_agreements_requires_current_version() {
+    let profile = StoredUserProfile::from_timestamp(1);
+    let required = BTreeMap::from([(AgreementKind::TermsOfService, 2)]);
+
+    assert_eq!(
+        profile.accept_agreements(
+            None,
+            2,
+            &BTreeMap::from([(AgreementKind::TermsOfService, 1)]),
+            &required
+        ),
+        Err(AcceptAgreementsError::AgreementVersionMismatch {
+            agreement: AgreementKind::TermsOfService,
+            required_version: Some(2),
+        })
+    );
+    assert_eq!(
+        profile.accept_agreements(
+            None,
+            2,
+            &BTreeMap::from([(AgreementKind::PrivacyPolicy, 1)]),
+            &required
+        ),
+        Err(AcceptAgreementsError::AgreementVersionMismatch {
+            agreement: AgreementKind::PrivacyPolicy,
+            required_version: None,
+        })
+    );
+    assert_eq!(
+        profile.accept_agreements(Some(3), 2, &required, &required),
+        Err(AcceptAgreementsError::VersionMismatch)
+    );
+
+    let accepted = profile
+        .accept_agreements(None, 2, &required, &required)
+        .expect("Failed to accept agreements");
+    assert_eq!(accepted.version, Some(1));
+    assert_eq!(accepted.updated_timestamp, 2);
+    assert!(accepted.has_accepted(AgreementKind::TermsOfService, 2));
+    assert!(!accepted.has_accepted(AgreementKind::TermsOfService, 3));
+    assert!(!accepted.has_accepted(AgreementKind::PrivacyPolicy, 1));
+    assert_eq!(
+        accepted.changes_since(Some(&profile)),
+        vec![UserProfileChange::AgreementAccepted {
+            agreement: AgreementKind::TermsOfService,
+            version: 2,
+        }]
+    );
 }
 
 impl From<&StoredUserProfile> for UserProfile {
@@ -213,6 +390,7 @@ impl From<&StoredUserProfile> for UserProfile {
             version,
             credentials,
             settings,
+            agreements,
         } = user;
         UserProfile {
             created_timestamp: *created_timestamp,
@@ -220,6 +398,7 @@ impl From<&StoredUserProfile> for UserProfile {
             version: *version,
             credentials: credentials.clone().into_values().collect(),
             settings: settings.clone(),
+            agreements: agreements.clone(),
         }
     }
 }
@@ -246,6 +425,54 @@ impl From<&Migration> for MigrationReport {
     }
 }
 
+impl Default for BtcFeeTierPercentiles {
+    fn default() -> Self {
+        Self {
+            slow: 25,
+            standard: 50,
+            fast: 75,
+        }
+    }
+}
+impl BtcFeeTierPercentiles {
+    /// The network fee percentile used for the given tier.
+    #[must_use]
+    pub fn percentile(&self, tier: BtcFeeTier) -> u8 {
+        match tier {
+            BtcFeeTier::Slow => self.slow,
+            BtcFeeTier::Standard => self.standard,
+            BtcFeeTier::Fast => self.fast,
+        }
+    }
+
+    /// Checks that the percentiles are at most 100, and that faster tiers do not use lower percentiles.
+    ///
+    /// # Errors
+    /// - If a percentile is above 100, or below the percentile of a slower tier.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fast > 100 {
+            return Err("Percentiles must be at most 100".to_string());
+        }
+        if self.slow > self.standard || self.standard > self.fast {
+            return Err("Percentiles must increase from the slow to the fast tier".to_string());
+        }
+        Ok(())
+    }
+}
+#[test]
+fn test_btc_fee_tier_percentiles_validate() {
+    let percentiles = |slow, standard, fast| BtcFeeTierPercentiles {
+        slow,
+        standard,
+        fast,
+    };
+    assert!(BtcFeeTierPercentiles::default().validate().is_ok());
+    assert!(percentiles(0, 0, 100).validate().is_ok());
+    assert!(percentiles(25, 50, 101).validate().is_err());
+    assert!(percentiles(50, 25, 75).validate().is_err());
+    assert!(percentiles(25, 75, 50).validate().is_err());
+}
+
 impl Default for ApiEnabled {
     fn default() -> Self {
         Self::Enabled
@@ -304,6 +531,21 @@ impl MigrationProgress {
                 MigrationProgress::MigratedUserProfilesUpTo(None)
             }
             MigrationProgress::MigratedUserProfilesUpTo(_) => {
+                MigrationProgress::MigratedUserProfileHistoryUpTo(None)
+            }
+            MigrationProgress::MigratedUserProfileHistoryUpTo(_) => {
+                MigrationProgress::MigratedPrincipalLinksUpTo(None)
+            }
+            MigrationProgress::MigratedPrincipalLinksUpTo(_) => {
+                MigrationProgress::MigratedBtcPendingTransactionsUpTo(None)
+            }
+            MigrationProgress::MigratedBtcPendingTransactionsUpTo(_) => {
+                MigrationProgress::MigratedBtcFrozenUtxosUpTo(None)
+            }
+            MigrationProgress::MigratedBtcFrozenUtxosUpTo(_) => {
+                MigrationProgress::MigratedEthPendingTransactionsUpTo(None)
+            }
+            MigrationProgress::MigratedEthPendingTransactionsUpTo(_) => {
                 MigrationProgress::CheckingDataMigration
             }
             MigrationProgress::CheckingDataMigration => MigrationProgress::UnlockingTarget,
@@ -1,4 +1,6 @@
 pub mod backend_api;
+pub mod eip191;
+pub mod eip712;
 pub mod http;
 mod impls;
 pub mod metrics;
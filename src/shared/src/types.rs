@@ -1,5 +1,6 @@
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_timers::TimerId;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use strum_macros::{EnumCount as EnumCountMacro, EnumIter};
 
@@ -10,6 +11,22 @@ pub enum CredentialType {
     ProofOfUniqueness,
 }
 
+/// A legal agreement that users have to accept.
+#[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+pub enum AgreementKind {
+    TermsOfService,
+    PrivacyPolicy,
+}
+
+/// The version of a legal agreement, e.g. of the terms of service.
+pub type AgreementVersion = u64;
+
+#[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SetRequiredAgreementVersionRequest {
+    pub agreement: AgreementKind,
+    pub version: AgreementVersion,
+}
+
 #[derive(CandidType, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct SupportedCredential {
     pub credential_type: CredentialType,
@@ -30,9 +47,17 @@ pub struct InitArg {
     pub api: Option<Guards>,
     /// Chain Fusion Signer canister id. Used to derive the bitcoin address in `btc_select_user_utxos_fee`
     pub cfs_canister_id: Option<Principal>,
+    /// EVM RPC canister id. Used to read the state of EVM chains.  If not set, EVM chains are not read.
+    pub evm_rpc_canister_id: Option<Principal>,
     /// Derivation origins when logging in the dapp with Internet Identity.
     /// Used to validate the id alias credential which includes the derivation origin of the id alias.
     pub derivation_origin: Option<String>,
+    /// Retention limits for the user profile change history.
+    pub user_profile_history: Option<UserProfileHistoryConfig>,
+    /// The version of each agreement that users are currently required to accept.
+    pub required_agreements: Option<BTreeMap<AgreementKind, AgreementVersion>>,
+    /// The network fee percentiles used for the Bitcoin fee tiers.  Defaults to 25, 50 and 75.
+    pub btc_fee_tier_percentiles: Option<bitcoin::BtcFeeTierPercentiles>,
 }
 
 #[derive(CandidType, Deserialize, Eq, PartialEq, Debug, Copy, Clone)]
@@ -77,15 +102,34 @@ pub struct Config {
     pub api: Option<Guards>,
     /// Chain Fusion Signer canister id. Used to derive the bitcoin address in `btc_select_user_utxos_fee`
     pub cfs_canister_id: Option<Principal>,
+    /// EVM RPC canister id. Used to read the state of EVM chains.  If not set, EVM chains are not read.
+    pub evm_rpc_canister_id: Option<Principal>,
     /// Derivation origins when logging in the dapp with Internet Identity.
     /// Used to validate the id alias credential which includes the derivation origin of the id alias.
     pub derivation_origin: Option<String>,
+    /// Retention limits for the user profile change history.
+    pub user_profile_history: Option<UserProfileHistoryConfig>,
+    /// The version of each agreement that users are currently required to accept.
+    pub required_agreements: Option<BTreeMap<AgreementKind, AgreementVersion>>,
+    /// The network fee percentiles used for the Bitcoin fee tiers.  Defaults to 25, 50 and 75.
+    pub btc_fee_tier_percentiles: Option<bitcoin::BtcFeeTierPercentiles>,
+}
+
+/// Retention limits for the per-user profile change history.
+///
+/// Limits are enforced whenever a new entry is appended to a user's history.
+#[derive(CandidType, Deserialize, Default, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UserProfileHistoryConfig {
+    /// The maximum number of entries kept per user.  The oldest entries are dropped first.
+    pub max_entries_per_user: Option<u64>,
+    /// Entries older than this, in nanoseconds, are dropped.
+    pub max_age_ns: Option<u64>,
 }
 
 pub mod transaction {
     use candid::{CandidType, Deserialize, Nat};
 
-    #[derive(CandidType, Deserialize)]
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct SignRequest {
         pub chain_id: Nat,
         pub to: String,
@@ -172,7 +216,7 @@ pub mod custom_token {
 
 pub mod bitcoin {
     use candid::CandidType;
-    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Utxo};
+    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
     use serde::Deserialize;
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -180,18 +224,389 @@ pub mod bitcoin {
         pub amount_satoshis: u64,
         pub network: BitcoinNetwork,
         pub min_confirmations: Option<u32>,
+        /// An explicit fee rate.  Cannot be combined with `fee_tier`.
+        pub fee_rate_millisatoshi_per_vbyte: Option<u64>,
+        /// The fee tier to pay.  Defaults to `Standard` if no explicit fee rate is given.
+        pub fee_tier: Option<BtcFeeTier>,
+        /// The destinations of a batch send.  If given, `amount_satoshis` must be the sum of the outputs.
+        ///
+        /// Without outputs, a single destination of the same type as the source address is assumed.
+        pub outputs: Option<Vec<BtcTxOutput>>,
+        /// How to select the UTXOs.  Defaults to `BranchAndBound`.
+        pub coin_selection: Option<BtcCoinSelectionStrategy>,
+        /// Sends all UTXOs that are not locked by a pending transaction to one destination, without change.
+        ///
+        /// The amount is computed, so `amount_satoshis`, `coin_selection` and the amount of the output are
+        /// ignored.  `outputs` may contain the one destination; otherwise the source address is assumed.
+        pub sweep: Option<bool>,
+        /// The type of the caller's address that funds the transaction and receives the change.  Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        /// The UTXOs to spend, chosen by the user.  If given, exactly these UTXOs are spent and `coin_selection` is ignored.
+        ///
+        /// They must be UTXOs of the caller's address that are neither locked by a pending transaction nor frozen.
+        pub outpoints: Option<Vec<Outpoint>>,
+    }
+
+    /// The type of a user's Bitcoin address, derived from the chain fusion signer key.
+    #[derive(
+        CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Ord, PartialOrd, Default,
+    )]
+    pub enum BtcAddressType {
+        /// Native segwit v0, signed with threshold ECDSA.
+        #[default]
+        P2wpkh,
+        /// Taproot key path, signed with threshold Schnorr (BIP-340).
+        P2tr,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetAddressRequest {
+        pub network: BitcoinNetwork,
+        /// Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetAddressResponse {
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcGetAddressError {
+        InternalError { msg: String },
+    }
+
+    /// A UTXO that the user does not want to spend, e.g. because it holds an inscription.
+    ///
+    /// Frozen UTXOs are never selected to fund a transaction.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcFrozenUtxo {
+        pub outpoint: Outpoint,
+        pub label: Option<String>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcFreezeUtxosRequest {
+        /// The UTXOs to freeze.  The label of a UTXO that is already frozen is replaced.
+        pub utxos: Vec<BtcFrozenUtxo>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcFreezeUtxosError {
+        TooManyFrozenUtxos { max_frozen_utxos: u32 },
+        LabelTooLong { max_label_length: u32 },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcUnfreezeUtxosRequest {
+        pub outpoints: Vec<Outpoint>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetBalanceRequest {
+        pub network: BitcoinNetwork,
+        /// Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        /// The number of confirmations for a UTXO to count as confirmed.  Defaults to 6.
+        pub min_confirmations: Option<u32>,
+    }
+
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+    pub struct BtcGetBalanceResponse {
+        /// The value of the UTXOs with at least `min_confirmations` confirmations.
+        pub confirmed_satoshis: u64,
+        /// The value of the UTXOs with fewer confirmations.
+        pub unconfirmed_incoming_satoshis: u64,
+        /// The value of the confirmed UTXOs that are spent by pending transactions.
+        pub locked_satoshis: u64,
+        /// The number of confirmed UTXOs that are not spent by pending transactions.
+        pub spendable_utxo_count: u32,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcGetBalanceError {
+        InternalError { msg: String },
+    }
+
+    /// How the UTXOs that fund a transaction are selected.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+    pub enum BtcCoinSelectionStrategy {
+        /// Searches for the UTXOs that waste the least, preferring solutions without change.
+        ///
+        /// Falls back to `SingleRandomDraw` if there is no such solution.
+        #[default]
+        BranchAndBound,
+        /// Adds UTXOs in random order until the amount and a change output are covered.
+        SingleRandomDraw,
+        /// The algorithm of the ckBTC minter, which ignores the cost of each input.
+        Greedy,
+    }
+
+    /// A payment to one destination of a Bitcoin transaction.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcTxOutput {
+        pub destination_address: String,
+        pub sent_satoshis: u64,
+    }
+
+    /// How much to pay for a transaction, trading off cost against confirmation time.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug, Default)]
+    pub enum BtcFeeTier {
+        Slow,
+        #[default]
+        Standard,
+        Fast,
+    }
+
+    /// The network fee percentile used for each fee tier.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcFeeTierPercentiles {
+        pub slow: u8,
+        pub standard: u8,
+        pub fast: u8,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetFeeTiersRequest {
+        pub network: BitcoinNetwork,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcFeeTierRate {
+        pub tier: BtcFeeTier,
+        pub percentile: u8,
+        pub fee_rate_millisatoshi_per_vbyte: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcGetFeeTiersResponse {
+        /// The fee rate of each tier, from slowest to fastest.
+        pub tiers: Vec<BtcFeeTierRate>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcGetFeeTiersError {
+        InternalError { msg: String },
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct SelectedUtxosFeeResponse {
         pub utxos: Vec<Utxo>,
         pub fee_satoshis: u64,
+        /// The amount sent to the destinations.  When sweeping, this is everything that is left after the fee.
+        pub amount_satoshis: u64,
+        /// The value of the change output, or zero if the change would be dust and is part of the fee.
+        pub change_satoshis: u64,
+        /// The requested outputs followed by the change output, if any.
+        ///
+        /// Without requested outputs, only the change output is listed.
+        pub outputs: Vec<BtcTxOutput>,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum SelectedUtxosFeeError {
-        InternalError { msg: String },
+        InternalError {
+            msg: String,
+        },
         PendingTransactions,
+        /// The fee rate or tier in the request is not valid.
+        InvalidFeeRequest {
+            msg: String,
+        },
+        /// The address is not a valid address on the requested network.
+        InvalidDestinationAddress {
+            address: String,
+        },
+        /// The outputs are empty, contain a zero amount, or do not add up to `amount_satoshis`.
+        InvalidOutputs {
+            msg: String,
+        },
+        /// The UTXOs of a sweep cannot pay for the fee and leave an amount above the dust limit.
+        InsufficientFunds,
+        /// A UTXO chosen by the user is not a UTXO of the caller's address with enough confirmations.
+        UtxoNotFound {
+            outpoint: Outpoint,
+        },
+        /// A UTXO chosen by the user is spent by a pending transaction.
+        UtxoLocked {
+            outpoint: Outpoint,
+        },
+        /// A UTXO chosen by the user is frozen.
+        UtxoFrozen {
+            outpoint: Outpoint,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBuildTransactionRequest {
+        pub network: BitcoinNetwork,
+        /// The destinations of the transaction.
+        pub outputs: Vec<BtcTxOutput>,
+        /// An explicit fee rate.  Cannot be combined with `fee_tier`.
+        pub fee_rate_millisatoshi_per_vbyte: Option<u64>,
+        /// The fee tier to pay.  Defaults to `Standard` if no explicit fee rate is given.
+        pub fee_tier: Option<BtcFeeTier>,
+        /// The address that receives the change.  Defaults to the source address.
+        pub change_address: Option<String>,
+        /// The type of the caller's address that funds the transaction.  Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+        pub min_confirmations: Option<u32>,
+        /// How to select the UTXOs.  Defaults to `BranchAndBound`.
+        pub coin_selection: Option<BtcCoinSelectionStrategy>,
+        /// The UTXOs to spend, chosen by the user.  See `SelectedUtxosFeeRequest::outpoints`.
+        pub outpoints: Option<Vec<Outpoint>>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBuildTransactionResponse {
+        /// The BIP-174 PSBT of the transaction, with the output spent by each input.
+        pub psbt: Vec<u8>,
+        /// The consensus encoding of the unsigned transaction.
+        pub unsigned_tx: Vec<u8>,
+        /// The id of the transaction, in internal byte order.
+        ///
+        /// As all inputs are segwit, signing does not change it.
+        pub txid: Vec<u8>,
+        /// The spent UTXOs, which are locked until the transaction is confirmed or expires.
+        pub utxos: Vec<Utxo>,
+        pub fee_satoshis: u64,
+        pub change_satoshis: u64,
+        /// The requested outputs followed by the change output, if any.
+        pub outputs: Vec<BtcTxOutput>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcBuildTransactionError {
+        InternalError {
+            msg: String,
+        },
+        /// The UTXOs could not be selected.
+        SelectUtxos(SelectedUtxosFeeError),
+        /// The change address is not a valid address on the requested network.
+        InvalidChangeAddress {
+            address: String,
+        },
+        /// The available UTXOs cannot pay for the outputs and the fee.
+        InsufficientFunds,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcBumpFeeRequest {
+        pub network: BitcoinNetwork,
+        /// The pending transaction to replace, in internal byte order.
+        pub txid: Vec<u8>,
+        /// The fee rate of the replacement.
+        pub fee_rate_millisatoshi_per_vbyte: u64,
+        /// The type of the caller's address that the transaction spends from.  Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcBumpFeeError {
+        InternalError {
+            msg: String,
+        },
+        /// The transaction is not pending, or it was not built or submitted by the backend.
+        TransactionNotFound,
+        /// The transaction has already been replaced.
+        AlreadyReplaced {
+            replaced_by: Vec<u8>,
+        },
+        /// The transaction does not signal replaceability (BIP-125).
+        NotReplaceable,
+        /// The replacement must pay more than the original and its pending descendants, by at least the incremental
+        /// relay fee.
+        FeeRateTooLow {
+            minimum_fee_rate_millisatoshi_per_vbyte: u64,
+        },
+        /// The fee rate is above the highest fee rate that may be requested.
+        FeeRateTooHigh {
+            maximum_fee_rate_millisatoshi_per_vbyte: u64,
+        },
+        /// The change of the transaction cannot pay for the higher fee.
+        InsufficientFunds,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcCpfpRequest {
+        pub network: BitcoinNetwork,
+        /// The pending transaction of the caller to accelerate, in internal byte order.
+        pub parent_txid: Vec<u8>,
+        /// The size and fee of the parent, as reported by the mempool.
+        ///
+        /// Defaults to the size and fee of the parent as it was built, with an estimated size if it was not signed here.
+        pub parent: Option<BtcParentTransaction>,
+        /// The fee rate of the parent and child together.
+        pub fee_rate_millisatoshi_per_vbyte: u64,
+        /// The address that receives the output of the child.  Defaults to the source address.
+        pub destination_address: Option<String>,
+        /// The type of the caller's address that the parent pays to.  Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    /// The size and fee of an unconfirmed transaction, as reported by the mempool.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcParentTransaction {
+        pub vsize: u64,
+        pub fee_satoshis: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcCpfpError {
+        InternalError {
+            msg: String,
+        },
+        /// The parent is not a pending transaction of the caller, so its outputs are not known.
+        ///
+        /// The Bitcoin API does not return the outputs of unconfirmed transactions.
+        ParentUnknown,
+        /// No output of the parent pays to the caller's address, or they are all locked or frozen.
+        UtxoNotFound,
+        /// The address is not a valid address on the requested network.
+        InvalidDestinationAddress {
+            address: String,
+        },
+        /// The fee rate is above the highest fee rate that may be requested.
+        FeeRateTooHigh {
+            maximum_fee_rate_millisatoshi_per_vbyte: u64,
+        },
+        /// The output of the parent cannot pay for the fee of the child.
+        InsufficientFunds,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcSubmitTransactionRequest {
+        pub network: BitcoinNetwork,
+        /// The signed transaction, in its consensus encoding.
+        pub raw_tx: Vec<u8>,
+        /// The type of the caller's address that the transaction spends from.  Defaults to `P2wpkh`.
+        pub address_type: Option<BtcAddressType>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct BtcSubmitTransactionResponse {
+        /// The id of the transaction, in internal byte order.
+        pub txid: Vec<u8>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum BtcSubmitTransactionError {
+        InternalError {
+            msg: String,
+        },
+        /// The transaction cannot be decoded.
+        InvalidTransaction {
+            msg: String,
+        },
+        /// An input does not spend a UTXO of the caller's address.
+        ForeignInput {
+            txid: Vec<u8>,
+            vout: u32,
+        },
+        /// The Bitcoin network did not accept the transaction.
+        SendFailed {
+            msg: String,
+        },
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -213,10 +628,26 @@ pub mod bitcoin {
         pub network: BitcoinNetwork,
     }
 
+    /// The status of a transaction sent by the user.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum BtcPendingTransactionStatus {
+        /// Not seen in a block yet.  Its UTXOs are locked.
+        Pending,
+        /// Seen in a block at the given height.
+        Confirmed { height: u32 },
+        /// Replaced by another transaction that pays a higher fee (BIP-125).
+        Replaced,
+        /// Not confirmed within a day.  Its UTXOs are no longer locked.
+        Expired,
+    }
+
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct PendingTransaction {
         pub txid: Vec<u8>,
         pub utxos: Vec<Utxo>,
+        pub status: BtcPendingTransactionStatus,
+        /// The number of blocks confirming the transaction, including the block that contains it.
+        pub confirmations: u32,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -230,6 +661,259 @@ pub mod bitcoin {
     }
 }
 
+/// Ethereum specific types.
+pub mod ethereum {
+    use super::{token::ChainId, transaction::SignRequest, Timestamp};
+    use candid::{CandidType, Deserialize, Nat};
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthAddressResponse {
+        /// The address in EIP-55 checksum form.
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EthAddressError {
+        InternalError { msg: String },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthSigningHashResponse {
+        /// The hash to be signed with the caller's Ethereum key, e.g. with `eth_sign_prehash` of the chain fusion
+        /// signer, hex encoded with a `0x` prefix.
+        pub hash: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthSignTransactionRequest {
+        pub transaction: SignRequest,
+        /// The signature of the signing hash of the transaction by the caller's Ethereum key: `r ‖ s`, optionally
+        /// followed by `v`, hex encoded with an optional `0x` prefix.
+        pub signature: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthSignTransactionResponse {
+        /// The signed EIP-1559 transaction, hex encoded with a `0x` prefix, ready to be sent with `eth_sendRawTransaction`.
+        pub signed_transaction: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EthSignTransactionError {
+        /// The recipient is not an Ethereum address.
+        InvalidToAddress {
+            address: String,
+        },
+        /// The data is not hex encoded.
+        InvalidData {
+            data: String,
+        },
+        /// A number field does not fit the transaction, e.g. a chain id above `u64::MAX` or an amount above `U256::MAX`.
+        ValueOutOfRange {
+            field: String,
+        },
+        /// The signature is not a signature of the signing hash by the caller's Ethereum key.
+        InvalidSignature,
+        InternalError {
+            msg: String,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthPersonalSignRequest {
+        /// The message, as in `personal_sign`: hex encoded bytes with a `0x` prefix, or else UTF-8 text.
+        pub message: String,
+        /// The signature of the hash of the message by the caller's Ethereum key: `r ‖ s`, optionally followed by
+        /// `v`, hex encoded with an optional `0x` prefix.
+        pub signature: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthSignTypedDataRequest {
+        /// The typed data as JSON, as in `eth_signTypedData_v4`.
+        pub typed_data: String,
+        /// The signature of the hash of the typed data by the caller's Ethereum key: `r ‖ s`, optionally followed by
+        /// `v`, hex encoded with an optional `0x` prefix.
+        pub signature: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthSignMessageResponse {
+        /// The signature `r ‖ s ‖ v`, hex encoded with a `0x` prefix, where `v` is 27 or 28.
+        pub signature: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EthSignMessageError {
+        /// The typed data is not valid JSON, or its values do not match their types.
+        InvalidTypedData {
+            msg: String,
+        },
+        /// The signature is not a signature of the hash by the caller's Ethereum key.
+        InvalidSignature,
+        InternalError {
+            msg: String,
+        },
+    }
+
+    /// An Ethereum transaction sent by the user, as shown in the activity until the chain has caught up with it.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthPendingTransaction {
+        /// The transaction hash, hex encoded with a `0x` prefix.
+        pub hash: String,
+        pub nonce: u64,
+        /// The recipient of the transfer, in EIP-55 checksum form.
+        pub to: String,
+        /// The amount transferred, in the smallest unit of the token.
+        pub value: Nat,
+        /// The contract of the ERC20 token transferred, or `None` for the native token.
+        pub token: Option<String>,
+        pub created_at_timestamp_ns: u64,
+        /// The hash of the transaction with the same nonce that replaced this one, if any.
+        pub replaced_by: Option<String>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthAddPendingTransactionRequest {
+        pub chain_id: ChainId,
+        pub hash: String,
+        pub nonce: u64,
+        pub to: String,
+        pub value: Nat,
+        pub token: Option<String>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EthAddPendingTransactionError {
+        /// The hash is not 32 bytes, hex encoded with a `0x` prefix.
+        InvalidHash {
+            hash: String,
+        },
+        /// The recipient or the token is not an Ethereum address.
+        InvalidAddress {
+            address: String,
+        },
+        InternalError {
+            msg: String,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetPendingTransactionsRequest {
+        pub chain_id: ChainId,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetPendingTransactionsResponse {
+        pub transactions: Vec<EthPendingTransaction>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthPrunePendingTransactionsRequest {
+        pub chain_id: ChainId,
+        /// The nonce of the next transaction according to the chain.  The transactions with lower nonces are pruned,
+        /// as the chain has included them or the transactions that replaced them.
+        pub on_chain_nonce: Option<u64>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthReserveNonceRequest {
+        pub chain_id: ChainId,
+        /// The nonce of the next transaction according to the chain, i.e. the transaction count of the address.
+        /// Required if the backend does not know the nonce of the chain yet.
+        pub on_chain_nonce: Option<u64>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthReserveNonceResponse {
+        pub nonce: u64,
+        /// The reservation is released at this time if it has not been used by then.
+        pub expires_at: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EthReserveNonceError {
+        /// The backend does not know the nonce of the chain and could not fetch it, so it must be provided in the
+        /// request.
+        UnknownOnChainNonce,
+        TooManyReservations {
+            max_reservations: u32,
+        },
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthReleaseNonceRequest {
+        pub chain_id: ChainId,
+        /// A reserved nonce whose transaction has not been sent.
+        pub nonce: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthReconcileNonceRequest {
+        pub chain_id: ChainId,
+        /// The nonce of the next transaction according to the chain.
+        pub on_chain_nonce: u64,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetBalanceRequest {
+        pub chain_id: ChainId,
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetBalanceResponse {
+        /// The balance of the native token, in wei.
+        pub balance: Nat,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetTransactionCountRequest {
+        pub chain_id: ChainId,
+        pub address: String,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthGetTransactionCountResponse {
+        /// The number of transactions sent from the address, including the pending ones, i.e. the next nonce.
+        pub transaction_count: u64,
+    }
+
+    /// Suggested EIP-1559 fees, in wei per gas.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct EthEstimateFeesResponse {
+        /// The base fee of the next block.
+        pub base_fee_per_gas: Nat,
+        pub max_priority_fee_per_gas: Nat,
+        /// Covers the priority fee and twice the base fee, so that the transaction stays valid for several full blocks.
+        pub max_fee_per_gas: Nat,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum EvmRpcError {
+        /// The EVM RPC canister is not configured.
+        Disabled,
+        UnsupportedChain {
+            chain_id: ChainId,
+        },
+        InvalidAddress {
+            address: String,
+        },
+        /// Too few providers returned the same result.  The results are the responses or errors of the providers.
+        InconsistentResults {
+            results: Vec<String>,
+        },
+        /// The result does not have the expected format.
+        InvalidResponse {
+            msg: String,
+        },
+        /// The caller has sent too many requests to the EVM RPC canister in the last minute.
+        TooManyRequests {
+            max_requests: u32,
+        },
+    }
+}
+
 /// Types related to the signer & topping up the cycles ledger account for use with the signer.
 pub mod signer {
     use super::{CandidType, Debug, Deserialize};
@@ -366,7 +1050,7 @@ pub mod settings {
 
 /// Types specifics to the user profile.
 pub mod user_profile {
-    use super::{CredentialType, Timestamp};
+    use super::{AgreementKind, AgreementVersion, CredentialType, Timestamp};
     use crate::types::settings::Settings;
     use crate::types::Version;
     use candid::{CandidType, Deserialize, Principal};
@@ -388,6 +1072,7 @@ pub mod user_profile {
         pub created_timestamp: Timestamp,
         pub updated_timestamp: Timestamp,
         pub version: Option<Version>,
+        pub agreements: Option<BTreeMap<AgreementKind, AcceptedAgreement>>,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -397,6 +1082,34 @@ pub mod user_profile {
         pub created_timestamp: Timestamp,
         pub updated_timestamp: Timestamp,
         pub version: Option<Version>,
+        /// The latest accepted version of each agreement.
+        pub agreements: Option<BTreeMap<AgreementKind, AcceptedAgreement>>,
+    }
+
+    /// A record of a user accepting a legal agreement.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct AcceptedAgreement {
+        pub version: AgreementVersion,
+        pub accepted_timestamp: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct AcceptAgreementsRequest {
+        /// The agreement versions that the user has accepted.
+        pub agreements: BTreeMap<AgreementKind, AgreementVersion>,
+        pub current_user_version: Option<Version>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum AcceptAgreementsError {
+        UserNotFound,
+        VersionMismatch,
+        /// The accepted version of an agreement is not the version that users are currently
+        /// required to accept.
+        AgreementVersionMismatch {
+            agreement: AgreementKind,
+            required_version: Option<AgreementVersion>,
+        },
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -418,7 +1131,13 @@ pub mod user_profile {
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub struct ListUsersRequest {
         pub updated_after_timestamp: Option<Timestamp>,
+        /// With `updated_after_timestamp`, the last user scanned by the previous request, after which to continue.
+        /// Without it, all the users updated at `updated_after_timestamp` are scanned.
+        pub updated_after_principal: Option<Principal>,
         pub matches_max_length: Option<u64>,
+        /// If set, lists only users who have not accepted the currently required version of
+        /// this agreement.
+        pub agreement_not_accepted: Option<AgreementKind>,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
@@ -432,12 +1151,158 @@ pub mod user_profile {
     pub struct ListUsersResponse {
         pub users: Vec<OisyUser>,
         pub matches_max_length: u64,
+        /// Set if not all users were scanned, e.g. because few users match the filter.  Request again with this
+        /// `updated_after_timestamp` and `next_updated_after_principal` as `updated_after_principal` to continue.
+        pub next_updated_after_timestamp: Option<Timestamp>,
+        /// The last user scanned, set together with `next_updated_after_timestamp`.  Many users may be updated at
+        /// the same timestamp, so the scan continues after this user rather than from the timestamp.
+        pub next_updated_after_principal: Option<Principal>,
     }
 
     #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
     pub enum GetUserProfileError {
         NotFound,
     }
+
+    /// The kind of operation that changed a user profile.
+    #[derive(CandidType, Deserialize, Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum UserProfileChangeKind {
+        Created,
+        CredentialAdded,
+        HiddenDappIdAdded,
+        AgreementsAccepted,
+    }
+
+    /// A single field-level difference between two consecutive versions of a user profile.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum UserProfileChange {
+        CredentialAdded {
+            credential_type: CredentialType,
+            issuer: String,
+        },
+        CredentialRemoved {
+            credential_type: CredentialType,
+        },
+        HiddenDappIdAdded {
+            dapp_id: String,
+        },
+        HiddenDappIdRemoved {
+            dapp_id: String,
+        },
+        AgreementAccepted {
+            agreement: AgreementKind,
+            version: AgreementVersion,
+        },
+    }
+
+    /// An entry in the append-only change log of a user profile.
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct UserProfileHistoryEntry {
+        /// When the change was stored.
+        pub timestamp: Timestamp,
+        /// The profile version after the change.
+        pub version: Option<Version>,
+        pub kind: UserProfileChangeKind,
+        /// The fields that changed, compared with the previous version of the profile.
+        pub changes: Vec<UserProfileChange>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug, Default)]
+    pub struct GetUserProfileHistoryRequest {
+        /// The user whose history is requested.  Defaults to the caller.
+        ///
+        /// Only allowed callers may request the history of another user.
+        pub principal: Option<Principal>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct GetUserProfileHistoryResponse {
+        /// History entries, oldest first.
+        pub entries: Vec<UserProfileHistoryEntry>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum GetUserProfileHistoryError {
+        /// The caller may not read the history of another user.
+        NotAllowed,
+    }
+}
+
+/// Linking several principals, e.g. from different Internet Identity derivation origins, to one
+/// wallet account.
+pub mod principal_link {
+    use super::Timestamp;
+    use candid::{CandidType, Deserialize, Principal};
+
+    /// A principal's membership in a linked group.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct PrincipalLink {
+        /// The principal whose tokens and settings are used by the whole group.
+        pub primary: Principal,
+        pub linked_timestamp: Timestamp,
+    }
+
+    /// An invitation, made by the initiator, for another principal to join the initiator's group.
+    #[derive(CandidType, Deserialize, Copy, Clone, Eq, PartialEq, Debug)]
+    pub struct PrincipalLinkChallenge {
+        pub initiator: Principal,
+        pub expires_timestamp: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct StartPrincipalLinkRequest {
+        /// The principal that is invited to join the caller's group.
+        pub principal: Principal,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct StartPrincipalLinkResponse {
+        /// The invited principal has to confirm the link before this time.
+        pub expires_timestamp: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct ConfirmPrincipalLinkRequest {
+        /// The principal that started the link.
+        pub initiator: Principal,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct UnlinkPrincipalRequest {
+        /// The principal to remove from the caller's group.  Defaults to the caller.
+        pub principal: Option<Principal>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub struct LinkedPrincipals {
+        pub primary: Principal,
+        /// All principals in the group, including the primary.
+        pub principals: Vec<Principal>,
+    }
+
+    #[derive(CandidType, Deserialize, Clone, Eq, PartialEq, Debug)]
+    pub enum PrincipalLinkError {
+        CannotLinkSelf,
+        /// The invited principal already belongs to a group.
+        AlreadyLinked,
+        GroupFull {
+            max_group_size: u64,
+        },
+        ChallengeNotFound,
+        ChallengeExpired,
+        /// The initiator has too many pending invitations.
+        TooManyChallenges {
+            max_challenges: u64,
+        },
+        /// The invited principal has tokens or a profile of its own, which it could no longer use once linked.
+        InvitedPrincipalHasData,
+        NotLinked,
+        /// The primary principal cannot leave its own group while other principals are linked.
+        CannotUnlinkPrimary,
+        /// The caller may only unlink itself, or, as the primary, members of its own group.
+        /// Only the primary principal of a group may invite principals into it.
+        NotAllowed,
+    }
 }
 
 /// The current state of progress of a user data migration.
@@ -460,6 +1325,16 @@ pub enum MigrationProgress {
     MigratedUserTimestampsUpTo(Option<Principal>),
     /// Migrated user profiles up to the given timestamp/user pair.
     MigratedUserProfilesUpTo(Option<(Timestamp, Principal)>),
+    /// Migrated user profile history entries up to the given user/sequence number pair.
+    MigratedUserProfileHistoryUpTo(Option<(Principal, u64)>),
+    /// Migrated principal links up to the given linked principal.
+    MigratedPrincipalLinksUpTo(Option<Principal>),
+    /// Migrated pending Bitcoin transactions up to the given user principal.
+    MigratedBtcPendingTransactionsUpTo(Option<Principal>),
+    /// Migrated frozen Bitcoin UTXOs up to the given user principal.
+    MigratedBtcFrozenUtxosUpTo(Option<Principal>),
+    /// Migrated pending Ethereum transactions up to the given user principal.
+    MigratedEthPendingTransactionsUpTo(Option<Principal>),
     /// Checking that the target canister has all the data.
     CheckingDataMigration,
     /// Unlock user data operations in the target canister.
@@ -519,4 +1394,9 @@ pub struct Stats {
     pub user_timestamps_count: u64,
     pub user_token_count: u64,
     pub custom_token_count: u64,
+    pub user_profile_history_count: u64,
+    pub principal_link_count: u64,
+    pub btc_pending_transaction_count: u64,
+    pub btc_frozen_utxo_count: u64,
+    pub eth_pending_transaction_count: u64,
 }